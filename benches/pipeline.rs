@@ -0,0 +1,96 @@
+// Decode, resize and encode for every upload format and served resolution. Run with
+// `cargo bench --features bench`, criterion compares against the previous run, so run it
+// before and after touching the encoder settings
+
+#[path = "../src/pipeline.rs"]
+mod pipeline;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use pipeline::{IMAGE_HEIGHT, IMAGE_WIDTH};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+use std::io::Cursor;
+
+// the UPLOAD_FORMATS default
+const FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+// the THUMBNAIL_MAX_BYTES default
+const MAX_ENCODED_SIZE: usize = 4 * 1024 * 1024;
+
+const RESOLUTIONS: [(&str, u32, u32); 3] =
+    [("high", 1920, 1080), ("medium", 1280, 720), ("small", 640, 360)];
+
+// Level screenshots are mostly smooth gradients and flat blocks with some noise on top,
+// a solid colour would encode far faster than anything players upload
+fn screenshot() -> DynamicImage {
+    let mut rng = StdRng::seed_from_u64(1);
+    let blocks: Vec<(u32, u32, u32, [u8; 3])> = (0..60)
+        .map(|_| {
+            let x = rng.random_range(0..IMAGE_WIDTH);
+            let y = rng.random_range(0..IMAGE_HEIGHT);
+            (x, y, rng.random_range(20..160), rng.random())
+        })
+        .collect();
+
+    let image = RgbImage::from_fn(IMAGE_WIDTH, IMAGE_HEIGHT, |x, y| {
+        let block = blocks
+            .iter()
+            .find(|(bx, by, size, _)| x.abs_diff(*bx) < *size && y.abs_diff(*by) < *size);
+        let base = match block {
+            Some((_, _, _, color)) => *color,
+            None => [(x * 255 / IMAGE_WIDTH) as u8, 40, (y * 255 / IMAGE_HEIGHT) as u8],
+        };
+        let noise: u8 = rng.random_range(0..8);
+        image::Rgb(base.map(|channel| channel.saturating_add(noise)))
+    });
+    DynamicImage::ImageRgb8(image)
+}
+
+fn encode_source(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+    if format == ImageFormat::WebP {
+        let rgb = image.to_rgb8();
+        return webp::Encoder::from_rgb(&rgb, rgb.width(), rgb.height()).encode(90.0).to_vec();
+    }
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, format).expect("encodable source image");
+    data.into_inner()
+}
+
+fn image_pipeline(c: &mut Criterion) {
+    let source = screenshot();
+    let mut group = c.benchmark_group("pipeline");
+    // a lossless 1080p encode takes long enough that the default 100 samples would take minutes
+    group.sample_size(10);
+
+    for format in FORMATS {
+        let data = encode_source(&source, format);
+        let name = format.extensions_str()[0];
+
+        for (res, width, height) in RESOLUTIONS {
+            group.bench_with_input(BenchmarkId::new(name, res), &data, |b, data| {
+                b.iter(|| {
+                    let image = pipeline::validate_upload(data, &FORMATS).unwrap();
+                    match res {
+                        // uploads are stored at full size, the others are resized variants
+                        "high" => pipeline::encode_thumbnail(image, MAX_ENCODED_SIZE).unwrap(),
+                        _ => pipeline::resize(&image, width, height),
+                    }
+                })
+            });
+        }
+    }
+
+    // the lossy fallback for uploads that don't fit losslessly
+    let data = encode_source(&source, ImageFormat::Png);
+    group.bench_function("png/high-lossy", |b| {
+        b.iter(|| {
+            let image = pipeline::validate_upload(&data, &FORMATS).unwrap();
+            pipeline::encode_thumbnail(image, black_box(256 * 1024))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, image_pipeline);
+criterion_main!(benches);
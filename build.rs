@@ -0,0 +1,10 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("Failed to locate protoc");
+        // SAFETY: the build script is single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc) };
+        tonic_prost_build::compile_protos("proto/thumbnails.proto")
+            .expect("Failed to compile protobuf definitions");
+    }
+}
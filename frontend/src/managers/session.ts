@@ -4,7 +4,7 @@ export interface User {
     id: number;
     account_id: number;
     username: string;
-    role: 'user' | 'verified' | 'moderator' | 'admin';
+    role: 'user' | 'verified' | 'trial_mod' | 'moderator' | 'admin';
     discord_id?: number | null;
 }
 
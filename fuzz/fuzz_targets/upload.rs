@@ -0,0 +1,18 @@
+// Feeds arbitrary bytes through the validation every upload goes through. Run with
+// `cargo +nightly fuzz run upload` from the repository root, seeding corpus/upload with a few
+// real thumbnails gets past the magic bytes much sooner
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/pipeline.rs"]
+mod pipeline;
+
+use image::ImageFormat;
+use libfuzzer_sys::fuzz_target;
+
+const FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+fuzz_target!(|data: &[u8]| {
+    // errors are fine, panics, hangs and huge allocations are what this looks for
+    let _ = pipeline::validate_upload(data, &FORMATS);
+});
@@ -0,0 +1,18 @@
+CREATE TABLE IF NOT EXISTS sync_changes
+(
+    id           BIGSERIAL PRIMARY KEY,
+    level_id     BIGINT    NOT NULL,
+    action       TEXT      NOT NULL CHECK (action IN ('accepted', 'removed')),
+    content_hash TEXT      DEFAULT NULL,
+    user_id      BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS sync_changes_content_hash_idx ON sync_changes (content_hash);
+
+-- Seed the log with the currently active thumbnails, hashes are computed on demand
+INSERT INTO sync_changes (level_id, action, user_id, created_at)
+SELECT DISTINCT ON (level_id) level_id, 'accepted', user_id, COALESCE(accepted_time, upload_time)
+FROM uploads
+WHERE accepted = TRUE
+ORDER BY level_id, upload_time DESC;
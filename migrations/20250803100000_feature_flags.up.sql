@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS feature_flags
+(
+    name       TEXT PRIMARY KEY,
+    enabled    BOOLEAN   NOT NULL DEFAULT FALSE,
+    updated_by BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS settings
+(
+    key        TEXT PRIMARY KEY,
+    value      TEXT      NOT NULL,
+    updated_by BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
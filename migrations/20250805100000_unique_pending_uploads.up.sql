@@ -0,0 +1,12 @@
+-- only the newest pending row per user and level matches the file on disk
+DELETE FROM uploads a
+    USING uploads b
+WHERE a.user_id = b.user_id
+  AND a.level_id = b.level_id
+  AND a.id < b.id
+  AND a.accepted = FALSE AND a.accepted_time IS NULL
+  AND b.accepted = FALSE AND b.accepted_time IS NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS uploads_pending_user_level
+    ON uploads (user_id, level_id)
+    WHERE accepted = FALSE AND accepted_time IS NULL;
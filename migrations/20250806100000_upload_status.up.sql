@@ -0,0 +1,19 @@
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'accepted', 'rejected', 'withdrawn', 'expired'));
+
+UPDATE uploads
+SET status = CASE
+                 WHEN accepted = TRUE THEN 'accepted'
+                 WHEN accepted_time IS NOT NULL THEN 'rejected'
+                 ELSE 'pending'
+    END;
+
+DROP INDEX IF EXISTS uploads_pending_user_level;
+CREATE UNIQUE INDEX uploads_pending_user_level
+    ON uploads (user_id, level_id)
+    WHERE status = 'pending';
+
+CREATE INDEX IF NOT EXISTS uploads_status_level ON uploads (status, level_id);
+
+ALTER TABLE uploads DROP COLUMN accepted;
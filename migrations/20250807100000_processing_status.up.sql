@@ -0,0 +1,7 @@
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS processing_status TEXT NOT NULL DEFAULT 'received'
+        CHECK (processing_status IN ('received', 'validated', 'encoded', 'queued_for_review', 'live'));
+
+-- everything stored so far already went through the whole pipeline
+UPDATE uploads
+SET processing_status = CASE WHEN status = 'accepted' THEN 'live' ELSE 'queued_for_review' END;
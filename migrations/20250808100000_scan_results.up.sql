@@ -0,0 +1,4 @@
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS scan_verdict TEXT DEFAULT NULL CHECK (scan_verdict IN ('clean', 'flagged', 'held')),
+    ADD COLUMN IF NOT EXISTS scan_score   REAL DEFAULT NULL,
+    ADD COLUMN IF NOT EXISTS scan_label   TEXT DEFAULT NULL;
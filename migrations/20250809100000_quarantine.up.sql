@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS quarantine
+(
+    id         BIGSERIAL PRIMARY KEY,
+    user_id    BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    level_id   BIGINT    NOT NULL,
+    sha256     TEXT      NOT NULL,
+    source     TEXT      NOT NULL, -- what put the upload here, e.g. hash_match
+    detail     TEXT      DEFAULT NULL,
+    file_path  TEXT      NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,11 @@
+ALTER TABLE quarantine
+    ADD COLUMN IF NOT EXISTS encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS quarantine_audit
+(
+    id            BIGSERIAL PRIMARY KEY,
+    quarantine_id BIGINT    DEFAULT NULL REFERENCES quarantine (id) ON DELETE SET NULL,
+    admin_id      BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    action        TEXT      NOT NULL, -- list, view, release or delete
+    created_at    TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
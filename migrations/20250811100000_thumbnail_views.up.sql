@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS thumbnail_views
+(
+    level_id BIGINT NOT NULL,
+    day      DATE   NOT NULL,
+    views    BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (level_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS thumbnail_views_day ON thumbnail_views (day);
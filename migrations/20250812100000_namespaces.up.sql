@@ -0,0 +1,35 @@
+-- Separate GDPS instances hosted by the same deployment, 'default' is the main game
+CREATE TABLE IF NOT EXISTS namespaces
+(
+    name         TEXT PRIMARY KEY,
+    display_name TEXT      NOT NULL,
+    created_by   BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO namespaces (name, display_name)
+VALUES ('default', 'Geometry Dash')
+ON CONFLICT DO NOTHING;
+
+-- roles granted inside one namespace, on top of the user's global role
+CREATE TABLE IF NOT EXISTS namespace_roles
+(
+    user_id    BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    namespace  TEXT   NOT NULL REFERENCES namespaces (name) ON DELETE CASCADE,
+    role       TEXT   NOT NULL CHECK (role IN ('user', 'verified', 'moderator', 'admin')),
+    PRIMARY KEY (user_id, namespace)
+);
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT 'default' REFERENCES namespaces (name);
+
+ALTER TABLE quarantine
+    ADD COLUMN IF NOT EXISTS namespace TEXT NOT NULL DEFAULT 'default' REFERENCES namespaces (name);
+
+DROP INDEX IF EXISTS uploads_pending_user_level;
+CREATE UNIQUE INDEX uploads_pending_user_level
+    ON uploads (namespace, user_id, level_id)
+    WHERE status = 'pending';
+
+DROP INDEX IF EXISTS uploads_status_level;
+CREATE INDEX uploads_status_level ON uploads (namespace, status, level_id);
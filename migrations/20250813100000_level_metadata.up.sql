@@ -0,0 +1,9 @@
+-- level details fetched from the metadata service, refreshed when stale
+CREATE TABLE IF NOT EXISTS level_metadata
+(
+    level_id   BIGINT PRIMARY KEY,
+    name       TEXT      NOT NULL,
+    difficulty TEXT      NOT NULL,
+    stars      INTEGER   NOT NULL DEFAULT 0,
+    fetched_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,19 @@
+-- rules evaluated on every upload by a regular or verified user, lowest priority first
+CREATE TABLE IF NOT EXISTS upload_rules
+(
+    id                   BIGSERIAL PRIMARY KEY,
+    name                 TEXT      NOT NULL,
+    role                 TEXT               DEFAULT NULL CHECK (role IN ('user', 'verified')),
+    condition            TEXT      NOT NULL CHECK (condition IN ('new_level', 'level_creator', 'new_account')),
+    max_account_age_days INTEGER            DEFAULT NULL,
+    action               TEXT      NOT NULL CHECK (action IN ('accept', 'hold')),
+    priority             INTEGER   NOT NULL DEFAULT 0,
+    enabled              BOOLEAN   NOT NULL DEFAULT TRUE,
+    created_by           BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    created_at           TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- accounts that existed before this migration count from the day it ran
+ALTER TABLE users ADD COLUMN IF NOT EXISTS created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+ALTER TABLE level_metadata ADD COLUMN IF NOT EXISTS author_account_id BIGINT DEFAULT NULL;
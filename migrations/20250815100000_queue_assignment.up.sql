@@ -0,0 +1,5 @@
+-- moderator a pending upload is assigned to when queue assignment is enabled
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS assigned_to BIGINT DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL;
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS assigned_at TIMESTAMP DEFAULT NULL;
+
+CREATE INDEX IF NOT EXISTS uploads_pending_assigned ON uploads (assigned_to) WHERE status = 'pending';
@@ -0,0 +1,8 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- the Discord name is kept separately, linking a GD account replaces username
+ALTER TABLE users ADD COLUMN IF NOT EXISTS discord_username TEXT DEFAULT NULL;
+UPDATE users SET discord_username = username WHERE discord_id IS NOT NULL AND account_id = -1;
+
+CREATE INDEX IF NOT EXISTS users_username_trgm ON users USING GIN (username gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS users_discord_username_trgm ON users USING GIN (discord_username gin_trgm_ops);
@@ -0,0 +1,2 @@
+-- avatar hash from the last Discord login, used for the avatar proxy
+ALTER TABLE users ADD COLUMN IF NOT EXISTS discord_avatar TEXT DEFAULT NULL;
@@ -0,0 +1,9 @@
+-- authenticated requests and uploaded bytes per user and day
+CREATE TABLE IF NOT EXISTS api_usage
+(
+    user_id      BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    day          DATE   NOT NULL,
+    requests     BIGINT NOT NULL DEFAULT 0,
+    upload_bytes BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, day)
+);
@@ -0,0 +1,23 @@
+-- addresses users logged in or uploaded from, one row per user, address and action
+CREATE TABLE IF NOT EXISTS user_ips
+(
+    user_id    BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    ip         INET      NOT NULL,
+    action     TEXT      NOT NULL CHECK (action IN ('login', 'upload')),
+    first_seen TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    count      BIGINT    NOT NULL DEFAULT 1,
+    PRIMARY KEY (user_id, ip, action)
+);
+
+CREATE INDEX IF NOT EXISTS user_ips_ip ON user_ips (ip);
+
+CREATE TABLE IF NOT EXISTS ip_bans
+(
+    id         BIGSERIAL PRIMARY KEY,
+    ip_range   CIDR      NOT NULL,
+    reason     TEXT               DEFAULT NULL,
+    created_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP          DEFAULT NULL
+);
@@ -0,0 +1,3 @@
+-- address for notification emails, only used once the user opts in
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email TEXT DEFAULT NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_notifications BOOLEAN NOT NULL DEFAULT FALSE;
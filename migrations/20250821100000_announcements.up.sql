@@ -0,0 +1,14 @@
+-- notices polled by the mod and the dashboard, shown between starts_at and ends_at
+CREATE TABLE IF NOT EXISTS announcements
+(
+    id         BIGSERIAL PRIMARY KEY,
+    title      TEXT      NOT NULL,
+    message    TEXT      NOT NULL,
+    severity   TEXT      NOT NULL DEFAULT 'info' CHECK (severity IN ('info', 'warning', 'critical')),
+    starts_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ends_at    TIMESTAMP          DEFAULT NULL,
+    created_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS announcements_active_idx ON announcements (starts_at, ends_at);
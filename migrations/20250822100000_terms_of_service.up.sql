@@ -0,0 +1,17 @@
+-- every published revision of the terms and content policy, the newest one is current
+CREATE TABLE IF NOT EXISTS tos_versions
+(
+    version      TEXT PRIMARY KEY,
+    url          TEXT      NOT NULL,
+    published_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    published_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- kept for every version a user agreed to, for audits
+CREATE TABLE IF NOT EXISTS tos_acceptances
+(
+    user_id     BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    version     TEXT      NOT NULL REFERENCES tos_versions (version) ON DELETE CASCADE,
+    accepted_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, version)
+);
@@ -0,0 +1,3 @@
+-- declared by the uploader, so disputes over reposted artwork have a paper trail
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS license TEXT DEFAULT NULL;
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS credit TEXT DEFAULT NULL;
@@ -0,0 +1,32 @@
+-- thumbnails pulled after a takedown claim keep their rows for the record
+ALTER TABLE uploads DROP CONSTRAINT IF EXISTS uploads_status_check;
+ALTER TABLE uploads
+    ADD CONSTRAINT uploads_status_check
+        CHECK (status IN ('pending', 'accepted', 'rejected', 'withdrawn', 'expired', 'removed'));
+
+CREATE TABLE IF NOT EXISTS takedown_requests
+(
+    id               BIGSERIAL PRIMARY KEY,
+    namespace        TEXT      NOT NULL DEFAULT 'default',
+    level_id         BIGINT    NOT NULL,
+    claimant_name    TEXT               DEFAULT NULL,
+    claimant_contact TEXT      NOT NULL,
+    reason           TEXT      NOT NULL,
+    ip               INET               DEFAULT NULL,
+    status           TEXT      NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'taken_down', 'dismissed')),
+    resolved_by      BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    resolved_at      TIMESTAMP          DEFAULT NULL,
+    created_at       TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS takedown_requests_status_idx ON takedown_requests (status, created_at);
+
+CREATE TABLE IF NOT EXISTS takedown_audit
+(
+    id          BIGSERIAL PRIMARY KEY,
+    takedown_id BIGINT    DEFAULT NULL REFERENCES takedown_requests (id) ON DELETE SET NULL,
+    admin_id    BIGINT    DEFAULT NULL REFERENCES users (id) ON DELETE SET NULL,
+    action      TEXT      NOT NULL, -- takedown or dismiss
+    note        TEXT      DEFAULT NULL,
+    created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
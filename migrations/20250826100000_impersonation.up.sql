@@ -0,0 +1,25 @@
+-- sessions admins opened as another user, shown to that user afterwards
+CREATE TABLE IF NOT EXISTS impersonation_sessions
+(
+    id         BIGSERIAL PRIMARY KEY,
+    admin_id   BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    user_id    BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    reason     TEXT      NOT NULL,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS impersonation_sessions_user_idx ON impersonation_sessions (user_id, started_at);
+
+-- every request made with an impersonation session
+CREATE TABLE IF NOT EXISTS impersonation_actions
+(
+    id         BIGSERIAL PRIMARY KEY,
+    session_id BIGINT    NOT NULL REFERENCES impersonation_sessions (id) ON DELETE CASCADE,
+    method     TEXT      NOT NULL,
+    path       TEXT      NOT NULL,
+    status     INTEGER   NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS impersonation_actions_session_idx ON impersonation_actions (session_id);
@@ -0,0 +1,24 @@
+-- what each role is allowed to do, checked instead of comparing roles in handlers
+CREATE TABLE IF NOT EXISTS role_permissions
+(
+    role       TEXT NOT NULL CHECK (role IN ('user', 'verified', 'moderator', 'admin')),
+    permission TEXT NOT NULL,
+    PRIMARY KEY (role, permission)
+);
+
+INSERT INTO role_permissions (role, permission)
+VALUES ('verified', 'publish_own'),
+       ('moderator', 'publish_own'),
+       ('moderator', 'publish_directly'),
+       ('moderator', 'bypass_quota'),
+       ('moderator', 'skip_captcha'),
+       ('moderator', 'review_uploads'),
+       ('admin', 'publish_own'),
+       ('admin', 'publish_directly'),
+       ('admin', 'bypass_quota'),
+       ('admin', 'skip_captcha'),
+       ('admin', 'review_uploads'),
+       ('admin', 'review_held'),
+       ('admin', 'manage_users'),
+       ('admin', 'purge_cache')
+ON CONFLICT DO NOTHING;
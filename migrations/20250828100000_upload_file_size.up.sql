@@ -0,0 +1,2 @@
+-- size of the stored WebP in bytes, older rows stay NULL
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS file_size BIGINT DEFAULT NULL;
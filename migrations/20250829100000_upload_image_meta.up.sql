@@ -0,0 +1,5 @@
+-- read from the stored file at save time, older rows stay NULL
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS width INTEGER DEFAULT NULL;
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS height INTEGER DEFAULT NULL;
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS encoding TEXT DEFAULT NULL; -- lossless or lossy
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS encoder_version INTEGER DEFAULT NULL;
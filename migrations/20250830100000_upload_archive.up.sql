@@ -0,0 +1,2 @@
+-- copy of the file an accepted upload served before it was replaced
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS archive_path TEXT DEFAULT NULL;
@@ -0,0 +1,6 @@
+-- when the file at archive_path was put there, retention rules count from this
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS archived_at TIMESTAMP DEFAULT NULL;
+
+UPDATE uploads SET archived_at = CURRENT_TIMESTAMP WHERE archive_path IS NOT NULL AND archived_at IS NULL;
+
+CREATE INDEX IF NOT EXISTS uploads_archived_at_idx ON uploads (archived_at) WHERE archive_path IS NOT NULL;
@@ -0,0 +1,12 @@
+-- curated level packs, the level IDs are kept in the order the owner chose
+CREATE TABLE IF NOT EXISTS collections
+(
+    id         BIGSERIAL PRIMARY KEY,
+    owner_id   BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name       TEXT      NOT NULL,
+    level_ids  BIGINT[]  NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS collections_owner_idx ON collections (owner_id);
@@ -0,0 +1,5 @@
+-- creator name as reported by the metadata service, for searching levels by creator
+ALTER TABLE level_metadata ADD COLUMN IF NOT EXISTS author TEXT DEFAULT NULL;
+
+CREATE INDEX IF NOT EXISTS level_metadata_name_trgm ON level_metadata USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS level_metadata_author_trgm ON level_metadata USING GIN (author gin_trgm_ops);
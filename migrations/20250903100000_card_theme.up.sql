@@ -0,0 +1,3 @@
+-- share-card style, applied to cards of the user's own thumbnails
+ALTER TABLE users ADD COLUMN IF NOT EXISTS card_accent TEXT DEFAULT NULL; -- #rrggbb
+ALTER TABLE users ADD COLUMN IF NOT EXISTS card_show_author BOOLEAN NOT NULL DEFAULT TRUE;
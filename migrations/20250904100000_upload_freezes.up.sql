@@ -0,0 +1,15 @@
+-- windows during which uploads for the listed levels are refused, e.g. while a contest is judged
+CREATE TABLE IF NOT EXISTS upload_freezes
+(
+    id         BIGSERIAL PRIMARY KEY,
+    namespace  TEXT      NOT NULL DEFAULT 'default',
+    name       TEXT      NOT NULL,
+    reason     TEXT      NOT NULL,
+    level_ids  BIGINT[]  NOT NULL,
+    starts_at  TIMESTAMP NOT NULL,
+    ends_at    TIMESTAMP NOT NULL,
+    created_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS upload_freezes_window_idx ON upload_freezes (namespace, ends_at);
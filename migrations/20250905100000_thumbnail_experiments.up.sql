@@ -0,0 +1,12 @@
+-- A/B tests of two accepted thumbnails, slot A is the live one and slot B a copy of an earlier upload
+CREATE TABLE IF NOT EXISTS thumbnail_experiments
+(
+    level_id   BIGINT PRIMARY KEY,
+    upload_id  BIGINT    NOT NULL REFERENCES uploads (id) ON DELETE CASCADE,
+    user_id    BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE, -- uploader of slot B
+    image_path TEXT      NOT NULL,
+    views_a    BIGINT    NOT NULL DEFAULT 0,
+    views_b    BIGINT    NOT NULL DEFAULT 0,
+    started_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
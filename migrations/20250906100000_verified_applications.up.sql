@@ -0,0 +1,17 @@
+-- users asking to be made verified, reviewed by moderators
+CREATE TABLE IF NOT EXISTS verified_applications
+(
+    id          BIGSERIAL PRIMARY KEY,
+    user_id     BIGINT    NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    links       TEXT[]    NOT NULL DEFAULT '{}', -- earlier work, like levels or art the applicant made
+    message     TEXT      NOT NULL,
+    status      TEXT      NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'denied')),
+    reviewed_by BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    review_note TEXT               DEFAULT NULL,
+    reviewed_at TIMESTAMP          DEFAULT NULL,
+    created_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS verified_applications_pending_idx
+    ON verified_applications (user_id) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS verified_applications_status_idx ON verified_applications (status, created_at);
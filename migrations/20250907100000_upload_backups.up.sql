@@ -0,0 +1,3 @@
+-- when an accepted upload was copied to the backup storage
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS backed_up_at TIMESTAMP DEFAULT NULL;
+CREATE INDEX IF NOT EXISTS uploads_unreplicated_idx ON uploads (accepted_time) WHERE status = 'accepted' AND backed_up_at IS NULL;
@@ -0,0 +1,8 @@
+-- One row per scheduled job, so only one replica runs it per interval
+CREATE TABLE IF NOT EXISTS job_leases
+(
+    name         TEXT PRIMARY KEY,
+    holder       TEXT      NOT NULL, -- instance ID of the replica that last claimed it
+    locked_until TIMESTAMP,          -- NULL once the run finished
+    last_run_at  TIMESTAMP NOT NULL
+);
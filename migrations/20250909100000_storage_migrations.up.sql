@@ -0,0 +1,21 @@
+-- Copies of the live thumbnails from local disk to object storage, the bucket is read from
+-- once one of them completed
+CREATE TABLE IF NOT EXISTS storage_migrations
+(
+    id          BIGSERIAL PRIMARY KEY,
+    destination TEXT      NOT NULL,
+    status      TEXT      NOT NULL DEFAULT 'running' CHECK (status IN ('running', 'completed', 'failed')),
+    total       BIGINT    NOT NULL DEFAULT 0,
+    copied      BIGINT    NOT NULL DEFAULT 0,
+    verified    BIGINT    NOT NULL DEFAULT 0,
+    skipped     BIGINT    NOT NULL DEFAULT 0,
+    failed      BIGINT    NOT NULL DEFAULT 0,
+    error       TEXT               DEFAULT NULL,
+    started_by  BIGINT             REFERENCES users (id) ON DELETE SET NULL,
+    started_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    finished_at TIMESTAMP          DEFAULT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS storage_migrations_running_idx
+    ON storage_migrations (status) WHERE status = 'running';
@@ -0,0 +1,7 @@
+-- set when the upload was signed by the game mod
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS attested BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE upload_rules DROP CONSTRAINT IF EXISTS upload_rules_condition_check;
+ALTER TABLE upload_rules
+    ADD CONSTRAINT upload_rules_condition_check
+        CHECK (condition IN ('new_level', 'level_creator', 'new_account', 'attested', 'unattested'));
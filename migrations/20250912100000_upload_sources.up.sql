@@ -0,0 +1,3 @@
+-- where the upload was submitted from, NULL for uploads from before this was recorded
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS source TEXT DEFAULT NULL
+    CHECK (source IN ('mod', 'web', 'api', 'import'));
@@ -0,0 +1,10 @@
+-- Live thumbnails whose latest state hasn't reached the storage bucket yet. A row is removed
+-- once the copy went through, until then it's retried in the background
+CREATE TABLE IF NOT EXISTS storage_mirror_queue
+(
+    path       TEXT PRIMARY KEY,
+    hash       TEXT                 DEFAULT NULL, -- sha256 of the file to copy, NULL to delete it
+    attempts   INTEGER     NOT NULL DEFAULT 0,
+    last_error TEXT                 DEFAULT NULL,
+    queued_at  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
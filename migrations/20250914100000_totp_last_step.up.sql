@@ -0,0 +1,3 @@
+-- The time step of the last TOTP code that was accepted, a code from that step or an earlier
+-- one is refused so it can't be replayed while it's still valid
+ALTER TABLE users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT DEFAULT NULL;
@@ -0,0 +1,5 @@
+-- Notification emails only go to addresses confirmed through a mailed link, only a hash of the
+-- link's token is kept
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token_hash TEXT DEFAULT NULL;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_token_expires TIMESTAMPTZ DEFAULT NULL;
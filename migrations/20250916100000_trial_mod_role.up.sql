@@ -0,0 +1,18 @@
+-- Trial moderators review uploads like moderators, but don't publish their own directly
+ALTER TABLE users DROP CONSTRAINT IF EXISTS users_role_check;
+ALTER TABLE users
+    ADD CONSTRAINT users_role_check CHECK (role IN ('user', 'verified', 'trial_mod', 'moderator', 'admin'));
+
+ALTER TABLE role_permissions DROP CONSTRAINT IF EXISTS role_permissions_role_check;
+ALTER TABLE role_permissions
+    ADD CONSTRAINT role_permissions_role_check CHECK (role IN ('user', 'verified', 'trial_mod', 'moderator', 'admin'));
+
+ALTER TABLE namespace_roles DROP CONSTRAINT IF EXISTS namespace_roles_role_check;
+ALTER TABLE namespace_roles
+    ADD CONSTRAINT namespace_roles_role_check CHECK (role IN ('user', 'verified', 'trial_mod', 'moderator', 'admin'));
+
+INSERT INTO role_permissions (role, permission)
+VALUES ('trial_mod', 'publish_own'),
+       ('trial_mod', 'skip_captcha'),
+       ('trial_mod', 'review_uploads')
+ON CONFLICT DO NOTHING;
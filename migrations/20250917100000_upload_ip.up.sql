@@ -0,0 +1,3 @@
+-- The address an upload was sent from, so rejections count against it and not its uploader's other logins
+ALTER TABLE uploads ADD COLUMN IF NOT EXISTS ip INET DEFAULT NULL;
+CREATE INDEX IF NOT EXISTS uploads_ip_idx ON uploads (ip) WHERE ip IS NOT NULL;
@@ -0,0 +1,6 @@
+-- Last presence ping of each moderator, shared so every replica sees who has the queue open
+CREATE TABLE IF NOT EXISTS moderator_presence
+(
+    user_id   BIGINT PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    last_seen TIMESTAMP NOT NULL
+);
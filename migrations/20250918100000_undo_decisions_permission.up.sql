@@ -0,0 +1,4 @@
+-- undoing another moderator's decision used to be hardcoded to admins
+INSERT INTO role_permissions (role, permission)
+VALUES ('admin', 'undo_decisions')
+ON CONFLICT DO NOTHING;
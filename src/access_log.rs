@@ -0,0 +1,113 @@
+use crate::auth::UserSession;
+use crate::models::UserId;
+use crate::{client_ip, util};
+use axum::body::HttpBody;
+use axum::extract::{MatchedPath, Request};
+use axum::http::{HeaderValue, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde_json::json;
+use std::io::Write;
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+
+// thumbnail images make up most of the traffic, so only a fraction of them is logged
+const SAMPLED_ROUTES: &[&str] = &["/thumbnail/{id}", "/thumbnail/{id}/{res}"];
+const DEFAULT_SAMPLE_RATE: f64 = 0.01;
+
+struct AccessLog {
+    writer: Mutex<Box<dyn Write + Send>>,
+    sample_rate: f64,
+}
+
+// ACCESS_LOG is either "stdout" or a file path, logging is disabled when unset
+static ACCESS_LOG: LazyLock<Option<AccessLog>> = LazyLock::new(|| {
+    let target = dotenv::var("ACCESS_LOG").ok()?;
+    let writer: Box<dyn Write + Send> = match target.as_str() {
+        "stdout" => Box::new(std::io::stdout()),
+        path => Box::new(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .expect("Failed to open ACCESS_LOG file"),
+        ),
+    };
+
+    let sample_rate = dotenv::var("ACCESS_LOG_THUMBNAIL_SAMPLE_RATE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+
+    Some(AccessLog {
+        writer: Mutex::new(writer),
+        sample_rate,
+    })
+});
+
+// Only decodes the session token, handlers still do the actual authentication
+pub fn session(request: &Request) -> Option<UserSession> {
+    UserSession::from_jwt(&util::session_token(request.headers())?).ok()
+}
+
+pub fn session_user_id(request: &Request) -> Option<UserId> {
+    session(request).map(|session| session.id)
+}
+
+pub async fn log(request: Request, next: Next) -> Response {
+    let Some(access_log) = ACCESS_LOG.as_ref() else {
+        return next.run(request).await;
+    };
+
+    let route = request.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string());
+    if route.as_deref().is_some_and(|route| SAMPLED_ROUTES.contains(&route))
+        && rand::random::<f64>() >= access_log.sample_rate
+    {
+        return next.run(request).await;
+    }
+
+    let request_id = request
+        .headers()
+        .get("X-Request-Id")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let session = session(&request);
+    let ip = client_ip::from_parts(request.extensions(), request.headers());
+
+    let start = Instant::now();
+    let mut response = next.run(request).await;
+    let latency = start.elapsed();
+
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|h| h.to_str().ok()?.parse().ok())
+        .or_else(|| response.body().size_hint().exact());
+
+    let line = json!({
+        "time": chrono::Utc::now().to_rfc3339(),
+        "request_id": request_id,
+        "method": method,
+        "path": path,
+        "route": route,
+        "status": response.status().as_u16(),
+        "latency_ms": latency.as_secs_f64() * 1000.0,
+        "bytes": bytes,
+        "user_id": session.as_ref().map(|s| s.id),
+        "impersonation": session.as_ref().and_then(|s| s.impersonation),
+        "ip": ip,
+    });
+
+    if let Ok(mut writer) = access_log.writer.lock() {
+        let _ = writeln!(writer, "{}", line);
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("X-Request-Id", value);
+    }
+
+    response
+}
@@ -0,0 +1,258 @@
+use crate::database;
+use crate::routes::{
+    admin, announcements, collections, discord, flags, graphql as graphql_routes, login, ops,
+    stats, sync as sync_routes, thumbnail, upload, user, ws,
+};
+use crate::{
+    access_log, auth, client_ip, csrf, impersonation, ip_bans, paths, reload, storage, usage_stats,
+};
+use axum::{
+    Router, middleware, routing::delete, routing::get, routing::patch, routing::post, routing::put,
+};
+use std::path::PathBuf;
+use tower_http::cors;
+use tower_http::services::{ServeDir, ServeFile};
+
+// What the router is built from, the server reads it from the environment and tests fill it in
+pub struct Config {
+    pub mirror: bool, // only thumbnails are served, everything else lives on the upstream
+    pub separate_internal: bool, // ops and admin routes are left to internal() for their own listener
+    pub dist_dir: String,        // the built dashboard, served for every path without a route
+    pub jwt_secret: String,      // signs sessions, CSRF tokens and signed links
+    pub storage_root: PathBuf,   // thumbnails, uploads and the other data directories live here
+    pub storage_bucket: Option<storage::BucketConfig>, // None reads it from the environment
+}
+
+impl Config {
+    pub fn from_env(mirror: bool) -> Self {
+        Self {
+            mirror,
+            separate_internal: dotenv::var("ADMIN_BIND_ADDRESS").is_ok(),
+            dist_dir: "dist".to_string(),
+            jwt_secret: dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set"),
+            storage_root: PathBuf::from("."),
+            storage_bucket: None,
+        }
+    }
+
+    // These are needed far from any handler state, so they're set once for the whole process
+    fn install(&self) {
+        auth::set_secret(self.jwt_secret.clone());
+        paths::set_root(self.storage_root.clone());
+        if let Some(bucket) = &self.storage_bucket {
+            storage::configure(bucket);
+        }
+    }
+}
+
+fn public_routes(config: &Config) -> Router<database::Database> {
+    let public = Router::new()
+        .route("/stats", get(crate::get_stats))
+        .route("/stats/levels/{id}", get(stats::get_level_stats))
+        .route("/stats/top-levels", get(stats::get_top_levels))
+        .route("/stats/sources", get(stats::get_upload_sources))
+        // /thumbnail
+        .route("/thumbnail/{id}", get(thumbnail::image_handler_default))
+        .route("/thumbnail/{id}/{res}", get(thumbnail::image_handler_with_res))
+        .route("/thumbnail/{id}/info", get(thumbnail::thumbnail_info_handler))
+        .route("/thumbnail/{id}/embed", get(thumbnail::embed_handler))
+        .route("/thumbnail/{id}/card", get(thumbnail::card_handler))
+        .route("/thumbnail/random", get(thumbnail::random_handler))
+        .route("/thumbnail/random/{res}", get(thumbnail::random_res_handler))
+        .route("/thumbnail/batch.zip", post(thumbnail::batch_zip_handler))
+        .route("/oembed", get(thumbnail::oembed_handler))
+        // /gdps/{ns}, other game instances hosted next to the main one
+        .route("/gdps/{ns}/thumbnail/{id}", get(thumbnail::namespaced_image_handler_default))
+        .route("/gdps/{ns}/thumbnail/{id}/{res}", get(thumbnail::namespaced_image_handler_with_res))
+        .route("/gdps/{ns}/thumbnail/{id}/info", get(thumbnail::namespaced_thumbnail_info_handler))
+        // /sync
+        .route("/sync/changes", get(sync_routes::get_changes))
+        .route("/sync/blob/{hash}", get(sync_routes::get_blob));
+
+    // mirrors only serve thumbnails, everything else lives on the upstream
+    match config.mirror {
+        true => public,
+        false => public
+            .route("/flags", get(flags::get_flags))
+            .route("/announcements", get(announcements::get_announcements))
+            .route("/takedown", post(thumbnail::submit_takedown))
+            .route("/tos", get(user::get_tos))
+            .route("/search", get(thumbnail::search_handler))
+            // /collections
+            .route("/collections", post(collections::create_collection))
+            .route("/collections/{id}", get(collections::get_collection))
+            .route("/collections/{id}", put(collections::update_collection))
+            .route("/collections/{id}", delete(collections::delete_collection))
+            .route(
+                "/thumbnail/{id}/history/{upload_id}/image",
+                get(thumbnail::archived_image_handler),
+            )
+            .route("/ws", get(ws::ws_handler))
+            .route("/graphql", get(graphql_routes::graphiql))
+            .route("/graphql", post(graphql_routes::graphql_handler))
+            // /auth
+            .route("/auth/login", post(login::login))
+            .route("/auth/2fa", post(login::two_factor_login))
+            .route("/auth/confirm", post(login::confirm_two_factor))
+            .route("/auth/discord", get(login::discord_oauth_handler))
+            .route("/auth/discord/login", get(login::discord_oauth_start))
+            .route("/auth/session", get(login::get_session))
+            .route("/auth/logout", post(login::logout))
+            .route("/auth/csrf", get(login::get_csrf_token))
+            .route("/auth/link", get(login::get_link_token))
+            .route("/auth/link", post(login::link_account))
+            // /user
+            .route("/user/me", get(user::get_me))
+            .route("/user/me/accept-tos", post(user::accept_tos))
+            .route("/user/me/impersonations", get(user::get_my_impersonations))
+            .route("/user/me/collections", get(collections::get_my_collections))
+            .route("/user/me/2fa", get(user::get_two_factor).delete(user::disable_two_factor))
+            .route("/user/me/2fa/enroll", post(user::enroll_two_factor))
+            .route("/user/me/2fa/verify", post(user::verify_two_factor))
+            .route("/user/me/2fa/recovery-codes", post(user::regenerate_recovery_codes))
+            .route("/user/me/email", get(user::get_my_email).put(user::set_my_email))
+            .route("/user/email/verify", get(user::verify_email))
+            .route("/user/me/card-theme", get(user::get_my_card_theme).put(user::set_my_card_theme))
+            .route("/user/me/apply-verified", post(user::apply_verified))
+            .route("/user/search", get(user::search_users))
+            .route("/user/by-account/{account_id}", get(user::get_user_by_account))
+            .route("/user/{id}", get(user::get_user_by_id))
+            .route("/user/{id}/avatar", get(user::get_user_avatar))
+            // .route("/user/me/uploads", get(routes::user::get_my_uploads))
+            // .route("/user/{id}/uploads", get(routes::user::get_user_uploads))
+            // /upload
+            .route("/upload/{id}", post(upload::upload))
+            .route("/upload/{id}/status", get(upload::get_upload_status))
+            .route("/upload/{id}/presign", post(upload::presign_upload))
+            .route("/upload/{id}/complete", post(upload::complete_upload))
+            .route("/uploads/decided", get(upload::get_decided_uploads))
+            // /pending
+            .route("/pending/stream", get(upload::pending_stream))
+            .route("/pending/assigned", get(upload::get_assigned_pending_uploads))
+            .route("/pending/presence", post(upload::pending_presence))
+            .route("/pending/{id}/image", get(upload::get_pending_image))
+            .route("/pending", get(upload::get_all_pending_uploads))
+            .route("/pending/{id}", get(upload::get_pending_info))
+            .route("/pending/{id}", post(upload::pending_action))
+            .route("/pending/{id}/undo", post(upload::undo_decision))
+            .route("/pending/level/{id}", get(upload::get_pending_uploads_for_level))
+            .route("/pending/user/{id}", get(upload::get_pending_uploads_for_user))
+            // /gdps/{ns}
+            .route("/gdps/{ns}/upload/{id}", post(upload::namespaced_upload))
+            .route("/gdps/{ns}/pending", get(upload::get_namespace_pending_uploads))
+            .route(
+                "/gdps/{ns}/pending/level/{id}",
+                get(upload::get_namespace_pending_uploads_for_level),
+            )
+            .route(
+                "/gdps/{ns}/pending/assigned",
+                get(upload::get_namespace_assigned_pending_uploads),
+            )
+            .route("/gdps/{ns}/pending/presence", post(upload::namespaced_pending_presence))
+            // /integrations
+            .route("/integrations/discord/interactions", post(discord::interactions)),
+    }
+}
+
+fn internal_routes(config: &Config) -> Router<database::Database> {
+    // ops and moderation endpoints, optionally served on their own internal address
+    let internal =
+        Router::new().route("/healthz", get(ops::healthz)).route("/metrics", get(ops::metrics));
+    match config.mirror {
+        true => internal,
+        false => internal
+            // /admin
+            // .route("/admin/users", get(routes::admin::get_users))
+            .route("/admin/user/{id}", get(admin::get_user_by_id))
+            .route("/admin/user/{id}/ips", get(admin::get_user_ips))
+            .route("/admin/user/{id}/alts", get(admin::get_alt_accounts))
+            .route("/admin/impersonate/{user_id}", post(admin::impersonate))
+            .route("/admin/ip-bans", get(admin::get_ip_bans))
+            .route("/admin/ip-bans", post(admin::create_ip_ban))
+            .route("/admin/ip-bans/{id}", delete(admin::delete_ip_ban))
+            // .route("/admin/user/:id", patch(routes::admin::update_user))
+            // .route("/admin/ban/:id", post(routes::admin::ban_user))
+            // .route("/admin/thumbnail/:id", delete(routes::admin::delete_thumbnail))
+            .route("/admin/export", get(admin::export))
+            .route("/admin/backup/status", get(admin::get_backup_status))
+            .route("/admin/storage/status", get(admin::get_storage_status))
+            .route("/admin/storage/migrate", post(admin::start_storage_migration))
+            .route("/admin/webhooks", get(admin::get_webhooks))
+            .route("/admin/webhooks", post(admin::create_webhook))
+            .route("/admin/webhooks/{id}", delete(admin::delete_webhook))
+            .route("/admin/flags", get(admin::get_feature_flags))
+            .route("/admin/flags/{flag}", put(admin::set_feature_flag))
+            .route("/admin/quarantine", get(admin::get_quarantine))
+            .route("/admin/quarantine/{id}", delete(admin::delete_quarantine))
+            .route("/admin/quarantine/{id}/image", get(admin::get_quarantine_image))
+            .route("/admin/quarantine/{id}/release", post(admin::release_quarantine))
+            .route("/admin/upload-rules", get(admin::get_upload_rules))
+            .route("/admin/upload-rules", post(admin::create_upload_rule))
+            .route("/admin/upload-rules/{id}", put(admin::update_upload_rule))
+            .route("/admin/upload-rules/{id}", delete(admin::delete_upload_rule))
+            .route("/admin/announcements", get(admin::get_announcements))
+            .route("/admin/announcements", post(admin::create_announcement))
+            .route("/admin/announcements/{id}", put(admin::update_announcement))
+            .route("/admin/announcements/{id}", delete(admin::delete_announcement))
+            .route("/admin/freezes", get(admin::get_upload_freezes))
+            .route("/admin/freezes", post(admin::create_upload_freeze))
+            .route("/admin/freezes/{id}", put(admin::update_upload_freeze))
+            .route("/admin/freezes/{id}", delete(admin::delete_upload_freeze))
+            .route("/admin/experiments", get(admin::get_experiments))
+            .route("/admin/experiments", post(admin::start_experiment))
+            .route("/admin/experiments/{level_id}", delete(admin::end_experiment))
+            .route("/admin/applications", get(admin::get_applications))
+            .route("/admin/applications/{id}/resolve", post(admin::resolve_application))
+            .route("/admin/takedowns", get(admin::get_takedowns))
+            .route("/admin/takedowns/{id}/resolve", post(admin::resolve_takedown))
+            .route("/admin/tos", get(admin::get_tos_versions))
+            .route("/admin/tos", post(admin::publish_tos))
+            .route("/admin/settings", get(admin::get_settings))
+            .route("/admin/settings", patch(admin::update_settings))
+            .route("/admin/reload", post(admin::reload_config))
+            .route("/admin/permissions", get(admin::get_role_permissions))
+            .route("/admin/permissions/{role}", put(admin::set_role_permissions))
+            .route("/admin/namespaces", get(admin::get_namespaces))
+            .route("/admin/namespaces", post(admin::create_namespace))
+            .route("/admin/namespaces/{ns}/roles", get(admin::get_namespace_roles))
+            .route("/admin/namespaces/{ns}/roles/{user_id}", put(admin::set_namespace_role))
+            .route("/admin/cache/purge/{id}", post(admin::purge_cache))
+            .route("/admin/cache/purge-all", post(admin::purge_all_cache))
+            .route("/admin/cache/stats", get(admin::get_cache_stats))
+            .route("/admin/outbound", get(admin::get_outbound_stats)),
+    }
+}
+
+// Shared by both listeners, the first layer is the innermost
+fn with_middleware(router: Router, db: database::Database) -> Router {
+    router
+        .layer(middleware::from_fn(csrf::protect))
+        .layer(middleware::from_fn(usage_stats::track))
+        .layer(middleware::from_fn_with_state(db.clone(), impersonation::track))
+        .layer(middleware::from_fn_with_state(db, ip_bans::enforce))
+        .layer(middleware::from_fn(client_ip::resolve))
+        .layer(middleware::from_fn(access_log::log))
+}
+
+// The public routes, plus the internal ones unless they get their own listener. Background
+// jobs are not started here, so tests get a router without anything running behind it
+pub fn app(config: &Config, db: database::Database) -> Router {
+    config.install();
+    let cors = cors::CorsLayer::new()
+        .allow_origin(reload::cors_origin())
+        .allow_methods(cors::Any)
+        .allow_headers(cors::Any);
+
+    let routes = match config.separate_internal {
+        true => public_routes(config),
+        false => public_routes(config).merge(internal_routes(config)),
+    };
+    let index = format!("{}/index.html", config.dist_dir);
+    with_middleware(routes.with_state(db.clone()).layer(cors), db)
+        .fallback_service(ServeDir::new(&config.dist_dir).fallback(ServeFile::new(index)))
+}
+
+pub fn internal(config: &Config, db: database::Database) -> Router {
+    config.install();
+    with_middleware(internal_routes(config).with_state(db.clone()), db)
+}
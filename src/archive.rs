@@ -0,0 +1,35 @@
+use crate::models::LevelId;
+use crate::{database, paths, storage};
+use tracing::{error, info};
+
+// Copies the live thumbnail of a level aside before something replaces it, so old art survives
+pub async fn supersede(db: &database::Database, namespace: &str, level_id: LevelId) {
+    // files from before uploads were tracked have no row to point at, those are just replaced
+    match db.get_live_upload_id(namespace, level_id).await {
+        Ok(Some(upload_id)) => keep(db, namespace, level_id, upload_id).await,
+        Ok(None) => {}
+        Err(e) => error!("Failed to look up live upload for level {}: {}", level_id, e),
+    }
+}
+
+// Same as supersede for callers that looked up the live upload before changing the rows
+pub async fn keep(db: &database::Database, namespace: &str, level_id: LevelId, upload_id: i64) {
+    let live_path = paths::thumbnail_path(namespace, level_id);
+    if !storage::ensure_local(&live_path).await {
+        return;
+    }
+
+    let archive_path = paths::archive_path(namespace, level_id, upload_id);
+    let copy = async {
+        tokio::fs::create_dir_all(paths::local(paths::archive_dir(namespace, level_id))).await?;
+        tokio::fs::copy(paths::local(&live_path), paths::local(&archive_path)).await
+    };
+    if let Err(e) = copy.await {
+        return error!("Failed to archive thumbnail for level {}: {}", level_id, e);
+    }
+
+    match db.set_archive_path(upload_id, &archive_path).await {
+        Ok(_) => info!("Archived upload {} for level {} in {}", upload_id, level_id, namespace),
+        Err(e) => error!("Failed to record archive of upload {}: {}", upload_id, e),
+    }
+}
@@ -0,0 +1,119 @@
+use crate::models::UserId;
+use crate::permissions::{self, Permission};
+use crate::{database, jobs, namespace, settings};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::{error, info};
+
+// moderators count as active for this long after their last presence ping
+const PRESENCE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// Presence lives in the database, the replica running the reassigner has to see moderators
+// whose pings went to the other replicas
+pub async fn ping(db: &database::Database, user_id: UserId) {
+    if let Err(e) = db.record_presence(user_id).await {
+        error!("Failed to record presence of {}: {}", user_id, e);
+    }
+}
+
+async fn active_moderators(db: &database::Database) -> Vec<UserId> {
+    match db.get_present_moderators(PRESENCE_TIMEOUT).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Failed to load active moderators: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+// Active moderators that can review uploads in the namespace
+async fn candidates(db: &database::Database, namespace: &str) -> Vec<UserId> {
+    let mut ids = Vec::new();
+    for id in active_moderators(db).await {
+        if let Some(user) = db.get_user_by_id(id).await
+            && permissions::has(
+                namespace::role(db, &user, namespace).await,
+                Permission::ReviewUploads,
+            )
+        {
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+// Next moderator in turn, skipping the current assignee when someone else is around
+async fn next_moderator(
+    db: &database::Database,
+    namespace: &str,
+    current: Option<UserId>,
+) -> Option<UserId> {
+    let mut ids = candidates(db, namespace).await;
+    if ids.len() > 1 {
+        ids.retain(|id| Some(*id) != current);
+    }
+    if ids.is_empty() {
+        return None;
+    }
+    Some(ids[NEXT.fetch_add(1, Ordering::Relaxed) % ids.len()])
+}
+
+// Called when an upload enters the queue, it stays unassigned while nobody is online
+pub async fn assign(db: &database::Database, namespace: &str, upload_id: i64) {
+    if !settings::current().queue_assignment {
+        return;
+    }
+
+    if let Some(moderator_id) = next_moderator(db, namespace, None).await
+        && let Err(e) = db.assign_upload(upload_id, moderator_id).await
+    {
+        error!("Failed to assign upload {} to {}: {}", upload_id, moderator_id, e);
+    }
+}
+
+// Unassigned uploads, uploads of moderators that went offline and uploads that sat
+// with the same moderator for too long are handed to someone else
+async fn reassign_stale(db: &database::Database) {
+    let timeout = chrono::Duration::minutes(settings::current().assignment_timeout as i64);
+    let active = active_moderators(db).await;
+    let now = db.now();
+
+    let assignments = match db.get_pending_assignments().await {
+        Ok(assignments) => assignments,
+        Err(e) => return error!("Failed to load queue assignments: {}", e),
+    };
+
+    for upload in assignments {
+        let stale = match (upload.assigned_to, upload.assigned_at) {
+            (Some(moderator_id), Some(assigned_at)) => {
+                !active.contains(&moderator_id) || now - assigned_at > timeout
+            }
+            _ => true,
+        };
+        if !stale {
+            continue;
+        }
+
+        let Some(moderator_id) = next_moderator(db, &upload.namespace, upload.assigned_to).await
+        else {
+            continue;
+        };
+        // with nobody else online the same moderator keeps it and the timer starts over
+        match db.assign_upload(upload.id, moderator_id).await {
+            Ok(_) => info!("Upload {} reassigned to {}", upload.id, moderator_id),
+            Err(e) => error!("Failed to reassign upload {}: {}", upload.id, e),
+        }
+    }
+}
+
+pub async fn run_reassigner(db: database::Database) {
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        if settings::current().queue_assignment {
+            jobs::run_exclusive(&db, "reassign", CHECK_INTERVAL, reassign_stale(&db)).await;
+        }
+    }
+}
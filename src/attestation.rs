@@ -0,0 +1,147 @@
+use axum::http::HeaderMap;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+// The Geode mod signs its uploads with a key built into it. Anyone can pull the key out of
+// the mod, so a signature doesn't prove much on its own, it only tells in-game submissions
+// apart from casual scripts so operators can weigh them differently. A bad signature makes
+// the upload count as unsigned, it is never refused for it
+
+pub const TIMESTAMP_HEADER: &str = "X-Attestation-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Attestation-Signature";
+
+// how far the mod's clock may be off from ours, either way
+pub const MAX_SKEW: i64 = 5 * 60;
+
+// MOD_ATTESTATION_KEY is the key shipped with the mod, attestation is off without it
+static KEY: LazyLock<Option<String>> =
+    LazyLock::new(|| dotenv::var("MOD_ATTESTATION_KEY").ok().filter(|key| !key.is_empty()));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Attested,
+    Unsigned, // no attestation headers
+    Invalid,  // malformed or wrong signature
+    Stale,    // timestamp outside MAX_SKEW
+    Replayed, // the same signature was already used
+}
+
+// The signature header is the hex HMAC-SHA256 of "{timestamp}:{sha256 of the body in hex}"
+fn mac(key: &str, timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}:{}", timestamp, hex::encode(Sha256::digest(body))).as_bytes());
+    mac
+}
+
+// Checks the headers against the body, the signature and its timestamp when they match.
+// Hashing the body is the slow part, so this runs before the replay set is locked
+pub fn signed(
+    key: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+    now: i64,
+) -> Result<(String, i64), Verdict> {
+    let (timestamp, signature) = match (timestamp, signature) {
+        (None, None) => return Err(Verdict::Unsigned),
+        (Some(timestamp), Some(signature)) => (timestamp, signature),
+        _ => return Err(Verdict::Invalid),
+    };
+    let Ok(timestamp) = timestamp.trim().parse::<i64>() else {
+        return Err(Verdict::Invalid);
+    };
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return Err(Verdict::Invalid);
+    };
+    if mac(key, timestamp, body).verify_slice(&signature).is_err() {
+        return Err(Verdict::Invalid);
+    }
+    if timestamp.abs_diff(now) > MAX_SKEW as u64 {
+        return Err(Verdict::Stale);
+    }
+    Ok((hex::encode(signature), timestamp))
+}
+
+// Signatures seen within the last 2 * MAX_SKEW, anything older fails the timestamp check anyway.
+// Each process keeps its own, so behind several replicas a signature can be used once on each.
+// That's accepted since an attested upload is only weighed differently, never let through
+#[derive(Debug, Default)]
+pub struct Verifier {
+    seen: HashMap<String, i64>,
+}
+
+impl Verifier {
+    pub fn remember(&mut self, signature: String, timestamp: i64, now: i64) -> Verdict {
+        self.seen.retain(|_, at| now - *at <= 2 * MAX_SKEW);
+        match self.seen.insert(signature, timestamp) {
+            Some(_) => Verdict::Replayed,
+            None => Verdict::Attested,
+        }
+    }
+}
+
+static VERIFIER: LazyLock<Mutex<Verifier>> = LazyLock::new(|| Mutex::new(Verifier::default()));
+
+static ATTESTED: AtomicU64 = AtomicU64::new(0);
+static UNSIGNED: AtomicU64 = AtomicU64::new(0);
+static INVALID: AtomicU64 = AtomicU64::new(0);
+static STALE: AtomicU64 = AtomicU64::new(0);
+static REPLAYED: AtomicU64 = AtomicU64::new(0);
+
+pub fn is_enabled() -> bool {
+    KEY.is_some()
+}
+
+// Whether the upload body was signed by the mod, always false while attestation is off
+pub fn check(headers: &HeaderMap, body: &[u8]) -> bool {
+    let Some(key) = KEY.as_deref() else {
+        return false;
+    };
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let now = chrono::Utc::now().timestamp();
+    let verdict = match signed(key, header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER), body, now) {
+        Ok((signature, timestamp)) => VERIFIER.lock().unwrap().remember(signature, timestamp, now),
+        Err(verdict) => verdict,
+    };
+
+    let counter = match verdict {
+        Verdict::Attested => &ATTESTED,
+        Verdict::Unsigned => &UNSIGNED,
+        Verdict::Invalid => &INVALID,
+        Verdict::Stale => &STALE,
+        Verdict::Replayed => &REPLAYED,
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    if !matches!(verdict, Verdict::Attested | Verdict::Unsigned) {
+        tracing::warn!("Upload attestation failed: {:?}", verdict);
+    }
+    verdict == Verdict::Attested
+}
+
+pub fn write_metrics(body: &mut String) {
+    if !is_enabled() {
+        return;
+    }
+
+    let _ = writeln!(body, "# HELP upload_attestations_total Uploads by attestation result");
+    let _ = writeln!(body, "# TYPE upload_attestations_total counter");
+    for (result, counter) in [
+        ("attested", &ATTESTED),
+        ("unsigned", &UNSIGNED),
+        ("invalid", &INVALID),
+        ("stale", &STALE),
+        ("replayed", &REPLAYED),
+    ] {
+        let _ = writeln!(
+            body,
+            "upload_attestations_total{{result=\"{}\"}} {}",
+            result,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+}
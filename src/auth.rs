@@ -1,20 +1,73 @@
+use crate::database::{self, Role};
+use crate::models::{AccountId, UserId};
+use crate::{outbound, util};
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::Response;
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fmt::Display;
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+
+// Set from app::Config when the router is built, commands that never build one read JWT_SECRET
+static SECRET: OnceLock<String> = OnceLock::new();
+
+pub fn set_secret(secret: String) {
+    let _ = SECRET.set(secret);
+}
+
+// Signs sessions and everything else the server has to recognize as its own later
+pub fn secret() -> String {
+    match SECRET.get() {
+        Some(secret) => secret.clone(),
+        None => dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set"),
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserSession {
-    pub id: i64,
+    pub id: UserId,
     pub username: String,
+    #[serde(default)]
+    pub mfa: bool, // issued after a second factor was checked
+    #[serde(default)]
+    pub auth_time: u64, // when the user last proved who they are, 0 for older tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonation: Option<i64>, // impersonation session, when an admin acts as this user
+    #[serde(default)]
+    pub dashboard: bool, // issued to the dashboard by the Discord login, not to the game or a script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>, // regular sessions don't expire
 }
 
 impl UserSession {
-    pub fn new(id: i64, username: String) -> Self {
-        Self { id, username }
+    pub fn new(id: UserId, username: String) -> Self {
+        Self {
+            id,
+            username,
+            mfa: false,
+            auth_time: chrono::Utc::now().timestamp() as u64,
+            impersonation: None,
+            dashboard: false,
+            exp: None,
+        }
+    }
+
+    pub fn with_mfa(mut self) -> Self {
+        self.mfa = true;
+        self
+    }
+
+    // Sessions renewed from another one keep where the first was issued to
+    pub fn for_dashboard(mut self, dashboard: bool) -> Self {
+        self.dashboard = dashboard;
+        self
     }
 
     pub fn to_jwt(&self) -> String {
-        let jwt_secret = dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        let jwt_secret = secret();
         jsonwebtoken::encode(
             &jsonwebtoken::Header::default(),
             &self,
@@ -23,15 +76,13 @@ impl UserSession {
         .expect("Failed to encode JWT")
     }
 
+    // Takes the bare token, util::session_token deals with the header format
     pub fn from_jwt(token: &str) -> Result<Self, jsonwebtoken::errors::Error> {
-        // strip the "Bearer " prefix if it exists
-        let token = if token.starts_with("Bearer ") { &token[7..] } else { token };
-
+        // exp is only checked when present
         let mut validation = jsonwebtoken::Validation::default();
-        validation.validate_exp = false;
         validation.required_spec_claims = HashSet::new();
 
-        let jwt_secret = dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        let jwt_secret = secret();
         jsonwebtoken::decode::<UserSession>(
             token,
             &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_bytes()),
@@ -41,12 +92,61 @@ impl UserSession {
     }
 }
 
+// The user behind the session token, every authenticated route goes through this
+pub struct AuthedUser(pub database::User);
+
+impl<S> FromRequestParts<S> for AuthedUser
+where
+    S: Send + Sync,
+    database::Database: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let db = database::Database::from_ref(state);
+        util::auth_middleware(&parts.headers, &db).await.map(AuthedUser)
+    }
+}
+
+// Marker types for RequireRole, each stands for the lowest role that gets through.
+// Checks that a role could be granted piecemeal use RequirePermission instead.
+pub trait MinRole {
+    const ROLE: Role;
+    const MESSAGE: &'static str;
+}
+
+pub struct Admin;
+
+impl MinRole for Admin {
+    const ROLE: Role = Role::Admin;
+    const MESSAGE: &'static str = "Only admins can perform this action";
+}
+
+// Authenticated user with at least the given global role, namespace roles are not considered
+pub struct RequireRole<R: MinRole>(pub database::User, pub PhantomData<R>);
+
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    R: MinRole,
+    database::Database: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AuthedUser(user) = AuthedUser::from_request_parts(parts, state).await?;
+        match user.role >= R::ROLE {
+            true => Ok(RequireRole(user, PhantomData)),
+            false => Err(util::str_response(StatusCode::FORBIDDEN, R::MESSAGE)),
+        }
+    }
+}
+
 // ArgonClient implementation taken from Globed:
 // https://github.com/GlobedGD/globed2/blob/main/server/central/src/argon_client.rs
 
 pub struct ArgonClient {
     base_url: String,
-    client: reqwest::Client,
 }
 
 pub enum Verdict {
@@ -59,6 +159,7 @@ pub enum ArgonClientError {
     RequestFailed(reqwest::Error),
     InvalidJson(serde_json::Error),
     ArgonError(String),
+    Unavailable(String),
 }
 
 impl From<reqwest::Error> for ArgonClientError {
@@ -67,6 +168,15 @@ impl From<reqwest::Error> for ArgonClientError {
     }
 }
 
+impl From<outbound::Error> for ArgonClientError {
+    fn from(value: outbound::Error) -> Self {
+        match value {
+            outbound::Error::Request(e) => Self::RequestFailed(e),
+            e => Self::Unavailable(e.to_string()),
+        }
+    }
+}
+
 impl From<serde_json::Error> for ArgonClientError {
     fn from(value: serde_json::Error) -> Self {
         Self::InvalidJson(value)
@@ -79,6 +189,7 @@ impl Display for ArgonClientError {
             Self::RequestFailed(err) => write!(f, "request failed: {err}"),
             Self::InvalidJson(msg) => write!(f, "invalid server response: {msg}"),
             Self::ArgonError(msg) => write!(f, "error from the server: {msg}"),
+            Self::Unavailable(msg) => write!(f, "server unavailable: {msg}"),
         }
     }
 }
@@ -111,34 +222,24 @@ impl ArgonClient {
     pub fn new() -> Self {
         let base_url = dotenv::var("ARGON_BASE_URL")
             .unwrap_or_else(|_| "https://argon.globed.dev/v1".to_string());
-        let client = reqwest::ClientBuilder::new()
-            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
-            .expect("Failed to create HTTP client");
-
-        Self { base_url, client }
+        Self { base_url }
     }
 
     pub async fn verify(
         &self,
-        account_id: i64,
+        account_id: AccountId,
         user_id: i64,
         username: &str,
         token: &str,
     ) -> Result<Verdict, ArgonClientError> {
         let url = format!("{}/validation/check_strong", self.base_url);
-        let response = self
-            .client
-            .get(&url)
-            .query(&[
-                ("account_id", account_id.to_string().as_str()),
-                ("user_id", user_id.to_string().as_str()),
-                ("username", username),
-                ("authtoken", token),
-            ])
-            .send()
-            .await?;
+        let request = outbound::ARGON.get(&url).query(&[
+            ("account_id", account_id.to_string().as_str()),
+            ("user_id", user_id.to_string().as_str()),
+            ("username", username),
+            ("authtoken", token),
+        ]);
+        let response = outbound::ARGON.send(request).await?;
 
         if !response.status().is_success() {
             let resp = response.text().await?;
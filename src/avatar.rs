@@ -0,0 +1,104 @@
+use crate::database;
+use crate::models::UserId;
+use crate::paths;
+use image::imageops::FilterType;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use webp::Encoder;
+
+const AVATAR_SIZE: u32 = 128;
+
+// icons and Discord avatars change rarely, cached files are refreshed after a day
+pub const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+// AVATAR_RENDER_URL renders a GD player's icon, with {account_id} or {username} in the URL
+static RENDER_URL: LazyLock<Option<String>> =
+    LazyLock::new(|| dotenv::var("AVATAR_RENDER_URL").ok());
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::ClientBuilder::new()
+        .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to create HTTP client")
+});
+
+static FAILED: LazyLock<Mutex<HashMap<UserId, Instant>>> = LazyLock::new(Default::default);
+
+fn recently_failed(user_id: UserId) -> bool {
+    let Ok(mut failed) = FAILED.lock() else {
+        return false;
+    };
+    failed.retain(|_, at| at.elapsed() < RETRY_AFTER);
+    failed.contains_key(&user_id)
+}
+
+// The GD icon is preferred, Discord-only users get their Discord avatar
+fn source_url(user: &database::User, discord_avatar: Option<String>) -> Option<String> {
+    if user.account_id.0 > 0
+        && let Some(url) = RENDER_URL.as_ref()
+    {
+        return Some(
+            url.replace("{account_id}", &user.account_id.to_string())
+                .replace("{username}", &user.username),
+        );
+    }
+
+    let (discord_id, avatar) = (user.discord_id?, discord_avatar?);
+    Some(format!("https://cdn.discordapp.com/avatars/{}/{}.png?size=256", discord_id, avatar))
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, String> {
+    let response = CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let data = response.bytes().await.map_err(|e| e.to_string())?;
+
+    crate::encoder::run(move || -> Result<Vec<u8>, String> {
+        let image = image::load_from_memory(&data).map_err(|e| format!("Invalid avatar: {}", e))?;
+        let image = image.resize(AVATAR_SIZE, AVATAR_SIZE, FilterType::Lanczos3).to_rgba8();
+        Ok(Encoder::from_rgba(&image, image.width(), image.height()).encode(90.0).to_vec())
+    })
+    .await?
+}
+
+async fn is_fresh(path: &PathBuf) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .ok()
+        .and_then(|metadata| metadata.modified().ok())
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < MAX_AGE)
+}
+
+// Path of the cached avatar, fetched again once stale; a stale file is kept when that fails
+pub async fn get(db: &database::Database, user: &database::User) -> Option<PathBuf> {
+    let path = paths::local(paths::avatar_path(user.id));
+    if is_fresh(&path).await || recently_failed(user.id) {
+        return path.exists().then_some(path);
+    }
+
+    let url = source_url(user, db.get_discord_avatar(user.id).await)?;
+    let write = async {
+        let data = fetch(&url).await?;
+        tokio::fs::create_dir_all(paths::local(paths::AVATAR_DIR))
+            .await
+            .map_err(|e| e.to_string())?;
+        tokio::fs::write(&path, data).await.map_err(|e| e.to_string())
+    };
+
+    if let Err(e) = write.await {
+        warn!("Failed to fetch avatar of user {}: {}", user.id, e);
+        if let Ok(mut failed) = FAILED.lock() {
+            failed.insert(user.id, Instant::now());
+        }
+    }
+    path.exists().then_some(path)
+}
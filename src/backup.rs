@@ -0,0 +1,492 @@
+use crate::models::LevelId;
+use crate::{archive, cache_controller, database, jobs, namespace, paths, storage, sync};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use rusty_s3::actions::ListObjectsV2;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+const RUN_INTERVAL: Duration = Duration::from_secs(60);
+const SNAPSHOT_INTERVAL: chrono::Duration = chrono::Duration::days(1);
+const PRESIGN_DURATION: Duration = Duration::from_secs(10 * 60);
+const BATCH_SIZE: i64 = 100;
+const SNAPSHOT_PREFIX: &str = "snapshots/";
+const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%d%H%M%S";
+
+enum Target {
+    S3 {
+        bucket: Bucket,
+        credentials: Credentials,
+        client: reqwest::Client,
+    },
+    // a local path, or a remote one mounted with something like rclone mount or sshfs
+    Dir(PathBuf),
+}
+
+// BACKUP_S3_BUCKET takes precedence over BACKUP_DIR, backups are off unless one is set
+static TARGET: LazyLock<Option<Target>> = LazyLock::new(|| {
+    if let Ok(name) = dotenv::var("BACKUP_S3_BUCKET") {
+        let endpoint = dotenv::var("BACKUP_S3_ENDPOINT")
+            .expect("BACKUP_S3_ENDPOINT must be set with BACKUP_S3_BUCKET");
+        let region = dotenv::var("BACKUP_S3_REGION").unwrap_or_else(|_| "auto".to_string());
+        let access_key =
+            dotenv::var("BACKUP_S3_ACCESS_KEY").expect("BACKUP_S3_ACCESS_KEY must be set");
+        let secret_key =
+            dotenv::var("BACKUP_S3_SECRET_KEY").expect("BACKUP_S3_SECRET_KEY must be set");
+
+        let endpoint = endpoint.parse().expect("BACKUP_S3_ENDPOINT must be a valid URL");
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, name, region)
+            .expect("Failed to configure backup bucket");
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        return Some(Target::S3 {
+            bucket,
+            credentials: Credentials::new(access_key, secret_key),
+            client,
+        });
+    }
+
+    dotenv::var("BACKUP_DIR").ok().map(|dir| Target::Dir(PathBuf::from(dir)))
+});
+
+impl Target {
+    fn kind(&self) -> &'static str {
+        match self {
+            Target::S3 { .. } => "s3",
+            Target::Dir(_) => "dir",
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), String> {
+        match self {
+            Target::S3 { bucket, credentials, client } => {
+                let url = bucket.put_object(Some(credentials), key).sign(PRESIGN_DURATION);
+                client
+                    .put(url)
+                    .body(data)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            // written next to the destination first so a half-copied file never looks complete
+            Target::Dir(dir) => {
+                let path = dir.join(key);
+                let partial = path.with_extension("partial");
+                let write = async {
+                    if let Some(parent) = path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::write(&partial, data).await?;
+                    tokio::fs::rename(&partial, &path).await
+                };
+                write.await.map_err(|e| format!("{}: {}", path.display(), e))
+            }
+        }
+    }
+
+    // None when the key isn't in the backup
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        match self {
+            Target::S3 { bucket, credentials, client } => {
+                let url = bucket.get_object(Some(credentials), key).sign(PRESIGN_DURATION);
+                let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let response = response.error_for_status().map_err(|e| e.to_string())?;
+                let data = response.bytes().await.map_err(|e| e.to_string())?;
+                Ok(Some(data.to_vec()))
+            }
+            Target::Dir(dir) => match tokio::fs::read(dir.join(key)).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.to_string()),
+            },
+        }
+    }
+
+    // Names of the objects directly under a prefix like snapshots/
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, String> {
+        match self {
+            Target::S3 { bucket, credentials, client } => {
+                let mut names = Vec::new();
+                let mut continuation = None;
+                loop {
+                    let mut action = bucket.list_objects_v2(Some(credentials));
+                    action.with_prefix(prefix);
+                    if let Some(token) = &continuation {
+                        action.with_continuation_token(token);
+                    }
+                    let url = action.sign(PRESIGN_DURATION);
+                    let body = client
+                        .get(url)
+                        .send()
+                        .await
+                        .and_then(|response| response.error_for_status())
+                        .map_err(|e| e.to_string())?
+                        .text()
+                        .await
+                        .map_err(|e| e.to_string())?;
+
+                    let listing =
+                        ListObjectsV2::parse_response(&body).map_err(|e| e.to_string())?;
+                    names.extend(
+                        listing.contents.into_iter().filter_map(|object| {
+                            object.key.strip_prefix(prefix).map(str::to_string)
+                        }),
+                    );
+                    match listing.next_continuation_token {
+                        Some(token) => continuation = Some(token),
+                        None => return Ok(names),
+                    }
+                }
+            }
+            Target::Dir(dir) => {
+                let mut names = Vec::new();
+                let mut entries = match tokio::fs::read_dir(dir.join(prefix)).await {
+                    Ok(entries) => entries,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(names),
+                    Err(e) => return Err(e.to_string()),
+                };
+                while let Ok(Some(entry)) = entries.next_entry().await {
+                    names.push(entry.file_name().to_string_lossy().to_string());
+                }
+                Ok(names)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Progress {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub last_snapshot: Option<DateTime<Utc>>,
+    pub copied_files: u64, // since this instance started
+    pub copied_bytes: u64,
+}
+
+static PROGRESS: LazyLock<Mutex<Progress>> = LazyLock::new(Default::default);
+
+pub fn target_kind() -> Option<&'static str> {
+    TARGET.as_ref().map(Target::kind)
+}
+
+pub fn progress() -> Progress {
+    PROGRESS.lock().map(|progress| progress.clone()).unwrap_or_default()
+}
+
+fn record_error(message: String) {
+    error!("Backup failed: {}", message);
+    if let Ok(mut progress) = PROGRESS.lock() {
+        progress.last_error = Some(message);
+        progress.last_error_at = Some(Utc::now());
+    }
+}
+
+// The live file while the upload is still live, its archived copy once it was replaced
+// Ok(None) only when the file is really gone. A failed read is an error so the upload is
+// tried again on the next run instead of being marked as backed up without a copy
+async fn read_upload(upload: &database::UnreplicatedUpload) -> Result<Option<Vec<u8>>, String> {
+    let path = match (upload.live, &upload.archive_path) {
+        (true, _) => paths::thumbnail_path(&upload.namespace, upload.level_id),
+        (false, Some(archive_path)) => archive_path.clone(),
+        (false, None) => return Ok(None),
+    };
+    if !storage::ensure_local(&path).await {
+        return match storage::exists(&path).await? {
+            false => Ok(None),
+            true => Err(format!("{} could not be fetched from storage", path)),
+        };
+    }
+    match tokio::fs::read(paths::local(&path)).await {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("{}: {}", path, e)),
+    }
+}
+
+// Copies accepted uploads that aren't in the backup yet, oldest first. Uploads whose file is
+// gone are marked as done too, there is nothing left to copy for them
+async fn replicate(db: &database::Database, target: &Target) -> Result<u64, String> {
+    let mut copied = 0;
+    loop {
+        let uploads = db.get_unreplicated_uploads(BATCH_SIZE).await.map_err(|e| e.to_string())?;
+
+        let mut done = Vec::with_capacity(uploads.len());
+        let mut failed = None;
+        for upload in &uploads {
+            let data = match read_upload(upload).await {
+                Ok(data) => data,
+                Err(e) => {
+                    failed = Some(format!("Failed to read upload {}: {}", upload.id, e));
+                    break;
+                }
+            };
+            if let Some(data) = data {
+                let size = data.len() as u64;
+                let key = paths::backup_key(&upload.namespace, upload.level_id, upload.id);
+                if let Err(e) = target.put(&key, data).await {
+                    failed = Some(format!("Failed to copy upload {}: {}", upload.id, e));
+                    break;
+                }
+                if let Ok(mut progress) = PROGRESS.lock() {
+                    progress.copied_files += 1;
+                    progress.copied_bytes += size;
+                }
+                copied += 1;
+            }
+            done.push(upload.id);
+        }
+
+        db.mark_backed_up(&done).await.map_err(|e| e.to_string())?;
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        if (uploads.len() as i64) < BATCH_SIZE {
+            return Ok(copied);
+        }
+    }
+}
+
+// Rows of every accepted upload, which restore rebuilds the uploads table from
+async fn snapshot(db: &database::Database, target: &Target) -> Result<(), String> {
+    let entries = db.get_backup_entries().await.map_err(|e| e.to_string())?;
+    let manifest = serde_json::to_vec(&entries).map_err(|e| e.to_string())?;
+
+    let now = Utc::now();
+    let key = format!("{}{}.json", SNAPSHOT_PREFIX, now.format(SNAPSHOT_TIME_FORMAT));
+    target.put(&key, manifest).await?;
+
+    if let Ok(mut progress) = PROGRESS.lock() {
+        progress.last_snapshot = Some(now);
+    }
+    info!("Backed up metadata of {} upload(s) to {}", entries.len(), key);
+    Ok(())
+}
+
+async fn backup(db: &database::Database, target: &Target) {
+    let now = Utc::now();
+    let snapshot_due = progress().last_snapshot.is_none_or(|at| now - at >= SNAPSHOT_INTERVAL);
+    if snapshot_due && let Err(e) = snapshot(db, target).await {
+        return record_error(format!("Failed to write metadata snapshot: {}", e));
+    }
+
+    match replicate(db, target).await {
+        Ok(copied) => {
+            if copied > 0 {
+                info!("Backed up {} thumbnail(s)", copied);
+            }
+            if let Ok(mut progress) = PROGRESS.lock() {
+                progress.last_success = Some(Utc::now());
+            }
+        }
+        Err(e) => record_error(e),
+    }
+}
+
+pub async fn run(db: database::Database) {
+    let Some(target) = TARGET.as_ref() else {
+        return;
+    };
+    info!("Backing up thumbnails to {} storage", target.kind());
+    loop {
+        jobs::run_exclusive(&db, "backup", RUN_INTERVAL, backup(&db, target)).await;
+        tokio::time::sleep(RUN_INTERVAL).await;
+    }
+}
+
+#[derive(Default)]
+pub struct RestoreSummary {
+    pub restored: usize,
+    pub skipped: usize, // levels that still have a thumbnail
+    pub missing: usize, // uploads in the snapshot without a copied image
+    pub failed: usize,
+}
+
+pub struct RestoreOptions {
+    pub until: DateTime<Utc>,
+    pub overwrite: bool, // also replace thumbnails that exist, taking down anything newer
+    pub dry_run: bool,
+}
+
+async fn load_snapshot(target: &Target, name: &str) -> Result<Vec<database::BackupEntry>, String> {
+    let key = format!("{}{}", SNAPSHOT_PREFIX, name);
+    let data = target.get(&key).await?.ok_or_else(|| format!("{} disappeared", key))?;
+    serde_json::from_slice(&data).map_err(|e| format!("Invalid snapshot {}: {}", key, e))
+}
+
+// Snapshots are only taken daily, so the last one before the restore point misses what was
+// accepted after it and the first one after misses what was taken down since. Both are merged
+async fn snapshot_entries(
+    target: &Target,
+    until: DateTime<Utc>,
+) -> Result<Vec<database::BackupEntry>, String> {
+    let mut snapshots: Vec<(DateTime<Utc>, String)> = target
+        .list(SNAPSHOT_PREFIX)
+        .await?
+        .into_iter()
+        .filter_map(|name| {
+            let stem = name.strip_suffix(".json")?;
+            let taken = NaiveDateTime::parse_from_str(stem, SNAPSHOT_TIME_FORMAT).ok()?.and_utc();
+            Some((taken, name))
+        })
+        .collect();
+    snapshots.sort();
+
+    let before = snapshots.iter().rfind(|(taken, _)| *taken <= until);
+    let after = snapshots.iter().find(|(taken, _)| *taken > until);
+    if before.is_none() && after.is_none() {
+        return Err("The backup has no snapshots".to_string());
+    }
+
+    let mut entries = HashMap::new();
+    for (_, name) in before.into_iter().chain(after) {
+        for entry in load_snapshot(target, name).await? {
+            entries.insert(entry.id, entry);
+        }
+    }
+    Ok(entries.into_values().collect())
+}
+
+// False when the image of the upload never made it into the backup
+async fn restore_level(
+    db: &database::Database,
+    target: &Target,
+    entry: &database::BackupEntry,
+    options: &RestoreOptions,
+) -> Result<bool, String> {
+    let key = paths::backup_key(&entry.namespace, entry.level_id, entry.id);
+    let Some(data) = target.get(&key).await? else {
+        return Ok(false);
+    };
+    let live_path = paths::thumbnail_path(&entry.namespace, entry.level_id);
+
+    let user = db
+        .find_or_create_user(entry.account_id, &entry.username)
+        .await
+        .map_err(|e| format!("Failed to resolve user {}: {}", entry.account_id, e))?;
+
+    // the level is written like any other upload of it, one at a time
+    let _lock = db
+        .try_lock_level(&entry.namespace, entry.level_id)
+        .await
+        .map_err(|e| format!("Failed to lock level: {}", e))?
+        .ok_or("Another upload for the level is in progress")?;
+    if options.overwrite {
+        archive::supersede(db, &entry.namespace, entry.level_id).await;
+    }
+
+    // the image is staged first and only moved over the live one once the rows are in place
+    let staged = paths::partial_path(paths::local(&live_path));
+    let write = async {
+        tokio::fs::create_dir_all(paths::local(paths::thumbnail_dir(&entry.namespace))).await?;
+        tokio::fs::write(&staged, &data).await
+    };
+    write.await.map_err(|e| format!("Failed to write {}: {}", staged.display(), e))?;
+
+    let take_down_after = options.overwrite.then_some(options.until);
+    let restored = db.restore_upload(entry, user.id, &live_path, take_down_after).await;
+    match restored {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(format!("Upload {} belongs to another level here", entry.id));
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(format!("Failed to restore row: {}", e));
+        }
+    }
+
+    if let Err(e) = tokio::fs::rename(&staged, paths::local(&live_path)).await {
+        // newer uploads are taken down already, whatever file is left mustn't stay live
+        let _ = tokio::fs::remove_file(&staged).await;
+        let _ = tokio::fs::remove_file(paths::local(&live_path)).await;
+        if entry.namespace == namespace::DEFAULT {
+            sync::record_removed(db, entry.level_id).await;
+        }
+        cache_controller::replaced(db, &entry.namespace, entry.level_id).await;
+        return Err(format!("Failed to write {}: {}", live_path, e));
+    }
+
+    if entry.namespace == namespace::DEFAULT {
+        sync::record_accepted(db, entry.level_id, user.id, &data).await;
+    }
+    cache_controller::replaced(db, &entry.namespace, entry.level_id).await;
+    Ok(true)
+}
+
+// Puts back the thumbnail every level had at the given time, from the backup images and the
+// snapshots of the uploads table. Levels that have a thumbnail are left alone unless overwriting
+pub async fn restore(
+    db: &database::Database,
+    options: &RestoreOptions,
+) -> Result<RestoreSummary, String> {
+    let target = TARGET.as_ref().ok_or("No backup storage is configured")?;
+    let entries = snapshot_entries(target, options.until).await?;
+
+    // the live upload of a level is its newest accepted one, as in get_live_upload_id
+    let mut live: HashMap<(String, LevelId), database::BackupEntry> = HashMap::new();
+    for entry in entries {
+        if entry.accepted_time.is_none_or(|at| at > options.until) {
+            continue;
+        }
+        let level = (entry.namespace.clone(), entry.level_id);
+        match live.get(&level) {
+            Some(current) if current.upload_time >= entry.upload_time => {}
+            _ => {
+                live.insert(level, entry);
+            }
+        }
+    }
+
+    let mut summary = RestoreSummary::default();
+    for entry in live.values() {
+        let live_path = paths::thumbnail_path(&entry.namespace, entry.level_id);
+        // a failed lookup counts as existing, so it's never overwritten by accident
+        if !options.overwrite && storage::exists(&live_path).await.unwrap_or(true) {
+            summary.skipped += 1;
+            continue;
+        }
+        if options.dry_run {
+            println!(
+                "Would restore upload {} for level {} in {}",
+                entry.id, entry.level_id, entry.namespace
+            );
+            summary.restored += 1;
+            continue;
+        }
+
+        match restore_level(db, target, entry, options).await {
+            Ok(true) => summary.restored += 1,
+            Ok(false) => {
+                eprintln!("Upload {} for level {} is not in the backup", entry.id, entry.level_id);
+                summary.missing += 1;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to restore level {} in {}: {}",
+                    entry.level_id, entry.namespace, e
+                );
+                summary.failed += 1;
+            }
+        }
+    }
+
+    if !options.dry_run {
+        db.sync_upload_id_sequence().await.map_err(|e| e.to_string())?;
+    }
+    Ok(summary)
+}
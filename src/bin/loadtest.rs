@@ -0,0 +1,220 @@
+// Sends thumbnail requests to a running server and reports throughput and latency. The mix
+// either comes from a sampled ACCESS_LOG, or is made up from a list of level IDs where a few
+// levels get most of the traffic, the way it goes on the real instance.
+//
+//     cargo run --release --features bench --bin loadtest -- http://127.0.0.1:3000 --requests 20000
+
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// the routes the access log samples, see access_log.rs
+const THUMBNAIL_ROUTES: &[&str] = &["/thumbnail/{id}", "/thumbnail/{id}/{res}"];
+
+// share of requests per resolution when there's no access log to take it from
+const RESOLUTION_MIX: [(&str, u32); 3] = [("high", 50), ("medium", 30), ("small", 20)];
+
+#[derive(Parser)]
+#[command(about = "Replay a thumbnail request distribution against a running server")]
+struct Args {
+    /// Base URL of the server
+    #[arg(default_value = "http://127.0.0.1:3000")]
+    target: String,
+    /// JSON access log to take the request paths from
+    #[arg(long)]
+    access_log: Option<PathBuf>,
+    /// File with one level ID per line, defaults to the files in ./thumbnails
+    #[arg(long)]
+    ids: Option<PathBuf>,
+    /// Zipf exponent of the level popularity, higher puts more traffic on the top levels
+    #[arg(long, default_value_t = 1.0)]
+    skew: f64,
+    #[arg(long, default_value_t = 10_000)]
+    requests: usize,
+    #[arg(long, default_value_t = 32)]
+    concurrency: usize,
+    /// Random seed, the same seed sends the same requests in the same order
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+}
+
+fn logged_paths(path: &Path) -> Result<Vec<String>, String> {
+    let log = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let paths: Vec<String> = log
+        .lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|entry| entry["method"] == "GET")
+        .filter(|entry| entry["route"].as_str().is_some_and(|r| THUMBNAIL_ROUTES.contains(&r)))
+        .filter_map(|entry| entry["path"].as_str().map(str::to_string))
+        .collect();
+    match paths.is_empty() {
+        true => Err(format!("{} has no thumbnail requests", path.display())),
+        false => Ok(paths),
+    }
+}
+
+fn level_ids(path: Option<&Path>) -> Result<Vec<i64>, String> {
+    let ids: Vec<i64> = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect(),
+        None => std::fs::read_dir("thumbnails")
+            .map_err(|e| format!("Failed to list thumbnails, pass --ids instead: {}", e))?
+            .filter_map(|entry| entry.ok()?.path().file_stem()?.to_str()?.parse().ok())
+            .collect(),
+    };
+    match ids.is_empty() {
+        true => Err("No level IDs to request".to_string()),
+        false => Ok(ids),
+    }
+}
+
+// The level at rank n is requested 1/n^skew as often as the most popular one
+fn synthetic_paths(ids: &[i64], skew: f64, count: usize, rng: &mut StdRng) -> Vec<String> {
+    let mut cumulative = Vec::with_capacity(ids.len());
+    let mut total = 0.0;
+    for rank in 1..=ids.len() {
+        total += 1.0 / (rank as f64).powf(skew);
+        cumulative.push(total);
+    }
+    let resolutions: u32 = RESOLUTION_MIX.iter().map(|(_, weight)| weight).sum();
+
+    (0..count)
+        .map(|_| {
+            let pick = rng.random_range(0.0..total);
+            let id = ids[cumulative.partition_point(|&c| c < pick).min(ids.len() - 1)];
+            let mut pick = rng.random_range(0..resolutions);
+            let mut res = RESOLUTION_MIX[0].0;
+            for (name, weight) in RESOLUTION_MIX {
+                if pick < weight {
+                    res = name;
+                    break;
+                }
+                pick -= weight;
+            }
+            format!("/thumbnail/{}/{}", id, res)
+        })
+        .collect()
+}
+
+struct Outcome {
+    status: Option<u16>, // None when the request didn't get a response
+    latency: Duration,
+    bytes: usize,
+}
+
+async fn worker(
+    client: reqwest::Client,
+    target: Arc<String>,
+    paths: Arc<Vec<String>>,
+    next: Arc<AtomicUsize>,
+) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    loop {
+        let index = next.fetch_add(1, Ordering::Relaxed);
+        let Some(path) = paths.get(index) else {
+            return outcomes;
+        };
+
+        let start = Instant::now();
+        let (status, bytes) = match client.get(format!("{}{}", target, path)).send().await {
+            Ok(response) => {
+                let status = response.status().as_u16();
+                (Some(status), response.bytes().await.map(|b| b.len()).unwrap_or(0))
+            }
+            Err(_) => (None, 0),
+        };
+        outcomes.push(Outcome {
+            status,
+            latency: start.elapsed(),
+            bytes,
+        });
+    }
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let index = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len()) - 1;
+    sorted[index]
+}
+
+fn report(outcomes: &[Outcome], elapsed: Duration) {
+    let mut statuses = BTreeMap::new();
+    for outcome in outcomes {
+        let status = outcome.status.map_or("failed".to_string(), |s| s.to_string());
+        *statuses.entry(status).or_insert(0) += 1;
+    }
+    let mut latencies: Vec<Duration> = outcomes.iter().map(|o| o.latency).collect();
+    latencies.sort();
+    let bytes: usize = outcomes.iter().map(|o| o.bytes).sum();
+    let seconds = elapsed.as_secs_f64();
+
+    println!(
+        "{} requests in {:.2}s, {:.1} req/s",
+        outcomes.len(),
+        seconds,
+        outcomes.len() as f64 / seconds
+    );
+    println!(
+        "{:.1} MiB received, {:.1} MiB/s",
+        bytes as f64 / 1048576.0,
+        bytes as f64 / 1048576.0 / seconds
+    );
+    for (status, count) in statuses {
+        println!("  {}: {}", status, count);
+    }
+    println!(
+        "latency p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        latencies[latencies.len() - 1],
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let mut rng = StdRng::seed_from_u64(args.seed);
+
+    // everything is picked up front, so choosing requests doesn't show up in the timings
+    let paths = match &args.access_log {
+        Some(log) => logged_paths(log).map(|logged| {
+            (0..args.requests).map(|_| logged[rng.random_range(0..logged.len())].clone()).collect()
+        }),
+        None => level_ids(args.ids.as_deref())
+            .map(|ids| synthetic_paths(&ids, args.skew, args.requests, &mut rng)),
+    };
+    let paths: Vec<String> = match paths {
+        Ok(paths) => paths,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if paths.is_empty() {
+        return println!("Nothing to request");
+    }
+
+    let client = reqwest::Client::new();
+    let target = Arc::new(args.target.trim_end_matches('/').to_string());
+    let paths = Arc::new(paths);
+    let next = Arc::new(AtomicUsize::new(0));
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..args.concurrency.max(1))
+        .map(|_| tokio::spawn(worker(client.clone(), target.clone(), paths.clone(), next.clone())))
+        .collect();
+    let mut outcomes = Vec::with_capacity(paths.len());
+    for worker in workers {
+        outcomes.extend(worker.await.expect("worker panicked"));
+    }
+    report(&outcomes, start.elapsed());
+}
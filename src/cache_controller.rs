@@ -1,8 +1,17 @@
+use crate::models::LevelId;
+use crate::{database, jobs, namespace, outbound, paths, storage};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+const INVALIDATION_CHANNEL: &str = "cache_invalidation";
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 struct CloudflareClient {
     api_token: String,
     zone_id: String,
     root_url: String,
-    client: reqwest::Client,
 }
 
 #[derive(Debug)]
@@ -28,35 +37,44 @@ impl CloudflareClient {
 
         let root_url = dotenv::var("HOME_URL").expect("HOME_URL must be set in the environment");
 
-        let client = reqwest::ClientBuilder::new()
-            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
-            .expect("Failed to create HTTP client");
-
-        Self {
-            api_token,
-            zone_id,
-            root_url,
-            client,
-        }
+        Self { api_token, zone_id, root_url }
     }
 
-    pub async fn purge_thumbnail(&self, level_id: i64) -> Result<(), PurgeError> {
+    pub async fn purge_thumbnail(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<(), PurgeError> {
+        let base = format!(
+            "{}{}/thumbnail/{}",
+            self.root_url,
+            namespace::route_prefix(namespace),
+            level_id
+        );
         let urls = [
-            format!("{}/thumbnail/{}", self.root_url, level_id),
-            format!("{}/thumbnail/{}/small", self.root_url, level_id),
-            format!("{}/thumbnail/{}/medium", self.root_url, level_id),
-            format!("{}/thumbnail/{}/high", self.root_url, level_id),
-            format!("{}/thumbnail/{}/info", self.root_url, level_id),
+            base.clone(),
+            format!("{}/small", base),
+            format!("{}/medium", base),
+            format!("{}/high", base),
+            format!("{}/info", base),
+            format!("{}/embed", base),
+            format!("{}/card", base),
         ];
 
+        self.purge_cache(serde_json::json!({ "files": urls })).await
+    }
+
+    pub async fn purge_everything(&self) -> Result<(), PurgeError> {
+        self.purge_cache(serde_json::json!({ "purge_everything": true })).await
+    }
+
+    async fn purge_cache(&self, payload: serde_json::Value) -> Result<(), PurgeError> {
         let endpoint =
             format!("https://api.cloudflare.com/client/v4/zones/{}/purge_cache", self.zone_id);
 
-        let payload = serde_json::json!({ "files": urls });
-        let response =
-            self.client.post(&endpoint).bearer_auth(&self.api_token).json(&payload).send().await;
+        let request =
+            outbound::CLOUDFLARE.post(&endpoint).bearer_auth(&self.api_token).json(&payload);
+        let response = outbound::CLOUDFLARE.send(request).await;
 
         let response = match response {
             Ok(resp) => resp,
@@ -78,17 +96,178 @@ impl CloudflareClient {
     }
 }
 
-pub fn purge(level_id: i64) {
-    if dotenv::var("CLOUDFLARE_API_KEY").is_err() {
+pub fn cdn_configured() -> bool {
+    dotenv::var("CLOUDFLARE_API_KEY").is_ok()
+}
+
+// Immediate purges for operators, they get the error instead of a background retry
+pub async fn purge_now(namespace: &str, level_id: LevelId) -> Result<(), PurgeError> {
+    CloudflareClient::get().purge_thumbnail(namespace, level_id).await
+}
+
+pub async fn purge_everything() -> Result<(), PurgeError> {
+    CloudflareClient::get().purge_everything().await
+}
+
+// Directories of derived images, rebuilt from the originals on demand: one per variant kind,
+// plus the rendered fallbacks of the default namespace
+async fn cache_locations(namespace: &str) -> Vec<PathBuf> {
+    let mut locations = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(paths::local(paths::variant_root(namespace))).await
+    {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_type().await.is_ok_and(|t| t.is_dir()) {
+                locations.push(entry.path());
+            }
+        }
+    }
+
+    if namespace == namespace::DEFAULT {
+        locations.push(paths::local(paths::AUTO_DIR));
+    }
+    locations
+}
+
+// Removes the cached images of one level, returns how many files were deleted
+async fn remove_cached(namespace: &str, level_id: LevelId) -> usize {
+    storage::forget(&paths::thumbnail_path(namespace, level_id));
+    let mut removed = 0;
+    for location in cache_locations(namespace).await {
+        if tokio::fs::remove_file(paths::level_file(&location.to_string_lossy(), level_id))
+            .await
+            .is_ok()
+        {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+async fn clear_cached(namespace: &str) -> usize {
+    storage::forget_all();
+    let mut removed = 0;
+    for location in cache_locations(namespace).await {
+        let (_, files) = crate::get_dir_stats(&location).await.unwrap_or((0, 0));
+        if tokio::fs::remove_dir_all(&location).await.is_ok() {
+            removed += files;
+        }
+    }
+    removed
+}
+
+// Tells the other instances to drop their copies too, so it also works when each replica
+// keeps its caches on its own disk. Messages sent while an instance is reconnecting are lost
+#[derive(Serialize, Deserialize)]
+struct Invalidation {
+    origin: String,
+    namespace: String,
+    level_id: Option<LevelId>, // None clears the whole namespace
+}
+
+async fn broadcast(db: &database::Database, namespace: &str, level_id: Option<LevelId>) {
+    let invalidation = Invalidation {
+        origin: jobs::INSTANCE_ID.clone(),
+        namespace: namespace.to_string(),
+        level_id,
+    };
+    let payload = serde_json::to_string(&invalidation).unwrap_or_default();
+    if let Err(e) = db.notify(INVALIDATION_CHANNEL, &payload).await {
+        error!("Failed to broadcast cache invalidation for {}: {}", namespace, e);
+    }
+}
+
+// Drops the cached images of one level here and on every other instance
+pub async fn invalidate(db: &database::Database, namespace: &str, level_id: LevelId) -> usize {
+    let removed = remove_cached(namespace, level_id).await;
+    broadcast(db, namespace, Some(level_id)).await;
+    removed
+}
+
+pub async fn invalidate_namespace(db: &database::Database, namespace: &str) -> usize {
+    let removed = clear_cached(namespace).await;
+    broadcast(db, namespace, None).await;
+    removed
+}
+
+async fn apply(payload: &str) {
+    let invalidation = match serde_json::from_str::<Invalidation>(payload) {
+        Ok(invalidation) => invalidation,
+        Err(e) => return error!("Ignoring invalid cache invalidation {}: {}", payload, e),
+    };
+    if invalidation.origin == *jobs::INSTANCE_ID {
+        return;
+    }
+
+    let removed = match invalidation.level_id {
+        Some(level_id) => remove_cached(&invalidation.namespace, level_id).await,
+        None => clear_cached(&invalidation.namespace).await,
+    };
+    debug!("Applied cache invalidation from {}, removed {} file(s)", invalidation.origin, removed);
+}
+
+// Applies invalidations broadcast by other instances, reconnecting whenever the connection drops
+pub async fn listen(db: database::Database) {
+    loop {
+        match db.listen(INVALIDATION_CHANNEL).await {
+            Ok(mut listener) => {
+                info!("Listening for cache invalidations");
+                loop {
+                    match listener.recv().await {
+                        Ok(notification) => apply(notification.payload()).await,
+                        Err(e) => {
+                            error!("Lost the cache invalidation listener: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            Err(e) => error!("Failed to listen for cache invalidations: {}", e),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub path: String,
+    pub bytes: u64,
+    pub files: usize,
+}
+
+pub async fn cache_stats(namespace: &str) -> Vec<CacheStats> {
+    let mut stats = Vec::new();
+    for location in cache_locations(namespace).await {
+        let (bytes, files) = crate::get_dir_stats(&location).await.unwrap_or((0, 0));
+        stats.push(CacheStats {
+            path: location.display().to_string(),
+            bytes,
+            files,
+        });
+    }
+    stats
+}
+
+// A new, reverted or removed thumbnail replaces whatever the storage bucket, the CDN and every
+// instance's caches still hold. Handlers call this themselves, the queue event broadcast drops
+// events when a listener lags behind
+pub async fn replaced(db: &database::Database, namespace: &str, level_id: LevelId) {
+    invalidate(db, namespace, level_id).await;
+    purge(namespace, level_id);
+    storage::mirror_later(db, &paths::thumbnail_path(namespace, level_id)).await;
+}
+
+pub fn purge(namespace: &str, level_id: LevelId) {
+    if !cdn_configured() {
         eprintln!("CLOUDFLARE_API_KEY is not set, not purging level {}", level_id);
         return;
     }
 
+    let namespace = namespace.to_string();
     tokio::spawn(async move {
         let max_retries = 5;
 
         for attempt in 1..=max_retries {
-            match CloudflareClient::get().purge_thumbnail(level_id).await {
+            match CloudflareClient::get().purge_thumbnail(&namespace, level_id).await {
                 Ok(_) => {
                     println!("Purge for id {} succeeded after {} attempt(s)", level_id, attempt);
                     return;
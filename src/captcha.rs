@@ -0,0 +1,164 @@
+use crate::client_ip::ClientIp;
+use crate::permissions::{self, Permission};
+use crate::{database, util};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::{error, info};
+
+// accounts younger than this have to solve a challenge before uploading
+const NEW_ACCOUNT_AGE: chrono::Duration = chrono::Duration::days(7);
+// addresses with a rejected upload in this many days have to solve one as well
+const REJECTION_WINDOW_DAYS: i64 = 7;
+
+pub const TOKEN_HEADER: &str = "X-Captcha-Token";
+
+#[derive(Debug, Clone, Copy)]
+enum Provider {
+    HCaptcha,
+    Turnstile,
+}
+
+impl Provider {
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::HCaptcha => "hcaptcha",
+            Provider::Turnstile => "turnstile",
+        }
+    }
+
+    fn verify_url(&self) -> &'static str {
+        match self {
+            Provider::HCaptcha => "https://api.hcaptcha.com/siteverify",
+            Provider::Turnstile => "https://challenges.cloudflare.com/turnstile/v0/siteverify",
+        }
+    }
+}
+
+struct Captcha {
+    provider: Provider,
+    site_key: String,
+    secret: String,
+    client: reqwest::Client,
+}
+
+// CAPTCHA_PROVIDER is "hcaptcha" or "turnstile", challenges are off unless it and the keys are set
+static CAPTCHA: LazyLock<Option<Captcha>> = LazyLock::new(|| {
+    let provider = match dotenv::var("CAPTCHA_PROVIDER").ok()?.as_str() {
+        "hcaptcha" => Provider::HCaptcha,
+        "turnstile" => Provider::Turnstile,
+        other => {
+            error!("Unknown CAPTCHA_PROVIDER {}, challenges are disabled", other);
+            return None;
+        }
+    };
+
+    Some(Captcha {
+        provider,
+        site_key: dotenv::var("CAPTCHA_SITE_KEY").ok()?,
+        secret: dotenv::var("CAPTCHA_SECRET").ok()?,
+        client: reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create HTTP client"),
+    })
+});
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+async fn is_suspicious(
+    db: &database::Database,
+    user: &database::User,
+    ip: Option<ClientIp>,
+) -> bool {
+    let now = chrono::Utc::now();
+    if db.get_user_created_at(user.id).await.is_some_and(|at| now - at < NEW_ACCOUNT_AGE) {
+        return true;
+    }
+
+    let Some(ClientIp(ip)) = ip else {
+        return false;
+    };
+    match db.count_recent_rejections_from_ip(&ip.to_string(), REJECTION_WINDOW_DAYS).await {
+        Ok(count) => count > 0,
+        Err(e) => {
+            error!("Failed to count rejections from {}: {}", ip, e);
+            false
+        }
+    }
+}
+
+async fn verify(captcha: &Captcha, token: &str, ip: Option<ClientIp>) -> Result<bool, String> {
+    let mut form = vec![("secret", captcha.secret.clone()), ("response", token.to_string())];
+    if let Some(ClientIp(ip)) = ip {
+        form.push(("remoteip", ip.to_string()));
+    }
+
+    let response = captcha
+        .client
+        .post(captcha.provider.verify_url())
+        .form(&form)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?;
+    let result: VerifyResponse = response.json().await.map_err(|e| e.to_string())?;
+    Ok(result.success)
+}
+
+fn challenge(message: &str, captcha: &Captcha) -> Response {
+    util::response(
+        StatusCode::PRECONDITION_REQUIRED,
+        json!({
+            "status": StatusCode::PRECONDITION_REQUIRED.as_u16(),
+            "error": "captcha_required",
+            "message": message,
+            "provider": captcha.provider.name(),
+            "site_key": captcha.site_key,
+            "header": TOKEN_HEADER,
+        }),
+    )
+}
+
+// Uploads by new accounts or from addresses with recent rejections need a solved challenge,
+// sent back in the X-Captcha-Token header with the retried upload
+pub async fn check(
+    db: &database::Database,
+    user: &database::User,
+    ip: Option<ClientIp>,
+    headers: &HeaderMap,
+) -> Result<(), Response> {
+    let Some(captcha) = CAPTCHA.as_ref() else {
+        return Ok(());
+    };
+
+    if permissions::has(user.role, Permission::SkipCaptcha) || !is_suspicious(db, user, ip).await {
+        return Ok(());
+    }
+
+    let token = headers.get(TOKEN_HEADER).and_then(|h| h.to_str().ok()).unwrap_or_default();
+    if token.is_empty() {
+        return Err(challenge("Please solve the challenge to continue", captcha));
+    }
+
+    match verify(captcha, token, ip).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            info!("Captcha verification failed for {}", user.username);
+            Err(challenge("Challenge verification failed, please try again", captcha))
+        }
+        Err(e) => {
+            error!("Captcha verification request failed: {}", e);
+            Err(util::str_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Uploads are temporarily unavailable, please try again later",
+            ))
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::database::LevelMetadata;
+use crate::models::LevelId;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use webp::Encoder;
+
+const CARD_WIDTH: u32 = 1280;
+const CARD_HEIGHT: u32 = 720;
+const SHADE_HEIGHT: u32 = 220;
+const FACE_SIZE: u32 = 140;
+const MARGIN: u32 = 40;
+const NAME_SIZE: f32 = 64.0;
+const DETAIL_SIZE: f32 = 40.0;
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+pub const GOLD: Rgba<u8> = Rgba([255, 214, 64, 255]);
+
+// drawn cards are kept this long, the level's name or stars may change in the meantime
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+const CACHE_SIZE: usize = 256;
+
+// CARD_FONT is a TTF/OTF file, cards can't be drawn without one. The doctor loads it at
+// startup and refuses a file that can't be read
+static FONT: LazyLock<Result<Option<FontVec>, String>> = LazyLock::new(|| {
+    let Ok(path) = dotenv::var("CARD_FONT") else {
+        return Ok(None);
+    };
+    let data = std::fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let font = FontVec::try_from_vec(data).map_err(|_| format!("{} is not a valid font", path))?;
+    Ok(Some(font))
+});
+
+// Whether CARD_FONT is set, or why it can't be used
+pub fn load_font() -> Result<bool, String> {
+    FONT.as_ref().map(Option::is_some).map_err(Clone::clone)
+}
+
+// How a card is drawn, users can pick their own for cards of their thumbnails
+pub struct Style {
+    pub accent: Rgba<u8>,
+    pub author: Option<String>, // drawn as "by ..." when set
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self { accent: GOLD, author: None }
+    }
+}
+
+// Accepts #rrggbb
+pub fn parse_accent(hex: &str) -> Option<Rgba<u8>> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+pub fn is_configured() -> bool {
+    matches!(*FONT, Ok(Some(_)))
+}
+
+// A card is drawn again when the live upload changes or it's asked for in another style.
+// Generated thumbnails have no upload
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    level_id: LevelId,
+    upload_id: Option<i64>,
+    accent: [u8; 4],
+    author: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(level_id: LevelId, upload_id: Option<i64>, style: &Style) -> Self {
+        Self {
+            level_id,
+            upload_id,
+            accent: style.accent.0,
+            author: style.author.clone(),
+        }
+    }
+}
+
+// when each card was drawn, next to the encoded image
+type Cache = HashMap<CacheKey, (Instant, Arc<Vec<u8>>)>;
+
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(Default::default);
+
+pub fn cached(key: &CacheKey) -> Option<Arc<Vec<u8>>> {
+    let cache = CACHE.lock().unwrap();
+    let (drawn, card) = cache.get(key)?;
+    (drawn.elapsed() < CACHE_TTL).then(|| card.clone())
+}
+
+pub fn remember(key: CacheKey, card: Arc<Vec<u8>>) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|_, (drawn, _)| drawn.elapsed() < CACHE_TTL);
+    if cache.len() >= CACHE_SIZE
+        && let Some(oldest) =
+            cache.iter().min_by_key(|(_, (drawn, _))| *drawn).map(|(k, _)| k.clone())
+    {
+        cache.remove(&oldest);
+    }
+    cache.insert(key, (Instant::now(), card));
+}
+
+// Difficulty faces are read from CARD_ASSETS_DIR as e.g. "hard-demon.png", missing ones are skipped
+fn difficulty_face(difficulty: &str) -> Option<RgbaImage> {
+    let dir = dotenv::var("CARD_ASSETS_DIR").ok()?;
+    let name = difficulty.trim().to_lowercase().replace(' ', "-");
+    let face = image::open(format!("{}/{}.png", dir, name)).ok()?;
+    Some(face.resize(FACE_SIZE, FACE_SIZE, FilterType::Lanczos3).to_rgba8())
+}
+
+fn text_width(font: &FontVec, text: &str, size: f32) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    text.chars().map(|c| font.h_advance(font.glyph_id(c))).sum()
+}
+
+// Cuts the text down with an ellipsis until it fits
+fn fit_text(font: &FontVec, text: &str, size: f32, max_width: f32) -> String {
+    if text_width(font, text, size) <= max_width {
+        return text.to_string();
+    }
+
+    let mut chars: Vec<char> = text.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let candidate = format!("{}…", chars.iter().collect::<String>().trim_end());
+        if text_width(font, &candidate, size) <= max_width {
+            return candidate;
+        }
+    }
+    String::new()
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: Rgba<u8>, alpha: f32) {
+    for channel in 0..3 {
+        let value = pixel.0[channel] as f32 * (1.0 - alpha) + color.0[channel] as f32 * alpha;
+        pixel.0[channel] = value.round() as u8;
+    }
+}
+
+fn draw_text(
+    image: &mut RgbaImage,
+    font: &FontVec,
+    text: &str,
+    x: f32,
+    baseline: f32,
+    size: f32,
+    color: Rgba<u8>,
+) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut caret = x;
+    for c in text.chars() {
+        let glyph = scaled.scaled_glyph(c);
+        let advance = scaled.h_advance(glyph.id);
+        let glyph = ab_glyph::Glyph {
+            position: point(caret, baseline),
+            ..glyph
+        };
+
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+            outline.draw(|gx, gy, coverage| {
+                let px = bounds.min.x as i64 + gx as i64;
+                let py = bounds.min.y as i64 + gy as i64;
+                if px >= 0 && py >= 0 && (px as u32) < image.width() && (py as u32) < image.height()
+                {
+                    blend(image.get_pixel_mut(px as u32, py as u32), color, coverage);
+                }
+            });
+        }
+        caret += advance;
+    }
+}
+
+// Thumbnail with the level name, difficulty face and star count along the bottom
+pub fn render(thumbnail: &[u8], level: &LevelMetadata, style: &Style) -> Result<Vec<u8>, String> {
+    let font = match &*FONT {
+        Ok(Some(font)) => font,
+        Ok(None) => return Err("CARD_FONT is not set".to_string()),
+        Err(e) => return Err(e.clone()),
+    };
+    let thumbnail =
+        image::load_from_memory(thumbnail).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let mut card = thumbnail.resize_exact(CARD_WIDTH, CARD_HEIGHT, FilterType::Triangle).to_rgba8();
+
+    // darken the bottom so the text stays readable on bright thumbnails
+    let shade_top = CARD_HEIGHT - SHADE_HEIGHT;
+    for y in shade_top..CARD_HEIGHT {
+        let alpha = 0.8 * (y - shade_top) as f32 / SHADE_HEIGHT as f32;
+        for x in 0..CARD_WIDTH {
+            blend(card.get_pixel_mut(x, y), Rgba([0, 0, 0, 255]), alpha);
+        }
+    }
+
+    let mut text_x = MARGIN as f32;
+    if let Some(face) = difficulty_face(&level.difficulty) {
+        let y = CARD_HEIGHT - MARGIN - face.height();
+        image::imageops::overlay(&mut card, &face, MARGIN as i64, y as i64);
+        text_x += (FACE_SIZE + MARGIN / 2) as f32;
+    }
+
+    let max_width = CARD_WIDTH as f32 - text_x - MARGIN as f32;
+    let name = fit_text(font, &level.name, NAME_SIZE, max_width);
+    let name_baseline = (CARD_HEIGHT - MARGIN) as f32 - DETAIL_SIZE * 1.4;
+    draw_text(&mut card, font, &name, text_x, name_baseline, NAME_SIZE, WHITE);
+
+    let detail_baseline = (CARD_HEIGHT - MARGIN) as f32 - DETAIL_SIZE * 0.2;
+    draw_text(&mut card, font, &level.difficulty, text_x, detail_baseline, DETAIL_SIZE, WHITE);
+    if level.stars > 0 {
+        let stars = format!("★ {}", level.stars);
+        let x = text_x + text_width(font, &level.difficulty, DETAIL_SIZE) + DETAIL_SIZE;
+        draw_text(&mut card, font, &stars, x, detail_baseline, DETAIL_SIZE, style.accent);
+    }
+
+    // right-aligned on the detail line, cut down so it never runs into the difficulty
+    if let Some(author) = &style.author {
+        let author = fit_text(font, &format!("by {}", author), DETAIL_SIZE, max_width / 2.0);
+        let x = CARD_WIDTH as f32 - MARGIN as f32 - text_width(font, &author, DETAIL_SIZE);
+        draw_text(&mut card, font, &author, x, detail_baseline, DETAIL_SIZE, style.accent);
+    }
+
+    let rgb = image::DynamicImage::ImageRgba8(card).to_rgb8();
+    Ok(Encoder::from_rgb(&rgb, CARD_WIDTH, CARD_HEIGHT).encode(90.0).to_vec())
+}
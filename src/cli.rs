@@ -0,0 +1,284 @@
+use crate::database::{self, Role};
+use crate::models::{AccountId, LevelId};
+use crate::routes::upload;
+use crate::sync;
+use crate::webhooks::{self, WebhookEvent};
+use crate::{backup, doctor, encoder, importer, namespace, paths, seed, storage};
+use chrono::NaiveDateTime;
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(version, about = "Level thumbnails server and administration tools")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the HTTP server (default)
+    Serve,
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Check the database, storage and credentials and explain what's misconfigured
+    Doctor {
+        /// Skip the requests to Argon, Discord and Cloudflare
+        #[arg(long)]
+        offline: bool,
+    },
+    /// Change the role of every user linked to a Geometry Dash account ID
+    PromoteUser {
+        account_id: i64,
+        role: Role,
+    },
+    /// Import `{level_id}.png` (or any supported image) files as accepted thumbnails
+    ImportThumbnails {
+        dir: PathBuf,
+        /// Geometry Dash account ID of the user the thumbnails are attributed to
+        /// (defaults to IMPORTER_ACCOUNT_ID)
+        #[arg(long)]
+        account_id: Option<i64>,
+        /// Leave levels that already have a thumbnail untouched
+        #[arg(long)]
+        skip_existing: bool,
+    },
+    /// Serve thumbnails read-only while following an upstream instance
+    Mirror {
+        /// Base URL of the upstream instance, e.g. https://levelthumbs.prevter.me
+        upstream: String,
+    },
+    /// Delete stored images that no longer have a matching database entry
+    Gc,
+    /// Re-encode every stored thumbnail with the current encoder settings
+    Reencode,
+    /// Put back the thumbnails levels had at a point in time from the backup storage
+    Restore {
+        /// Restore point in UTC, like 2025-09-01T12:00:00
+        #[arg(long)]
+        until: NaiveDateTime,
+        /// Also replace thumbnails that exist, taking down uploads accepted after the restore point
+        #[arg(long)]
+        overwrite: bool,
+        /// Only list what would be restored
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Fill an empty database with sample users, uploads and placeholder images for development
+    Seed {
+        /// Number of uploads to create, spread over every upload state
+        #[arg(long, default_value_t = 300)]
+        uploads: usize,
+        /// Number of regular uploaders besides the one account per role
+        #[arg(long, default_value_t = 25)]
+        uploaders: usize,
+        /// Random seed, the same seed produces the same data
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+}
+
+pub async fn run(command: Command) {
+    match command {
+        Command::Serve | Command::Mirror { .. } => unreachable!("serving is handled by main"),
+        Command::Migrate => {
+            // connecting to the database applies all pending migrations
+            database::get_db().await;
+            println!("Migrations applied");
+        }
+        Command::Doctor { offline } => run_doctor(offline).await,
+        Command::PromoteUser { account_id, role } => promote_user(account_id, role).await,
+        Command::ImportThumbnails { dir, account_id, skip_existing } => {
+            import_thumbnails(&dir, account_id, skip_existing).await
+        }
+        Command::Gc => gc().await,
+        Command::Reencode => reencode().await,
+        Command::Restore { until, overwrite, dry_run } => {
+            restore(backup::RestoreOptions {
+                until: until.and_utc(),
+                overwrite,
+                dry_run,
+            })
+            .await
+        }
+        Command::Seed { uploads, uploaders, seed } => {
+            seed_database(seed::SeedOptions { uploads, uploaders, seed }).await
+        }
+    }
+}
+
+async fn run_doctor(offline: bool) {
+    let checks = doctor::run(!offline).await;
+    for check in &checks {
+        println!("{}", check);
+    }
+    if checks.iter().any(|check| check.status == doctor::Status::Fail) {
+        std::process::exit(1);
+    }
+}
+
+async fn promote_user(account_id: i64, role: Role) {
+    let db = database::get_db().await;
+    match db.set_user_role(AccountId(account_id), role).await {
+        Ok(users) if users.is_empty() => eprintln!("No user found with account ID {}", account_id),
+        Ok(users) => {
+            for user in users {
+                println!("User {} ({}) is now {}", user.username, user.id, user.role);
+            }
+        }
+        Err(e) => eprintln!("Failed to update role: {}", e),
+    }
+}
+
+async fn import_thumbnails(dir: &Path, account_id: Option<i64>, skip_existing: bool) {
+    let db = database::get_db().await;
+    let user = match importer::importer_user(&db, account_id).await {
+        Ok(user) => user,
+        Err(e) => {
+            eprintln!("Failed to resolve importer user: {}", e);
+            return;
+        }
+    };
+
+    let options = importer::ImportOptions {
+        skip_existing,
+        ..importer::ImportOptions::from_env()
+    };
+    match importer::import_directory(&db, dir, &user, &options).await {
+        Ok(summary) => println!(
+            "Imported {} thumbnail(s) as {}, {} skipped, {} failed",
+            summary.imported, user.username, summary.skipped, summary.failed
+        ),
+        Err(e) => eprintln!("Failed to read {}: {}", dir.display(), e),
+    }
+}
+
+async fn restore(options: backup::RestoreOptions) {
+    let db = database::get_db().await;
+    match backup::restore(&db, &options).await {
+        Ok(summary) => println!(
+            "Restored {} thumbnail(s), {} skipped, {} missing from the backup, {} failed",
+            summary.restored, summary.skipped, summary.missing, summary.failed
+        ),
+        Err(e) => eprintln!("Restore failed: {}", e),
+    }
+}
+
+async fn seed_database(options: seed::SeedOptions) {
+    let db = database::get_db().await;
+    match seed::run(&db, &options).await {
+        Ok(summary) => println!(
+            "Seeded {} user(s) and {} accepted, {} pending, {} rejected, {} withdrawn and {} expired upload(s)",
+            summary.users,
+            summary.accepted,
+            summary.pending,
+            summary.rejected,
+            summary.withdrawn,
+            summary.expired
+        ),
+        Err(e) => eprintln!("Seeding failed: {}", e),
+    }
+}
+
+async fn remove_unreferenced(dir: &str, referenced: &HashSet<String>) -> Vec<String> {
+    let mut removed = Vec::new();
+    let Ok(mut entries) = tokio::fs::read_dir(paths::local(dir)).await else {
+        return removed;
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if referenced.contains(&format!("{}/{}", dir, name)) {
+            continue;
+        }
+
+        match tokio::fs::remove_file(entry.path()).await {
+            Ok(_) => removed.push(name),
+            Err(e) => eprintln!("Failed to remove {}/{}: {}", dir, name, e),
+        }
+    }
+
+    removed
+}
+
+async fn gc() {
+    let db = database::get_db().await;
+
+    let pending: HashSet<String> = match db.get_pending_uploads(namespace::DEFAULT).await {
+        Ok(uploads) => uploads
+            .iter()
+            .map(|u| paths::pending_path(namespace::DEFAULT, u.user_id, u.level_id))
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to fetch pending uploads: {}", e);
+            return;
+        }
+    };
+
+    let accepted: HashSet<String> = match db.get_accepted_level_ids().await {
+        Ok(ids) => ids.iter().map(|&id| paths::thumbnail_path(namespace::DEFAULT, id)).collect(),
+        Err(e) => {
+            eprintln!("Failed to fetch accepted uploads: {}", e);
+            return;
+        }
+    };
+
+    let removed_uploads =
+        remove_unreferenced(&paths::upload_dir(namespace::DEFAULT), &pending).await;
+    let removed_thumbnails =
+        remove_unreferenced(&paths::thumbnail_dir(namespace::DEFAULT), &accepted).await;
+    for name in &removed_thumbnails {
+        if let Ok(level_id) = name.trim_end_matches(".webp").parse::<LevelId>() {
+            sync::record_removed(&db, level_id).await;
+            webhooks::dispatch(
+                &db,
+                WebhookEvent::ThumbnailRemoved,
+                json!({ "level_id": level_id }),
+            )
+            .await;
+        }
+    }
+
+    println!(
+        "Removed {} orphaned pending image(s) and {} orphaned thumbnail(s)",
+        removed_uploads.len(),
+        removed_thumbnails.len()
+    );
+}
+
+async fn reencode() {
+    let db = database::get_db().await;
+    let dir = paths::thumbnail_dir(namespace::DEFAULT);
+    let mut entries = match tokio::fs::read_dir(paths::local(&dir)).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read thumbnails: {}", e);
+            return;
+        }
+    };
+
+    let (mut reencoded, mut failed) = (0, 0);
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+        let result = async {
+            let data = tokio::fs::read(entry.path()).await.map_err(|e| e.to_string())?;
+            let webp_data = encoder::run(move || upload::process_image(&data)).await??;
+            tokio::fs::write(entry.path(), webp_data).await.map_err(|e| e.to_string())?;
+            storage::mirror(&db, &path).await;
+            Ok::<_, String>(())
+        }
+        .await;
+
+        match result {
+            Ok(_) => reencoded += 1,
+            Err(e) => {
+                eprintln!("Failed to re-encode {}: {}", path, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("Re-encoded {} thumbnail(s), {} failed", reencoded, failed);
+}
@@ -0,0 +1,141 @@
+use crate::models::UserId;
+use crate::{database, util};
+use axum::extract::{ConnectInfo, FromRequestParts, OptionalFromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::{Extensions, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::LazyLock;
+use tracing::{debug, error};
+
+// headers a client could use to claim another address, only proxies we trust may set them
+const FORWARDING_HEADERS: &[&str] = &["X-Forwarded-For", "X-Real-IP", "Forwarded"];
+
+// TRUSTED_PROXIES is a comma-separated list of proxy addresses or CIDR ranges
+static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    dotenv::var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let net = entry.parse::<IpNet>().or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+            if net.is_err() {
+                error!("Ignoring invalid TRUSTED_PROXIES entry {}", entry);
+            }
+            net.ok()
+        })
+        .collect()
+});
+
+// Address of the client that made the request, usable as an extractor in any handler
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+// IPv6 users usually get a whole /64, so limits count it as one address
+pub fn network(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & !(u64::MAX as u128))),
+    }
+}
+
+fn is_trusted(ip: &IpAddr) -> bool {
+    TRUSTED_PROXIES.iter().any(|net| net.contains(ip))
+}
+
+// Unix sockets have no peer address, only a proxy on the same machine can connect to them
+fn peer_is_trusted(peer: Option<IpAddr>) -> bool {
+    peer.is_none_or(|peer| is_trusted(&peer))
+}
+
+fn peer(extensions: &Extensions) -> Option<IpAddr> {
+    extensions.get::<ConnectInfo<SocketAddr>>().map(|info| info.0.ip())
+}
+
+// The rightmost X-Forwarded-For entry that isn't one of our proxies is the client
+fn forwarded_client(headers: &HeaderMap) -> Option<IpAddr> {
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+
+    let hops: Vec<IpAddr> = header("X-Forwarded-For")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+    hops.iter()
+        .rev()
+        .find(|hop| !is_trusted(hop))
+        .or(hops.first())
+        .copied()
+        .or_else(|| header("X-Real-IP")?.trim().parse().ok())
+}
+
+// Forwarding headers are only read when the peer is a trusted proxy
+pub fn from_parts(extensions: &Extensions, headers: &HeaderMap) -> Option<IpAddr> {
+    let peer = peer(extensions);
+    if !peer_is_trusted(peer) {
+        return peer;
+    }
+    forwarded_client(headers).or(peer)
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(ip) = parts.extensions.get::<ClientIp>() {
+            return Ok(*ip);
+        }
+
+        from_parts(&parts.extensions, &parts.headers).map(ClientIp).ok_or_else(|| {
+            util::str_response(StatusCode::BAD_REQUEST, "Could not determine client address")
+        })
+    }
+}
+
+impl<S: Send + Sync> OptionalFromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(<ClientIp as FromRequestParts<S>>::from_request_parts(parts, state).await.ok())
+    }
+}
+
+// Drops forwarding headers sent by untrusted peers so nothing further down can be fooled
+// by them, then resolves the client address once for the rest of the request
+pub async fn resolve(mut request: Request, next: Next) -> Response {
+    let peer = peer(request.extensions());
+    if !peer_is_trusted(peer) {
+        for name in FORWARDING_HEADERS {
+            if request.headers_mut().remove(*name).is_some() {
+                debug!("Dropped {} header sent by untrusted peer {:?}", name, peer);
+            }
+        }
+    }
+
+    if let Some(ip) = from_parts(request.extensions(), request.headers()) {
+        request.extensions_mut().insert(ClientIp(ip));
+    }
+    next.run(request).await
+}
+
+pub async fn record(
+    db: &database::Database,
+    user_id: UserId,
+    ip: Option<ClientIp>,
+    action: database::IpAction,
+) {
+    let Some(ClientIp(ip)) = ip else {
+        return;
+    };
+
+    if let Err(e) = db.record_user_ip(user_id, &ip.to_string(), action).await {
+        error!("Failed to record {} address of user {}: {}", action, user_id, e);
+    }
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+// Source of the current time for expiry checks and stats windows. The database carries one,
+// so tests can swap in a clock they move by hand instead of waiting for real time to pass
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    // the day views and API usage are counted under
+    fn today(&self) -> NaiveDate {
+        self.now().date_naive()
+    }
+}
+
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+// Source of the non-secret random values handed out, like object keys and random picks.
+// Secrets, nonces and tokens keep drawing from the OS generator directly
+pub trait IdGenerator: std::fmt::Debug + Send + Sync {
+    fn next_u64(&self) -> u64;
+
+    fn hex_id(&self) -> String {
+        format!("{:016x}{:016x}", self.next_u64(), self.next_u64())
+    }
+
+    // Index into a non-empty list
+    fn pick(&self, len: usize) -> usize {
+        (self.next_u64() % len as u64) as usize
+    }
+}
+
+#[derive(Debug)]
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_u64(&self) -> u64 {
+        rand::random()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::csrf;
+use axum::http::{HeaderValue, header};
+use axum::response::Response;
+use std::sync::LazyLock;
+use tracing::error;
+
+const SESSION_MAX_AGE: i64 = 60 * 60 * 24 * 400; // browsers cap cookies at 400 days anyway
+
+struct CookieConfig {
+    secure: bool,
+    same_site: &'static str,
+}
+
+// COOKIE_SAMESITE is lax, strict or none, COOKIE_SECURE defaults to whether HOME_URL is https
+static CONFIG: LazyLock<CookieConfig> = LazyLock::new(|| {
+    let same_site = dotenv::var("COOKIE_SAMESITE").unwrap_or_default().to_lowercase();
+    let same_site = match same_site.as_str() {
+        "" | "lax" => "Lax",
+        "strict" => "Strict",
+        "none" => "None",
+        other => {
+            error!("Ignoring invalid COOKIE_SAMESITE {}, using lax", other);
+            "Lax"
+        }
+    };
+    let secure = match dotenv::var("COOKIE_SECURE") {
+        Ok(value) => value == "true" || value == "1",
+        Err(_) => dotenv::var("HOME_URL").is_ok_and(|url| url.starts_with("https://")),
+    };
+
+    // browsers drop SameSite=None cookies that aren't Secure
+    CookieConfig {
+        secure: secure || same_site == "None",
+        same_site,
+    }
+});
+
+fn build(name: &str, value: &str, path: &str, http_only: bool, max_age: i64) -> String {
+    build_with(name, value, path, http_only, max_age, CONFIG.same_site)
+}
+
+fn build_with(
+    name: &str,
+    value: &str,
+    path: &str,
+    http_only: bool,
+    max_age: i64,
+    same_site: &str,
+) -> String {
+    let mut cookie =
+        format!("{}={}; Path={}; SameSite={}; Max-Age={}", name, value, path, same_site, max_age);
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    if CONFIG.secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+// Short-lived cookies scoped to a path, like the 2FA login challenge
+pub fn temporary(name: &str, value: &str, path: &str, max_age: i64) -> String {
+    build(name, value, path, true, max_age)
+}
+
+// Has to come back with a redirect from another site, which Strict would prevent
+pub fn cross_site_redirect(name: &str, value: &str, path: &str, max_age: i64) -> String {
+    let same_site = match CONFIG.same_site {
+        "Strict" => "Lax",
+        same_site => same_site,
+    };
+    build_with(name, value, path, true, max_age, same_site)
+}
+
+pub fn clear(name: &str, path: &str, http_only: bool) -> String {
+    build(name, "", path, http_only, 0)
+}
+
+// The dashboard reads its session from these, scripts can only see the role and the CSRF token
+pub fn session(token: &str, role: crate::database::Role) -> [String; 3] {
+    [
+        build("auth_token", token, "/", true, SESSION_MAX_AGE),
+        build("auth_role", &role.to_string(), "/", false, SESSION_MAX_AGE),
+        build(csrf::TOKEN_COOKIE, &csrf::token(token), "/", false, SESSION_MAX_AGE),
+    ]
+}
+
+pub fn clear_session() -> [String; 3] {
+    [
+        clear("auth_token", "/", true),
+        clear("auth_role", "/", false),
+        clear(csrf::TOKEN_COOKIE, "/", false),
+    ]
+}
+
+pub fn append(response: &mut Response, cookies: &[String]) {
+    for cookie in cookies {
+        if let Ok(value) = HeaderValue::from_str(cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{auth, util};
+use axum::extract::Request;
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::sync::LazyLock;
+
+pub const TOKEN_HEADER: &str = "X-CSRF-Token";
+pub const TOKEN_COOKIE: &str = "csrf_token";
+
+// scheme://host[:port] of HOME_URL, the only origin the dashboard is served from
+static HOME_ORIGIN: LazyLock<Option<String>> = LazyLock::new(|| {
+    dotenv::var("HOME_URL").ok().and_then(|url| origin_of(&url).map(str::to_string))
+});
+
+fn origin_of(url: &str) -> Option<&str> {
+    let rest_start = url.find("://")? + 3;
+    let end = url[rest_start..].find('/').map_or(url.len(), |i| rest_start + i);
+    Some(&url[..end])
+}
+
+// Origin is sent with every cross-site POST by current browsers, Referer covers the rest.
+// Without HOME_URL the request has to come from the host it was sent to.
+fn is_same_origin(headers: &HeaderMap) -> bool {
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| headers.get(header::REFERER).and_then(|h| h.to_str().ok()))
+        .and_then(origin_of);
+    let Some(origin) = origin else {
+        return false;
+    };
+
+    match HOME_ORIGIN.as_deref() {
+        Some(home) => origin.eq_ignore_ascii_case(home),
+        None => {
+            let host = headers.get(header::HOST).and_then(|h| h.to_str().ok());
+            host.is_some_and(|host| origin.split_once("://").is_some_and(|(_, o)| o == host))
+        }
+    }
+}
+
+fn signature(session_token: &str) -> Hmac<Sha256> {
+    let secret = auth::secret();
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("csrf:{}", session_token).as_bytes());
+    mac
+}
+
+// Derived from the session token, so it is only good for the session it was issued with
+// and doesn't need to be stored anywhere
+pub fn token(session_token: &str) -> String {
+    hex::encode(signature(session_token).finalize().into_bytes())
+}
+
+// The dashboard copies the csrf_token cookie into the X-CSRF-Token header, which
+// another site can't do since it can't read our cookies
+fn has_valid_token(headers: &HeaderMap) -> bool {
+    let Some(session_token) = util::session_token(headers) else {
+        return false;
+    };
+    headers
+        .get(TOKEN_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|token| hex::decode(token).ok())
+        .is_some_and(|token| signature(&session_token).verify_slice(&token).is_ok())
+}
+
+// Cookie sessions can only change state from the dashboard's own origin and with a CSRF
+// token, requests with an Authorization header aren't affected
+pub async fn protect(request: Request, next: Next) -> Response {
+    let safe = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if safe || !util::is_cookie_session(request.headers()) {
+        return next.run(request).await;
+    }
+
+    if !is_same_origin(request.headers()) {
+        return util::str_response(StatusCode::FORBIDDEN, "Cross-site request rejected");
+    }
+    if !has_valid_token(request.headers()) {
+        return util::str_response(StatusCode::FORBIDDEN, "Missing or invalid CSRF token");
+    }
+
+    next.run(request).await
+}
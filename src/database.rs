@@ -1,147 +1,269 @@
-use sqlx::postgres::PgPoolOptions;
-use sqlx::{FromRow, Postgres};
+use sqlx::Postgres;
+use sqlx::postgres::{PgConnectOptions, PgListener, PgPoolOptions};
 
-use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use crate::clock::{Clock, IdGenerator, RandomIds, SystemClock};
+use crate::moderation;
+use crate::namespace;
+use crate::permissions::Permission;
+use crate::scanner::ScanResult;
+use crate::storage;
+use crate::upload_source::UploadSource;
+use chrono::{DateTime, NaiveDate, Utc};
 use std::sync::Arc;
+use std::time::Duration;
+
+pub use crate::models::*;
 
 #[derive(Debug, Clone)]
 pub struct Database {
     pub pool: Arc<sqlx::Pool<Postgres>>,
+    clock: Arc<dyn Clock>,
+    ids: Arc<dyn IdGenerator>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
-#[serde(rename_all = "lowercase")]
-#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
-pub enum Role {
-    User,      // regular user
-    Verified,  // verified users can upload thumbnails without approval
-    Moderator, // moderators can approve or reject uploads
-    Admin,     // admins can manage users and uploads
-}
-
-impl std::fmt::Display for Role {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Role::User => write!(f, "user"),
-            Role::Verified => write!(f, "verified"),
-            Role::Moderator => write!(f, "moderator"),
-            Role::Admin => write!(f, "admin"),
-        }
-    }
-}
-
-#[derive(Debug, FromRow, Serialize)]
-pub struct User {
-    pub id: i64,
-    pub account_id: i64,
-    pub username: String,
-    pub role: Role,
-    pub discord_id: Option<i64>,
-}
+// a running migration not updated for this long belongs to an instance that went away
+const STALE_MIGRATION_MINUTES: i32 = 10;
 
-#[derive(FromRow)]
-pub struct UploadInfo {
-    pub account_id: i64,
-    pub username: String,
-}
+const TAKEDOWN_COLUMNS: &str = "id, namespace, level_id, claimant_name, claimant_contact, reason,
+    host(ip) AS ip, status, resolved_by, resolved_at, created_at";
 
-#[derive(FromRow, Serialize, Deserialize)]
-pub struct UploadExtended {
-    pub level_id: i64,
-    pub account_id: i64,
-    pub username: String,
-    pub upload_time: NaiveDateTime,
-    pub first_upload_time: NaiveDateTime,
-    pub accepted_time: Option<NaiveDateTime>,
-    pub accepted_by: Option<i64>,
-    pub accepted_by_username: Option<String>,
-}
+// Expects the applications as a and their users as u
+const APPLICATION_COLUMNS: &str = "a.id, a.user_id, u.username, u.account_id, a.links, a.message,
+    a.status, a.reviewed_by, a.review_note, a.reviewed_at, a.created_at";
 
-#[derive(FromRow, Serialize, Deserialize)]
-pub struct PendingUpload {
-    pub id: i64,
-    pub user_id: i64,
-    pub username: String,
-    pub level_id: i64,
-    pub accepted: bool,
-    pub upload_time: NaiveDateTime,
-
-    #[sqlx(skip)]
-    pub replacement: bool,
+// Transaction-scoped advisory lock on a level, released when dropped
+pub struct LevelLock {
+    _transaction: sqlx::Transaction<'static, Postgres>,
 }
 
-#[derive(FromRow, Serialize, Deserialize)]
-pub struct UserStats {
-    pub id: i64,
-    pub account_id: i64,
-    pub username: String,
-    pub role: Role,
-    pub upload_count: i64,
-    pub accepted_upload_count: i64,
-    pub level_count: i64,
-    pub accepted_level_count: i64,
-    pub active_thumbnail_count: i64,
-}
+// Counts of one user's uploads, $1 is the user ID
+const USER_STATS_QUERY: &str = "SELECT
+    users.id, users.account_id,
+    users.username, users.role,
+    COUNT(uploads.id) AS upload_count,
+    COUNT(DISTINCT uploads.level_id) AS level_count,
+    COUNT(uploads.id) FILTER (WHERE uploads.status = 'accepted') AS accepted_upload_count,
+    COUNT(DISTINCT uploads.level_id) FILTER (WHERE uploads.status = 'accepted') AS accepted_level_count,
+    (
+      SELECT COUNT(*)
+      FROM (
+        SELECT u.level_id
+        FROM uploads u
+        WHERE u.status = 'accepted'
+        AND u.user_id = users.id
+        AND u.upload_time = (
+          SELECT MAX(u2.upload_time)
+          FROM uploads u2
+          WHERE u2.namespace = u.namespace
+            AND u2.level_id = u.level_id
+            AND u2.status = 'accepted'
+        )
+      ) active_levels
+    ) AS active_thumbnail_count
+  FROM users
+  LEFT JOIN uploads ON users.id = uploads.user_id
+  WHERE users.id = $1
+  GROUP BY users.id, users.account_id, users.username, users.role";
 
 impl Database {
     pub async fn new() -> Self {
         let connection_string = dotenv::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let pool = PgPoolOptions::new()
-            .max_connections(5)
-            .connect(&connection_string)
-            .await
-            .expect("Failed to connect to the database");
+        let options = connection_string.parse().expect("DATABASE_URL must be a valid Postgres URL");
+        Self::connect(options).await.expect("Failed to connect to the database")
+    }
+
+    // Also applies pending migrations, tests point the options at a schema of their own
+    pub async fn connect(options: PgConnectOptions) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().max_connections(5).connect_with(options).await?;
+        sqlx::migrate!("./migrations").run(&pool).await?;
+        Ok(Database {
+            pool: Arc::new(pool),
+            clock: Arc::new(SystemClock),
+            ids: Arc::new(RandomIds),
+        })
+    }
 
-        // Run migrations if needed
-        sqlx::migrate!("./migrations").run(&pool).await.expect("Failed to run migrations");
+    #[cfg(test)]
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        Self { clock, ..self }
+    }
+
+    #[cfg(test)]
+    pub fn with_ids(self, ids: Arc<dyn IdGenerator>) -> Self {
+        Self { ids, ..self }
+    }
+
+    // Expiry and stats windows are measured against this rather than the database's NOW(),
+    // only job leases and storage migrations, shared between replicas, keep the server time
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    fn today(&self) -> NaiveDate {
+        self.clock.today()
+    }
 
-        Database { pool: Arc::new(pool) }
+    pub fn ids(&self) -> &dyn IdGenerator {
+        &*self.ids
     }
 
-    pub async fn get_upload_info(&self, id: i64) -> Option<UploadInfo> {
+    pub async fn get_upload_info(&self, namespace: &str, level_id: LevelId) -> Option<UploadInfo> {
         sqlx::query_as::<_, UploadInfo>(
-            "SELECT users.account_id, users.username
+            "SELECT users.account_id, users.username, uploads.license, uploads.credit
                  FROM uploads
                  JOIN users ON uploads.user_id = users.id
-                 WHERE uploads.level_id = $1 AND accepted = TRUE
+                 WHERE uploads.namespace = $1 AND uploads.level_id = $2 AND status = 'accepted'
                  ORDER BY upload_time DESC LIMIT 1",
         )
-        .bind(id)
+        .bind(namespace)
+        .bind(level_id)
         .fetch_optional(&*self.pool)
         .await
         .ok()?
     }
 
-    pub async fn get_upload_extended(&self, id: i64) -> Option<UploadExtended> {
+    pub async fn get_upload_extended(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Option<UploadExtended> {
         sqlx::query_as::<_, UploadExtended>(
             "SELECT 
                     uploads.level_id,
+                    uploads.user_id,
                     users.account_id,
                     users.username,
                     uploads.upload_time,
                     (
                         SELECT MIN(upload_time) FROM uploads u2
-                        WHERE u2.level_id = uploads.level_id AND u2.accepted = TRUE
+                        WHERE u2.namespace = uploads.namespace AND u2.level_id = uploads.level_id
+                          AND u2.status = 'accepted'
                     ) AS first_upload_time,
                     uploads.accepted_time,
                     accepted_by.account_id AS accepted_by,
-                    accepted_by.username AS accepted_by_username
+                    accepted_by.username AS accepted_by_username,
+                    uploads.license,
+                    uploads.credit,
+                    uploads.width,
+                    uploads.height,
+                    uploads.file_size,
+                    uploads.encoding,
+                    uploads.encoder_version
                  FROM uploads
                  JOIN users ON uploads.user_id = users.id
                  LEFT JOIN users AS accepted_by ON uploads.accepted_by = accepted_by.id
-                 WHERE uploads.level_id = $1 AND accepted = TRUE
+                 WHERE uploads.namespace = $1 AND uploads.level_id = $2 AND status = 'accepted'
                  ORDER BY upload_time DESC LIMIT 1",
         )
-        .bind(id)
+        .bind(namespace)
+        .bind(level_id)
         .fetch_optional(&*self.pool)
         .await
         .ok()?
     }
 
+    pub async fn get_active_uploads_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<UploadExtended>, sqlx::Error> {
+        sqlx::query_as::<_, UploadExtended>(
+            "SELECT * FROM (
+                SELECT DISTINCT ON (uploads.level_id)
+                    uploads.level_id,
+                    uploads.user_id,
+                    users.account_id,
+                    users.username,
+                    uploads.upload_time,
+                    (
+                        SELECT MIN(upload_time) FROM uploads u2
+                        WHERE u2.namespace = uploads.namespace AND u2.level_id = uploads.level_id
+                          AND u2.status = 'accepted'
+                    ) AS first_upload_time,
+                    uploads.accepted_time,
+                    accepted_by.account_id AS accepted_by,
+                    accepted_by.username AS accepted_by_username,
+                    uploads.license,
+                    uploads.credit,
+                    uploads.width,
+                    uploads.height,
+                    uploads.file_size,
+                    uploads.encoding,
+                    uploads.encoder_version
+                 FROM uploads
+                 JOIN users ON uploads.user_id = users.id
+                 LEFT JOIN users AS accepted_by ON uploads.accepted_by = accepted_by.id
+                 WHERE uploads.namespace = 'default' AND uploads.status = 'accepted'
+                 ORDER BY uploads.level_id, uploads.upload_time DESC
+             ) active
+             WHERE $1::TIMESTAMP IS NULL OR active.accepted_time >= $1
+             ORDER BY active.level_id",
+        )
+        .bind(since)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_upload_history(
+        &self,
+        level_id: LevelId,
+    ) -> Result<Vec<UploadExtended>, sqlx::Error> {
+        sqlx::query_as::<_, UploadExtended>(
+            "SELECT
+                    uploads.level_id,
+                    uploads.user_id,
+                    users.account_id,
+                    users.username,
+                    uploads.upload_time,
+                    (
+                        SELECT MIN(upload_time) FROM uploads u2
+                        WHERE u2.namespace = uploads.namespace AND u2.level_id = uploads.level_id
+                          AND u2.status = 'accepted'
+                    ) AS first_upload_time,
+                    uploads.accepted_time,
+                    accepted_by.account_id AS accepted_by,
+                    accepted_by.username AS accepted_by_username,
+                    uploads.license,
+                    uploads.credit,
+                    uploads.width,
+                    uploads.height,
+                    uploads.file_size,
+                    uploads.encoding,
+                    uploads.encoder_version
+                 FROM uploads
+                 JOIN users ON uploads.user_id = users.id
+                 LEFT JOIN users AS accepted_by ON uploads.accepted_by = accepted_by.id
+                 WHERE uploads.namespace = 'default' AND uploads.level_id = $1 AND status = 'accepted'
+                 ORDER BY upload_time DESC",
+        )
+        .bind(level_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_existing_levels(
+        &self,
+        level_ids: &[LevelId],
+    ) -> Result<Vec<LevelId>, sqlx::Error> {
+        sqlx::query_scalar::<_, LevelId>(
+            "SELECT DISTINCT level_id FROM uploads
+             WHERE namespace = 'default' AND status = 'accepted' AND level_id = ANY($1)",
+        )
+        .bind(level_ids)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn count_thumbnails(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(DISTINCT (namespace, level_id)) FROM uploads WHERE status = 'accepted'",
+        )
+        .fetch_one(&*self.pool)
+        .await
+    }
+
     pub async fn find_or_create_user(
         &self,
-        account_id: i64,
+        account_id: AccountId,
         username: &str,
     ) -> Result<User, sqlx::Error> {
         let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE account_id = $1")
@@ -175,6 +297,11 @@ impl Database {
             .await?;
 
         if let Some(user) = user {
+            sqlx::query("UPDATE users SET discord_username = $1 WHERE id = $2")
+                .bind(username)
+                .bind(user.id)
+                .execute(&*self.pool)
+                .await?;
             Ok(user)
         } else {
             // first check if we can link to existing legacy account
@@ -186,16 +313,20 @@ impl Database {
             .await?;
             if let Some(legacy_user) = legacy_user {
                 // update the legacy user with the discord_id
-                sqlx::query("UPDATE users SET discord_id = $1 WHERE id = $2")
-                    .bind(discord_id)
-                    .bind(legacy_user.id)
-                    .execute(&*self.pool)
-                    .await?;
+                sqlx::query(
+                    "UPDATE users SET discord_id = $1, discord_username = $3 WHERE id = $2",
+                )
+                .bind(discord_id)
+                .bind(legacy_user.id)
+                .bind(username)
+                .execute(&*self.pool)
+                .await?;
                 return Ok(legacy_user);
             }
             // if no legacy user found, create a new user
             let new_user = sqlx::query_as::<_, User>(
-                "INSERT INTO users (account_id, username, role, discord_id) VALUES (-1, $1, 'user', $2) RETURNING *",
+                "INSERT INTO users (account_id, username, role, discord_id, discord_username)
+                 VALUES (-1, $1, 'user', $2, $1) RETURNING *",
             )
             .bind(username)
             .bind(discord_id)
@@ -205,7 +336,7 @@ impl Database {
         }
     }
 
-    pub async fn get_user_by_id(&self, id: i64) -> Option<User> {
+    pub async fn get_user_by_id(&self, id: UserId) -> Option<User> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
             .bind(id)
             .fetch_optional(&*self.pool)
@@ -213,132 +344,2548 @@ impl Database {
             .ok()?
     }
 
-    pub async fn add_upload(
+    // Oldest user holding the GD account, accounts linked through Discord are merged into one row
+    pub async fn get_user_id_by_account_id(&self, account_id: AccountId) -> Option<UserId> {
+        sqlx::query_scalar("SELECT id FROM users WHERE account_id = $1 ORDER BY id LIMIT 1")
+            .bind(account_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    pub async fn set_discord_avatar(
         &self,
-        level_id: i64,
-        user_id: i64,
-        image_path: &str,
-        accepted: bool,
+        user_id: UserId,
+        avatar: Option<&str>,
     ) -> Result<(), sqlx::Error> {
-        sqlx::query(
-                if accepted {
-                    "INSERT INTO uploads (level_id, user_id, image_path, accepted, accepted_time, accepted_by)
-                     VALUES ($1, $2, $3, $4, NOW(), $2)"
-                } else {
-                    "INSERT INTO uploads (level_id, user_id, image_path, accepted)
-                     VALUES ($1, $2, $3, $4)"
-                }
-            )
-            .bind(level_id)
+        sqlx::query("UPDATE users SET discord_avatar = $2 WHERE id = $1")
             .bind(user_id)
-            .bind(image_path)
-            .bind(accepted)
+            .bind(avatar)
             .execute(&*self.pool)
             .await?;
         Ok(())
     }
 
-    pub async fn get_pending_uploads(&self) -> Result<Vec<PendingUpload>, sqlx::Error> {
-        sqlx::query_as::<_, PendingUpload>(
-            "SELECT uploads.id, user_id, username, level_id, accepted, upload_time FROM uploads
-             LEFT JOIN users ON users.id = user_id
-             WHERE accepted = FALSE AND accepted_time IS NULL
-             ORDER BY upload_time",
+    pub async fn get_discord_avatar(&self, user_id: UserId) -> Option<String> {
+        sqlx::query_scalar::<_, Option<String>>("SELECT discord_avatar FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()??
+    }
+
+    pub async fn add_impersonation_session(
+        &self,
+        admin_id: UserId,
+        user_id: UserId,
+        reason: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "INSERT INTO impersonation_sessions (admin_id, user_id, reason, expires_at)
+             VALUES ($1, $2, $3, $4) RETURNING id",
+        )
+        .bind(admin_id)
+        .bind(user_id)
+        .bind(reason)
+        .bind(expires_at)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn add_impersonation_action(
+        &self,
+        session_id: i64,
+        method: &str,
+        path: &str,
+        status: i32,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO impersonation_actions (session_id, method, path, status)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(session_id)
+        .bind(method)
+        .bind(path)
+        .bind(status)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_impersonation_sessions(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<ImpersonationSession>, sqlx::Error> {
+        sqlx::query_as::<_, ImpersonationSession>(
+            "SELECT impersonation_sessions.id, admin_id, users.username AS admin_username,
+                    user_id, reason, started_at, expires_at
+             FROM impersonation_sessions
+             LEFT JOIN users ON impersonation_sessions.admin_id = users.id
+             WHERE user_id = $1
+             ORDER BY started_at DESC",
         )
+        .bind(user_id)
         .fetch_all(&*self.pool)
         .await
     }
 
-    pub async fn get_pending_uploads_for_level(
+    pub async fn get_impersonation_actions(
         &self,
-        level_id: i64,
-    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
-        sqlx::query_as::<_, PendingUpload>(
-            "SELECT uploads.id, user_id, username, accepted, upload_time FROM uploads
-                 LEFT JOIN users ON users.id = user_id
-                 WHERE accepted = FALSE AND accepted_time IS NULL AND level_id = $1
-                 ORDER BY upload_time",
+        session_ids: &[i64],
+    ) -> Result<Vec<ImpersonationAction>, sqlx::Error> {
+        sqlx::query_as::<_, ImpersonationAction>(
+            "SELECT session_id, method, path, status, created_at FROM impersonation_actions
+             WHERE session_id = ANY($1)
+             ORDER BY created_at",
         )
-        .bind(level_id)
+        .bind(session_ids)
         .fetch_all(&*self.pool)
         .await
     }
 
-    pub async fn get_pending_uploads_for_user(
+    pub async fn get_two_factor(&self, user_id: UserId) -> Option<TwoFactor> {
+        sqlx::query_as::<_, TwoFactor>("SELECT totp_secret, totp_enabled FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    pub async fn set_two_factor(
         &self,
-        user_id: i64,
-    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
-        sqlx::query_as::<_, PendingUpload>(
-            "SELECT uploads.id, user_id, username, level_id, accepted, upload_time FROM uploads
-             LEFT JOIN users ON users.id = user_id
-             WHERE accepted = FALSE AND accepted_time IS NULL AND user_id = $1
-             ORDER BY upload_time",
+        user_id: UserId,
+        secret: Option<&str>,
+        enabled: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET totp_secret = $2, totp_enabled = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(secret)
+            .bind(enabled)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Issuing new codes invalidates every earlier one
+    pub async fn replace_recovery_codes(
+        &self,
+        user_id: UserId,
+        code_hashes: &[String],
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::TEXT[])",
         )
         .bind(user_id)
-        .fetch_all(&*self.pool)
+        .bind(code_hashes)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await
+    }
+
+    // Marks the code as used, false when it doesn't exist or was used before
+    // Claims a TOTP time step, false when a code from it or a later one was already used
+    pub async fn use_totp_step(&self, user_id: UserId, step: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE users SET totp_last_step = $2
+             WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)",
+        )
+        .bind(user_id)
+        .bind(step)
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn use_recovery_code(
+        &self,
+        user_id: UserId,
+        code_hash: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE recovery_codes SET used_at = $3
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .bind(code_hash)
+        .bind(self.now())
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn count_recovery_codes(&self, user_id: UserId) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_one(&*self.pool)
         .await
     }
 
-    pub async fn get_pending_upload(&self, id: i64) -> Result<PendingUpload, sqlx::Error> {
-        sqlx::query_as::<_, PendingUpload>(
-            "SELECT uploads.id, user_id, username, level_id, accepted, upload_time FROM uploads
-             LEFT JOIN users ON users.id = user_id
-             WHERE accepted = FALSE AND accepted_time IS NULL AND uploads.id = $1",
+    pub async fn get_email_settings(&self, user_id: UserId) -> Option<EmailSettings> {
+        sqlx::query_as::<_, EmailSettings>(
+            "SELECT email, email_notifications, email_verified FROM users WHERE id = $1",
         )
-        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    // A new address has to be confirmed again, returns whether the stored one is confirmed
+    pub async fn set_email_settings(
+        &self,
+        user_id: UserId,
+        email: Option<&str>,
+        notifications: bool,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "UPDATE users SET email_verified = email_verified AND email IS NOT DISTINCT FROM $2,
+                              email = $2, email_notifications = $3
+             WHERE id = $1 RETURNING email_verified",
+        )
+        .bind(user_id)
+        .bind(email)
+        .bind(notifications)
         .fetch_one(&*self.pool)
         .await
     }
 
-    pub async fn accept_upload(
+    pub async fn set_email_token(
         &self,
-        id: i64,
-        accepted_by: i64,
-        reason: Option<String>,
-        accept: bool,
+        user_id: UserId,
+        token_hash: &str,
+        expires: DateTime<Utc>,
     ) -> Result<(), sqlx::Error> {
         sqlx::query(
-                "UPDATE uploads SET accepted = $1, accepted_time = NOW(), accepted_by = $2, reason = $3 WHERE id = $4",
-            )
-            .bind(accept)
-            .bind(accepted_by)
-            .bind(reason)
-            .bind(id)
+            "UPDATE users SET email_token_hash = $2, email_token_expires = $3 WHERE id = $1",
+        )
+        .bind(user_id)
+        .bind(token_hash)
+        .bind(expires)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Confirms the address the token was mailed to, false for unknown or expired tokens
+    pub async fn verify_email(&self, token_hash: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE users SET email_verified = TRUE, email_token_hash = NULL, email_token_expires = NULL
+             WHERE email_token_hash = $1 AND email_token_expires > $2 AND email IS NOT NULL",
+        )
+        .bind(token_hash)
+        .bind(self.now())
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_card_theme(&self, user_id: UserId) -> Option<CardTheme> {
+        sqlx::query_as::<_, CardTheme>(
+            "SELECT card_accent, card_show_author FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    // Discord-only accounts share account ID -1, so only linked GD accounts have a theme here
+    pub async fn get_card_theme_by_account(&self, account_id: AccountId) -> Option<CardTheme> {
+        sqlx::query_as::<_, CardTheme>(
+            "SELECT card_accent, card_show_author FROM users WHERE account_id = $1 AND account_id > 0",
+        )
+        .bind(account_id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    pub async fn set_card_theme(
+        &self,
+        user_id: UserId,
+        accent: Option<&str>,
+        show_author: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET card_accent = $2, card_show_author = $3 WHERE id = $1")
+            .bind(user_id)
+            .bind(accent)
+            .bind(show_author)
             .execute(&*self.pool)
             .await?;
         Ok(())
     }
 
-    pub async fn get_user_stats(&self, id: i64) -> Option<UserStats> {
-        sqlx::query_as::<_, UserStats>(
-            "SELECT
-                users.id, users.account_id,
-                users.username, users.role,
-                COUNT(uploads.id) AS upload_count,
-                COUNT(DISTINCT uploads.level_id) AS level_count,
-                COUNT(uploads.id) FILTER (WHERE uploads.accepted = TRUE) AS accepted_upload_count,
-                COUNT(DISTINCT uploads.level_id) FILTER (WHERE uploads.accepted = TRUE) AS accepted_level_count,
-                (
-                  SELECT COUNT(*)
-                  FROM (
-                    SELECT u.level_id
-                    FROM uploads u
-                    WHERE u.accepted = TRUE
-                    AND u.user_id = users.id
-                    AND u.upload_time = (
-                      SELECT MAX(u2.upload_time)
-                      FROM uploads u2
-                      WHERE u2.level_id = u.level_id
-                        AND u2.accepted = TRUE
-                    )
-                  ) active_levels
-                ) AS active_thumbnail_count
-              FROM users
-              LEFT JOIN uploads ON users.id = uploads.user_id
-              WHERE users.id = $1
-              GROUP BY users.id, users.account_id, users.username, users.role",
+    // Only users who opted in and confirmed their address get emails
+    pub async fn get_email_recipient(&self, user_id: UserId) -> Option<EmailRecipient> {
+        sqlx::query_as::<_, EmailRecipient>(
+            "SELECT username, email FROM users
+             WHERE id = $1 AND email_notifications AND email IS NOT NULL AND email_verified",
         )
-        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    pub async fn get_user_by_discord_id(&self, discord_id: i64) -> Option<User> {
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE discord_id = $1")
+            .bind(discord_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    pub async fn set_user_role(
+        &self,
+        account_id: AccountId,
+        role: Role,
+    ) -> Result<Vec<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>("UPDATE users SET role = $1 WHERE account_id = $2 RETURNING *")
+            .bind(role)
+            .bind(account_id)
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn add_upload(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+        user_id: UserId,
+        image_path: &str,
+        accepted: bool,
+        meta: &ImageMeta,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(if accepted {
+            "INSERT INTO uploads (namespace, level_id, user_id, image_path, width, height, file_size, encoding, encoder_version,
+                                  upload_time, status, processing_status, accepted_time, accepted_by)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'accepted', 'live', $10, $3) RETURNING id"
+        } else {
+            "INSERT INTO uploads (namespace, level_id, user_id, image_path, width, height, file_size, encoding, encoder_version,
+                                  upload_time, status, processing_status)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'pending', 'queued_for_review') RETURNING id"
+        })
+        .bind(namespace)
+        .bind(level_id)
+        .bind(user_id)
+        .bind(image_path)
+        .bind(meta.width)
+        .bind(meta.height)
+        .bind(meta.file_size)
+        .bind(meta.encoding)
+        .bind(meta.encoder_version)
+        .bind(self.now())
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn add_seed_upload(&self, upload: &SeedUpload<'_>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "INSERT INTO uploads (namespace, level_id, user_id, image_path, width, height, file_size, encoding, encoder_version,
+                                  status, processing_status, upload_time, accepted_time, accepted_by, reason)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10,
+                     CASE WHEN $10 = 'accepted' THEN 'live' ELSE 'queued_for_review' END, $11, $12, $13, $14)
+             RETURNING id",
+        )
+        .bind(upload.namespace)
+        .bind(upload.level_id)
+        .bind(upload.user_id)
+        .bind(upload.image_path)
+        .bind(upload.meta.width)
+        .bind(upload.meta.height)
+        .bind(upload.meta.file_size)
+        .bind(upload.meta.encoding)
+        .bind(upload.meta.encoder_version)
+        .bind(upload.status)
+        .bind(upload.upload_time)
+        .bind(upload.decided_at)
+        .bind(upload.decided_by)
+        .bind(upload.reason)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn set_upload_attribution(
+        &self,
+        id: i64,
+        license: Option<&str>,
+        credit: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE uploads SET license = $2, credit = $3 WHERE id = $1")
+            .bind(id)
+            .bind(license)
+            .bind(credit)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_upload_source(
+        &self,
+        id: i64,
+        source: UploadSource,
+        attested: bool,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE uploads SET source = $2, attested = $3 WHERE id = $1")
+            .bind(id)
+            .bind(source)
+            .bind(attested)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_upload_ip(&self, id: i64, ip: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE uploads SET ip = $2::TEXT::INET WHERE id = $1")
+            .bind(id)
+            .bind(ip)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_scan_result(&self, id: i64, scan: &ScanResult) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE uploads SET scan_verdict = $1, scan_score = $2, scan_label = $3 WHERE id = $4",
+        )
+        .bind(scan.verdict)
+        .bind(scan.score)
+        .bind(&scan.label)
+        .bind(id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn add_quarantine(&self, entry: NewQuarantineEntry<'_>) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "INSERT INTO quarantine (user_id, namespace, level_id, sha256, source, detail, file_path, encrypted)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id",
+        )
+        .bind(entry.user_id)
+        .bind(entry.namespace)
+        .bind(entry.level_id)
+        .bind(entry.sha256)
+        .bind(entry.source)
+        .bind(entry.detail)
+        .bind(entry.file_path)
+        .bind(entry.encrypted)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_quarantine(&self) -> Result<Vec<QuarantineEntry>, sqlx::Error> {
+        sqlx::query_as::<_, QuarantineEntry>(
+            "SELECT quarantine.*, users.username FROM quarantine
+             LEFT JOIN users ON users.id = quarantine.user_id
+             ORDER BY quarantine.id DESC",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_quarantine_entry(&self, id: i64) -> Option<QuarantineEntry> {
+        sqlx::query_as::<_, QuarantineEntry>(
+            "SELECT quarantine.*, users.username FROM quarantine
+             LEFT JOIN users ON users.id = quarantine.user_id
+             WHERE quarantine.id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    // Returns how many other entries still point at the same file
+    pub async fn delete_quarantine(&self, id: i64, file_path: &str) -> Result<i64, sqlx::Error> {
+        sqlx::query("DELETE FROM quarantine WHERE id = $1").bind(id).execute(&*self.pool).await?;
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM quarantine WHERE file_path = $1")
+            .bind(file_path)
+            .fetch_one(&*self.pool)
+            .await
+    }
+
+    pub async fn add_quarantine_audit(
+        &self,
+        quarantine_id: Option<i64>,
+        admin_id: UserId,
+        action: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO quarantine_audit (quarantine_id, admin_id, action) VALUES ($1, $2, $3)",
+        )
+        .bind(quarantine_id)
+        .bind(admin_id)
+        .bind(action)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_upload_processing(&self, id: i64) -> Option<UploadProcessing> {
+        sqlx::query_as::<_, UploadProcessing>(
+            "SELECT id, user_id, namespace, level_id, status, processing_status, upload_time
+             FROM uploads WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    // Returns None when another request is already writing an upload for this level
+    pub async fn try_lock_level(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<Option<LevelLock>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let locked = sqlx::query_scalar::<_, bool>(
+            "SELECT pg_try_advisory_xact_lock(hashtextextended($1 || ':' || $2::TEXT, 0))",
+        )
+        .bind(namespace)
+        .bind(level_id)
+        .fetch_one(&mut *transaction)
+        .await?;
+        Ok(locked.then_some(LevelLock { _transaction: transaction }))
+    }
+
+    pub async fn add_accepted_uploads(
+        &self,
+        user_id: UserId,
+        uploads: &[(LevelId, String, ImageMeta)],
+    ) -> Result<(), sqlx::Error> {
+        let level_ids: Vec<LevelId> = uploads.iter().map(|(level_id, _, _)| *level_id).collect();
+        let image_paths: Vec<&str> = uploads.iter().map(|(_, path, _)| path.as_str()).collect();
+        let metas = uploads.iter().map(|(_, _, meta)| meta);
+        let widths: Vec<Option<i32>> = metas.clone().map(|meta| meta.width).collect();
+        let heights: Vec<Option<i32>> = metas.clone().map(|meta| meta.height).collect();
+        let file_sizes: Vec<i64> = metas.clone().map(|meta| meta.file_size).collect();
+        let encodings: Vec<Option<&str>> = metas.clone().map(|meta| meta.encoding).collect();
+        let versions: Vec<Option<i32>> = metas.map(|meta| meta.encoder_version).collect();
+        sqlx::query(
+            "INSERT INTO uploads (level_id, user_id, image_path, width, height, file_size, encoding, encoder_version,
+                                  upload_time, status, processing_status, accepted_time, accepted_by, source)
+             SELECT level_id, $1, image_path, width, height, file_size, encoding, encoder_version,
+                    $9, 'accepted', 'live', $9, $1, 'import'
+             FROM UNNEST($2::BIGINT[], $3::TEXT[], $4::INT[], $5::INT[], $6::BIGINT[], $7::TEXT[], $8::INT[])
+                  AS batch(level_id, image_path, width, height, file_size, encoding, encoder_version)",
+        )
+        .bind(user_id)
+        .bind(level_ids)
+        .bind(image_paths)
+        .bind(widths)
+        .bind(heights)
+        .bind(file_sizes)
+        .bind(encodings)
+        .bind(versions)
+        .bind(self.now())
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_pending_uploads(
+        &self,
+        namespace: &str,
+    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
+        sqlx::query_as::<_, PendingUpload>(
+            "SELECT uploads.id, user_id, users.username, namespace, level_id, status, upload_time,
+                    image_path, reason, accepted_time, accepted_by,
+                    decided_by.username AS accepted_by_username, scan_verdict, scan_score, scan_label,
+                    assigned_to, attested, source
+             FROM uploads
+             LEFT JOIN users ON users.id = user_id
+             LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+             WHERE status = 'pending' AND namespace = $1
+             ORDER BY upload_time",
+        )
+        .bind(namespace)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn count_pending_uploads(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM uploads WHERE status = 'pending'")
+            .fetch_one(&*self.pool)
+            .await
+    }
+
+    pub async fn get_pending_uploads_for_level(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
+        sqlx::query_as::<_, PendingUpload>(
+            "SELECT uploads.id, user_id, users.username, namespace, level_id, status, upload_time,
+                        image_path, reason, accepted_time, accepted_by,
+                        decided_by.username AS accepted_by_username, scan_verdict, scan_score, scan_label,
+                        assigned_to, attested, source
+                 FROM uploads
+                 LEFT JOIN users ON users.id = user_id
+                 LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+                 WHERE status = 'pending' AND namespace = $1 AND level_id = $2
+                 ORDER BY upload_time",
+        )
+        .bind(namespace)
+        .bind(level_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_pending_uploads_for_user(
+        &self,
+        user_id: UserId,
+    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
+        sqlx::query_as::<_, PendingUpload>(
+            "SELECT uploads.id, user_id, users.username, namespace, level_id, status, upload_time,
+                    image_path, reason, accepted_time, accepted_by,
+                    decided_by.username AS accepted_by_username, scan_verdict, scan_score, scan_label,
+                    assigned_to, attested, source
+             FROM uploads
+             LEFT JOIN users ON users.id = user_id
+             LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+             WHERE status = 'pending' AND user_id = $1
+             ORDER BY upload_time",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_assigned_uploads(
+        &self,
+        moderator_id: UserId,
+    ) -> Result<Vec<PendingUpload>, sqlx::Error> {
+        sqlx::query_as::<_, PendingUpload>(
+            "SELECT uploads.id, user_id, users.username, namespace, level_id, status, upload_time,
+                    image_path, reason, accepted_time, accepted_by,
+                    decided_by.username AS accepted_by_username, scan_verdict, scan_score, scan_label,
+                    assigned_to, attested, source
+             FROM uploads
+             LEFT JOIN users ON users.id = user_id
+             LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+             WHERE status = 'pending' AND assigned_to = $1
+             ORDER BY upload_time",
+        )
+        .bind(moderator_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_pending_assignments(&self) -> Result<Vec<Assignment>, sqlx::Error> {
+        sqlx::query_as::<_, Assignment>(
+            "SELECT id, namespace, assigned_to, assigned_at FROM uploads
+             WHERE status = 'pending' ORDER BY upload_time",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn record_presence(&self, user_id: UserId) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO moderator_presence (user_id, last_seen) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE SET last_seen = EXCLUDED.last_seen",
+        )
+        .bind(user_id)
+        .bind(self.now())
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_present_moderators(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<UserId>, sqlx::Error> {
+        sqlx::query_scalar::<_, UserId>(
+            "SELECT user_id FROM moderator_presence
+             WHERE last_seen > $1 - make_interval(secs => $2) ORDER BY user_id",
+        )
+        .bind(self.now())
+        .bind(timeout.as_secs_f64())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn assign_upload(&self, id: i64, moderator_id: UserId) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE uploads SET assigned_to = $2, assigned_at = CURRENT_TIMESTAMP
+             WHERE id = $1 AND status = 'pending'",
+        )
+        .bind(id)
+        .bind(moderator_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Same pick as get_upload_info, the upload whose file is currently served
+    pub async fn get_live_upload_id(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM uploads
+             WHERE namespace = $1 AND level_id = $2 AND status = 'accepted'
+             ORDER BY upload_time DESC LIMIT 1",
+        )
+        .bind(namespace)
+        .bind(level_id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn set_archive_path(&self, id: i64, archive_path: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE uploads SET archive_path = $2, archived_at = $3 WHERE id = $1")
+            .bind(id)
+            .bind(archive_path)
+            .bind(self.now())
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Archived files of rejected uploads, or of everything else, kept for longer than the given days
+    pub async fn get_expired_archives(
+        &self,
+        rejected: bool,
+        days: u32,
+        limit: i64,
+    ) -> Result<Vec<(i64, String)>, sqlx::Error> {
+        sqlx::query_as::<_, (i64, String)>(
+            "SELECT id, archive_path FROM uploads
+             WHERE archive_path IS NOT NULL AND (status = 'rejected') = $1
+               AND archived_at < $4 - make_interval(days => $2::INT)
+             ORDER BY archived_at LIMIT $3",
+        )
+        .bind(rejected)
+        .bind(days as i32)
+        .bind(limit)
+        .bind(self.now())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn clear_archive_paths(&self, ids: &[i64]) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE uploads SET archive_path = NULL, archived_at = NULL WHERE id = ANY($1)",
+        )
+        .bind(ids)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Uploader and archived file of an upload that used to be live for the level
+    pub async fn get_archived_upload(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+        upload_id: i64,
+    ) -> Result<Option<(UserId, String)>, sqlx::Error> {
+        sqlx::query_as::<_, (UserId, String)>(
+            "SELECT user_id, archive_path FROM uploads
+             WHERE id = $1 AND namespace = $2 AND level_id = $3 AND status = 'accepted'
+               AND archive_path IS NOT NULL",
+        )
+        .bind(upload_id)
+        .bind(namespace)
+        .bind(level_id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_upload_info_by_id(&self, upload_id: i64) -> Option<UploadInfo> {
+        sqlx::query_as::<_, UploadInfo>(
+            "SELECT users.account_id, users.username, uploads.license, uploads.credit
+                 FROM uploads
+                 JOIN users ON uploads.user_id = users.id
+                 WHERE uploads.id = $1",
+        )
+        .bind(upload_id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    pub async fn get_experiments(&self) -> Result<Vec<ThumbnailExperiment>, sqlx::Error> {
+        sqlx::query_as::<_, ThumbnailExperiment>(
+            "SELECT * FROM thumbnail_experiments ORDER BY started_at DESC",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // None when the level already has an experiment running
+    pub async fn add_experiment(
+        &self,
+        level_id: LevelId,
+        upload_id: i64,
+        user_id: UserId,
+        image_path: &str,
+        started_by: UserId,
+    ) -> Result<Option<ThumbnailExperiment>, sqlx::Error> {
+        sqlx::query_as::<_, ThumbnailExperiment>(
+            "INSERT INTO thumbnail_experiments (level_id, upload_id, user_id, image_path, started_by)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (level_id) DO NOTHING RETURNING *",
+        )
+        .bind(level_id)
+        .bind(upload_id)
+        .bind(user_id)
+        .bind(image_path)
+        .bind(started_by)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_experiment(
+        &self,
+        level_id: LevelId,
+    ) -> Result<Option<ThumbnailExperiment>, sqlx::Error> {
+        sqlx::query_as::<_, ThumbnailExperiment>(
+            "SELECT * FROM thumbnail_experiments WHERE level_id = $1",
+        )
+        .bind(level_id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    // Ends the experiment and records slot B as the live upload in one go, None when the
+    // experiment was already over
+    pub async fn promote_experiment(
+        &self,
+        experiment: &ThumbnailExperiment,
+        image_path: &str,
+        meta: &ImageMeta,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let ended = sqlx::query("DELETE FROM thumbnail_experiments WHERE level_id = $1")
+            .bind(experiment.level_id)
+            .execute(&mut *transaction)
+            .await?;
+        if ended.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        let upload_id = sqlx::query_scalar::<_, i64>(
+            "INSERT INTO uploads (namespace, level_id, user_id, image_path, width, height, file_size, encoding, encoder_version,
+                                  upload_time, status, processing_status, accepted_time, accepted_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, 'accepted', 'live', $10, $3) RETURNING id",
+        )
+        .bind(namespace::DEFAULT)
+        .bind(experiment.level_id)
+        .bind(experiment.user_id)
+        .bind(image_path)
+        .bind(meta.width)
+        .bind(meta.height)
+        .bind(meta.file_size)
+        .bind(meta.encoding)
+        .bind(meta.encoder_version)
+        .bind(self.now())
+        .fetch_one(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(Some(upload_id))
+    }
+
+    pub async fn delete_experiment(
+        &self,
+        level_id: LevelId,
+    ) -> Result<Option<ThumbnailExperiment>, sqlx::Error> {
+        sqlx::query_as::<_, ThumbnailExperiment>(
+            "DELETE FROM thumbnail_experiments WHERE level_id = $1 RETURNING *",
+        )
+        .bind(level_id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn add_experiment_views(
+        &self,
+        level_id: LevelId,
+        views_a: i64,
+        views_b: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE thumbnail_experiments SET views_a = views_a + $2, views_b = views_b + $3
+             WHERE level_id = $1",
+        )
+        .bind(level_id)
+        .bind(views_a)
+        .bind(views_b)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_archive_path(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+        upload_id: i64,
+    ) -> Result<Option<String>, sqlx::Error> {
+        sqlx::query_scalar::<_, String>(
+            "SELECT archive_path FROM uploads
+             WHERE id = $1 AND namespace = $2 AND level_id = $3 AND archive_path IS NOT NULL",
+        )
+        .bind(upload_id)
+        .bind(namespace)
+        .bind(level_id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_unreplicated_uploads(
+        &self,
+        limit: i64,
+    ) -> Result<Vec<UnreplicatedUpload>, sqlx::Error> {
+        sqlx::query_as::<_, UnreplicatedUpload>(
+            "SELECT id, namespace, level_id, archive_path,
+                    id = (
+                        SELECT u2.id FROM uploads u2
+                        WHERE u2.namespace = uploads.namespace AND u2.level_id = uploads.level_id
+                          AND u2.status = 'accepted'
+                        ORDER BY u2.upload_time DESC LIMIT 1
+                    ) AS live
+             FROM uploads
+             WHERE status = 'accepted' AND backed_up_at IS NULL
+             ORDER BY accepted_time LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn mark_backed_up(&self, ids: &[i64]) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE uploads SET backed_up_at = NOW() WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_backup_lag(&self) -> Result<BackupLag, sqlx::Error> {
+        sqlx::query_as::<_, BackupLag>(
+            "SELECT COUNT(*) AS pending, MIN(accepted_time) AS oldest_accepted_time
+             FROM uploads WHERE status = 'accepted' AND backed_up_at IS NULL",
+        )
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // A dedicated connection that receives NOTIFY messages sent to the channel
+    pub async fn listen(&self, channel: &str) -> Result<PgListener, sqlx::Error> {
+        let mut listener = PgListener::connect_with(&self.pool).await?;
+        listener.listen(channel).await?;
+        Ok(listener)
+    }
+
+    pub async fn notify(&self, channel: &str, payload: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(channel)
+            .bind(payload)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Takes the job's lease when nobody holds it and its last run is at least min_gap ago
+    pub async fn claim_job(
+        &self,
+        name: &str,
+        holder: &str,
+        min_gap: Duration,
+        lease: Duration,
+    ) -> Result<bool, sqlx::Error> {
+        let claimed = sqlx::query_scalar::<_, String>(
+            "INSERT INTO job_leases (name, holder, locked_until, last_run_at)
+             VALUES ($1, $2, NOW() + make_interval(secs => $4), NOW())
+             ON CONFLICT (name) DO UPDATE
+             SET holder = EXCLUDED.holder,
+                 locked_until = EXCLUDED.locked_until,
+                 last_run_at = EXCLUDED.last_run_at
+             WHERE (job_leases.locked_until IS NULL OR job_leases.locked_until < NOW())
+               AND job_leases.last_run_at <= NOW() - make_interval(secs => $3)
+             RETURNING name",
+        )
+        .bind(name)
+        .bind(holder)
+        .bind(min_gap.as_secs_f64())
+        .bind(lease.as_secs_f64())
+        .fetch_optional(&*self.pool)
+        .await?;
+        Ok(claimed.is_some())
+    }
+
+    // Pushes the lease forward, false once another replica took it over
+    pub async fn renew_job(
+        &self,
+        name: &str,
+        holder: &str,
+        lease: Duration,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE job_leases SET locked_until = NOW() + make_interval(secs => $3)
+             WHERE name = $1 AND holder = $2 AND locked_until IS NOT NULL",
+        )
+        .bind(name)
+        .bind(holder)
+        .bind(lease.as_secs_f64())
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn release_job(&self, name: &str, holder: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE job_leases SET locked_until = NULL WHERE name = $1 AND holder = $2")
+            .bind(name)
+            .bind(holder)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Namespace and level of every level with an accepted thumbnail
+    pub async fn get_live_thumbnail_levels(&self) -> Result<Vec<(String, LevelId)>, sqlx::Error> {
+        sqlx::query_as::<_, (String, LevelId)>(
+            "SELECT DISTINCT namespace, level_id FROM uploads
+             WHERE status = 'accepted'
+             ORDER BY namespace, level_id",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // None while another migration is running. One whose instance stopped updating it for
+    // STALE_MIGRATION_MINUTES is taken to have died with it
+    pub async fn start_storage_migration(
+        &self,
+        destination: &str,
+        started_by: UserId,
+    ) -> Result<Option<StorageMigration>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "UPDATE storage_migrations
+             SET status = 'failed', error = 'Interrupted', finished_at = NOW()
+             WHERE status = 'running' AND updated_at < NOW() - make_interval(mins => $1)",
+        )
+        .bind(STALE_MIGRATION_MINUTES)
+        .execute(&mut *transaction)
+        .await?;
+
+        let migration = sqlx::query_as::<_, StorageMigration>(
+            "INSERT INTO storage_migrations (destination, started_by)
+             VALUES ($1, $2)
+             ON CONFLICT (status) WHERE status = 'running' DO NOTHING
+             RETURNING *",
+        )
+        .bind(destination)
+        .bind(started_by)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(migration)
+    }
+
+    pub async fn update_storage_migration(
+        &self,
+        id: i64,
+        progress: &storage::MigrationProgress,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE storage_migrations
+             SET total = $2, copied = $3, verified = $4, skipped = $5, failed = $6, updated_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(progress.total)
+        .bind(progress.copied)
+        .bind(progress.verified)
+        .bind(progress.skipped)
+        .bind(progress.failed)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn finish_storage_migration(
+        &self,
+        id: i64,
+        status: StorageMigrationStatus,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE storage_migrations
+             SET status = $2, error = $3, updated_at = NOW(), finished_at = NOW()
+             WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_latest_storage_migration(
+        &self,
+    ) -> Result<Option<StorageMigration>, sqlx::Error> {
+        sqlx::query_as::<_, StorageMigration>(
+            "SELECT * FROM storage_migrations ORDER BY id DESC LIMIT 1",
+        )
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    // A newer change to the same path replaces the queued one
+    pub async fn queue_mirror(&self, path: &str, hash: Option<&str>) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO storage_mirror_queue (path, hash) VALUES ($1, $2)
+             ON CONFLICT (path) DO UPDATE
+             SET hash = EXCLUDED.hash, attempts = 0, last_error = NULL, queued_at = NOW()",
+        )
+        .bind(path)
+        .bind(hash)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_queued_mirrors(&self, limit: i64) -> Result<Vec<QueuedMirror>, sqlx::Error> {
+        sqlx::query_as::<_, QueuedMirror>(
+            "SELECT path, hash FROM storage_mirror_queue ORDER BY queued_at LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // Only clears the row when nothing newer was queued for the path in the meantime
+    pub async fn finish_mirror(&self, mirror: &QueuedMirror) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "DELETE FROM storage_mirror_queue WHERE path = $1 AND hash IS NOT DISTINCT FROM $2",
+        )
+        .bind(&mirror.path)
+        .bind(&mirror.hash)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn fail_mirror(&self, mirror: &QueuedMirror, error: &str) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE storage_mirror_queue SET attempts = attempts + 1, last_error = $3
+             WHERE path = $1 AND hash IS NOT DISTINCT FROM $2",
+        )
+        .bind(&mirror.path)
+        .bind(&mirror.hash)
+        .bind(error)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // Every accepted upload, live or replaced, for the backup snapshots
+    pub async fn get_backup_entries(&self) -> Result<Vec<BackupEntry>, sqlx::Error> {
+        sqlx::query_as::<_, BackupEntry>(
+            "SELECT uploads.id, namespace, level_id, users.account_id, users.username,
+                    upload_time, accepted_time, accepted_by, license, credit,
+                    width, height, file_size, encoding, encoder_version
+             FROM uploads
+             JOIN users ON users.id = uploads.user_id
+             WHERE status = 'accepted'
+             ORDER BY uploads.id",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // Brings back the row of a backed up upload as accepted, recreating it if it's gone.
+    // Deciding moderators that no longer exist are left out. With take_down_after, whatever
+    // went live for the level after that point is taken down in the same transaction. None
+    // when the upload ID belongs to another level in this database, nothing is changed then
+    pub async fn restore_upload(
+        &self,
+        entry: &BackupEntry,
+        user_id: UserId,
+        image_path: &str,
+        take_down_after: Option<DateTime<Utc>>,
+    ) -> Result<Option<u64>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let mut taken_down = 0;
+        if let Some(after) = take_down_after {
+            let (live, removed) = moderation::removal();
+            taken_down = sqlx::query(
+                "UPDATE uploads SET status = $5
+                 WHERE namespace = $1 AND level_id = $2 AND status = $4 AND accepted_time > $3",
+            )
+            .bind(&entry.namespace)
+            .bind(entry.level_id)
+            .bind(after)
+            .bind(live)
+            .bind(removed)
+            .execute(&mut *transaction)
+            .await?
+            .rows_affected();
+        }
+
+        let restored = sqlx::query(
+            "INSERT INTO uploads (id, namespace, level_id, user_id, image_path, upload_time, accepted_time,
+                                  accepted_by, license, credit, width, height, file_size, encoding,
+                                  encoder_version, status, processing_status, backed_up_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, (SELECT id FROM users WHERE id = $8), $9, $10,
+                     $11, $12, $13, $14, $15, 'accepted', 'live', NOW())
+             ON CONFLICT (id) DO UPDATE
+             SET status = 'accepted', processing_status = 'live', archive_path = NULL, archived_at = NULL
+             WHERE uploads.namespace = EXCLUDED.namespace AND uploads.level_id = EXCLUDED.level_id",
+        )
+        .bind(entry.id)
+        .bind(&entry.namespace)
+        .bind(entry.level_id)
+        .bind(user_id)
+        .bind(image_path)
+        .bind(entry.upload_time)
+        .bind(entry.accepted_time)
+        .bind(entry.accepted_by)
+        .bind(&entry.license)
+        .bind(&entry.credit)
+        .bind(entry.width)
+        .bind(entry.height)
+        .bind(entry.file_size)
+        .bind(&entry.encoding)
+        .bind(entry.encoder_version)
+        .execute(&mut *transaction)
+        .await?;
+        if restored.rows_affected() == 0 {
+            return Ok(None);
+        }
+        transaction.commit().await?;
+        Ok(Some(taken_down))
+    }
+
+    // Restored rows keep their old IDs, new uploads have to be numbered after them
+    pub async fn sync_upload_id_sequence(&self) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "SELECT setval(pg_get_serial_sequence('uploads', 'id'), GREATEST(MAX(id), 1)) FROM uploads",
+        )
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_upload_decision(
+        &self,
+        id: i64,
+    ) -> Result<Option<UploadDecision>, sqlx::Error> {
+        sqlx::query_as::<_, UploadDecision>(
+            "SELECT id, namespace, level_id, user_id, status, accepted_by, accepted_time, archive_path
+             FROM uploads WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    // Whether something else went live for the level after the given upload was accepted
+    pub async fn is_replaced(&self, upload: &UploadDecision) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(
+                 SELECT 1 FROM uploads
+                 WHERE namespace = $1 AND level_id = $2 AND status = 'accepted' AND id <> $3
+                   AND accepted_time >= $4
+             )",
+        )
+        .bind(&upload.namespace)
+        .bind(upload.level_id)
+        .bind(upload.id)
+        .bind(upload.accepted_time)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // Puts a decided upload back in the queue, false if its status changed in the meantime
+    pub async fn reopen_upload(&self, id: i64, status: UploadStatus) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE uploads
+             SET status = 'pending', accepted_time = NULL, accepted_by = NULL, reason = NULL,
+                 archive_path = NULL, archived_at = NULL,
+                 processing_status = CASE WHEN status = 'accepted' THEN 'queued_for_review'
+                                          ELSE processing_status END
+             WHERE id = $1 AND status = $2",
+        )
+        .bind(id)
+        .bind(status)
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_pending_upload(&self, id: i64) -> Result<PendingUpload, sqlx::Error> {
+        sqlx::query_as::<_, PendingUpload>(
+            "SELECT uploads.id, user_id, users.username, namespace, level_id, status, upload_time,
+                    image_path, reason, accepted_time, accepted_by,
+                    decided_by.username AS accepted_by_username, scan_verdict, scan_score, scan_label,
+                    assigned_to, attested, source
+             FROM uploads
+             LEFT JOIN users ON users.id = user_id
+             LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+             WHERE status = 'pending' AND uploads.id = $1",
+        )
+        .bind(id)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_decided_uploads(
+        &self,
+        level_id: Option<LevelId>,
+        user_id: Option<UserId>,
+        before: Option<i64>,
+        limit: i64,
+    ) -> Result<Vec<DecidedUpload>, sqlx::Error> {
+        sqlx::query_as::<_, DecidedUpload>(
+            "SELECT uploads.id, user_id, users.username, level_id, status, upload_time, image_path,
+                    reason, accepted_time AS decided_at, accepted_by AS decided_by,
+                    decided_by.username AS decided_by_username
+             FROM uploads
+             LEFT JOIN users ON users.id = user_id
+             LEFT JOIN users AS decided_by ON decided_by.id = uploads.accepted_by
+             WHERE status IN ('accepted', 'rejected')
+               AND ($1::BIGINT IS NULL OR level_id = $1)
+               AND ($2::BIGINT IS NULL OR user_id = $2)
+               AND ($3::BIGINT IS NULL OR uploads.id < $3)
+             ORDER BY uploads.id DESC
+             LIMIT $4",
+        )
+        .bind(level_id)
+        .bind(user_id)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_accepted_level_ids(&self) -> Result<Vec<LevelId>, sqlx::Error> {
+        sqlx::query_scalar::<_, LevelId>(
+            "SELECT DISTINCT level_id FROM uploads WHERE namespace = 'default' AND status = 'accepted'",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn accept_upload(
+        &self,
+        id: i64,
+        accepted_by: UserId,
+        reason: Option<String>,
+        accept: bool,
+    ) -> Result<bool, sqlx::Error> {
+        // false when another moderator decided the upload first
+        let result = sqlx::query(
+            "UPDATE uploads
+             SET status = $1, accepted_time = $5, accepted_by = $2, reason = $3,
+                 processing_status = CASE WHEN $1 = 'accepted' THEN 'live' ELSE processing_status END
+             WHERE id = $4 AND status = 'pending'",
+        )
+        .bind(if accept { UploadStatus::Accepted } else { UploadStatus::Rejected })
+        .bind(accepted_by)
+        .bind(reason)
+        .bind(id)
+        .bind(self.now())
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn add_sync_change(
+        &self,
+        level_id: LevelId,
+        action: SyncAction,
+        content_hash: Option<&str>,
+        user_id: Option<UserId>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO sync_changes (level_id, action, content_hash, user_id) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(level_id)
+        .bind(action)
+        .bind(content_hash)
+        .bind(user_id)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_sync_changes(
+        &self,
+        since: i64,
+        limit: i64,
+    ) -> Result<Vec<SyncChange>, sqlx::Error> {
+        sqlx::query_as::<_, SyncChange>(
+            "SELECT sync_changes.id AS cursor, level_id, action, content_hash,
+                    users.account_id, users.username, created_at
+             FROM sync_changes
+             LEFT JOIN users ON users.id = sync_changes.user_id
+             WHERE sync_changes.id > $1
+             ORDER BY sync_changes.id
+             LIMIT $2",
+        )
+        .bind(since)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn set_sync_change_hash(&self, cursor: i64, hash: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE sync_changes SET content_hash = $1 WHERE id = $2")
+            .bind(hash)
+            .bind(cursor)
+            .execute(&*self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_level_by_hash(&self, hash: &str) -> Option<LevelId> {
+        sqlx::query_scalar::<_, LevelId>(
+            "SELECT level_id FROM sync_changes
+             WHERE content_hash = $1 AND action = 'accepted'
+             ORDER BY id DESC LIMIT 1",
+        )
+        .bind(hash)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    pub async fn get_webhooks(&self) -> Result<Vec<Webhook>, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks ORDER BY id")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn get_webhooks_for_event(&self, event: &str) -> Result<Vec<Webhook>, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>("SELECT * FROM webhooks WHERE $1 = ANY(events)")
+            .bind(event)
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn add_webhook(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[String],
+        created_by: UserId,
+    ) -> Result<Webhook, sqlx::Error> {
+        sqlx::query_as::<_, Webhook>(
+            "INSERT INTO webhooks (url, secret, events, created_by) VALUES ($1, $2, $3, $4) RETURNING *",
+        )
+        .bind(url)
+        .bind(secret)
+        .bind(events)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn delete_webhook(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result =
+            sqlx::query("DELETE FROM webhooks WHERE id = $1").bind(id).execute(&*self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_feature_flags(&self) -> Result<Vec<FeatureFlag>, sqlx::Error> {
+        sqlx::query_as::<_, FeatureFlag>("SELECT * FROM feature_flags ORDER BY name")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn set_feature_flag(
+        &self,
+        name: &str,
+        enabled: bool,
+        updated_by: UserId,
+    ) -> Result<FeatureFlag, sqlx::Error> {
+        sqlx::query_as::<_, FeatureFlag>(
+            "INSERT INTO feature_flags (name, enabled, updated_by) VALUES ($1, $2, $3)
+             ON CONFLICT (name) DO UPDATE
+             SET enabled = EXCLUDED.enabled, updated_by = EXCLUDED.updated_by, updated_at = CURRENT_TIMESTAMP
+             RETURNING *",
+        )
+        .bind(name)
+        .bind(enabled)
+        .bind(updated_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_upload_rules(&self) -> Result<Vec<UploadRule>, sqlx::Error> {
+        sqlx::query_as::<_, UploadRule>("SELECT * FROM upload_rules ORDER BY priority, id")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn get_enabled_upload_rules(&self) -> Result<Vec<UploadRule>, sqlx::Error> {
+        sqlx::query_as::<_, UploadRule>(
+            "SELECT * FROM upload_rules WHERE enabled ORDER BY priority, id",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn add_upload_rule(
+        &self,
+        rule: NewUploadRule<'_>,
+        created_by: UserId,
+    ) -> Result<UploadRule, sqlx::Error> {
+        sqlx::query_as::<_, UploadRule>(
+            "INSERT INTO upload_rules (name, role, condition, max_account_age_days, action, priority, enabled, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING *",
+        )
+        .bind(rule.name)
+        .bind(rule.role)
+        .bind(rule.condition)
+        .bind(rule.max_account_age_days)
+        .bind(rule.action)
+        .bind(rule.priority)
+        .bind(rule.enabled)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn update_upload_rule(
+        &self,
+        id: i64,
+        rule: NewUploadRule<'_>,
+    ) -> Result<Option<UploadRule>, sqlx::Error> {
+        sqlx::query_as::<_, UploadRule>(
+            "UPDATE upload_rules
+             SET name = $2, role = $3, condition = $4, max_account_age_days = $5, action = $6,
+                 priority = $7, enabled = $8
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(rule.name)
+        .bind(rule.role)
+        .bind(rule.condition)
+        .bind(rule.max_account_age_days)
+        .bind(rule.action)
+        .bind(rule.priority)
+        .bind(rule.enabled)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn delete_upload_rule(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM upload_rules WHERE id = $1")
+            .bind(id)
+            .execute(&*self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_collection(&self, id: i64) -> Result<Option<Collection>, sqlx::Error> {
+        sqlx::query_as::<_, Collection>("SELECT * FROM collections WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&*self.pool)
+            .await
+    }
+
+    pub async fn get_user_collections(
+        &self,
+        owner_id: UserId,
+    ) -> Result<Vec<Collection>, sqlx::Error> {
+        sqlx::query_as::<_, Collection>(
+            "SELECT * FROM collections WHERE owner_id = $1 ORDER BY updated_at DESC",
+        )
+        .bind(owner_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn count_user_collections(&self, owner_id: UserId) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM collections WHERE owner_id = $1")
+            .bind(owner_id)
+            .fetch_one(&*self.pool)
+            .await
+    }
+
+    pub async fn add_collection(
+        &self,
+        collection: NewCollection<'_>,
+        owner_id: UserId,
+    ) -> Result<Collection, sqlx::Error> {
+        sqlx::query_as::<_, Collection>(
+            "INSERT INTO collections (owner_id, name, level_ids) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(owner_id)
+        .bind(collection.name)
+        .bind(collection.level_ids)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // None when the collection doesn't exist or belongs to someone else
+    pub async fn update_collection(
+        &self,
+        id: i64,
+        owner_id: UserId,
+        collection: NewCollection<'_>,
+    ) -> Result<Option<Collection>, sqlx::Error> {
+        sqlx::query_as::<_, Collection>(
+            "UPDATE collections SET name = $3, level_ids = $4, updated_at = NOW()
+             WHERE id = $1 AND owner_id = $2 RETURNING *",
+        )
+        .bind(id)
+        .bind(owner_id)
+        .bind(collection.name)
+        .bind(collection.level_ids)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn delete_collection(&self, id: i64, owner_id: UserId) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM collections WHERE id = $1 AND owner_id = $2")
+            .bind(id)
+            .bind(owner_id)
+            .execute(&*self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_upload_freezes(&self) -> Result<Vec<UploadFreeze>, sqlx::Error> {
+        sqlx::query_as::<_, UploadFreeze>("SELECT * FROM upload_freezes ORDER BY starts_at DESC")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    // The freeze that ends last wins when several cover the same level
+    pub async fn get_active_freeze(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<Option<UploadFreeze>, sqlx::Error> {
+        sqlx::query_as::<_, UploadFreeze>(
+            "SELECT * FROM upload_freezes
+             WHERE namespace = $1 AND $2 = ANY(level_ids) AND starts_at <= $3 AND ends_at > $3
+             ORDER BY ends_at DESC LIMIT 1",
+        )
+        .bind(namespace)
+        .bind(level_id)
+        .bind(self.now())
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn add_upload_freeze(
+        &self,
+        freeze: NewUploadFreeze<'_>,
+        created_by: UserId,
+    ) -> Result<UploadFreeze, sqlx::Error> {
+        sqlx::query_as::<_, UploadFreeze>(
+            "INSERT INTO upload_freezes (namespace, name, reason, level_ids, starts_at, ends_at, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        )
+        .bind(freeze.namespace)
+        .bind(freeze.name)
+        .bind(freeze.reason)
+        .bind(freeze.level_ids)
+        .bind(freeze.starts_at)
+        .bind(freeze.ends_at)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn update_upload_freeze(
+        &self,
+        id: i64,
+        freeze: NewUploadFreeze<'_>,
+    ) -> Result<Option<UploadFreeze>, sqlx::Error> {
+        sqlx::query_as::<_, UploadFreeze>(
+            "UPDATE upload_freezes
+             SET namespace = $2, name = $3, reason = $4, level_ids = $5, starts_at = $6, ends_at = $7
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(freeze.namespace)
+        .bind(freeze.name)
+        .bind(freeze.reason)
+        .bind(freeze.level_ids)
+        .bind(freeze.starts_at)
+        .bind(freeze.ends_at)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn delete_upload_freeze(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM upload_freezes WHERE id = $1")
+            .bind(id)
+            .execute(&*self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_announcements(&self) -> Result<Vec<Announcement>, sqlx::Error> {
+        sqlx::query_as::<_, Announcement>("SELECT * FROM announcements ORDER BY starts_at DESC")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn get_active_announcements(&self) -> Result<Vec<Announcement>, sqlx::Error> {
+        sqlx::query_as::<_, Announcement>(
+            "SELECT * FROM announcements
+             WHERE starts_at <= $1 AND (ends_at IS NULL OR ends_at > $1)
+             ORDER BY starts_at DESC",
+        )
+        .bind(self.now())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn add_announcement(
+        &self,
+        announcement: NewAnnouncement<'_>,
+        created_by: UserId,
+    ) -> Result<Announcement, sqlx::Error> {
+        sqlx::query_as::<_, Announcement>(
+            "INSERT INTO announcements (title, message, severity, starts_at, ends_at, created_by)
+             VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+        )
+        .bind(announcement.title)
+        .bind(announcement.message)
+        .bind(announcement.severity)
+        .bind(announcement.starts_at.unwrap_or_else(|| self.now()))
+        .bind(announcement.ends_at)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn update_announcement(
+        &self,
+        id: i64,
+        announcement: NewAnnouncement<'_>,
+    ) -> Result<Option<Announcement>, sqlx::Error> {
+        sqlx::query_as::<_, Announcement>(
+            "UPDATE announcements
+             SET title = $2, message = $3, severity = $4, starts_at = COALESCE($5, starts_at),
+                 ends_at = $6
+             WHERE id = $1 RETURNING *",
+        )
+        .bind(id)
+        .bind(announcement.title)
+        .bind(announcement.message)
+        .bind(announcement.severity)
+        .bind(announcement.starts_at)
+        .bind(announcement.ends_at)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn delete_announcement(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM announcements WHERE id = $1")
+            .bind(id)
+            .execute(&*self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_current_tos(&self) -> Result<Option<TosVersion>, sqlx::Error> {
+        sqlx::query_as::<_, TosVersion>(
+            "SELECT * FROM tos_versions ORDER BY published_at DESC LIMIT 1",
+        )
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_tos_versions(&self) -> Result<Vec<TosVersion>, sqlx::Error> {
+        sqlx::query_as::<_, TosVersion>("SELECT * FROM tos_versions ORDER BY published_at DESC")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    // None when the version already exists, published versions are never edited
+    pub async fn publish_tos(
+        &self,
+        version: &str,
+        url: &str,
+        published_by: UserId,
+    ) -> Result<Option<TosVersion>, sqlx::Error> {
+        sqlx::query_as::<_, TosVersion>(
+            "INSERT INTO tos_versions (version, url, published_by) VALUES ($1, $2, $3)
+             ON CONFLICT (version) DO NOTHING RETURNING *",
+        )
+        .bind(version)
+        .bind(url)
+        .bind(published_by)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn has_accepted_tos(
+        &self,
+        user_id: UserId,
+        version: &str,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM tos_acceptances WHERE user_id = $1 AND version = $2)",
+        )
+        .bind(user_id)
+        .bind(version)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // Accepting again keeps the original timestamp
+    pub async fn accept_tos(
+        &self,
+        user_id: UserId,
+        version: &str,
+    ) -> Result<TosAcceptance, sqlx::Error> {
+        sqlx::query_as::<_, TosAcceptance>(
+            "INSERT INTO tos_acceptances (user_id, version) VALUES ($1, $2)
+             ON CONFLICT (user_id, version) DO UPDATE SET version = EXCLUDED.version
+             RETURNING version, accepted_at",
+        )
+        .bind(user_id)
+        .bind(version)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_user_created_at(&self, user_id: UserId) -> Option<DateTime<Utc>> {
+        sqlx::query_scalar("SELECT created_at FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    pub async fn get_settings(&self) -> Result<Vec<Setting>, sqlx::Error> {
+        sqlx::query_as::<_, Setting>("SELECT key, value FROM settings").fetch_all(&*self.pool).await
+    }
+
+    pub async fn get_role_permissions(&self) -> Result<Vec<RolePermission>, sqlx::Error> {
+        sqlx::query_as::<_, RolePermission>("SELECT role, permission FROM role_permissions")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn set_role_permissions(
+        &self,
+        role: Role,
+        permissions: &[Permission],
+    ) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query("DELETE FROM role_permissions WHERE role = $1")
+            .bind(role)
+            .execute(&mut *transaction)
+            .await?;
+        sqlx::query(
+            "INSERT INTO role_permissions (role, permission)
+             SELECT $1, permission FROM UNNEST($2::TEXT[]) AS p(permission)
+             ON CONFLICT DO NOTHING",
+        )
+        .bind(role)
+        .bind(permissions.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await
+    }
+
+    pub async fn set_settings(
+        &self,
+        settings: &[(String, String)],
+        updated_by: UserId,
+    ) -> Result<(), sqlx::Error> {
+        let (keys, values): (Vec<_>, Vec<_>) = settings.iter().cloned().unzip();
+        sqlx::query(
+            "INSERT INTO settings (key, value, updated_by)
+             SELECT key, value, $3 FROM UNNEST($1::TEXT[], $2::TEXT[]) AS s(key, value)
+             ON CONFLICT (key) DO UPDATE
+             SET value = EXCLUDED.value, updated_by = EXCLUDED.updated_by, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(keys)
+        .bind(values)
+        .bind(updated_by)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn count_recent_uploads(&self, user_id: UserId) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM uploads
+             WHERE user_id = $1 AND upload_time > $2 - INTERVAL '1 day'",
+        )
+        .bind(user_id)
+        .bind(self.now())
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn count_recent_uploads_from(
+        &self,
+        user_id: UserId,
+        source: UploadSource,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM uploads
+             WHERE user_id = $1 AND source = $2 AND upload_time > $3 - INTERVAL '1 day'",
+        )
+        .bind(user_id)
+        .bind(source)
+        .bind(self.now())
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // Uploads of the last few days by where they came from and how they ended up,
+    // uploads from before sources were recorded have none
+    pub async fn get_upload_source_counts(
+        &self,
+        days: i64,
+    ) -> Result<Vec<UploadSourceCount>, sqlx::Error> {
+        sqlx::query_as::<_, UploadSourceCount>(
+            "SELECT source, status, COUNT(*) AS uploads FROM uploads
+             WHERE upload_time > $1 - make_interval(days => $2::INT)
+             GROUP BY source, status
+             ORDER BY source NULLS LAST, status",
+        )
+        .bind(self.now())
+        .bind(days as i32)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_namespaces(&self) -> Result<Vec<Namespace>, sqlx::Error> {
+        sqlx::query_as::<_, Namespace>("SELECT * FROM namespaces ORDER BY name")
+            .fetch_all(&*self.pool)
+            .await
+    }
+
+    pub async fn namespace_exists(&self, name: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM namespaces WHERE name = $1)")
+            .bind(name)
+            .fetch_one(&*self.pool)
+            .await
+    }
+
+    pub async fn add_namespace(
+        &self,
+        name: &str,
+        display_name: &str,
+        created_by: UserId,
+    ) -> Result<Namespace, sqlx::Error> {
+        sqlx::query_as::<_, Namespace>(
+            "INSERT INTO namespaces (name, display_name, created_by) VALUES ($1, $2, $3) RETURNING *",
+        )
+        .bind(name)
+        .bind(display_name)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_namespace_role(
+        &self,
+        user_id: UserId,
+        namespace: &str,
+    ) -> Result<Option<Role>, sqlx::Error> {
+        sqlx::query_scalar::<_, Role>(
+            "SELECT role FROM namespace_roles WHERE user_id = $1 AND namespace = $2",
+        )
+        .bind(user_id)
+        .bind(namespace)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_namespace_roles(
+        &self,
+        namespace: &str,
+    ) -> Result<Vec<NamespaceRole>, sqlx::Error> {
+        sqlx::query_as::<_, NamespaceRole>(
+            "SELECT user_id, users.username, namespace_roles.role FROM namespace_roles
+             JOIN users ON users.id = namespace_roles.user_id
+             WHERE namespace = $1
+             ORDER BY user_id",
+        )
+        .bind(namespace)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // A role of None removes the user's namespace role
+    pub async fn set_namespace_role(
+        &self,
+        user_id: UserId,
+        namespace: &str,
+        role: Option<Role>,
+    ) -> Result<(), sqlx::Error> {
+        match role {
+            Some(role) => {
+                sqlx::query(
+                    "INSERT INTO namespace_roles (user_id, namespace, role) VALUES ($1, $2, $3)
+                     ON CONFLICT (user_id, namespace) DO UPDATE SET role = EXCLUDED.role",
+                )
+                .bind(user_id)
+                .bind(namespace)
+                .bind(role)
+                .execute(&*self.pool)
+                .await?
+            }
+            None => {
+                sqlx::query("DELETE FROM namespace_roles WHERE user_id = $1 AND namespace = $2")
+                    .bind(user_id)
+                    .bind(namespace)
+                    .execute(&*self.pool)
+                    .await?
+            }
+        };
+        Ok(())
+    }
+
+    pub async fn get_level_metadata(&self, level_id: LevelId) -> Option<LevelMetadata> {
+        sqlx::query_as::<_, LevelMetadata>("SELECT * FROM level_metadata WHERE level_id = $1")
+            .bind(level_id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    pub async fn set_level_metadata(
+        &self,
+        level_id: LevelId,
+        name: &str,
+        difficulty: &str,
+        stars: i32,
+        author_account_id: Option<AccountId>,
+        author: Option<&str>,
+    ) -> Result<LevelMetadata, sqlx::Error> {
+        sqlx::query_as::<_, LevelMetadata>(
+            "INSERT INTO level_metadata (level_id, name, difficulty, stars, author_account_id, author)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (level_id) DO UPDATE
+             SET name = EXCLUDED.name, difficulty = EXCLUDED.difficulty, stars = EXCLUDED.stars,
+                 author_account_id = EXCLUDED.author_account_id, author = EXCLUDED.author,
+                 fetched_at = CURRENT_TIMESTAMP
+             RETURNING *",
+        )
+        .bind(level_id)
+        .bind(name)
+        .bind(difficulty)
+        .bind(stars)
+        .bind(author_account_id)
+        .bind(author)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // Only levels with a live thumbnail in the main game, matched on level or creator name
+    pub async fn search_levels(
+        &self,
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<LevelSearchResult>, sqlx::Error> {
+        let pattern =
+            format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        sqlx::query_as::<_, LevelSearchResult>(
+            "SELECT level_id, name, author, difficulty, stars,
+                    GREATEST(similarity(name, $1), COALESCE(similarity(author, $1), 0)) AS score
+             FROM level_metadata
+             WHERE (name % $1 OR author % $1 OR name ILIKE $2 OR author ILIKE $2)
+               AND EXISTS (SELECT 1 FROM uploads
+                           WHERE uploads.namespace = 'default' AND uploads.level_id = level_metadata.level_id
+                             AND uploads.status = 'accepted')
+             ORDER BY score DESC, level_id
+             LIMIT $3 OFFSET $4",
+        )
+        .bind(query)
+        .bind(pattern)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn add_thumbnail_views(&self, views: &[(LevelId, i64)]) -> Result<(), sqlx::Error> {
+        let (level_ids, counts): (Vec<LevelId>, Vec<i64>) = views.iter().cloned().unzip();
+        sqlx::query(
+            "INSERT INTO thumbnail_views (level_id, day, views)
+             SELECT level_id, $3, views FROM UNNEST($1::BIGINT[], $2::BIGINT[]) AS v(level_id, views)
+             ON CONFLICT (level_id, day) DO UPDATE SET views = thumbnail_views.views + EXCLUDED.views",
+        )
+        .bind(level_ids)
+        .bind(counts)
+        .bind(self.today())
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn add_api_usage(&self, usage: &[(UserId, i64, i64)]) -> Result<(), sqlx::Error> {
+        let user_ids: Vec<UserId> = usage.iter().map(|u| u.0).collect();
+        let requests: Vec<i64> = usage.iter().map(|u| u.1).collect();
+        let upload_bytes: Vec<i64> = usage.iter().map(|u| u.2).collect();
+        sqlx::query(
+            "INSERT INTO api_usage (user_id, day, requests, upload_bytes)
+             SELECT user_id, $4, requests, upload_bytes
+             FROM UNNEST($1::BIGINT[], $2::BIGINT[], $3::BIGINT[]) AS u(user_id, requests, upload_bytes)
+             WHERE EXISTS (SELECT 1 FROM users WHERE users.id = u.user_id)
+             ON CONFLICT (user_id, day) DO UPDATE
+             SET requests = api_usage.requests + EXCLUDED.requests,
+                 upload_bytes = api_usage.upload_bytes + EXCLUDED.upload_bytes",
+        )
+        .bind(user_ids)
+        .bind(requests)
+        .bind(upload_bytes)
+        .bind(self.today())
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_api_usage(
+        &self,
+        user_id: UserId,
+        days: i64,
+    ) -> Result<Vec<DailyUsage>, sqlx::Error> {
+        sqlx::query_as::<_, DailyUsage>(
+            "SELECT day, requests, upload_bytes FROM api_usage
+             WHERE user_id = $1 AND day > $3 - $2::INT
+             ORDER BY day",
+        )
+        .bind(user_id)
+        .bind(days as i32)
+        .bind(self.today())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn count_active_days(&self, user_id: UserId, days: i64) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM api_usage WHERE user_id = $1 AND day > $3 - $2::INT",
+        )
+        .bind(user_id)
+        .bind(days as i32)
+        .bind(self.today())
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_level_views(
+        &self,
+        level_id: LevelId,
+        days: i64,
+    ) -> Result<Vec<DailyViews>, sqlx::Error> {
+        sqlx::query_as::<_, DailyViews>(
+            "SELECT day, views FROM thumbnail_views
+             WHERE level_id = $1 AND day > $3 - $2::INT
+             ORDER BY day",
+        )
+        .bind(level_id)
+        .bind(days as i32)
+        .bind(self.today())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_top_levels(
+        &self,
+        days: i64,
+        limit: i64,
+    ) -> Result<Vec<LevelViews>, sqlx::Error> {
+        sqlx::query_as::<_, LevelViews>(
+            "SELECT level_id, SUM(views)::BIGINT AS views FROM thumbnail_views
+             WHERE day > $3 - $1::INT
+             GROUP BY level_id
+             ORDER BY views DESC
+             LIMIT $2",
+        )
+        .bind(days as i32)
+        .bind(limit)
+        .bind(self.today())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // Fuzzy match on both names, substring matches are included even when too short to score well
+    pub async fn search_users(
+        &self,
+        query: &str,
+        role: Option<Role>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<UserSearchResult>, sqlx::Error> {
+        let pattern =
+            format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        sqlx::query_as::<_, UserSearchResult>(
+            "SELECT id, account_id, username, discord_username, role,
+                    GREATEST(similarity(username, $1), COALESCE(similarity(discord_username, $1), 0)) AS score
+             FROM users
+             WHERE (username % $1 OR discord_username % $1
+                    OR username ILIKE $2 OR discord_username ILIKE $2)
+               AND ($3::TEXT IS NULL OR role = $3)
+             ORDER BY score DESC, id
+             LIMIT $4 OFFSET $5",
+        )
+        .bind(query)
+        .bind(pattern)
+        .bind(role)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn record_user_ip(
+        &self,
+        user_id: UserId,
+        ip: &str,
+        action: IpAction,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO user_ips (user_id, ip, action) VALUES ($1, $2::TEXT::INET, $3)
+             ON CONFLICT (user_id, ip, action) DO UPDATE
+             SET last_seen = CURRENT_TIMESTAMP, count = user_ips.count + 1",
+        )
+        .bind(user_id)
+        .bind(ip)
+        .bind(action)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_user_ips(&self, user_id: UserId) -> Result<Vec<UserIp>, sqlx::Error> {
+        sqlx::query_as::<_, UserIp>(
+            "SELECT host(ip) AS ip, action, first_seen, last_seen, count FROM user_ips
+             WHERE user_id = $1 ORDER BY last_seen DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // Rejected uploads that were sent from the address
+    pub async fn count_recent_rejections_from_ip(
+        &self,
+        ip: &str,
+        days: i64,
+    ) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM uploads
+             WHERE status = 'rejected'
+               AND accepted_time > $3 - make_interval(days => $2::INT)
+               AND ip = $1::TEXT::INET",
+        )
+        .bind(ip)
+        .bind(days as i32)
+        .bind(self.now())
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    // Other accounts that logged in or uploaded from any address the user did
+    pub async fn get_alt_accounts(&self, user_id: UserId) -> Result<Vec<AltAccount>, sqlx::Error> {
+        sqlx::query_as::<_, AltAccount>(
+            "SELECT users.id, users.account_id, users.username, users.role,
+                    ARRAY_AGG(DISTINCT host(other.ip)) AS shared_ips,
+                    MAX(other.last_seen) AS last_seen
+             FROM user_ips AS own
+             JOIN user_ips AS other ON other.ip = own.ip AND other.user_id <> own.user_id
+             JOIN users ON users.id = other.user_id
+             WHERE own.user_id = $1
+             GROUP BY users.id
+             ORDER BY COUNT(DISTINCT other.ip) DESC, last_seen DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_ip_bans(&self) -> Result<Vec<IpBan>, sqlx::Error> {
+        sqlx::query_as::<_, IpBan>(
+            "SELECT id, ip_range::TEXT AS ip_range, reason, created_by, created_at, expires_at
+             FROM ip_bans ORDER BY id",
+        )
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_active_ip_bans(&self) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT ip_range::TEXT FROM ip_bans
+             WHERE expires_at IS NULL OR expires_at > $1",
+        )
+        .bind(self.now())
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn add_ip_ban(
+        &self,
+        ip_range: &str,
+        reason: Option<&str>,
+        expires_at: Option<DateTime<Utc>>,
+        created_by: UserId,
+    ) -> Result<IpBan, sqlx::Error> {
+        sqlx::query_as::<_, IpBan>(
+            "INSERT INTO ip_bans (ip_range, reason, expires_at, created_by)
+             VALUES ($1::TEXT::CIDR, $2, $3, $4)
+             RETURNING id, ip_range::TEXT AS ip_range, reason, created_by, created_at, expires_at",
+        )
+        .bind(ip_range)
+        .bind(reason)
+        .bind(expires_at)
+        .bind(created_by)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn add_takedown_request(
+        &self,
+        request: NewTakedownRequest<'_>,
+    ) -> Result<TakedownRequest, sqlx::Error> {
+        sqlx::query_as::<_, TakedownRequest>(&format!(
+            "INSERT INTO takedown_requests (namespace, level_id, claimant_name, claimant_contact, reason, ip)
+             VALUES ($1, $2, $3, $4, $5, $6::TEXT::INET) RETURNING {}",
+            TAKEDOWN_COLUMNS
+        ))
+        .bind(request.namespace)
+        .bind(request.level_id)
+        .bind(request.claimant_name)
+        .bind(request.claimant_contact)
+        .bind(request.reason)
+        .bind(request.ip)
+        .fetch_one(&*self.pool)
+        .await
+    }
+
+    pub async fn get_takedown_requests(
+        &self,
+        status: Option<TakedownStatus>,
+    ) -> Result<Vec<TakedownRequest>, sqlx::Error> {
+        sqlx::query_as::<_, TakedownRequest>(&format!(
+            "SELECT {} FROM takedown_requests
+             WHERE $1::TEXT IS NULL OR status = $1
+             ORDER BY created_at",
+            TAKEDOWN_COLUMNS
+        ))
+        .bind(status)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    pub async fn get_takedown_request(&self, id: i64) -> Option<TakedownRequest> {
+        sqlx::query_as::<_, TakedownRequest>(&format!(
+            "SELECT {} FROM takedown_requests WHERE id = $1",
+            TAKEDOWN_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await
+        .ok()?
+    }
+
+    // None when the request was already resolved
+    pub async fn resolve_takedown_request(
+        &self,
+        id: i64,
+        status: TakedownStatus,
+        resolved_by: UserId,
+    ) -> Result<Option<TakedownRequest>, sqlx::Error> {
+        sqlx::query_as::<_, TakedownRequest>(&format!(
+            "UPDATE takedown_requests SET status = $2, resolved_by = $3, resolved_at = NOW()
+             WHERE id = $1 AND status = 'open' RETURNING {}",
+            TAKEDOWN_COLUMNS
+        ))
+        .bind(id)
+        .bind(status)
+        .bind(resolved_by)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn add_takedown_audit(
+        &self,
+        takedown_id: i64,
+        admin_id: UserId,
+        action: &str,
+        note: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO takedown_audit (takedown_id, admin_id, action, note) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(takedown_id)
+        .bind(admin_id)
+        .bind(action)
+        .bind(note)
+        .execute(&*self.pool)
+        .await?;
+        Ok(())
+    }
+
+    // None when the user already has an application waiting
+    pub async fn add_verified_application(
+        &self,
+        user_id: UserId,
+        application: NewVerifiedApplication<'_>,
+    ) -> Result<Option<VerifiedApplication>, sqlx::Error> {
+        sqlx::query_as::<_, VerifiedApplication>(&format!(
+            "WITH a AS (
+                 INSERT INTO verified_applications (user_id, links, message) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id) WHERE status = 'pending' DO NOTHING RETURNING *
+             )
+             SELECT {} FROM a JOIN users u ON u.id = a.user_id",
+            APPLICATION_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(application.links)
+        .bind(application.message)
+        .fetch_optional(&*self.pool)
+        .await
+    }
+
+    pub async fn get_verified_applications(
+        &self,
+        status: Option<ApplicationStatus>,
+    ) -> Result<Vec<VerifiedApplication>, sqlx::Error> {
+        sqlx::query_as::<_, VerifiedApplication>(&format!(
+            "SELECT {} FROM verified_applications a JOIN users u ON u.id = a.user_id
+             WHERE $1::TEXT IS NULL OR a.status = $1
+             ORDER BY a.created_at",
+            APPLICATION_COLUMNS
+        ))
+        .bind(status)
+        .fetch_all(&*self.pool)
+        .await
+    }
+
+    // Approving only promotes plain users, so it can't demote anyone who was given more meanwhile.
+    // None when the application doesn't exist or was already reviewed
+    pub async fn resolve_verified_application(
+        &self,
+        id: i64,
+        status: ApplicationStatus,
+        reviewed_by: UserId,
+        note: Option<&str>,
+    ) -> Result<Option<VerifiedApplication>, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let application = sqlx::query_as::<_, VerifiedApplication>(&format!(
+            "WITH a AS (
+                 UPDATE verified_applications
+                 SET status = $2, reviewed_by = $3, review_note = $4, reviewed_at = NOW()
+                 WHERE id = $1 AND status = 'pending' RETURNING *
+             )
+             SELECT {} FROM a JOIN users u ON u.id = a.user_id",
+            APPLICATION_COLUMNS
+        ))
+        .bind(id)
+        .bind(status)
+        .bind(reviewed_by)
+        .bind(note)
+        .fetch_optional(&mut *transaction)
+        .await?;
+
+        if let Some(application) = &application
+            && application.status == ApplicationStatus::Approved
+        {
+            sqlx::query("UPDATE users SET role = 'verified' WHERE id = $1 AND role = 'user'")
+                .bind(application.user_id)
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(application)
+    }
+
+    // Marks every accepted upload of the level as removed, returns how many there were
+    pub async fn remove_thumbnail(
+        &self,
+        namespace: &str,
+        level_id: LevelId,
+    ) -> Result<u64, sqlx::Error> {
+        let (live, removed) = moderation::removal();
+        let result = sqlx::query(
+            "UPDATE uploads SET status = $4
+             WHERE namespace = $1 AND level_id = $2 AND status = $3",
+        )
+        .bind(namespace)
+        .bind(level_id)
+        .bind(live)
+        .bind(removed)
+        .execute(&*self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Takes down a single upload, e.g. one whose file is gone
+    pub async fn remove_upload(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let (live, removed) = moderation::removal();
+        let result = sqlx::query("UPDATE uploads SET status = $3 WHERE id = $1 AND status = $2")
+            .bind(id)
+            .bind(live)
+            .bind(removed)
+            .execute(&*self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn delete_ip_ban(&self, id: i64) -> Result<bool, sqlx::Error> {
+        let result =
+            sqlx::query("DELETE FROM ip_bans WHERE id = $1").bind(id).execute(&*self.pool).await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn get_user_stats(&self, id: UserId) -> Option<UserStats> {
+        sqlx::query_as::<_, UserStats>(USER_STATS_QUERY)
+            .bind(id)
+            .fetch_optional(&*self.pool)
+            .await
+            .ok()?
+    }
+
+    // What a moderator wants to know about an uploader, fetched in one go for the review screen
+    pub async fn get_uploader_summary(
+        &self,
+        user_id: UserId,
+        active_window_days: i64,
+        rejection_limit: i64,
+    ) -> Option<UploaderSummary> {
+        sqlx::query_as::<_, UploaderSummary>(&format!(
+            "WITH stats AS ({})
+             SELECT stats.*, users.created_at,
+                    (SELECT COUNT(*) FROM uploads
+                     WHERE user_id = $1 AND status = 'rejected') AS rejected_upload_count,
+                    ARRAY(SELECT reason FROM uploads
+                          WHERE user_id = $1 AND status = 'rejected' AND reason IS NOT NULL
+                          ORDER BY accepted_time DESC NULLS LAST LIMIT $3) AS recent_rejection_reasons,
+                    (SELECT COUNT(*) FROM api_usage
+                     WHERE user_id = $1 AND day > $4 - $2::INT) AS active_days
+             FROM stats
+             JOIN users ON users.id = stats.id",
+            USER_STATS_QUERY
+        ))
+        .bind(user_id)
+        .bind(active_window_days as i32)
+        .bind(rejection_limit)
+        .bind(self.today())
         .fetch_optional(&*self.pool)
         .await
         .ok()?
@@ -346,8 +2893,8 @@ impl Database {
 
     pub async fn migrate_user_account(
         &self,
-        old_account_id: i64,
-        new_account_id: i64,
+        old_account_id: UserId,
+        new_account_id: UserId,
     ) -> Result<User, sqlx::Error> {
         sqlx::query("CALL migrate($1, $2)")
             .bind(new_account_id)
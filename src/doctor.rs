@@ -0,0 +1,444 @@
+use crate::routes::thumbnail;
+use crate::{card, namespace, outbound, paths, quarantine, scanner};
+use reqwest::StatusCode;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::Duration;
+
+// the JWT_SECRET shipped in .env.example, a deployment still using it signs sessions anyone can forge
+const EXAMPLE_JWT_SECRET: &str = "MySuperSecretJWTSecret1234567890";
+const MIN_JWT_SECRET_LENGTH: usize = 32;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Display) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "[{:>4}] {}: {}", label, self.name, self.detail)
+    }
+}
+
+fn var(name: &str) -> Option<String> {
+    dotenv::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+fn is_url(value: &str) -> bool {
+    reqwest::Url::parse(value).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+pub fn jwt_secret(secret: Option<&str>) -> Check {
+    let name = "jwt secret";
+    let Some(secret) = secret else {
+        return Check::new(name, Status::Fail, "JWT_SECRET is not set, sessions can't be signed");
+    };
+    if secret == EXAMPLE_JWT_SECRET {
+        return Check::new(
+            name,
+            Status::Fail,
+            "JWT_SECRET is the example value, generate one with `openssl rand -hex 32`",
+        );
+    }
+    if secret.len() < MIN_JWT_SECRET_LENGTH {
+        return Check::new(
+            name,
+            Status::Warn,
+            format!(
+                "JWT_SECRET is {} characters, use at least {} random ones",
+                secret.len(),
+                MIN_JWT_SECRET_LENGTH
+            ),
+        );
+    }
+    // a long run of the same few characters is as guessable as a short secret
+    let distinct = secret.chars().collect::<std::collections::HashSet<_>>().len();
+    if distinct < 10 {
+        return Check::new(
+            name,
+            Status::Warn,
+            format!("JWT_SECRET only uses {} distinct characters, use a random one", distinct),
+        );
+    }
+    Check::new(name, Status::Ok, format!("{} characters", secret.len()))
+}
+
+async fn database() -> Vec<Check> {
+    let Some(url) = var("DATABASE_URL") else {
+        return vec![Check::new("database", Status::Fail, "DATABASE_URL is not set")];
+    };
+    let options = match url.parse::<PgConnectOptions>() {
+        Ok(options) => options,
+        Err(e) => {
+            return vec![Check::new(
+                "database",
+                Status::Fail,
+                format!("DATABASE_URL isn't a valid Postgres URL: {}", e),
+            )];
+        }
+    };
+
+    // connecting without Database::connect, which would apply the migrations it checks for
+    let pool = match PgPoolOptions::new()
+        .max_connections(1)
+        .acquire_timeout(CONNECT_TIMEOUT)
+        .connect_with(options)
+        .await
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            return vec![Check::new(
+                "database",
+                Status::Fail,
+                format!("Can't connect to {}: {}", redact(&url), e),
+            )];
+        }
+    };
+
+    let connected = Check::new("database", Status::Ok, format!("connected to {}", redact(&url)));
+    let migrations = migrations(&pool).await;
+    pool.close().await;
+    vec![connected, migrations]
+}
+
+// Credentials in the URL stay out of the output
+fn redact(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut url) => {
+            if url.password().is_some() {
+                let _ = url.set_password(Some("***"));
+            }
+            url.to_string()
+        }
+        Err(_) => "DATABASE_URL".to_string(),
+    }
+}
+
+async fn migrations(pool: &sqlx::PgPool) -> Check {
+    let name = "migrations";
+    let applied: Vec<(i64, bool, Vec<u8>)> =
+        match sqlx::query_as("SELECT version, success, checksum FROM _sqlx_migrations")
+            .fetch_all(pool)
+            .await
+        {
+            Ok(applied) => applied,
+            // a fresh database has no migrations table yet
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42P01") => Vec::new(),
+            Err(e) => return Check::new(name, Status::Fail, format!("Can't read them: {}", e)),
+        };
+    let applied: HashMap<i64, (bool, Vec<u8>)> = applied
+        .into_iter()
+        .map(|(version, success, checksum)| (version, (success, checksum)))
+        .collect();
+
+    let migrator = sqlx::migrate!("./migrations");
+    let known: Vec<_> = migrator.iter().filter(|m| !m.migration_type.is_down_migration()).collect();
+
+    if let Some((version, _)) = applied.iter().find(|(_, (success, _))| !success) {
+        return Check::new(
+            name,
+            Status::Fail,
+            format!("migration {} failed part way, fix the schema by hand and rerun it", version),
+        );
+    }
+    if let Some(changed) =
+        known.iter().find(|m| applied.get(&m.version).is_some_and(|(_, sum)| *sum != *m.checksum))
+    {
+        return Check::new(
+            name,
+            Status::Fail,
+            format!("migration {} was edited after it was applied", changed.version),
+        );
+    }
+    let unknown = applied.keys().filter(|v| !known.iter().any(|m| m.version == **v)).count();
+    if unknown > 0 {
+        return Check::new(
+            name,
+            Status::Fail,
+            format!(
+                "{} applied migrations are unknown to this build, it's older than the database",
+                unknown
+            ),
+        );
+    }
+
+    let pending = known.iter().filter(|m| !applied.contains_key(&m.version)).count();
+    match pending {
+        0 => Check::new(name, Status::Ok, format!("all {} applied", known.len())),
+        pending => Check::new(
+            name,
+            Status::Warn,
+            format!("{} pending, they're applied by `migrate` or on the next start", pending),
+        ),
+    }
+}
+
+// Writes and removes a probe file in every directory the server stores files in
+async fn storage() -> Vec<Check> {
+    let mut failures = Vec::new();
+    let dirs = [
+        paths::thumbnail_dir(namespace::DEFAULT),
+        paths::upload_dir(namespace::DEFAULT),
+        paths::variant_root(namespace::DEFAULT),
+        paths::rejected_dir(namespace::DEFAULT),
+        paths::AUTO_DIR.to_string(),
+        paths::AVATAR_DIR.to_string(),
+        paths::EXPERIMENT_DIR.to_string(),
+        paths::QUARANTINE_DIR.to_string(),
+    ];
+    for dir in &dirs {
+        let probe = paths::local(format!("{}/.doctor", dir));
+        let result = async {
+            tokio::fs::create_dir_all(paths::local(dir)).await?;
+            tokio::fs::write(&probe, b"ok").await?;
+            tokio::fs::remove_file(&probe).await
+        }
+        .await;
+        if let Err(e) = result {
+            failures.push(format!("{}: {}", dir, e));
+        }
+    }
+
+    match failures.is_empty() {
+        true => {
+            vec![Check::new("storage", Status::Ok, format!("{} directories writable", dirs.len()))]
+        }
+        false => failures
+            .into_iter()
+            .map(|failure| {
+                Check::new("storage", Status::Fail, format!("not writable, {}", failure))
+            })
+            .collect(),
+    }
+}
+
+// A variable that takes one of a few fixed values, unset leaves the feature at its default
+pub fn one_of(name: &'static str, var: &str, value: Option<&str>, allowed: &[&str]) -> Check {
+    match value {
+        None => Check::new(name, Status::Ok, "not set"),
+        Some(value) if allowed.contains(&value) => Check::new(name, Status::Ok, value),
+        Some(value) => Check::new(
+            name,
+            Status::Fail,
+            format!("{} {:?} isn't one of {}", var, value, allowed.join(", ")),
+        ),
+    }
+}
+
+fn home_url() -> Check {
+    match var("HOME_URL") {
+        Some(url) if is_url(&url) => Check::new("home url", Status::Ok, url),
+        Some(url) => {
+            Check::new("home url", Status::Fail, format!("HOME_URL {} isn't an http(s) URL", url))
+        }
+        None => Check::new("home url", Status::Fail, "HOME_URL is not set, links and logins break"),
+    }
+}
+
+async fn argon(online: bool) -> Check {
+    let name = "argon";
+    let base_url = var("ARGON_BASE_URL");
+    if base_url.is_none() && var("ARGON_INSTANCE").is_some() {
+        return Check::new(
+            name,
+            Status::Warn,
+            "ARGON_INSTANCE is set but ignored, the server reads ARGON_BASE_URL",
+        );
+    }
+    let base_url = base_url.unwrap_or_else(|| "https://argon.globed.dev/v1".to_string());
+    if !is_url(&base_url) {
+        return Check::new(name, Status::Fail, format!("ARGON_BASE_URL {} isn't a URL", base_url));
+    }
+    if !online {
+        return Check::new(name, Status::Ok, base_url);
+    }
+
+    // any answer means the instance is there, it has no endpoint that works without a token
+    match outbound::ARGON.send(outbound::ARGON.get(&base_url)).await {
+        Ok(response) if response.status().is_server_error() => Check::new(
+            name,
+            Status::Warn,
+            format!("{} answered with {}", base_url, response.status()),
+        ),
+        Ok(_) => Check::new(name, Status::Ok, format!("{} is reachable", base_url)),
+        Err(e) => Check::new(name, Status::Fail, format!("Can't reach {}: {}", base_url, e)),
+    }
+}
+
+async fn discord(online: bool) -> Check {
+    let name = "discord";
+    let (client_id, secret) = match (var("DISCORD_CLIENT_ID"), var("DISCORD_CLIENT_SECRET")) {
+        (Some(id), Some(secret)) => (id, secret),
+        (None, None) => {
+            return Check::new(
+                name,
+                Status::Warn,
+                "DISCORD_CLIENT_ID/SECRET not set, Discord login fails",
+            );
+        }
+        (Some(_), None) => {
+            return Check::new(name, Status::Fail, "DISCORD_CLIENT_SECRET is missing");
+        }
+        (None, Some(_)) => return Check::new(name, Status::Fail, "DISCORD_CLIENT_ID is missing"),
+    };
+    if let Some(key) = var("DISCORD_PUBLIC_KEY")
+        && hex::decode(&key).map(|key| key.len()) != Ok(32)
+    {
+        return Check::new(name, Status::Fail, "DISCORD_PUBLIC_KEY must be 64 hex characters");
+    }
+    if !online {
+        return Check::new(name, Status::Ok, "credentials set");
+    }
+
+    // a client credentials grant checks the pair without any user involved
+    let request = outbound::DISCORD
+        .post("https://discord.com/api/oauth2/token")
+        .basic_auth(&client_id, Some(&secret))
+        .form(&[("grant_type", "client_credentials"), ("scope", "identify")]);
+    match outbound::DISCORD.send(request).await {
+        Ok(response) if response.status() == StatusCode::UNAUTHORIZED => {
+            Check::new(name, Status::Fail, "Discord rejected DISCORD_CLIENT_ID/SECRET")
+        }
+        Ok(response) if response.status().is_success() => {
+            Check::new(name, Status::Ok, "Discord accepted the credentials")
+        }
+        Ok(response) => {
+            Check::new(name, Status::Warn, format!("Discord answered with {}", response.status()))
+        }
+        Err(e) => Check::new(name, Status::Warn, format!("Can't reach Discord: {}", e)),
+    }
+}
+
+async fn cdn(online: bool) -> Check {
+    let name = "cdn";
+    let (key, zone) = match (var("CLOUDFLARE_API_KEY"), var("CLOUDFLARE_ZONE_ID")) {
+        (Some(key), Some(zone)) => (key, zone),
+        (None, None) => return Check::new(name, Status::Ok, "no Cloudflare zone, purging is off"),
+        (Some(_), None) => return Check::new(name, Status::Fail, "CLOUDFLARE_ZONE_ID is missing"),
+        (None, Some(_)) => return Check::new(name, Status::Fail, "CLOUDFLARE_API_KEY is missing"),
+    };
+    if !online {
+        return Check::new(name, Status::Ok, format!("zone {}", zone));
+    }
+
+    let request = outbound::CLOUDFLARE
+        .get(format!("https://api.cloudflare.com/client/v4/zones/{}", zone))
+        .bearer_auth(key);
+    match outbound::CLOUDFLARE.send(request).await {
+        Ok(response) if response.status().is_success() => {
+            Check::new(name, Status::Ok, format!("zone {} is accessible", zone))
+        }
+        Ok(response) if matches!(response.status().as_u16(), 400 | 401 | 403 | 404) => Check::new(
+            name,
+            Status::Fail,
+            format!("Cloudflare refused the key for zone {} ({})", zone, response.status()),
+        ),
+        Ok(response) => Check::new(
+            name,
+            Status::Warn,
+            format!("Cloudflare answered with {}", response.status()),
+        ),
+        Err(e) => Check::new(name, Status::Warn, format!("Can't reach Cloudflare: {}", e)),
+    }
+}
+
+// Online checks call the third parties with the configured credentials, the startup check
+// stays offline so a provider outage can't keep the server down
+pub fn quarantine_key(key: Option<&str>, needed: bool) -> Check {
+    let name = "quarantine key";
+    match key {
+        Some(key) if quarantine::cipher(key).is_none() => Check::new(
+            name,
+            Status::Fail,
+            "QUARANTINE_KEY must be 64 hex characters, generate one with `openssl rand -hex 32`",
+        ),
+        Some(_) => Check::new(name, Status::Ok, "quarantined files are encrypted"),
+        None if needed => Check::new(
+            name,
+            Status::Fail,
+            "QUARANTINE_KEY is not set, flagged uploads can't be stored encrypted",
+        ),
+        None => Check::new(name, Status::Ok, "nothing is quarantined without a scanner"),
+    }
+}
+
+fn card_font() -> Check {
+    let name = "card font";
+    match card::load_font() {
+        Ok(true) => Check::new(name, Status::Ok, "loaded"),
+        Ok(false) => Check::new(name, Status::Ok, "not set, share cards are off"),
+        Err(e) => Check::new(name, Status::Fail, e),
+    }
+}
+
+pub async fn run(online: bool) -> Vec<Check> {
+    let mut checks = database().await;
+    checks.extend(storage().await);
+    checks.push(jwt_secret(var("JWT_SECRET").as_deref()));
+    checks.push(home_url());
+    checks.push(one_of(
+        "accel mode",
+        "ACCEL_MODE",
+        var("ACCEL_MODE").as_deref(),
+        thumbnail::ACCEL_MODES,
+    ));
+    checks.push(one_of(
+        "hotlink mode",
+        "HOTLINK_MODE",
+        var("HOTLINK_MODE").as_deref(),
+        thumbnail::HOTLINK_MODES,
+    ));
+    checks.push(one_of("content scanner", "SCANNER", var("SCANNER").as_deref(), scanner::SCANNERS));
+    // both the hash matcher and the scanner can quarantine uploads
+    checks.push(card_font());
+    let quarantines = var("HASH_MATCH_URL").is_some() || var("SCANNER").is_some();
+    checks.push(quarantine_key(var("QUARANTINE_KEY").as_deref(), quarantines));
+    checks.push(argon(online).await);
+    checks.push(discord(online).await);
+    checks.push(cdn(online).await);
+    checks
+}
+
+// Refuses to start with a configuration that would only fail later, at some random request
+pub async fn startup() {
+    let checks = run(false).await;
+    for check in checks.iter().filter(|check| check.status == Status::Warn) {
+        tracing::warn!("{}", check);
+    }
+
+    let failed: Vec<_> = checks.iter().filter(|check| check.status == Status::Fail).collect();
+    if !failed.is_empty() {
+        for check in &failed {
+            tracing::error!("{}", check);
+            eprintln!("{}", check);
+        }
+        eprintln!("Not starting, run `doctor` for the full report");
+        std::process::exit(1);
+    }
+}
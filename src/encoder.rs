@@ -0,0 +1,18 @@
+use std::sync::LazyLock;
+use tokio::sync::Semaphore;
+
+// image decoding and encoding is CPU bound, so only a few jobs run at once on the blocking pool
+static ENCODE_PERMITS: LazyLock<Semaphore> = LazyLock::new(|| {
+    let permits = dotenv::var("ENCODE_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(4);
+    Semaphore::new(permits.max(1))
+});
+
+// Run an image job off the async runtime, waiting for a free slot in the encode pool
+pub async fn run<T: Send + 'static>(job: impl FnOnce() -> T + Send + 'static) -> Result<T, String> {
+    let _permit = ENCODE_PERMITS.acquire().await.map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(job).await.map_err(|e| format!("Task join error: {}", e))
+}
@@ -0,0 +1,95 @@
+use crate::models::{LevelId, UserId};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueueEvent {
+    // a new upload entered the pending queue
+    Submitted {
+        upload_id: i64,
+        level_id: LevelId,
+        user_id: UserId,
+    },
+    // a moderator opened an upload for review
+    Claimed {
+        upload_id: i64,
+        moderator_id: UserId,
+    },
+    // an upload was accepted or rejected
+    Decided {
+        upload_id: i64,
+        namespace: String,
+        level_id: LevelId,
+        user_id: UserId,
+        accepted: bool,
+        moderator_id: UserId,
+        reason: Option<String>,
+    },
+    // a thumbnail went live without going through the queue
+    Published {
+        upload_id: i64,
+        namespace: String,
+        level_id: LevelId,
+        user_id: UserId,
+    },
+    // a moderator took back their decision and the upload is pending again
+    Undone {
+        upload_id: i64,
+        namespace: String,
+        level_id: LevelId,
+        user_id: UserId,
+        was_accepted: bool,
+        moderator_id: UserId,
+    },
+    // a live thumbnail was taken down by an admin
+    Removed {
+        namespace: String,
+        level_id: LevelId,
+        admin_id: UserId,
+        reason: Option<String>,
+    },
+}
+
+impl QueueEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueueEvent::Submitted { .. } => "submitted",
+            QueueEvent::Claimed { .. } => "claimed",
+            QueueEvent::Decided { .. } => "decided",
+            QueueEvent::Published { .. } => "published",
+            QueueEvent::Undone { .. } => "undone",
+            QueueEvent::Removed { .. } => "removed",
+        }
+    }
+}
+
+static QUEUE_EVENTS: std::sync::LazyLock<broadcast::Sender<QueueEvent>> =
+    std::sync::LazyLock::new(|| broadcast::channel(256).0);
+
+pub fn publish(event: QueueEvent) {
+    // sending only fails when nobody is listening, which is fine
+    let _ = QUEUE_EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<QueueEvent> {
+    QUEUE_EVENTS.subscribe()
+}
+
+// Runs the handler for every event published after this call,
+// a subscriber that falls behind skips the events it missed
+pub fn listen(name: &'static str, mut handler: impl FnMut(QueueEvent)) -> impl Future<Output = ()> {
+    let mut receiver = subscribe();
+    async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => handler(event),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("{} subscriber missed {} queue events", name, missed)
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+}
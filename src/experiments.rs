@@ -0,0 +1,170 @@
+use crate::models::LevelId;
+use crate::routes::upload;
+use crate::{archive, cache_controller, database, level_info, namespace, paths, sync};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::ops::RangeInclusive;
+use std::sync::{LazyLock, Mutex, RwLock};
+use std::time::Duration;
+use tracing::error;
+
+// running experiments are picked up and view counts written back on this interval
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+// IDs of the levels that ship with the game and its spin-offs
+const OFFICIAL_LEVELS: &[RangeInclusive<i64>] = &[
+    1..=22,      // Geometry Dash
+    1001..=1003, // Meltdown
+    2001..=2010, // World
+    3001..=3001, // The Challenge
+    4001..=4003, // SubZero
+    5001..=5004, // The Tower
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Slot {
+    A, // the live thumbnail
+    B, // the challenger, a copy of an earlier accepted upload
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Slot::A => write!(f, "a"),
+            Slot::B => write!(f, "b"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Challenger {
+    pub upload_id: i64,
+    pub image_path: String,
+}
+
+// level ID to slot B, only the main game runs experiments
+static ACTIVE: LazyLock<RwLock<HashMap<LevelId, Challenger>>> = LazyLock::new(Default::default);
+static PENDING_VIEWS: LazyLock<Mutex<HashMap<(LevelId, Slot), i64>>> =
+    LazyLock::new(Default::default);
+
+pub fn is_official(level_id: LevelId) -> bool {
+    OFFICIAL_LEVELS.iter().any(|range| range.contains(&level_id.0))
+}
+
+// Experiments are for official and rated levels, the only ones with enough views to compare
+pub async fn is_eligible(db: &database::Database, level_id: LevelId) -> bool {
+    is_official(level_id)
+        || level_info::get(db, level_id).await.is_some_and(|level| level.stars > 0)
+}
+
+// The same requester always lands in the same slot of a level, without storing anything
+pub fn pick(level_id: LevelId, requester: IpAddr) -> Option<(Slot, Challenger)> {
+    let challenger = ACTIVE.read().unwrap().get(&level_id)?.clone();
+    let digest = Sha256::digest(format!("{}:{}", level_id, requester));
+    let slot = match digest[0] & 1 {
+        0 => Slot::A,
+        _ => Slot::B,
+    };
+    Some((slot, challenger))
+}
+
+pub fn record_view(level_id: LevelId, slot: Slot) {
+    if let Ok(mut views) = PENDING_VIEWS.lock() {
+        *views.entry((level_id, slot)).or_default() += 1;
+    }
+}
+
+pub async fn reload(db: &database::Database) {
+    let experiments = match db.get_experiments().await {
+        Ok(experiments) => experiments,
+        Err(e) => return error!("Failed to load thumbnail experiments: {}", e),
+    };
+
+    *ACTIVE.write().unwrap() = experiments
+        .into_iter()
+        .map(|experiment| {
+            let challenger = Challenger {
+                upload_id: experiment.upload_id,
+                image_path: experiment.image_path,
+            };
+            (experiment.level_id, challenger)
+        })
+        .collect();
+}
+
+async fn flush(db: &database::Database) {
+    let views = match PENDING_VIEWS.lock() {
+        Ok(mut views) => std::mem::take(&mut *views),
+        Err(_) => return,
+    };
+
+    let mut per_level: HashMap<LevelId, (i64, i64)> = HashMap::new();
+    for ((level_id, slot), count) in views {
+        let entry = per_level.entry(level_id).or_default();
+        match slot {
+            Slot::A => entry.0 += count,
+            Slot::B => entry.1 += count,
+        }
+    }
+
+    for (level_id, (views_a, views_b)) in per_level {
+        if let Err(e) = db.add_experiment_views(level_id, views_a, views_b).await {
+            error!("Failed to store experiment views for level {}: {}", level_id, e);
+        }
+    }
+}
+
+pub async fn watch(db: database::Database) {
+    reload(&db).await;
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        flush(&db).await;
+        reload(&db).await;
+    }
+}
+
+// Removes slot B's copy and its resized variants once an experiment is over
+pub async fn discard(level_id: LevelId) {
+    let _ = tokio::fs::remove_file(paths::local(paths::experiment_path(level_id))).await;
+    for res in ["medium", "small"] {
+        let dir = paths::experiment_variant_dir(res);
+        let _ = tokio::fs::remove_file(paths::local(paths::level_file(&dir, level_id))).await;
+    }
+}
+
+// Ends the experiment with slot B as the live thumbnail, credited to its original uploader.
+// The file is staged next to the live one and only moved over it once the experiment row is
+// gone and the upload recorded. Ok(false) when the experiment had ended already
+pub async fn promote(
+    db: &database::Database,
+    experiment: &database::ThumbnailExperiment,
+) -> Result<bool, String> {
+    let image_path = paths::canonical(&experiment.image_path).ok_or("Invalid experiment path")?;
+    let data = tokio::fs::read(paths::local(image_path)).await.map_err(|e| e.to_string())?;
+    let live_path = paths::thumbnail_path(namespace::DEFAULT, experiment.level_id);
+
+    archive::supersede(db, namespace::DEFAULT, experiment.level_id).await;
+    let staged = paths::partial_path(paths::local(&live_path));
+    tokio::fs::write(&staged, &data).await.map_err(|e| e.to_string())?;
+
+    let meta = upload::image_meta(&data);
+    let promoted = match db.promote_experiment(experiment, &live_path, &meta).await {
+        Ok(promoted) => promoted.is_some(),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged).await;
+            return Err(e.to_string());
+        }
+    };
+    if !promoted {
+        let _ = tokio::fs::remove_file(&staged).await;
+        return Ok(false);
+    }
+    tokio::fs::rename(&staged, paths::local(&live_path)).await.map_err(|e| e.to_string())?;
+
+    sync::record_accepted(db, experiment.level_id, experiment.user_id, &data).await;
+    cache_controller::replaced(db, namespace::DEFAULT, experiment.level_id).await;
+    Ok(true)
+}
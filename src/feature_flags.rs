@@ -0,0 +1,65 @@
+use crate::database;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+// how long a lookup is served from memory before the table is read again
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    CommunityVoting,
+    AutoAccept,
+    AnimatedThumbnails,
+}
+
+impl Flag {
+    pub const ALL: &[Flag] = &[Flag::CommunityVoting, Flag::AutoAccept, Flag::AnimatedThumbnails];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Flag::CommunityVoting => "community_voting",
+            Flag::AutoAccept => "auto_accept",
+            Flag::AnimatedThumbnails => "animated_thumbnails",
+        }
+    }
+}
+
+impl std::fmt::Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+type CachedFlags = Option<(Instant, HashMap<String, bool>)>;
+
+static CACHE: LazyLock<RwLock<CachedFlags>> = LazyLock::new(|| RwLock::new(None));
+
+// Flags missing from the table are disabled
+pub async fn is_enabled(db: &database::Database, flag: Flag) -> bool {
+    if let Some((loaded_at, flags)) = CACHE.read().unwrap().as_ref()
+        && loaded_at.elapsed() < CACHE_TTL
+    {
+        return flags.get(flag.as_str()).copied().unwrap_or(false);
+    }
+
+    let flags = match db.get_feature_flags().await {
+        Ok(flags) => flags.into_iter().map(|f| (f.name, f.enabled)).collect::<HashMap<_, _>>(),
+        Err(e) => {
+            error!("Failed to load feature flags: {}", e);
+            return false;
+        }
+    };
+
+    let enabled = flags.get(flag.as_str()).copied().unwrap_or(false);
+    *CACHE.write().unwrap() = Some((Instant::now(), flags));
+    enabled
+}
+
+// Drops the cached flags so the next lookup sees changes made through the admin endpoints
+pub fn invalidate() {
+    *CACHE.write().unwrap() = None;
+}
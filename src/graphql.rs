@@ -0,0 +1,116 @@
+use crate::database::{self, PendingUpload, UploadExtended, UserStats};
+use crate::models::{LevelId, UserId};
+use crate::permissions::{self, Permission};
+use crate::routes::upload;
+use crate::{namespace, paths, storage};
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Error, Object, Result, Schema,
+    SimpleObject,
+};
+
+pub type ThumbnailsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// history nests into itself and every level multiplies the lookups, so queries are capped
+// well below what could hurt. GraphiQL's introspection query needs a depth of 13
+const MAX_DEPTH: usize = 13;
+const MAX_COMPLEXITY: usize = 500;
+const HISTORY_COST: usize = 10;
+
+static SCHEMA: std::sync::LazyLock<ThumbnailsSchema> = std::sync::LazyLock::new(|| {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+});
+
+pub fn schema() -> &'static ThumbnailsSchema {
+    &SCHEMA
+}
+
+// The authenticated user (if any) is attached to every request as context data
+fn current_user<'a>(ctx: &Context<'a>) -> Option<&'a database::User> {
+    ctx.data_unchecked::<Option<database::User>>().as_ref()
+}
+
+fn require_moderator(ctx: &Context<'_>) -> Result<()> {
+    match current_user(ctx) {
+        Some(user) if permissions::has(user.role, Permission::ReviewUploads) => Ok(()),
+        Some(_) => Err(Error::new("Only moderators or admins can perform this action")),
+        None => Err(Error::new("Missing Authorization header")),
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Stats {
+    thumbnails: i64,
+    pending: i64,
+}
+
+#[ComplexObject]
+impl UploadExtended {
+    async fn author(&self, ctx: &Context<'_>) -> Option<UserStats> {
+        ctx.data_unchecked::<database::Database>().get_user_stats(self.user_id).await
+    }
+
+    // a level rarely has more than a handful of uploads, nesting it is what gets expensive
+    #[graphql(complexity = "HISTORY_COST * child_complexity")]
+    async fn history(&self, ctx: &Context<'_>) -> Result<Vec<UploadExtended>> {
+        let db = ctx.data_unchecked::<database::Database>();
+        Ok(db.get_upload_history(self.level_id).await?)
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn thumbnail(&self, ctx: &Context<'_>, level_id: LevelId) -> Option<UploadExtended> {
+        ctx.data_unchecked::<database::Database>()
+            .get_upload_extended(namespace::DEFAULT, level_id)
+            .await
+    }
+
+    async fn user(&self, ctx: &Context<'_>, id: UserId) -> Option<UserStats> {
+        ctx.data_unchecked::<database::Database>().get_user_stats(id).await
+    }
+
+    async fn me(&self, ctx: &Context<'_>) -> Result<Option<UserStats>> {
+        let user = current_user(ctx).ok_or_else(|| Error::new("Missing Authorization header"))?;
+        Ok(ctx.data_unchecked::<database::Database>().get_user_stats(user.id).await)
+    }
+
+    async fn stats(&self, ctx: &Context<'_>) -> Result<Stats> {
+        let db = ctx.data_unchecked::<database::Database>();
+        Ok(Stats {
+            thumbnails: db.count_thumbnails().await?,
+            pending: db.count_pending_uploads().await?,
+        })
+    }
+
+    async fn pending(
+        &self,
+        ctx: &Context<'_>,
+        level_id: Option<LevelId>,
+        user_id: Option<UserId>,
+    ) -> Result<Vec<PendingUpload>> {
+        require_moderator(ctx)?;
+        let moderator_id = current_user(ctx).map(|user| user.id).unwrap_or_default();
+
+        let db = ctx.data_unchecked::<database::Database>();
+        let mut uploads = match (level_id, user_id) {
+            (Some(level_id), _) => {
+                db.get_pending_uploads_for_level(namespace::DEFAULT, level_id).await?
+            }
+            (None, Some(user_id)) => db.get_pending_uploads_for_user(user_id).await?,
+            (None, None) => db.get_pending_uploads(namespace::DEFAULT).await?,
+        };
+
+        for upload in &mut uploads {
+            let path = paths::thumbnail_path(&upload.namespace, upload.level_id);
+            upload.replacement = storage::exists(&path).await.unwrap_or(true);
+            upload.image_url = Some(upload::pending_image_url(db, upload.id, moderator_id));
+        }
+
+        Ok(uploads)
+    }
+}
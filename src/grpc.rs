@@ -0,0 +1,124 @@
+use crate::models::LevelId;
+use crate::routes::thumbnail::{self, Res};
+use crate::{database, level_path, namespace, paths, storage};
+use std::path::PathBuf;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+pub mod proto {
+    tonic::include_proto!("thumbnails");
+}
+
+use proto::thumbnail_service_server::{ThumbnailService, ThumbnailServiceServer};
+
+const MAX_BATCH_SIZE: usize = 1000;
+
+pub struct ThumbnailServiceImpl {
+    db: database::Database,
+}
+
+fn resolution(value: i32) -> Res {
+    match proto::Resolution::try_from(value).unwrap_or(proto::Resolution::High) {
+        proto::Resolution::High => Res::High,
+        proto::Resolution::Medium => Res::Medium,
+        proto::Resolution::Small => Res::Small,
+    }
+}
+
+#[tonic::async_trait]
+impl ThumbnailService for ThumbnailServiceImpl {
+    async fn get_thumbnail(
+        &self,
+        request: Request<proto::GetThumbnailRequest>,
+    ) -> Result<Response<proto::ThumbnailImage>, Status> {
+        let request = request.into_inner();
+        let level_id =
+            level_path::check(LevelId(request.level_id)).map_err(Status::invalid_argument)?;
+        let image_path = paths::thumbnail_path(namespace::DEFAULT, level_id);
+        if !storage::ensure_local(&image_path).await {
+            return Err(Status::not_found("Image not found"));
+        }
+        let image_path = PathBuf::from(image_path);
+
+        let upload_info = self
+            .db
+            .get_upload_info(namespace::DEFAULT, level_id)
+            .await
+            .ok_or_else(|| Status::not_found("Image not found"))?;
+
+        let data = match resolution(request.resolution) {
+            Res::High => thumbnail::read_original_image(&image_path).await,
+            res => thumbnail::resize_image(image_path, res).await,
+        }
+        .map_err(|_| Status::internal("Failed to process image"))?;
+
+        Ok(Response::new(proto::ThumbnailImage {
+            level_id: request.level_id,
+            data,
+            author: upload_info.username,
+            author_account_id: upload_info.account_id.0,
+        }))
+    }
+
+    async fn batch_exists(
+        &self,
+        request: Request<proto::BatchExistsRequest>,
+    ) -> Result<Response<proto::BatchExistsResponse>, Status> {
+        let level_ids: Vec<LevelId> =
+            request.into_inner().level_ids.into_iter().map(LevelId).collect();
+        if level_ids.len() > MAX_BATCH_SIZE {
+            return Err(Status::invalid_argument(format!(
+                "At most {} level IDs can be checked at once",
+                MAX_BATCH_SIZE
+            )));
+        }
+
+        let existing = self
+            .db
+            .get_existing_levels(&level_ids)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(proto::BatchExistsResponse {
+            existing: existing.into_iter().map(|id| id.0).collect(),
+        }))
+    }
+
+    async fn get_info(
+        &self,
+        request: Request<proto::GetInfoRequest>,
+    ) -> Result<Response<proto::ThumbnailInfo>, Status> {
+        let level_id = level_path::check(LevelId(request.into_inner().level_id))
+            .map_err(Status::invalid_argument)?;
+        let upload = self
+            .db
+            .get_upload_extended(namespace::DEFAULT, level_id)
+            .await
+            .ok_or_else(|| Status::not_found("Image not found"))?;
+
+        Ok(Response::new(proto::ThumbnailInfo {
+            level_id: upload.level_id.0,
+            user_id: upload.user_id.0,
+            account_id: upload.account_id.0,
+            username: upload.username,
+            upload_time: upload.upload_time.timestamp(),
+            first_upload_time: upload.first_upload_time.timestamp(),
+            accepted_time: upload.accepted_time.map(|t| t.timestamp()),
+            accepted_by: upload.accepted_by.map(|id| id.0),
+            accepted_by_username: upload.accepted_by_username,
+        }))
+    }
+}
+
+pub async fn serve(db: database::Database) {
+    let bind_address =
+        dotenv::var("GRPC_BIND_ADDRESS").unwrap_or_else(|_| "0.0.0.0:50051".to_string());
+    let address = bind_address.parse().expect("GRPC_BIND_ADDRESS must be a socket address");
+
+    info!("Started gRPC server on {}", bind_address);
+    tonic::transport::Server::builder()
+        .add_service(ThumbnailServiceServer::new(ThumbnailServiceImpl { db }))
+        .serve(address)
+        .await
+        .expect("gRPC server failed");
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+pub enum HashMatch {
+    Clean,
+    Match(Option<String>), // the service's reference for the matched entry
+}
+
+struct HashMatchService {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Serialize)]
+struct HashMatchRequest<'a> {
+    sha256: &'a str,
+    dhash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HashMatchResponse {
+    #[serde(rename = "match")]
+    matched: bool,
+    reference: Option<String>,
+}
+
+// HASH_MATCH_URL points at an operator-run service that knows the illegal-content hash lists
+static SERVICE: LazyLock<Option<HashMatchService>> = LazyLock::new(|| {
+    Some(HashMatchService {
+        url: dotenv::var("HASH_MATCH_URL").ok()?,
+        api_key: dotenv::var("HASH_MATCH_API_KEY").ok(),
+        client: reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create HTTP client"),
+    })
+});
+
+// 64-bit difference hash, survives re-encoding and small resizes unlike sha256
+fn dhash(data: &[u8]) -> Option<String> {
+    let image = image::load_from_memory(data).ok()?;
+    let pixels = image.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | bit as u64;
+        }
+    }
+    Some(format!("{:016x}", hash))
+}
+
+// Checks the raw upload before anything touches the disk, errors mean the check couldn't run
+pub async fn check(data: &[u8]) -> Result<HashMatch, String> {
+    let Some(service) = SERVICE.as_ref() else {
+        return Ok(HashMatch::Clean);
+    };
+
+    let sha256 = crate::sync::hash(data);
+    let owned = data.to_vec();
+    let dhash = crate::encoder::run(move || dhash(&owned)).await?;
+
+    let mut request =
+        service.client.post(&service.url).json(&HashMatchRequest { sha256: &sha256, dhash });
+    if let Some(api_key) = &service.api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let response =
+        request.send().await.and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?;
+    let result: HashMatchResponse = response.json().await.map_err(|e| e.to_string())?;
+
+    Ok(match result.matched {
+        true => HashMatch::Match(result.reference),
+        false => HashMatch::Clean,
+    })
+}
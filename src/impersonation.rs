@@ -0,0 +1,78 @@
+use crate::auth::UserSession;
+use crate::{access_log, database, util};
+use axum::extract::{FromRequestParts, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use tracing::{error, warn};
+
+// impersonation sessions are only meant for looking into a problem, not for working as someone
+const SESSION_TTL: chrono::Duration = chrono::Duration::minutes(30);
+
+pub const SESSION_HEADER: &str = "X-Impersonation-Session";
+
+// Opens an impersonation session and returns its token along with when it expires
+pub async fn start(
+    db: &database::Database,
+    admin: &database::User,
+    target: &database::User,
+    reason: &str,
+) -> Result<(String, DateTime<Utc>), sqlx::Error> {
+    let expires = db.now() + SESSION_TTL;
+    let session_id = db.add_impersonation_session(admin.id, target.id, reason, expires).await?;
+
+    warn!(
+        "{} started impersonating {} (session {}): {}",
+        admin.username, target.username, session_id, reason
+    );
+
+    // impersonation is started and used from the admin dashboard
+    let mut session = UserSession::new(target.id, target.username.clone()).for_dashboard(true);
+    session.impersonation = Some(session_id);
+    session.exp = Some(expires.timestamp() as u64);
+    Ok((session.to_jwt(), expires))
+}
+
+// Keeps impersonation sessions away from the user's own consent and account security,
+// 2FA, email and ToS acceptance are for the account owner only. Requests without a valid
+// session are left to the handler's own auth check
+pub struct NotImpersonating;
+
+impl<S: Send + Sync> FromRequestParts<S> for NotImpersonating {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let session = util::session_token(&parts.headers)
+            .and_then(|token| UserSession::from_jwt(&token).ok());
+        match session.is_some_and(|session| session.impersonation.is_some()) {
+            true => Err(util::str_response(
+                StatusCode::FORBIDDEN,
+                "This action is not available while impersonating",
+            )),
+            false => Ok(NotImpersonating),
+        }
+    }
+}
+
+// Records every request made under impersonation and marks the response
+pub async fn track(State(db): State<database::Database>, request: Request, next: Next) -> Response {
+    let Some(session_id) = access_log::session(&request).and_then(|s| s.impersonation) else {
+        return next.run(request).await;
+    };
+
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let mut response = next.run(request).await;
+
+    let status = response.status().as_u16() as i32;
+    tokio::spawn(async move {
+        if let Err(e) = db.add_impersonation_action(session_id, &method, &path, status).await {
+            error!("Failed to record impersonated request in session {}: {}", session_id, e);
+        }
+    });
+
+    response.headers_mut().insert(SESSION_HEADER, HeaderValue::from(session_id));
+    response
+}
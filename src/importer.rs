@@ -0,0 +1,189 @@
+use crate::database::{self, SyncAction};
+use crate::models::{AccountId, LevelId};
+use crate::routes::upload;
+use crate::{archive, encoder, namespace, paths, storage, sync};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+#[derive(Default)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+pub struct ImportOptions {
+    pub batch_size: usize,
+    pub skip_existing: bool,
+}
+
+impl ImportOptions {
+    pub fn from_env() -> Self {
+        let batch_size = dotenv::var("IMPORT_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .filter(|&v| v > 0)
+            .unwrap_or(DEFAULT_BATCH_SIZE);
+
+        Self {
+            batch_size,
+            skip_existing: false,
+        }
+    }
+}
+
+// Resolve the user imported thumbnails are attributed to, creating it if needed
+pub async fn importer_user(
+    db: &database::Database,
+    account_id: Option<i64>,
+) -> Result<database::User, sqlx::Error> {
+    let account_id = account_id
+        .or_else(|| dotenv::var("IMPORTER_ACCOUNT_ID").ok().and_then(|v| v.parse().ok()))
+        .unwrap_or(0);
+    let username = dotenv::var("IMPORTER_USERNAME").unwrap_or_else(|_| "importer".to_string());
+
+    db.find_or_create_user(AccountId(account_id), &username).await
+}
+
+fn level_id_from_path(path: &Path) -> Option<LevelId> {
+    path.file_stem()?.to_str()?.parse::<LevelId>().ok().filter(|id| id.0 > 0)
+}
+
+struct Converted {
+    level_id: LevelId,
+    image_path: String,
+    staged: PathBuf, // the encoded image, moved to image_path once its row is inserted
+    hash: String,
+    meta: database::ImageMeta,
+}
+
+async fn convert(path: &Path, level_id: LevelId) -> Result<Converted, String> {
+    let data = tokio::fs::read(path).await.map_err(|e| e.to_string())?;
+    let webp_data = encoder::run(move || upload::process_image(&data)).await??;
+
+    let image_path = paths::thumbnail_path(namespace::DEFAULT, level_id);
+    let staged = paths::partial_path(paths::local(&image_path));
+    let write = async {
+        if let Some(parent) = staged.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&staged, &webp_data).await
+    };
+    write.await.map_err(|e| e.to_string())?;
+    Ok(Converted {
+        level_id,
+        image_path,
+        staged,
+        hash: sync::hash(&webp_data),
+        meta: upload::image_meta(&webp_data),
+    })
+}
+
+// Rows go in first and the staged files are only moved into place once they're committed,
+// so a failed insert never leaves thumbnails live without an upload behind them
+async fn flush(
+    db: &database::Database,
+    user: &database::User,
+    batch: &mut Vec<Converted>,
+    summary: &mut ImportSummary,
+) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let uploads: Vec<(LevelId, String, database::ImageMeta)> = batch
+        .iter()
+        .map(|converted| (converted.level_id, converted.image_path.clone(), converted.meta.clone()))
+        .collect();
+
+    if let Err(e) = db.add_accepted_uploads(user.id, &uploads).await {
+        eprintln!("Failed to insert batch of {} upload(s): {}", batch.len(), e);
+        summary.failed += batch.len();
+        for converted in batch.drain(..) {
+            let _ = tokio::fs::remove_file(&converted.staged).await;
+        }
+        return;
+    }
+
+    for converted in batch.drain(..) {
+        let level_id = converted.level_id;
+        let live_path = paths::local(&converted.image_path);
+        if let Err(e) = tokio::fs::rename(&converted.staged, &live_path).await {
+            eprintln!("Failed to move thumbnail for level {} into place: {}", level_id, e);
+            let _ = tokio::fs::remove_file(&converted.staged).await;
+            summary.failed += 1;
+            continue;
+        }
+        storage::mirror(db, &converted.image_path).await;
+        summary.imported += 1;
+
+        if let Err(e) = db
+            .add_sync_change(level_id, SyncAction::Accepted, Some(&converted.hash), Some(user.id))
+            .await
+        {
+            eprintln!("Failed to record sync change for level {}: {}", level_id, e);
+        }
+    }
+}
+
+pub async fn import_directory(
+    db: &database::Database,
+    dir: &Path,
+    user: &database::User,
+    options: &ImportOptions,
+) -> Result<ImportSummary, std::io::Error> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    let mut summary = ImportSummary::default();
+    let mut batch = Vec::with_capacity(options.batch_size);
+    // 123.png and 123.jpg would otherwise both be imported for level 123
+    let mut seen = HashSet::new();
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+
+        let Some(level_id) = level_id_from_path(&path) else {
+            eprintln!("Skipping {}: file name is not a level ID", path.display());
+            summary.skipped += 1;
+            continue;
+        };
+        if !seen.insert(level_id) {
+            eprintln!("Skipping {}: level {} was already imported", path.display(), level_id);
+            summary.skipped += 1;
+            continue;
+        }
+
+        if options.skip_existing
+            && tokio::fs::metadata(paths::local(paths::thumbnail_path(
+                namespace::DEFAULT,
+                level_id,
+            )))
+            .await
+            .is_ok()
+        {
+            summary.skipped += 1;
+            continue;
+        }
+
+        archive::supersede(db, namespace::DEFAULT, level_id).await;
+        match convert(&path, level_id).await {
+            Ok(converted) => batch.push(converted),
+            Err(e) => {
+                eprintln!("Failed to import {}: {}", path.display(), e);
+                summary.failed += 1;
+            }
+        }
+
+        if batch.len() >= options.batch_size {
+            flush(db, user, &mut batch, &mut summary).await;
+            println!("Imported {} thumbnail(s) so far", summary.imported);
+        }
+    }
+
+    flush(db, user, &mut batch, &mut summary).await;
+    Ok(summary)
+}
@@ -0,0 +1,55 @@
+use crate::client_ip::ClientIp;
+use crate::{database, util};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use ipnet::IpNet;
+use std::sync::{LazyLock, RwLock};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+// how long the ban list is served from memory before the table is read again
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+type CachedBans = Option<(Instant, Vec<IpNet>)>;
+
+static CACHE: LazyLock<RwLock<CachedBans>> = LazyLock::new(|| RwLock::new(None));
+
+async fn banned_ranges(db: &database::Database) -> Vec<IpNet> {
+    if let Some((loaded_at, ranges)) = CACHE.read().unwrap().as_ref()
+        && loaded_at.elapsed() < CACHE_TTL
+    {
+        return ranges.clone();
+    }
+
+    let ranges: Vec<IpNet> = match db.get_active_ip_bans().await {
+        Ok(ranges) => ranges.iter().filter_map(|range| range.parse().ok()).collect(),
+        Err(e) => {
+            error!("Failed to load IP bans: {}", e);
+            return Vec::new();
+        }
+    };
+
+    *CACHE.write().unwrap() = Some((Instant::now(), ranges.clone()));
+    ranges
+}
+
+// Drops the cached list so the next request sees changes made through the admin endpoints
+pub fn invalidate() {
+    *CACHE.write().unwrap() = None;
+}
+
+// Runs before any extractor, so banned clients never get their request bodies read
+pub async fn enforce(
+    State(db): State<database::Database>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some(ClientIp(ip)) = request.extensions().get::<ClientIp>().copied()
+        && banned_ranges(&db).await.iter().any(|range| range.contains(&ip))
+    {
+        return util::str_response(StatusCode::FORBIDDEN, "Your address is banned");
+    }
+    next.run(request).await
+}
@@ -0,0 +1,49 @@
+use crate::database;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::{debug, error, warn};
+
+// how long a claim holds without renewal, a replica that dies mid-run blocks the job this long
+const LEASE: Duration = Duration::from_secs(5 * 60);
+const RENEW_INTERVAL: Duration = Duration::from_secs(60);
+
+// Random per-process ID that tells replicas apart
+pub static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| hex::encode(rand::random::<[u8; 8]>()));
+
+// Runs a scheduled job unless another replica is running it or already ran it within the
+// last interval, so jobs that delete files or send notifications only happen once per cluster
+pub async fn run_exclusive(
+    db: &database::Database,
+    name: &str,
+    interval: Duration,
+    job: impl Future<Output = ()>,
+) {
+    // loops on different replicas drift, a little slack keeps a late tick from skipping a round
+    let min_gap = interval.mul_f32(0.9);
+    match db.claim_job(name, &INSTANCE_ID, min_gap, LEASE).await {
+        Ok(true) => {}
+        Ok(false) => return debug!("Job {} is handled by another instance", name),
+        Err(e) => return error!("Failed to claim job {}: {}", name, e),
+    }
+
+    let mut job = std::pin::pin!(job);
+    let mut renew =
+        tokio::time::interval_at(tokio::time::Instant::now() + RENEW_INTERVAL, RENEW_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = &mut job => break,
+            _ = renew.tick() => match db.renew_job(name, &INSTANCE_ID, LEASE).await {
+                Ok(true) => {}
+                // another replica may have claimed it by now, dropping the future stops the job
+                Ok(false) => {
+                    return warn!("Lease on job {} expired while it was running, cancelling", name);
+                }
+                Err(e) => error!("Failed to renew lease on job {}: {}", name, e),
+            },
+        }
+    }
+
+    if let Err(e) = db.release_job(name, &INSTANCE_ID).await {
+        error!("Failed to release job {}: {}", name, e);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::models::{AccountId, LevelId};
+use crate::{database, outbound};
+use serde::Deserialize;
+use std::sync::LazyLock;
+use tracing::warn;
+
+// ratings change rarely, cached rows are refreshed after a day
+const MAX_AGE: chrono::Duration = chrono::Duration::days(1);
+
+struct MetadataService {
+    url: String, // with {id} in place of the level ID
+}
+
+// LEVEL_INFO_URL returns JSON with name, difficulty, stars and accountID, e.g. a GDBrowser instance
+static SERVICE: LazyLock<Option<MetadataService>> = LazyLock::new(|| {
+    Some(MetadataService {
+        url: dotenv::var("LEVEL_INFO_URL").ok()?,
+    })
+});
+
+#[derive(Deserialize)]
+struct RemoteLevel {
+    name: String,
+    difficulty: String,
+    #[serde(default)]
+    stars: i32,
+    #[serde(default, rename = "accountID", deserialize_with = "account_id")]
+    account_id: Option<AccountId>,
+    #[serde(default)]
+    author: Option<String>,
+}
+
+// GDBrowser sends the creator's account ID as a string
+fn account_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<AccountId>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(n) => n.as_i64().map(AccountId),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    })
+}
+
+pub fn is_configured() -> bool {
+    SERVICE.is_some()
+}
+
+async fn fetch(service: &MetadataService, level_id: LevelId) -> Result<RemoteLevel, String> {
+    let url = service.url.replace("{id}", &level_id.to_string());
+    let request = outbound::LEVEL_INFO.get(url);
+    let response = outbound::LEVEL_INFO.send(request).await.map_err(|e| e.to_string())?;
+    let response = response.error_for_status().map_err(|e| e.to_string())?;
+    response.json().await.map_err(|e| e.to_string())
+}
+
+// Cached metadata for a level, a stale row is still used when the service is down
+pub async fn get(db: &database::Database, level_id: LevelId) -> Option<database::LevelMetadata> {
+    let cached = db.get_level_metadata(level_id).await;
+    let now = chrono::Utc::now();
+    if cached.as_ref().is_some_and(|cached| now - cached.fetched_at < MAX_AGE) {
+        return cached;
+    }
+
+    let service = SERVICE.as_ref()?;
+    let level = match fetch(service, level_id).await {
+        Ok(level) => level,
+        Err(e) => {
+            warn!("Failed to fetch metadata for level {}: {}", level_id, e);
+            return cached;
+        }
+    };
+
+    match db
+        .set_level_metadata(
+            level_id,
+            &level.name,
+            &level.difficulty,
+            level.stars,
+            level.account_id,
+            level.author.as_deref(),
+        )
+        .await
+    {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            warn!("Failed to cache metadata for level {}: {}", level_id, e);
+            cached
+        }
+    }
+}
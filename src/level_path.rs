@@ -0,0 +1,93 @@
+use crate::models::LevelId;
+use crate::util;
+use axum::extract::rejection::PathRejection;
+use axum::extract::{FromRequestParts, Path, RawPathParams};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::Response;
+use serde::de::DeserializeOwned;
+use std::sync::LazyLock;
+
+// The game stores level IDs as 32-bit integers, GDPS deployments with bigger ones can raise this
+static MAX_LEVEL_ID: LazyLock<i64> = LazyLock::new(|| {
+    dotenv::var("MAX_LEVEL_ID").ok().and_then(|v| v.parse().ok()).unwrap_or(i32::MAX as i64)
+});
+
+fn message() -> String {
+    format!("Level IDs are whole numbers from 1 to {}", *MAX_LEVEL_ID)
+}
+
+pub fn check(id: LevelId) -> Result<LevelId, String> {
+    match (1..=*MAX_LEVEL_ID).contains(&id.0) {
+        true => Ok(id),
+        false => Err(message()),
+    }
+}
+
+// Path parameters of a route with a level ID in its `{id}` or `{level_id}` segment
+pub trait LevelParams {
+    fn level_id(&self) -> LevelId;
+}
+
+impl LevelParams for LevelId {
+    fn level_id(&self) -> LevelId {
+        *self
+    }
+}
+
+impl<B> LevelParams for (LevelId, B) {
+    fn level_id(&self) -> LevelId {
+        self.0
+    }
+}
+
+impl LevelParams for (String, LevelId) {
+    fn level_id(&self) -> LevelId {
+        self.1
+    }
+}
+
+impl<C> LevelParams for (String, LevelId, C) {
+    fn level_id(&self) -> LevelId {
+        self.1
+    }
+}
+
+// Like `Path`, but garbage and out-of-range level IDs are turned away with a 400 before the
+// handler gets to look for files or rows that can't exist
+pub struct LevelPath<T>(pub T);
+
+// Single-parameter paths don't say which parameter failed, so the raw level ID is looked at
+async fn rejection<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    rejection: PathRejection,
+) -> Response {
+    let bad_level_id = RawPathParams::from_request_parts(parts, state).await.is_ok_and(|params| {
+        params.iter().any(|(key, value)| {
+            matches!(key, "id" | "level_id") && value.parse::<LevelId>().is_err()
+        })
+    });
+    match bad_level_id {
+        true => util::str_response(StatusCode::BAD_REQUEST, &message()),
+        false => util::str_response(rejection.status(), &rejection.body_text()),
+    }
+}
+
+impl<S, T> FromRequestParts<S> for LevelPath<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + LevelParams + Send,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let params = match Path::<T>::from_request_parts(parts, state).await {
+            Ok(Path(params)) => params,
+            Err(error) => return Err(rejection(parts, state, error).await),
+        };
+        check(params.level_id())
+            .map_err(|message| util::str_response(StatusCode::BAD_REQUEST, &message))?;
+        Ok(LevelPath(params))
+    }
+}
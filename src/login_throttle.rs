@@ -0,0 +1,230 @@
+use crate::client_ip;
+use crate::models::AccountId;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+// Every login is checked against Argon, so guessing tokens costs us an upstream request each.
+// Attempts are counted per IP and per account from that IP, and repeated failures lock the key
+// out for twice as long each time. Account keys include the address so that failures sent in
+// someone else's name don't lock them out of their own account
+
+// attempts allowed per key within ATTEMPT_WINDOW, whatever their outcome
+const MAX_IP_ATTEMPTS: u32 = 30;
+const MAX_ACCOUNT_ATTEMPTS: u32 = 10;
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(60);
+// failures before the first lockout, the IP limit is higher since players share addresses
+const FREE_IP_FAILURES: u32 = 10;
+const FREE_ACCOUNT_FAILURES: u32 = 5;
+const BASE_LOCKOUT: Duration = Duration::from_secs(30);
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+// a key without failures for this long starts over
+const FORGET_AFTER: Duration = Duration::from_secs(6 * 60 * 60);
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Ip(IpAddr),
+    Account(AccountId, Option<IpAddr>),
+}
+
+impl Key {
+    pub fn ip(ip: IpAddr) -> Self {
+        Key::Ip(client_ip::network(ip))
+    }
+
+    pub fn account(account_id: AccountId, ip: Option<IpAddr>) -> Self {
+        Key::Account(account_id, ip.map(client_ip::network))
+    }
+
+    fn limits(&self) -> (u32, u32) {
+        match self {
+            Key::Ip(_) => (MAX_IP_ATTEMPTS, FREE_IP_FAILURES),
+            Key::Account(..) => (MAX_ACCOUNT_ATTEMPTS, FREE_ACCOUNT_FAILURES),
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Ip(ip) => write!(f, "IP {}", ip),
+            Key::Account(account_id, Some(ip)) => write!(f, "account {} from {}", account_id, ip),
+            Key::Account(account_id, None) => write!(f, "account {}", account_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    window_start: Instant,
+    attempts: u32,
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl Entry {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            attempts: 0,
+            failures: 0,
+            last_failure: None,
+            locked_until: None,
+        }
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        now.duration_since(self.window_start) >= ATTEMPT_WINDOW
+            && self.locked_until.is_none_or(|until| until <= now)
+            && self.last_failure.is_none_or(|at| now.duration_since(at) >= FORGET_AFTER)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Throttle {
+    entries: HashMap<Key, Entry>,
+}
+
+impl Throttle {
+    // Counts an attempt for every key, or says how long to wait when one of them is over
+    // its limit or locked out. Nothing is counted for a refused attempt
+    pub fn check(&mut self, keys: &[Key], now: Instant) -> Result<(), Duration> {
+        let mut wait = Duration::ZERO;
+        for key in keys {
+            let Some(entry) = self.entries.get_mut(key) else {
+                continue;
+            };
+            if now.duration_since(entry.window_start) >= ATTEMPT_WINDOW {
+                entry.window_start = now;
+                entry.attempts = 0;
+            }
+            if let Some(until) = entry.locked_until.filter(|until| *until > now) {
+                wait = wait.max(until - now);
+            }
+            if entry.attempts >= key.limits().0 {
+                wait = wait.max(ATTEMPT_WINDOW - now.duration_since(entry.window_start));
+            }
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys {
+            self.entries.entry(*key).or_insert_with(|| Entry::new(now)).attempts += 1;
+        }
+        Ok(())
+    }
+
+    // Returns the keys that got locked out by this failure
+    pub fn fail(&mut self, keys: &[Key], now: Instant) -> Vec<Key> {
+        let mut locked = Vec::new();
+        for key in keys {
+            let entry = self.entries.entry(*key).or_insert_with(|| Entry::new(now));
+            entry.failures += 1;
+            entry.last_failure = Some(now);
+
+            let free = key.limits().1;
+            if entry.failures > free {
+                let doublings = (entry.failures - free - 1).min(16);
+                let lockout = BASE_LOCKOUT.saturating_mul(1 << doublings).min(MAX_LOCKOUT);
+                entry.locked_until = Some(now + lockout);
+                locked.push(*key);
+            }
+        }
+        locked
+    }
+
+    // A valid token clears the account. The IP keeps its failures, or one working account
+    // would let it guess at others forever
+    pub fn succeed(&mut self, account: Key) {
+        self.entries.remove(&account);
+    }
+
+    pub fn prune(&mut self, now: Instant) {
+        self.entries.retain(|_, entry| !entry.is_stale(now));
+    }
+
+    fn locked(&self, now: Instant) -> usize {
+        self.entries.values().filter(|entry| entry.locked_until.is_some_and(|u| u > now)).count()
+    }
+}
+
+static THROTTLE: LazyLock<Mutex<Throttle>> = LazyLock::new(|| Mutex::new(Throttle::default()));
+
+static SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+static THROTTLED: AtomicU64 = AtomicU64::new(0);
+static IP_LOCKOUTS: AtomicU64 = AtomicU64::new(0);
+static ACCOUNT_LOCKOUTS: AtomicU64 = AtomicU64::new(0);
+
+fn keys(ip: Option<IpAddr>, account: AccountId) -> Vec<Key> {
+    ip.map(Key::ip).into_iter().chain([Key::account(account, ip)]).collect()
+}
+
+pub fn check(ip: Option<IpAddr>, account: AccountId) -> Result<(), Duration> {
+    let result = THROTTLE.lock().unwrap().check(&keys(ip, account), Instant::now());
+    if result.is_err() {
+        THROTTLED.fetch_add(1, Ordering::Relaxed);
+    }
+    result
+}
+
+pub fn fail(ip: Option<IpAddr>, account: AccountId) {
+    FAILED.fetch_add(1, Ordering::Relaxed);
+    let locked = THROTTLE.lock().unwrap().fail(&keys(ip, account), Instant::now());
+    for key in locked {
+        tracing::warn!("Logins locked out for {} after repeated failures", key);
+        match key {
+            Key::Ip(_) => IP_LOCKOUTS.fetch_add(1, Ordering::Relaxed),
+            Key::Account(..) => ACCOUNT_LOCKOUTS.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+pub fn succeed(ip: Option<IpAddr>, account: AccountId) {
+    SUCCEEDED.fetch_add(1, Ordering::Relaxed);
+    THROTTLE.lock().unwrap().succeed(Key::account(account, ip));
+}
+
+pub async fn run_pruner() {
+    loop {
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+        THROTTLE.lock().unwrap().prune(Instant::now());
+    }
+}
+
+pub fn write_metrics(body: &mut String) {
+    let _ = writeln!(body, "# HELP login_attempts_total Argon logins by outcome");
+    let _ = writeln!(body, "# TYPE login_attempts_total counter");
+    for (result, counter) in
+        [("success", &SUCCEEDED), ("failure", &FAILED), ("throttled", &THROTTLED)]
+    {
+        let _ = writeln!(
+            body,
+            "login_attempts_total{{result=\"{}\"}} {}",
+            result,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    let _ = writeln!(body, "# HELP login_lockouts_total Lockouts after repeated failed logins");
+    let _ = writeln!(body, "# TYPE login_lockouts_total counter");
+    for (key, counter) in [("ip", &IP_LOCKOUTS), ("account", &ACCOUNT_LOCKOUTS)] {
+        let _ = writeln!(
+            body,
+            "login_lockouts_total{{key=\"{}\"}} {}",
+            key,
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    let locked = THROTTLE.lock().unwrap().locked(Instant::now());
+    let _ = writeln!(body, "# HELP login_locked_keys IPs and accounts currently locked out");
+    let _ = writeln!(body, "# TYPE login_locked_keys gauge");
+    let _ = writeln!(body, "login_locked_keys {}", locked);
+}
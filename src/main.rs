@@ -1,84 +1,199 @@
+use axum::Router;
 use axum::response::Response;
-use axum::{Router, routing::get, routing::post};
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use std::net::SocketAddr;
 use std::path::Path;
-use tower_http::cors;
-use tower_http::services::{ServeDir, ServeFile};
 use tracing::info;
 use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
 
+mod access_log;
+mod app;
+mod archive;
+mod assignment;
+mod attestation;
 mod auth;
+mod avatar;
+mod backup;
 mod cache_controller;
+mod captcha;
+mod card;
+mod cli;
+mod client_ip;
+mod clock;
+mod cookies;
+mod csrf;
 mod database;
+mod doctor;
+mod email;
+mod encoder;
+mod events;
+mod experiments;
+mod feature_flags;
+mod graphql;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod hash_match;
+mod impersonation;
+mod importer;
+mod ip_bans;
+mod jobs;
+mod level_info;
+mod level_path;
+mod login_throttle;
+mod models;
+mod moderation;
+mod namespace;
+mod oauth;
+mod object_storage;
+mod outbound;
+mod paths;
+mod permissions;
+mod pipeline;
+mod quarantine;
+mod rate_limit;
+mod recent_auth;
+mod reload;
+mod renderer;
+mod retention;
 mod routes;
+mod scanner;
+mod seed;
+mod settings;
+mod storage;
+mod sync;
+mod takedown;
+#[cfg(test)]
+mod tests;
+mod tos;
+mod two_factor;
+mod upload_rules;
+mod upload_source;
+mod usage_stats;
 mod util;
-
-use routes::{login, thumbnail, upload, user};
+mod view_stats;
+mod warmup;
+mod webhooks;
 
 #[tokio::main]
 async fn main() {
-    // parse .env file
+    // parse .env file, after noting what the environment held without it
+    reload::capture_environment();
     dotenv::dotenv().ok();
 
     // setup logging
     let file_appender = RollingFileAppender::new(Rotation::DAILY, "logs", "server.log");
     let (non_blocking_logger, _guard) = tracing_appender::non_blocking(file_appender);
 
-    tracing_subscriber::fmt().with_writer(non_blocking_logger).with_ansi(false).init();
+    tracing_subscriber::registry()
+        .with(reload::log_filter())
+        .with(tracing_subscriber::fmt::layer().with_writer(non_blocking_logger).with_ansi(false))
+        .init();
 
     // setup directories
-    tokio::fs::create_dir_all("thumbnails").await.unwrap();
-    tokio::fs::create_dir_all("uploads").await.unwrap();
-
-    let cors = cors::CorsLayer::new()
-        .allow_origin(cors::Any)
-        .allow_methods(cors::Any)
-        .allow_headers(cors::Any);
+    tokio::fs::create_dir_all(paths::local(paths::thumbnail_dir(namespace::DEFAULT)))
+        .await
+        .unwrap();
+    tokio::fs::create_dir_all(paths::local(paths::upload_dir(namespace::DEFAULT))).await.unwrap();
+
+    match cli::Cli::parse().command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve(None).await,
+        cli::Command::Mirror { upstream } => serve(Some(upstream)).await,
+        command => cli::run(command).await,
+    }
+}
 
+async fn serve(mirror_upstream: Option<String>) {
+    doctor::startup().await;
     let db = database::get_db().await;
+    settings::reload(&db).await;
+    tokio::spawn(settings::watch(db.clone()));
+    permissions::reload(&db).await;
+    tokio::spawn(permissions::watch(db.clone()));
+    tokio::spawn(experiments::watch(db.clone()));
+    storage::reload(&db).await;
+    tokio::spawn(storage::watch(db.clone()));
+    tokio::spawn(storage::run_mirror_queue(db.clone()));
+    tokio::spawn(cache_controller::listen(db.clone()));
+    tokio::spawn(outbound::watch_alerts(db.clone()));
+    tokio::spawn(view_stats::run_flusher(db.clone()));
+    tokio::spawn(usage_stats::run_flusher(db.clone()));
+    tokio::spawn(warmup::run(db.clone()));
+    tokio::spawn(assignment::run_reassigner(db.clone()));
+    tokio::spawn(rate_limit::run_pruner());
+    tokio::spawn(login_throttle::run_pruner());
+    tokio::spawn(retention::run(db.clone()));
+    tokio::spawn(backup::run(db.clone()));
+    #[cfg(unix)]
+    tokio::spawn(reload::watch_signal(db.clone()));
+
+    // side effects of moderation decisions hang off the queue event bus
+    let webhook_db = db.clone();
+    tokio::spawn(events::listen("webhooks", move |event| {
+        webhooks::on_queue_event(&webhook_db, event)
+    }));
+    let email_db = db.clone();
+    tokio::spawn(events::listen("email", move |event| email::on_queue_event(&email_db, event)));
+    tokio::spawn(events::listen("renderer", renderer::on_queue_event));
+    tokio::spawn(routes::ws::relay(db.clone()));
+
+    let config = app::Config::from_env(mirror_upstream.is_some());
+    if let Some(upstream) = mirror_upstream {
+        tokio::spawn(sync::run_mirror(db.clone(), upstream));
+    }
+    if let Ok(admin_address) = dotenv::var("ADMIN_BIND_ADDRESS") {
+        let internal = app::internal(&config, db.clone());
+        tokio::spawn(async move { listen(internal, &admin_address).await });
+    }
 
-    let app = Router::new()
-        .route("/stats", get(get_stats))
-        // /thumbnail
-        .route("/thumbnail/{id}", get(thumbnail::image_handler_default))
-        .route("/thumbnail/{id}/{res}", get(thumbnail::image_handler_with_res))
-        .route("/thumbnail/{id}/info", get(thumbnail::thumbnail_info_handler))
-        .route("/thumbnail/random", get(thumbnail::random_handler))
-        .route("/thumbnail/random/{res}", get(thumbnail::random_res_handler))
-        // /auth
-        .route("/auth/login", post(login::login))
-        .route("/auth/discord", get(login::discord_oauth_handler))
-        .route("/auth/session", get(login::get_session))
-        .route("/auth/link", get(login::get_link_token))
-        .route("/auth/link", post(login::link_account))
-        // /user
-        .route("/user/me", get(user::get_me))
-        .route("/user/{id}", get(user::get_user_by_id))
-        // .route("/user/me/uploads", get(routes::user::get_my_uploads))
-        // .route("/user/{id}/uploads", get(routes::user::get_user_uploads))
-        // /upload
-        .route("/upload/{id}", post(upload::upload))
-        // /pending
-        .route("/pending/{id}/image", get(upload::get_pending_image))
-        .route("/pending", get(upload::get_all_pending_uploads))
-        .route("/pending/{id}", get(upload::get_pending_info))
-        .route("/pending/{id}", post(upload::pending_action))
-        .route("/pending/level/{id}", get(upload::get_pending_uploads_for_level))
-        .route("/pending/user/{id}", get(upload::get_pending_uploads_for_user))
-        // /admin
-        // .route("/admin/users", get(routes::admin::get_users))
-        // .route("/admin/user/:id", get(routes::admin::get_user_by_id))
-        // .route("/admin/user/:id", patch(routes::admin::update_user))
-        // .route("/admin/ban/:id", post(routes::admin::ban_user))
-        // .route("/admin/thumbnail/:id", delete(routes::admin::delete_thumbnail))
-        .with_state(db)
-        .layer(cors)
-        .fallback_service(ServeDir::new("dist").fallback(ServeFile::new("dist/index.html")));
+    #[cfg(feature = "grpc")]
+    tokio::spawn(grpc::serve(db.clone()));
 
+    let app = app::app(&config, db);
     let bind_address = dotenv::var("BIND_ADDRESS").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
-    let listener = tokio::net::TcpListener::bind(bind_address).await.unwrap();
+    listen(app, &bind_address).await;
+}
 
-    info!("Started server!");
-    axum::serve(listener, app).await.unwrap();
+// Serve the app on every address of a comma-separated list, `unix:/path` entries bind a unix socket
+async fn listen(app: Router, addresses: &str) {
+    // terminate TLS ourselves when a certificate is configured, HTTP/2 is negotiated via ALPN
+    let tls = match (dotenv::var("TLS_CERT"), dotenv::var("TLS_KEY")) {
+        (Ok(cert), Ok(key)) => {
+            let _ = rustls::crypto::ring::default_provider().install_default();
+            Some(
+                RustlsConfig::from_pem_file(cert, key)
+                    .await
+                    .expect("Failed to load TLS_CERT/TLS_KEY"),
+            )
+        }
+        _ => None,
+    };
+
+    let mut servers = tokio::task::JoinSet::new();
+    for address in addresses.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+        let app = app.clone();
+        if let Some(path) = address.strip_prefix("unix:") {
+            // remove the socket left behind by a previous run
+            let _ = tokio::fs::remove_file(path).await;
+            let listener = tokio::net::UnixListener::bind(path).unwrap();
+            servers.spawn(async move { axum::serve(listener, app).await });
+        } else if let Some(config) = tls.clone() {
+            let address = address.parse().expect("Bind addresses must be socket addresses");
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            servers.spawn(axum_server::bind_rustls(address, config).serve(app));
+        } else {
+            let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            servers.spawn(async move { axum::serve(listener, app).await });
+        }
+
+        info!("Started server on {}{}", address, if tls.is_some() { " with TLS" } else { "" });
+    }
+
+    while let Some(result) = servers.join_next().await {
+        result.unwrap().unwrap();
+    }
 }
 
 async fn get_dir_stats(path: &Path) -> Result<(u64, usize), std::io::Error> {
@@ -96,10 +211,11 @@ async fn get_dir_stats(path: &Path) -> Result<(u64, usize), std::io::Error> {
 }
 
 async fn get_stats() -> Response {
-    let (storage_size, thumbnails_count) = match get_dir_stats(Path::new("thumbnails")).await {
-        Ok((size, count)) => (size, count),
-        Err(_) => (0, 0),
-    };
+    let (storage_size, thumbnails_count) =
+        match get_dir_stats(&paths::local(paths::thumbnail_dir(namespace::DEFAULT))).await {
+            Ok((size, count)) => (size, count),
+            Err(_) => (0, 0),
+        };
 
     let users_per_month = 3292188; // TODO: Fetch this from Cloudflare API
 
@@ -0,0 +1,823 @@
+use crate::permissions::Permission;
+use crate::scanner::ScanVerdict;
+use crate::upload_rules::{RuleAction, RuleCondition};
+use crate::upload_source::UploadSource;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+// Level and account IDs of some GDPS deployments don't fit in the numbers JavaScript clients
+// parse JSON into. With JSON_STRING_IDS=true they are written as strings, and either form is
+// read back, so a mirror keeps syncing whichever way its primary is set up
+mod id {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::sync::LazyLock;
+
+    static AS_STRING: LazyLock<bool> =
+        LazyLock::new(|| dotenv::var("JSON_STRING_IDS").is_ok_and(|value| value == "true"));
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wire {
+        Number(i64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(serializer: S, id: i64) -> Result<S::Ok, S::Error> {
+        match *AS_STRING {
+            true => serializer.collect_str(&id),
+            false => serializer.serialize_i64(id),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        match Wire::deserialize(deserializer)? {
+            Wire::Number(id) => Ok(id),
+            Wire::String(id) => id.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+// IDs get a type each so a level ID can't be passed where a user ID goes. Level and account
+// IDs come from the game servers, user IDs are our own
+macro_rules! id_type {
+    ($name:ident, $serialize:path) => {
+        #[derive(
+            Debug,
+            Default,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            Hash,
+            PartialOrd,
+            Ord,
+            sqlx::Type,
+            async_graphql::NewType,
+        )]
+        #[sqlx(transparent)]
+        pub struct $name(pub i64);
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = std::num::ParseIntError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse().map($name)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $serialize(serializer, self.0)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                id::deserialize(deserializer).map($name)
+            }
+        }
+    };
+}
+
+id_type!(LevelId, id::serialize);
+id_type!(AccountId, id::serialize);
+id_type!(UserId, serde::Serializer::serialize_i64);
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    clap::ValueEnum,
+    async_graphql::Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Role {
+    User,     // regular user
+    Verified, // verified users can upload thumbnails without approval
+    #[serde(rename = "trial_mod")]
+    #[sqlx(rename = "trial_mod")]
+    TrialMod, // reviews uploads, but their own still go through the queue
+    Moderator, // moderators can approve or reject uploads
+    Admin,    // admins can manage users and uploads
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::User => write!(f, "user"),
+            Role::Verified => write!(f, "verified"),
+            Role::TrialMod => write!(f, "trial_mod"),
+            Role::Moderator => write!(f, "moderator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum IpAction {
+    Login,
+    Upload,
+}
+
+impl std::fmt::Display for IpAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpAction::Login => write!(f, "login"),
+            IpAction::Upload => write!(f, "upload"),
+        }
+    }
+}
+
+#[derive(Debug, FromRow, Serialize)]
+pub struct User {
+    pub id: UserId,
+    pub account_id: AccountId,
+    pub username: String,
+    pub role: Role,
+    pub discord_id: Option<i64>,
+    #[sqlx(skip)]
+    #[serde(skip)]
+    pub limited: bool, // staff session without a second factor, acts as a verified user
+}
+
+#[derive(FromRow)]
+pub struct UploadInfo {
+    pub account_id: AccountId,
+    pub username: String,
+    pub license: Option<String>,
+    pub credit: Option<String>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, async_graphql::SimpleObject)]
+#[graphql(name = "Thumbnail", complex)]
+pub struct UploadExtended {
+    pub level_id: LevelId,
+    pub user_id: UserId,
+    pub account_id: AccountId,
+    pub username: String,
+    pub upload_time: DateTime<Utc>,
+    pub first_upload_time: DateTime<Utc>,
+    pub accepted_time: Option<DateTime<Utc>>,
+    pub accepted_by: Option<AccountId>,
+    pub accepted_by_username: Option<String>,
+    pub license: Option<String>,
+    pub credit: Option<String>, // original artist, when the uploader isn't
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub file_size: Option<i64>,
+    pub encoding: Option<String>,
+    pub encoder_version: Option<i32>,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type, async_graphql::Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum UploadStatus {
+    Pending,   // waiting for a moderator
+    Accepted,  // live, or was live before being replaced
+    Rejected,  // declined by a moderator
+    Withdrawn, // pulled back by the uploader
+    Expired,   // left in the queue for too long
+    Removed,   // taken down after a claim
+}
+
+impl std::fmt::Display for UploadStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadStatus::Pending => write!(f, "pending"),
+            UploadStatus::Accepted => write!(f, "accepted"),
+            UploadStatus::Rejected => write!(f, "rejected"),
+            UploadStatus::Withdrawn => write!(f, "withdrawn"),
+            UploadStatus::Expired => write!(f, "expired"),
+            UploadStatus::Removed => write!(f, "removed"),
+        }
+    }
+}
+
+// Where an upload is in the processing pipeline, independent of the moderation outcome
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ProcessingStatus {
+    Received,        // the image arrived
+    Validated,       // format and dimensions were checked
+    Encoded,         // converted to WebP
+    QueuedForReview, // waiting in the pending queue
+    Live,            // published as the level's thumbnail
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UploadProcessing {
+    pub id: i64,
+    pub user_id: UserId,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub status: UploadStatus,
+    pub processing_status: ProcessingStatus,
+    pub upload_time: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, async_graphql::SimpleObject)]
+pub struct PendingUpload {
+    pub id: i64,
+    pub user_id: UserId,
+    pub username: String,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub status: UploadStatus,
+    pub upload_time: DateTime<Utc>,
+    pub image_path: String,
+    pub reason: Option<String>,
+    pub accepted_time: Option<DateTime<Utc>>,
+    pub accepted_by: Option<UserId>,
+    pub accepted_by_username: Option<String>,
+    pub scan_verdict: Option<ScanVerdict>,
+    pub scan_score: Option<f32>,
+    pub scan_label: Option<String>,
+    pub assigned_to: Option<UserId>,
+    pub attested: bool, // signed by the game mod
+    pub source: Option<UploadSource>,
+
+    #[sqlx(skip)]
+    pub replacement: bool,
+    #[sqlx(skip)]
+    pub image_url: Option<String>,
+}
+
+// An accepted upload the backup worker hasn't copied yet
+#[derive(FromRow)]
+pub struct UnreplicatedUpload {
+    pub id: i64,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub live: bool, // still the level's thumbnail, otherwise only its archived copy is left
+    pub archive_path: Option<String>,
+}
+
+// One accepted upload in a backup snapshot, enough to rebuild its row
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct BackupEntry {
+    pub id: i64,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub account_id: AccountId,
+    pub username: String,
+    // snapshots taken before timestamps carried an offset hold them without one
+    #[serde(deserialize_with = "crate::util::deserialize_utc")]
+    pub upload_time: DateTime<Utc>,
+    #[serde(default, deserialize_with = "crate::util::deserialize_utc_opt")]
+    pub accepted_time: Option<DateTime<Utc>>,
+    pub accepted_by: Option<UserId>,
+    pub license: Option<String>,
+    pub credit: Option<String>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub file_size: Option<i64>,
+    pub encoding: Option<String>,
+    pub encoder_version: Option<i32>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct BackupLag {
+    pub pending: i64,
+    pub oldest_accepted_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum StorageMigrationStatus {
+    Running,
+    Completed, // the destination is the active backend
+    Failed,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct StorageMigration {
+    pub id: i64,
+    pub destination: String,
+    pub status: StorageMigrationStatus,
+    pub total: i64,
+    pub copied: i64,
+    pub verified: i64,
+    pub skipped: i64,
+    pub failed: i64,
+    pub error: Option<String>,
+    pub started_by: Option<UserId>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+// A live thumbnail waiting to be copied to the storage bucket
+#[derive(Debug, FromRow)]
+pub struct QueuedMirror {
+    pub path: String,
+    pub hash: Option<String>, // None when the thumbnail was removed
+}
+
+// Enough of a decided upload to take the decision back
+#[derive(FromRow)]
+pub struct UploadDecision {
+    pub id: i64,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub user_id: UserId,
+    pub status: UploadStatus,
+    pub accepted_by: Option<UserId>,
+    pub accepted_time: Option<DateTime<Utc>>,
+    pub archive_path: Option<String>, // where a rejected image was moved to
+}
+
+// An upload a moderator has accepted or rejected, with who decided, when and why
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct DecidedUpload {
+    pub id: i64,
+    pub user_id: UserId,
+    pub username: String,
+    pub level_id: LevelId,
+    pub status: UploadStatus,
+    pub upload_time: DateTime<Utc>,
+    pub image_path: String,
+    pub reason: Option<String>,
+    pub decided_at: DateTime<Utc>,
+    pub decided_by: Option<UserId>,
+    pub decided_by_username: Option<String>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, async_graphql::SimpleObject)]
+pub struct UserStats {
+    pub id: UserId,
+    pub account_id: AccountId,
+    pub username: String,
+    pub role: Role,
+    pub upload_count: i64,
+    pub accepted_upload_count: i64,
+    pub level_count: i64,
+    pub accepted_level_count: i64,
+    pub active_thumbnail_count: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UploaderSummary {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub stats: UserStats,
+    pub created_at: DateTime<Utc>,
+    pub rejected_upload_count: i64,
+    pub recent_rejection_reasons: Vec<String>, // newest first
+    pub active_days: i64,                      // days with API usage in the requested window
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UserIp {
+    pub ip: String,
+    pub action: IpAction,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub count: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct AltAccount {
+    pub id: UserId,
+    pub account_id: AccountId,
+    pub username: String,
+    pub role: Role,
+    pub shared_ips: Vec<String>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct IpBan {
+    pub id: i64,
+    pub ip_range: String,
+    pub reason: Option<String>,
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum TakedownStatus {
+    Open,      // waiting for an admin
+    TakenDown, // the thumbnail was removed
+    Dismissed, // the claim was rejected
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TakedownRequest {
+    pub id: i64,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub claimant_name: Option<String>,
+    pub claimant_contact: String,
+    pub reason: String,
+    pub ip: Option<String>,
+    pub status: TakedownStatus,
+    pub resolved_by: Option<UserId>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewTakedownRequest<'a> {
+    pub namespace: &'a str,
+    pub level_id: LevelId,
+    pub claimant_name: Option<&'a str>,
+    pub claimant_contact: &'a str,
+    pub reason: &'a str,
+    pub ip: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ApplicationStatus {
+    Pending,  // waiting for a moderator
+    Approved, // the applicant was made verified
+    Denied,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct VerifiedApplication {
+    pub id: i64,
+    pub user_id: UserId,
+    pub username: String,
+    pub account_id: AccountId,
+    pub links: Vec<String>,
+    pub message: String,
+    pub status: ApplicationStatus,
+    pub reviewed_by: Option<UserId>,
+    pub review_note: Option<String>,
+    pub reviewed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewVerifiedApplication<'a> {
+    pub links: &'a [String],
+    pub message: &'a str,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UserSearchResult {
+    pub id: UserId,
+    pub account_id: AccountId,
+    pub username: String,
+    pub discord_username: Option<String>,
+    pub role: Role,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AnnouncementSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Announcement {
+    pub id: i64,
+    pub title: String,
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: Option<DateTime<Utc>>, // shown until deleted when unset
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct Collection {
+    pub id: i64,
+    pub owner_id: UserId,
+    pub name: String,
+    pub level_ids: Vec<LevelId>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct NewCollection<'a> {
+    pub name: &'a str,
+    pub level_ids: &'a [LevelId],
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct UploadFreeze {
+    pub id: i64,
+    pub namespace: String,
+    pub name: String,
+    pub reason: String, // shown to uploaders who run into the freeze
+    pub level_ids: Vec<LevelId>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewUploadFreeze<'a> {
+    pub namespace: &'a str,
+    pub name: &'a str,
+    pub reason: &'a str,
+    pub level_ids: &'a [LevelId],
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ThumbnailExperiment {
+    pub level_id: LevelId,
+    pub upload_id: i64, // the upload slot B was copied from
+    pub user_id: UserId,
+    pub image_path: String,
+    pub views_a: i64,
+    pub views_b: i64,
+    pub started_by: Option<UserId>,
+    pub started_at: DateTime<Utc>,
+}
+
+pub struct NewAnnouncement<'a> {
+    pub title: &'a str,
+    pub message: &'a str,
+    pub severity: AnnouncementSeverity,
+    pub starts_at: Option<DateTime<Utc>>,
+    pub ends_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TosVersion {
+    pub version: String,
+    pub url: String,
+    pub published_by: Option<UserId>,
+    pub published_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct TosAcceptance {
+    pub version: String,
+    pub accepted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ImpersonationSession {
+    pub id: i64,
+    pub admin_id: Option<UserId>,
+    pub admin_username: Option<String>,
+    pub user_id: UserId,
+    pub reason: String,
+    pub started_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ImpersonationAction {
+    pub session_id: i64,
+    pub method: String,
+    pub path: String,
+    pub status: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct TwoFactor {
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct EmailSettings {
+    pub email: Option<String>,
+    pub email_notifications: bool,
+    pub email_verified: bool, // notifications only go out once the address is confirmed
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct CardTheme {
+    pub card_accent: Option<String>, // #rrggbb, the default gold when unset
+    pub card_show_author: bool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct EmailRecipient {
+    pub username: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum SyncAction {
+    Accepted, // thumbnail went live or was replaced
+    Removed,  // thumbnail was deleted
+}
+
+#[derive(FromRow, Serialize, Deserialize)]
+pub struct SyncChange {
+    pub cursor: i64,
+    pub level_id: LevelId,
+    pub action: SyncAction,
+    pub content_hash: Option<String>,
+    pub account_id: Option<AccountId>,
+    pub username: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub events: Vec<String>,
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct FeatureFlag {
+    pub name: String,
+    pub enabled: bool,
+    pub updated_by: Option<UserId>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+pub struct RolePermission {
+    pub role: Role,
+    pub permission: Permission,
+}
+
+#[derive(FromRow)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct QuarantineEntry {
+    pub id: i64,
+    pub user_id: Option<UserId>,
+    pub username: Option<String>,
+    pub namespace: String,
+    pub level_id: LevelId,
+    pub sha256: String,
+    pub source: String,
+    pub detail: Option<String>,
+    #[serde(skip_serializing)]
+    pub file_path: String,
+    pub encrypted: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct NewQuarantineEntry<'a> {
+    pub user_id: UserId,
+    pub namespace: &'a str,
+    pub level_id: LevelId,
+    pub sha256: &'a str,
+    pub source: &'a str,
+    pub detail: Option<&'a str>,
+    pub file_path: &'a str,
+    pub encrypted: bool,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct Namespace {
+    pub name: String,
+    pub display_name: String,
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct NamespaceRole {
+    pub user_id: UserId,
+    pub username: String,
+    pub role: Role,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct LevelMetadata {
+    pub level_id: LevelId,
+    pub name: String,
+    pub difficulty: String,
+    pub stars: i32,
+    pub fetched_at: DateTime<Utc>,
+    pub author_account_id: Option<AccountId>,
+    pub author: Option<String>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct LevelSearchResult {
+    pub level_id: LevelId,
+    pub name: String,
+    pub author: Option<String>,
+    pub difficulty: String,
+    pub stars: i32,
+    pub score: f32,
+}
+
+#[derive(FromRow)]
+pub struct Assignment {
+    pub id: i64,
+    pub namespace: String,
+    pub assigned_to: Option<UserId>,
+    pub assigned_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UploadRule {
+    pub id: i64,
+    pub name: String,
+    pub role: Option<Role>, // applies to both regular and verified users when unset
+    pub condition: RuleCondition,
+    pub max_account_age_days: Option<i32>,
+    pub action: RuleAction,
+    pub priority: i32,
+    pub enabled: bool,
+    pub created_by: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Recorded on the upload row so files don't have to be opened to know what's stored
+#[derive(Debug, Clone)]
+pub struct ImageMeta {
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub file_size: i64,
+    pub encoding: Option<&'static str>, // lossless or lossy
+    pub encoder_version: Option<i32>,   // None for files this server didn't encode
+}
+
+// A sample upload with its whole history filled in, see seed.rs
+pub struct SeedUpload<'a> {
+    pub namespace: &'a str,
+    pub level_id: LevelId,
+    pub user_id: UserId,
+    pub image_path: &'a str,
+    pub status: UploadStatus,
+    pub upload_time: DateTime<Utc>,
+    pub decided_by: Option<UserId>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub reason: Option<&'a str>,
+    pub meta: &'a ImageMeta,
+}
+
+pub struct NewUploadRule<'a> {
+    pub name: &'a str,
+    pub role: Option<Role>,
+    pub condition: RuleCondition,
+    pub max_account_age_days: Option<i32>,
+    pub action: RuleAction,
+    pub priority: i32,
+    pub enabled: bool,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct DailyUsage {
+    pub day: chrono::NaiveDate,
+    pub requests: i64,
+    pub upload_bytes: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct DailyViews {
+    pub day: chrono::NaiveDate,
+    pub views: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct LevelViews {
+    pub level_id: LevelId,
+    pub views: i64,
+}
+
+#[derive(FromRow, Serialize)]
+pub struct UploadSourceCount {
+    pub source: Option<UploadSource>, // None for uploads from before sources were recorded
+    pub status: UploadStatus,
+    pub uploads: i64,
+}
@@ -0,0 +1,86 @@
+use crate::models::UploadStatus;
+use chrono::{DateTime, TimeDelta, Utc};
+
+// The statuses an upload can move between. Handlers ask here before they move files or
+// touch rows, so the rules live in one place instead of a check in every endpoint
+
+// Removals are done in bulk by takedowns and restores, on whatever is live for a level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Accept,
+    Reject,
+    Remove, // taken down after a claim
+    Undo,   // the moderator takes their decision back
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::Accept => write!(f, "accept"),
+            Action::Reject => write!(f, "reject"),
+            Action::Remove => write!(f, "remove"),
+            Action::Undo => write!(f, "undo"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    AlreadyDecided(UploadStatus),
+    NothingToUndo(UploadStatus),
+    UndoWindowOver,
+    NotAllowed(Action, UploadStatus),
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Refusal::AlreadyDecided(status) => write!(f, "This upload has already been {}", status),
+            Refusal::NothingToUndo(status) => {
+                write!(f, "This upload is {}, there is no decision to undo", status)
+            }
+            Refusal::UndoWindowOver => write!(f, "The undo window for this upload is over"),
+            Refusal::NotAllowed(action, status) => {
+                write!(f, "Can't {} an upload that is {}", action, status)
+            }
+        }
+    }
+}
+
+// The status an upload ends up in, or why it can't
+pub fn next(status: UploadStatus, action: Action) -> Result<UploadStatus, Refusal> {
+    use UploadStatus::*;
+
+    match (status, action) {
+        (Pending, Action::Accept) => Ok(Accepted),
+        (Pending, Action::Reject) => Ok(Rejected),
+        (Accepted, Action::Remove) => Ok(Removed),
+        (Accepted | Rejected, Action::Undo) => Ok(Pending),
+        (status, Action::Accept | Action::Reject) => Err(Refusal::AlreadyDecided(status)),
+        (status, Action::Undo) => Err(Refusal::NothingToUndo(status)),
+        (status, action) => Err(Refusal::NotAllowed(action, status)),
+    }
+}
+
+// The status a live thumbnail's upload moves from when it's taken down, and the one it moves to
+pub fn removal() -> (UploadStatus, UploadStatus) {
+    let live = UploadStatus::Accepted;
+    match next(live, Action::Remove) {
+        Ok(removed) => (live, removed),
+        Err(refusal) => unreachable!("{}", refusal),
+    }
+}
+
+// Undoing is only possible for a while after the decision, a zero window turns it off
+pub fn undo(
+    status: UploadStatus,
+    decided_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+    window: TimeDelta,
+) -> Result<UploadStatus, Refusal> {
+    let status = next(status, Action::Undo)?;
+    match window.is_zero() || now - decided_at > window {
+        true => Err(Refusal::UndoWindowOver),
+        false => Ok(status),
+    }
+}
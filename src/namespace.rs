@@ -0,0 +1,57 @@
+use crate::{database, two_factor, util};
+use axum::http::StatusCode;
+use axum::response::Response;
+use tracing::error;
+
+// The main game, served from the unprefixed routes with the original storage layout
+pub const DEFAULT: &str = "default";
+
+const MAX_NAME_LENGTH: usize = 32;
+
+// Namespace names end up in paths and URLs, so only a conservative set is allowed
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+}
+
+// Prefix for public URLs of a namespace, empty for the default one
+pub fn route_prefix(namespace: &str) -> String {
+    match namespace {
+        DEFAULT => String::new(),
+        namespace => format!("/gdps/{}", namespace),
+    }
+}
+
+// 404 for namespaces that were never registered
+pub async fn resolve(db: &database::Database, namespace: &str) -> Result<(), Response> {
+    match db.namespace_exists(namespace).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(util::str_response(StatusCode::NOT_FOUND, "Unknown namespace")),
+        Err(e) => Err(util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error looking up namespace: {}", e),
+        )),
+    }
+}
+
+// The global role applies everywhere, a namespace role can only raise it inside that namespace
+pub async fn role(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+) -> database::Role {
+    if namespace == DEFAULT {
+        return user.role;
+    }
+
+    match db.get_namespace_role(user.id, namespace).await {
+        Ok(Some(role)) => two_factor::cap_role(user, role).max(user.role),
+        Ok(None) => user.role,
+        Err(e) => {
+            error!("Failed to look up role of {} in {}: {}", user.id, namespace, e);
+            user.role
+        }
+    }
+}
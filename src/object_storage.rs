@@ -0,0 +1,91 @@
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use std::time::Duration;
+use tracing::error;
+
+const PRESIGN_DURATION: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+pub struct ObjectStorage {
+    bucket: Bucket,
+    credentials: Credentials,
+    max_upload_size: u64,
+    client: reqwest::Client,
+}
+
+static OBJECT_STORAGE: std::sync::LazyLock<Option<ObjectStorage>> =
+    std::sync::LazyLock::new(ObjectStorage::new);
+
+impl ObjectStorage {
+    // Returns None when no bucket is configured
+    pub fn get() -> Option<&'static Self> {
+        OBJECT_STORAGE.as_ref()
+    }
+
+    fn new() -> Option<Self> {
+        let name = dotenv::var("S3_BUCKET").ok()?;
+        let endpoint = dotenv::var("S3_ENDPOINT").expect("S3_ENDPOINT must be set with S3_BUCKET");
+        let region = dotenv::var("S3_REGION").unwrap_or_else(|_| "auto".to_string());
+        let access_key = dotenv::var("S3_ACCESS_KEY").expect("S3_ACCESS_KEY must be set");
+        let secret_key = dotenv::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set");
+
+        let endpoint = endpoint.parse().expect("S3_ENDPOINT must be a valid URL");
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, name, region)
+            .expect("Failed to configure S3 bucket");
+
+        let max_upload_size = dotenv::var("S3_MAX_UPLOAD_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE);
+
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Some(Self {
+            bucket,
+            credentials: Credentials::new(access_key, secret_key),
+            max_upload_size,
+            client,
+        })
+    }
+
+    pub fn max_upload_size(&self) -> u64 {
+        self.max_upload_size
+    }
+
+    // Presigned URL the client can PUT the image to, and how long it stays valid (in seconds)
+    pub fn presign_put(&self, key: &str) -> (String, u64) {
+        let url = self.bucket.put_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        (url.to_string(), PRESIGN_DURATION.as_secs())
+    }
+
+    pub async fn fetch(&self, key: &str) -> Result<Vec<u8>, String> {
+        let url = self.bucket.get_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        let response = self.client.get(url).send().await.map_err(|e| e.to_string())?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err("Uploaded object not found".to_string());
+        }
+
+        let response = response.error_for_status().map_err(|e| e.to_string())?;
+        if response.content_length().is_some_and(|len| len > self.max_upload_size) {
+            return Err(format!("Uploaded object exceeds {} bytes", self.max_upload_size));
+        }
+
+        let data = response.bytes().await.map_err(|e| e.to_string())?;
+        if data.len() as u64 > self.max_upload_size {
+            return Err(format!("Uploaded object exceeds {} bytes", self.max_upload_size));
+        }
+
+        Ok(data.to_vec())
+    }
+
+    pub async fn delete(&self, key: &str) {
+        let url = self.bucket.delete_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        if let Err(e) = self.client.delete(url).send().await.and_then(|r| r.error_for_status()) {
+            error!("Failed to delete object {}: {}", key, e);
+        }
+    }
+}
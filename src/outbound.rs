@@ -0,0 +1,409 @@
+use crate::webhooks::{self, WebhookEvent};
+use crate::{database, settings};
+use reqwest::{Method, RequestBuilder, Response};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Write};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+// consecutive failures before a host is skipped, and for how long
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+// error rates are judged over this window, and only once there were enough calls in it
+const ALERT_WINDOW: Duration = Duration::from_secs(5 * 60);
+const ALERT_MIN_CALLS: usize = 10;
+const ALERT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+// upper bounds in seconds for the latency histogram
+const LATENCY_BUCKETS: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// One pooled client for the third parties below, so connections are reused between calls
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::ClientBuilder::new()
+        .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+        .connect_timeout(Duration::from_secs(5))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .pool_max_idle_per_host(16)
+        .build()
+        .expect("Failed to create HTTP client")
+});
+
+pub struct Destination {
+    pub name: &'static str,
+    timeout: Duration,
+    retries: u32,
+    retry_post: bool, // POSTs are only repeated when the endpoint is known to be idempotent
+}
+
+pub const DISCORD: Destination = Destination {
+    name: "discord",
+    timeout: Duration::from_secs(10),
+    retries: 2,
+    retry_post: false, // authorization codes can only be exchanged once
+};
+
+pub const ARGON: Destination = Destination {
+    name: "argon",
+    timeout: Duration::from_secs(10),
+    retries: 2,
+    retry_post: false,
+};
+
+pub const CLOUDFLARE: Destination = Destination {
+    name: "cloudflare",
+    timeout: Duration::from_secs(10),
+    retries: 2,
+    retry_post: true,
+};
+
+// webhooks::deliver has its own, much slower, retry schedule
+pub const WEBHOOK: Destination = Destination {
+    name: "webhook",
+    timeout: Duration::from_secs(10),
+    retries: 0,
+    retry_post: false,
+};
+
+pub const LEVEL_INFO: Destination = Destination {
+    name: "level_info",
+    timeout: Duration::from_secs(5),
+    retries: 1,
+    retry_post: false,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    CircuitOpen(String), // the host failed too often recently and wasn't contacted
+    Request(reqwest::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CircuitOpen(host) => write!(f, "{} is unavailable, not retrying yet", host),
+            Self::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Request(value)
+    }
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+// keyed by host, so one broken webhook receiver doesn't block the others
+static BREAKERS: LazyLock<Mutex<HashMap<String, Breaker>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// After the cooldown a single request is let through, further failures reopen the breaker
+fn allow(host: &str) -> bool {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let Some(breaker) = breakers.get_mut(host) else {
+        return true;
+    };
+    match breaker.open_until {
+        Some(until) if Instant::now() < until => false,
+        Some(_) => {
+            breaker.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+            true
+        }
+        None => true,
+    }
+}
+
+fn record(destination: &Destination, host: &str, success: bool) {
+    let mut breakers = BREAKERS.lock().unwrap();
+    let breaker = breakers.entry(host.to_string()).or_default();
+    if success {
+        *breaker = Breaker::default();
+        return;
+    }
+
+    breaker.failures += 1;
+    if breaker.failures >= BREAKER_THRESHOLD {
+        if breaker.open_until.is_none() {
+            warn!(
+                "Circuit for {} ({}) opened after {} failures",
+                host, destination.name, breaker.failures
+            );
+        }
+        breaker.open_until = Some(Instant::now() + BREAKER_COOLDOWN);
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    success: u64,
+    failure: u64,
+    rejected: u64, // not sent because the circuit was open
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+    recent: VecDeque<(Instant, bool)>,
+    alerting: bool,
+}
+
+impl Stats {
+    fn prune(&mut self, now: Instant) {
+        while self.recent.front().is_some_and(|(at, _)| now.duration_since(*at) > ALERT_WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    // failed share of the calls in the window, None while there are too few to tell
+    fn error_rate(&self) -> Option<f64> {
+        if self.recent.len() < ALERT_MIN_CALLS {
+            return None;
+        }
+        let failed = self.recent.iter().filter(|(_, success)| !success).count();
+        Some(failed as f64 / self.recent.len() as f64)
+    }
+}
+
+static STATS: LazyLock<Mutex<HashMap<&'static str, Stats>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn observe(destination: &Destination, success: bool, latency: Duration) {
+    let now = Instant::now();
+    let mut stats = STATS.lock().unwrap();
+    let stats = stats.entry(destination.name).or_default();
+    match success {
+        true => stats.success += 1,
+        false => stats.failure += 1,
+    }
+
+    let seconds = latency.as_secs_f64();
+    stats.latency_sum += seconds;
+    for (bucket, bound) in stats.latency_buckets.iter_mut().zip(LATENCY_BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+
+    stats.recent.push_back((now, success));
+    stats.prune(now);
+}
+
+fn observe_rejected(destination: &Destination) {
+    STATS.lock().unwrap().entry(destination.name).or_default().rejected += 1;
+}
+
+// Prometheus counters and a latency histogram per destination, appended to /metrics
+pub fn write_metrics(body: &mut String) {
+    let stats = STATS.lock().unwrap();
+    let mut destinations: Vec<_> = stats.iter().collect();
+    destinations.sort_by_key(|(name, _)| *name);
+
+    let _ = writeln!(body, "# HELP outbound_requests_total Calls to third-party services");
+    let _ = writeln!(body, "# TYPE outbound_requests_total counter");
+    for (name, stats) in &destinations {
+        for (outcome, count) in
+            [("success", stats.success), ("failure", stats.failure), ("rejected", stats.rejected)]
+        {
+            let _ = writeln!(
+                body,
+                "outbound_requests_total{{destination=\"{}\",outcome=\"{}\"}} {}",
+                name, outcome, count
+            );
+        }
+    }
+
+    let _ = writeln!(body, "# HELP outbound_request_duration_seconds Latency of third-party calls");
+    let _ = writeln!(body, "# TYPE outbound_request_duration_seconds histogram");
+    for (name, stats) in &destinations {
+        for (count, bound) in stats.latency_buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                body,
+                "outbound_request_duration_seconds_bucket{{destination=\"{}\",le=\"{}\"}} {}",
+                name, bound, count
+            );
+        }
+        let total = stats.success + stats.failure;
+        let _ = writeln!(
+            body,
+            "outbound_request_duration_seconds_bucket{{destination=\"{}\",le=\"+Inf\"}} {}",
+            name, total
+        );
+        let _ = writeln!(
+            body,
+            "outbound_request_duration_seconds_sum{{destination=\"{}\"}} {}",
+            name, stats.latency_sum
+        );
+        let _ = writeln!(
+            body,
+            "outbound_request_duration_seconds_count{{destination=\"{}\"}} {}",
+            name, total
+        );
+    }
+
+    let _ = writeln!(body, "# HELP outbound_alert Whether the error rate alert is firing");
+    let _ = writeln!(body, "# TYPE outbound_alert gauge");
+    for (name, stats) in &destinations {
+        let _ =
+            writeln!(body, "outbound_alert{{destination=\"{}\"}} {}", name, stats.alerting as u8);
+    }
+}
+
+#[derive(Serialize)]
+pub struct DestinationSummary {
+    destination: &'static str,
+    success: u64,
+    failure: u64,
+    rejected: u64,
+    average_latency_ms: Option<f64>,
+    recent_calls: usize,
+    recent_error_rate: Option<f64>,
+    alerting: bool,
+}
+
+pub fn summary() -> Vec<DestinationSummary> {
+    let now = Instant::now();
+    let mut stats = STATS.lock().unwrap();
+    let mut summary: Vec<_> = stats
+        .iter_mut()
+        .map(|(name, stats)| {
+            stats.prune(now);
+            let total = stats.success + stats.failure;
+            DestinationSummary {
+                destination: name,
+                success: stats.success,
+                failure: stats.failure,
+                rejected: stats.rejected,
+                average_latency_ms: (total > 0).then(|| stats.latency_sum * 1000.0 / total as f64),
+                recent_calls: stats.recent.len(),
+                recent_error_rate: stats.error_rate(),
+                alerting: stats.alerting,
+            }
+        })
+        .collect();
+    summary.sort_by_key(|summary| summary.destination);
+    summary
+}
+
+// Raises an alert when a destination's error rate over the last five minutes goes above
+// outbound_alert_error_rate percent, and clears it once the rate is back below
+pub async fn watch_alerts(db: database::Database) {
+    loop {
+        tokio::time::sleep(ALERT_CHECK_INTERVAL).await;
+
+        let threshold = settings::current().outbound_alert_error_rate;
+        let now = Instant::now();
+        let mut changes = Vec::new();
+        {
+            let mut stats = STATS.lock().unwrap();
+            for (name, stats) in stats.iter_mut() {
+                stats.prune(now);
+                let rate = stats.error_rate();
+                let firing =
+                    threshold > 0 && rate.is_some_and(|rate| rate * 100.0 > threshold as f64);
+                if firing != stats.alerting {
+                    stats.alerting = firing;
+                    changes.push((*name, firing, rate.unwrap_or(0.0), stats.recent.len()));
+                }
+            }
+        }
+
+        for (name, firing, rate, calls) in changes {
+            match firing {
+                true => error!(
+                    "Outbound alert: {:.0}% of {} calls to {} failed in the last 5 minutes",
+                    rate * 100.0,
+                    calls,
+                    name
+                ),
+                false => info!("Outbound alert for {} cleared", name),
+            }
+
+            // an alert about webhook delivery would itself go out as a webhook
+            if name != WEBHOOK.name {
+                let data = serde_json::json!({
+                    "destination": name,
+                    "firing": firing,
+                    "error_rate": rate,
+                    "calls": calls,
+                });
+                webhooks::emit(&db, WebhookEvent::OutboundAlert, data);
+            }
+        }
+    }
+}
+
+// rate limits and server errors are worth another try, client errors are not
+fn is_retryable(result: &Result<Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => response.status().is_server_error() || response.status().as_u16() == 429,
+        Err(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+    }
+}
+
+// exponential backoff with full jitter
+fn backoff(attempt: u32) -> Duration {
+    let cap = RETRY_BASE_DELAY.as_millis() as u64 * 2u64.pow(attempt);
+    Duration::from_millis(rand::random_range(0..=cap))
+}
+
+impl Destination {
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        CLIENT.get(url)
+    }
+
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        CLIENT.post(url)
+    }
+
+    // Sends a request built from get/post with this destination's timeout, retries and breaker.
+    // Responses with error statuses are returned as they are, only transport errors are errors.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let (client, request) = request.build_split();
+        let mut request = request?;
+        if request.timeout().is_none() {
+            *request.timeout_mut() = Some(self.timeout);
+        }
+
+        let host = request.url().host_str().unwrap_or_default().to_string();
+        if !allow(&host) {
+            observe_rejected(self);
+            return Err(Error::CircuitOpen(host));
+        }
+
+        let retries = match *request.method() == Method::POST && !self.retry_post {
+            true => 0,
+            false => self.retries,
+        };
+
+        let mut attempt = 0;
+        loop {
+            // bodies that can't be replayed (streams) only get one attempt
+            let Some(next) = request.try_clone() else {
+                let started = Instant::now();
+                let result = client.execute(request).await;
+                let success = !is_retryable(&result);
+                observe(self, success, started.elapsed());
+                record(self, &host, success);
+                return result.map_err(Error::Request);
+            };
+
+            let started = Instant::now();
+            let result = client.execute(next).await;
+            let retryable = is_retryable(&result);
+            observe(self, !retryable, started.elapsed());
+            record(self, &host, !retryable);
+            if !retryable || attempt >= retries || !allow(&host) {
+                return result.map_err(Error::Request);
+            }
+
+            attempt += 1;
+            tokio::time::sleep(backoff(attempt)).await;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::models::{LevelId, UserId};
+use crate::namespace;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+// Every file the server keeps is placed here. Paths are only put together from typed IDs,
+// registered namespace names and our own constants, so nothing a client sends can reach
+// outside the directories below
+
+pub const AVATAR_DIR: &str = "avatars";
+pub const AUTO_DIR: &str = "auto";
+pub const EXPERIMENT_DIR: &str = "experiments";
+pub const QUARANTINE_DIR: &str = "quarantine";
+
+// the top level directories files are ever read from or written to
+const ROOTS: &[&str] = &[
+    "thumbnails",
+    "uploads",
+    "variants",
+    "rejected",
+    "archive",
+    "gdps",
+    AVATAR_DIR,
+    AUTO_DIR,
+    EXPERIMENT_DIR,
+    QUARANTINE_DIR,
+];
+
+// Where the directories above are kept, the working directory unless app::Config says
+// otherwise. Paths stay relative in the database and as storage keys, they are only joined
+// with the root where a file is actually touched
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_root(root: PathBuf) {
+    let _ = ROOT.set(root);
+}
+
+pub fn local(path: impl AsRef<Path>) -> PathBuf {
+    match ROOT.get() {
+        Some(root) => root.join(path),
+        None => path.as_ref().to_path_buf(),
+    }
+}
+
+// A namespace or variant name used as a directory. Names are checked when a namespace is
+// registered, one that isn't valid here means a caller skipped namespace::resolve
+fn segment(name: &str) -> &str {
+    assert!(namespace::is_valid_name(name), "{:?} is not a valid path segment", name);
+    name
+}
+
+// Other namespaces keep the same layout below gdps/{namespace}/
+fn dir(namespace: &str, name: &str) -> String {
+    match namespace {
+        namespace::DEFAULT => name.to_string(),
+        namespace => format!("gdps/{}/{}", segment(namespace), name),
+    }
+}
+
+pub fn level_file(dir: &str, level_id: LevelId) -> String {
+    format!("{}/{}.webp", dir, level_id)
+}
+
+pub fn thumbnail_dir(namespace: &str) -> String {
+    dir(namespace, "thumbnails")
+}
+
+pub fn upload_dir(namespace: &str) -> String {
+    dir(namespace, "uploads")
+}
+
+pub fn variant_root(namespace: &str) -> String {
+    dir(namespace, "variants")
+}
+
+pub fn variant_dir(namespace: &str, variant: &str) -> String {
+    format!("{}/{}", variant_root(namespace), segment(variant))
+}
+
+pub fn rejected_dir(namespace: &str) -> String {
+    dir(namespace, "rejected")
+}
+
+pub fn rejected_path(namespace: &str, upload_id: i64) -> String {
+    format!("{}/{}.webp", rejected_dir(namespace), upload_id)
+}
+
+pub fn archive_dir(namespace: &str, level_id: LevelId) -> String {
+    format!("{}/{}", dir(namespace, "archive"), level_id)
+}
+
+pub fn archive_path(namespace: &str, level_id: LevelId, upload_id: i64) -> String {
+    format!("{}/{}.webp", archive_dir(namespace, level_id), upload_id)
+}
+
+pub fn thumbnail_path(namespace: &str, level_id: LevelId) -> String {
+    level_file(&thumbnail_dir(namespace), level_id)
+}
+
+pub fn pending_path(namespace: &str, user_id: UserId, level_id: LevelId) -> String {
+    format!("{}/{}_{}.webp", upload_dir(namespace), user_id, level_id)
+}
+
+pub fn avatar_path(user_id: UserId) -> PathBuf {
+    PathBuf::from(format!("{}/{}.webp", AVATAR_DIR, user_id))
+}
+
+pub fn auto_path(level_id: LevelId) -> PathBuf {
+    PathBuf::from(level_file(AUTO_DIR, level_id))
+}
+
+pub fn experiment_path(level_id: LevelId) -> String {
+    level_file(EXPERIMENT_DIR, level_id)
+}
+
+pub fn experiment_variant_dir(variant: &str) -> String {
+    format!("{}/{}", EXPERIMENT_DIR, segment(variant))
+}
+
+// sha256 is the hex digest of the quarantined data, computed here and never taken from input
+pub fn quarantine_path(sha256: &str) -> String {
+    assert!(sha256.chars().all(|c| c.is_ascii_hexdigit()), "{:?} is not a digest", sha256);
+    format!("{}/{}.bin", QUARANTINE_DIR, sha256)
+}
+
+// Scratch name next to a file, written first and renamed over it. Each writer gets its own so
+// two of them never interleave into the same partial file
+pub fn partial_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut partial = path.as_ref().as_os_str().to_owned();
+    partial.push(format!(".{:016x}.partial", rand::random::<u64>()));
+    PathBuf::from(partial)
+}
+
+// Object keys in the backup bucket
+pub fn backup_key(namespace: &str, level_id: LevelId, upload_id: i64) -> String {
+    format!("thumbnails/{}/{}/{}.webp", segment(namespace), level_id, upload_id)
+}
+
+// Object keys direct uploads land under before they're checked
+pub fn incoming_prefix(user_id: UserId, level_id: LevelId) -> String {
+    format!("incoming/{}/{}/", user_id, level_id)
+}
+
+// Paths read back from the database or a listing are only used when they stay inside one of
+// the directories above. Anything absolute, with `..` or outside them is refused
+pub fn canonical(path: impl AsRef<Path>) -> Option<PathBuf> {
+    let mut components = path.as_ref().components().filter(|c| *c != Component::CurDir);
+    let mut canonical = PathBuf::new();
+    match components.next()? {
+        Component::Normal(root) if ROOTS.iter().any(|r| root == *r) => canonical.push(root),
+        _ => return None,
+    }
+    for component in components {
+        match component {
+            Component::Normal(part) => canonical.push(part),
+            _ => return None,
+        }
+    }
+    Some(canonical)
+}
@@ -0,0 +1,181 @@
+use crate::auth::AuthedUser;
+use crate::database::{self, Role};
+use crate::{namespace, util};
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+use tracing::error;
+
+// other instances pick up changes made through the admin endpoint within this interval
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum Permission {
+    PublishOwn,      // new thumbnails and replacements of your own skip the queue
+    PublishDirectly, // every upload skips the queue, the scanner and upload rules
+    BypassQuota,     // no daily upload quota and no limit on pending uploads
+    SkipCaptcha,     // never asked for a captcha
+    ReviewUploads,   // see and decide on pending uploads
+    ReviewHeld,      // see uploads held by the content scanner
+    UndoDecisions,   // undo decisions other moderators made
+    ManageUsers,     // look up users, their IPs and alts, manage IP bans
+    PurgeCache,      // purge cached thumbnails locally and on the CDN
+}
+
+impl Permission {
+    pub const ALL: [Permission; 9] = [
+        Permission::PublishOwn,
+        Permission::PublishDirectly,
+        Permission::BypassQuota,
+        Permission::SkipCaptcha,
+        Permission::ReviewUploads,
+        Permission::ReviewHeld,
+        Permission::UndoDecisions,
+        Permission::ManageUsers,
+        Permission::PurgeCache,
+    ];
+}
+
+impl std::fmt::Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::PublishOwn => write!(f, "publish_own"),
+            Permission::PublishDirectly => write!(f, "publish_directly"),
+            Permission::BypassQuota => write!(f, "bypass_quota"),
+            Permission::SkipCaptcha => write!(f, "skip_captcha"),
+            Permission::ReviewUploads => write!(f, "review_uploads"),
+            Permission::ReviewHeld => write!(f, "review_held"),
+            Permission::UndoDecisions => write!(f, "undo_decisions"),
+            Permission::ManageUsers => write!(f, "manage_users"),
+            Permission::PurgeCache => write!(f, "purge_cache"),
+        }
+    }
+}
+
+// Same as the seeded role_permissions rows, used until the table has been read
+fn defaults() -> HashMap<Role, HashSet<Permission>> {
+    let staff = [
+        Permission::PublishOwn,
+        Permission::PublishDirectly,
+        Permission::BypassQuota,
+        Permission::SkipCaptcha,
+        Permission::ReviewUploads,
+    ];
+    let admin = [
+        Permission::ReviewHeld,
+        Permission::UndoDecisions,
+        Permission::ManageUsers,
+        Permission::PurgeCache,
+    ];
+    HashMap::from([
+        (Role::User, HashSet::new()),
+        (Role::Verified, HashSet::from([Permission::PublishOwn])),
+        (
+            Role::TrialMod,
+            HashSet::from([
+                Permission::PublishOwn,
+                Permission::SkipCaptcha,
+                Permission::ReviewUploads,
+            ]),
+        ),
+        (Role::Moderator, HashSet::from(staff)),
+        (Role::Admin, staff.into_iter().chain(admin).collect()),
+    ])
+}
+
+static PERMISSIONS: LazyLock<RwLock<HashMap<Role, HashSet<Permission>>>> =
+    LazyLock::new(|| RwLock::new(defaults()));
+
+pub fn has(role: Role, permission: Permission) -> bool {
+    PERMISSIONS.read().unwrap().get(&role).is_some_and(|set| set.contains(&permission))
+}
+
+pub fn current() -> HashMap<Role, HashSet<Permission>> {
+    PERMISSIONS.read().unwrap().clone()
+}
+
+pub async fn reload(db: &database::Database) {
+    let rows = match db.get_role_permissions().await {
+        Ok(rows) => rows,
+        Err(e) => return error!("Failed to load role permissions: {}", e),
+    };
+
+    // roles without any rows simply have no permissions
+    let mut map: HashMap<Role, HashSet<Permission>> = HashMap::new();
+    for row in rows {
+        map.entry(row.role).or_default().insert(row.permission);
+    }
+    *PERMISSIONS.write().unwrap() = map;
+}
+
+pub async fn watch(db: database::Database) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        reload(&db).await;
+    }
+}
+
+fn forbidden() -> Response {
+    util::str_response(StatusCode::FORBIDDEN, "You don't have permission to perform this action")
+}
+
+// For checks that depend on the namespace, the role there decides
+pub async fn require(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+    permission: Permission,
+) -> Result<(), Response> {
+    match has(namespace::role(db, user, namespace).await, permission) {
+        true => Ok(()),
+        false => Err(forbidden()),
+    }
+}
+
+// Marker types naming a permission, so handlers can take RequirePermission<PurgeCache>
+pub trait Required {
+    const PERMISSION: Permission;
+}
+
+macro_rules! required {
+    ($($name:ident),*) => {
+        $(
+            pub struct $name;
+
+            impl Required for $name {
+                const PERMISSION: Permission = Permission::$name;
+            }
+        )*
+    };
+}
+
+required!(ReviewUploads, ReviewHeld, ManageUsers, PurgeCache);
+
+// Authenticates the request and checks the user's global role for the permission
+pub struct RequirePermission<P: Required>(pub database::User, pub PhantomData<P>);
+
+impl<S, P> FromRequestParts<S> for RequirePermission<P>
+where
+    S: Send + Sync,
+    P: Required,
+    database::Database: FromRef<S>,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let AuthedUser(user) = AuthedUser::from_request_parts(parts, state).await?;
+
+        match has(user.role, P::PERMISSION) {
+            true => Ok(RequirePermission(user, PhantomData)),
+            false => Err(forbidden()),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+use webp::Encoder;
+
+// The CPU heavy part of storing and serving thumbnails. Nothing in here touches the rest of
+// the crate, so the benchmarks in benches/ and the fuzz targets in fuzz/ can build it on their own
+
+pub const IMAGE_WIDTH: u32 = 1920;
+pub const IMAGE_HEIGHT: u32 = 1080;
+
+// a decoded thumbnail takes 8 MiB, or 16 MiB for 16-bit PNGs, decoders get some room on top
+const MAX_DECODE_ALLOC: u64 = 64 * 1024 * 1024;
+
+// tried in order when the lossless encode is over the size cap
+const LOSSY_QUALITIES: [f32; 6] = [95.0, 90.0, 80.0, 70.0, 60.0, 50.0];
+
+// Everything untrusted upload data goes through before it counts as an image: the format is
+// sniffed from the magic bytes, the size is read from the header before anything is decoded,
+// and the decoder isn't allowed to allocate more than a thumbnail needs, whatever the file claims
+pub fn validate_upload(data: &[u8], formats: &[ImageFormat]) -> Result<DynamicImage, String> {
+    let format = image::guess_format(data)
+        .ok()
+        .filter(|format| formats.contains(format))
+        .ok_or("Unsupported image format")?;
+    let wrong_size = || format!("Image must be exactly {}x{}", IMAGE_WIDTH, IMAGE_HEIGHT);
+
+    let dimensions = ImageReader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|e| format!("Invalid image data: {}", e))?;
+    if dimensions != (IMAGE_WIDTH, IMAGE_HEIGHT) {
+        return Err(wrong_size());
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(IMAGE_WIDTH);
+    limits.max_image_height = Some(IMAGE_HEIGHT);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| format!("Invalid image data: {}", e))?;
+
+    // checked again in case the decoded frame doesn't match the header
+    if image.width() != IMAGE_WIDTH || image.height() != IMAGE_HEIGHT {
+        return Err(wrong_size());
+    }
+    Ok(image)
+}
+
+// Encodes an uploaded image as it's stored, lossless unless that's over max_size
+pub fn encode_thumbnail(image: DynamicImage, max_size: usize) -> Result<Vec<u8>, String> {
+    let rgb_data = image.into_rgb8();
+    let encoder = Encoder::from_rgb(&rgb_data, rgb_data.width(), rgb_data.height());
+    let lossless = encoder.encode_lossless();
+    if lossless.len() <= max_size {
+        return Ok(lossless.to_owned());
+    }
+
+    // noisy screenshots can get huge losslessly, fall back to lossy until it fits
+    for quality in LOSSY_QUALITIES {
+        let lossy = encoder.encode(quality);
+        if lossy.len() <= max_size {
+            return Ok(lossy.to_owned());
+        }
+    }
+    Err(format!("Image is too large even after compression, the limit is {} bytes", max_size))
+}
+
+// A lower resolution copy of a stored thumbnail
+pub fn resize(image: &DynamicImage, width: u32, height: u32) -> Vec<u8> {
+    let resized_image = image.resize_exact(width, height, FilterType::Lanczos3).to_rgb8();
+    Encoder::from_rgb(&resized_image, width, height).encode_lossless().to_vec()
+}
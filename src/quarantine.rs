@@ -0,0 +1,123 @@
+use crate::database;
+use crate::models::LevelId;
+use crate::paths;
+use crate::webhooks::{self, WebhookEvent};
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use serde_json::json;
+use std::sync::LazyLock;
+use tracing::warn;
+
+const NONCE_SIZE: usize = 12;
+
+// QUARANTINE_KEY is 32 bytes of hex, doctor refuses to start without a valid one when
+// anything can quarantine uploads
+static CIPHER: LazyLock<Option<Aes256Gcm>> =
+    LazyLock::new(|| cipher(&dotenv::var("QUARANTINE_KEY").ok()?));
+
+pub fn cipher(key: &str) -> Option<Aes256Gcm> {
+    Aes256Gcm::new_from_slice(&hex::decode(key.trim()).ok()?).ok()
+}
+
+fn encrypt(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = rand::random::<[u8; NONCE_SIZE]>();
+    let ciphertext =
+        cipher.encrypt(Nonce::from_slice(&nonce), data).map_err(|_| "Encryption failed")?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn decrypt(cipher: &Aes256Gcm, data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_SIZE {
+        return Err("Quarantined file is truncated".to_string());
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt quarantined file".to_string())
+}
+
+// Keeps the original bytes of a held upload as evidence and alerts admins
+pub async fn store(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+    level_id: LevelId,
+    data: &[u8],
+    source: &str,
+    detail: Option<&str>,
+) -> Result<i64, String> {
+    let sha256 = crate::sync::hash(data);
+    let file_path = paths::quarantine_path(&sha256);
+
+    // flagged content is never written in the clear
+    let cipher = CIPHER.as_ref().ok_or("QUARANTINE_KEY is not set, uploads can't be held")?;
+    let contents = encrypt(cipher, data)?;
+
+    tokio::fs::create_dir_all(paths::local(paths::QUARANTINE_DIR))
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::fs::write(paths::local(&file_path), contents).await.map_err(|e| e.to_string())?;
+
+    let id = db
+        .add_quarantine(database::NewQuarantineEntry {
+            user_id: user.id,
+            namespace,
+            level_id,
+            sha256: &sha256,
+            source,
+            detail,
+            file_path: &file_path,
+            encrypted: true,
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+
+    warn!(
+        "Quarantined upload {} for level {} by {} ({}: {})",
+        id,
+        level_id,
+        user.username,
+        source,
+        detail.unwrap_or("-")
+    );
+    webhooks::emit(
+        db,
+        WebhookEvent::UploadQuarantined,
+        json!({
+            "quarantine_id": id,
+            "namespace": namespace,
+            "level_id": level_id,
+            "user_id": user.id,
+            "source": source,
+        }),
+    );
+    Ok(id)
+}
+
+pub async fn read(entry: &database::QuarantineEntry) -> Result<Vec<u8>, String> {
+    let path = paths::canonical(&entry.file_path).ok_or("Invalid quarantine file path")?;
+    let data = tokio::fs::read(paths::local(path)).await.map_err(|e| e.to_string())?;
+    if !entry.encrypted {
+        return Ok(data);
+    }
+
+    match CIPHER.as_ref() {
+        Some(cipher) => decrypt(cipher, &data),
+        None => Err("QUARANTINE_KEY is needed to read this file".to_string()),
+    }
+}
+
+// The file is shared between entries with the same content, so it stays while others use it
+pub async fn remove(
+    db: &database::Database,
+    entry: &database::QuarantineEntry,
+) -> Result<(), String> {
+    let remaining =
+        db.delete_quarantine(entry.id, &entry.file_path).await.map_err(|e| e.to_string())?;
+    if remaining == 0
+        && let Some(path) = paths::canonical(&entry.file_path)
+    {
+        let _ = tokio::fs::remove_file(paths::local(path)).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::client_ip::{self, ClientIp};
+use crate::{settings, util};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::Response;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+// Requests per client address for public endpoints that cost a lot more to answer than to
+// send. Each bucket counts in fixed windows, the limits are settings so they can be tuned
+// without a restart
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bucket {
+    Graphql,
+    Batch,
+    Takedown,
+}
+
+impl Bucket {
+    // requests allowed per window, 0 turns the limit off
+    fn limit(self) -> (u32, Duration) {
+        let settings = settings::current();
+        match self {
+            Bucket::Graphql => (settings.graphql_rate_limit, Duration::from_secs(60)),
+            Bucket::Batch => (settings.batch_rate_limit, Duration::from_secs(60 * 60)),
+            Bucket::Takedown => (settings.takedown_rate_limit, Duration::from_secs(60 * 60)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    length: Duration,
+    count: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Limiter {
+    windows: HashMap<(Bucket, IpAddr), Window>,
+}
+
+impl Limiter {
+    // Counts a request, or says how long until the window starts over
+    pub fn hit(
+        &mut self,
+        bucket: Bucket,
+        ip: IpAddr,
+        limit: u32,
+        length: Duration,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let window =
+            self.windows.entry((bucket, ip)).or_insert(Window { start: now, length, count: 0 });
+        if now.duration_since(window.start) >= length {
+            window.start = now;
+            window.count = 0;
+        }
+        window.length = length;
+        if window.count >= limit {
+            return Err(length - now.duration_since(window.start));
+        }
+        window.count += 1;
+        Ok(())
+    }
+
+    pub fn prune(&mut self, now: Instant) {
+        self.windows.retain(|_, window| now.duration_since(window.start) < window.length);
+    }
+}
+
+static LIMITER: LazyLock<Mutex<Limiter>> = LazyLock::new(|| Mutex::new(Limiter::default()));
+
+fn throttled(retry_after: Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    let mut response = util::response(
+        StatusCode::TOO_MANY_REQUESTS,
+        json!({
+            "status": StatusCode::TOO_MANY_REQUESTS.as_u16(),
+            "message": format!("Too many requests, try again in {} seconds", seconds),
+            "retry_after": seconds,
+        }),
+    );
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+// 429 once the address is over the limit. Requests without a known address share one
+// window, so they can't get around it
+pub fn check(bucket: Bucket, ip: Option<ClientIp>) -> Option<Response> {
+    let (limit, length) = bucket.limit();
+    if limit == 0 {
+        return None;
+    }
+    let ip = ip.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ClientIp(ip)| client_ip::network(ip));
+    LIMITER.lock().unwrap().hit(bucket, ip, limit, length, Instant::now()).err().map(throttled)
+}
+
+pub async fn run_pruner() {
+    loop {
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+        LIMITER.lock().unwrap().prune(Instant::now());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::auth::UserSession;
+use crate::{settings, util};
+use axum::extract::FromRequestParts;
+use axum::http::StatusCode;
+use axum::http::request::Parts;
+use axum::response::Response;
+use serde_json::json;
+
+// Guards destructive endpoints: the session has to come from a login or a 2FA confirmation
+// within the last recent_auth_window minutes. Role checks are still up to the handler.
+pub struct RequireRecentAuth;
+
+impl<S: Send + Sync> FromRequestParts<S> for RequireRecentAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(token) = util::session_token(&parts.headers) else {
+            return Err(util::str_response(
+                StatusCode::UNAUTHORIZED,
+                "Missing Authorization header",
+            ));
+        };
+        let session = UserSession::from_jwt(&token)
+            .map_err(|e| util::str_response(StatusCode::UNAUTHORIZED, &e.to_string()))?;
+
+        if session.impersonation.is_some() {
+            return Err(util::str_response(
+                StatusCode::FORBIDDEN,
+                "This action is not available while impersonating",
+            ));
+        }
+
+        let window = settings::current().recent_auth_window;
+        let age = chrono::Utc::now().timestamp() - session.auth_time as i64;
+        if age >= 0 && age < (window * 60) as i64 {
+            return Ok(RequireRecentAuth);
+        }
+
+        Err(util::response(
+            StatusCode::UNAUTHORIZED,
+            json!({
+                "status": StatusCode::UNAUTHORIZED.as_u16(),
+                "message": "Please log in again or confirm with your second factor to continue",
+                "reauth_required": true,
+                "window_minutes": window,
+            }),
+        ))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{database, feature_flags, permissions, settings};
+use axum::http::HeaderValue;
+use std::collections::HashMap;
+use std::sync::{LazyLock, OnceLock, RwLock};
+use tower_http::cors::AllowOrigin;
+use tracing::level_filters::LevelFilter;
+use tracing::{error, info};
+use tracing_subscriber::{Registry, reload};
+
+// Configuration that can change without a restart. A SIGHUP or POST /admin/reload reads the
+// .env file again and swaps these in place, so open connections and warm caches are kept
+
+static LOG_LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+// The variables the process was started with, before dotenv copied .env into the environment.
+// Those win over the file on a reload just like they did at startup
+static STARTED_WITH: LazyLock<HashMap<String, String>> =
+    LazyLock::new(|| std::env::vars().collect());
+
+// Called first thing in main, anything later would see the values dotenv loaded as well
+pub fn capture_environment() {
+    LazyLock::force(&STARTED_WITH);
+}
+
+// None allows every origin
+static CORS_ORIGINS: LazyLock<RwLock<Option<Vec<HeaderValue>>>> = LazyLock::new(|| {
+    let origins = dotenv::var("CORS_ORIGINS").ok();
+    RwLock::new(parse_origins(origins.as_deref()).unwrap_or_else(|e| {
+        error!("{}, allowing every origin", e);
+        None
+    }))
+});
+
+fn parse_level(level: Option<&str>) -> Result<LevelFilter, String> {
+    let level = level.unwrap_or("info");
+    level.parse().map_err(|_| format!("Invalid LOG_LEVEL {:?}", level))
+}
+
+pub fn parse_origins(origins: Option<&str>) -> Result<Option<Vec<HeaderValue>>, String> {
+    let origins = match origins.map(str::trim) {
+        None | Some("") | Some("*") => return Ok(None),
+        Some(origins) => origins,
+    };
+    origins
+        .split(',')
+        .map(|origin| {
+            let origin = origin.trim().trim_end_matches('/');
+            HeaderValue::from_str(origin).map_err(|_| format!("Invalid CORS origin {:?}", origin))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+// The filter layer of the subscriber set up in main, its level follows LOG_LEVEL
+pub fn log_filter() -> reload::Layer<LevelFilter, Registry> {
+    let level = dotenv::var("LOG_LEVEL").ok();
+    let level = parse_level(level.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}, logging at info", e);
+        LevelFilter::INFO
+    });
+    let (layer, handle) = reload::Layer::new(level);
+    let _ = LOG_LEVEL.set(handle);
+    layer
+}
+
+// Checked on every request, so the CORS layer never has to be rebuilt
+pub fn cors_origin() -> AllowOrigin {
+    AllowOrigin::predicate(|origin, _| match CORS_ORIGINS.read().unwrap().as_ref() {
+        Some(origins) => origins.contains(origin),
+        None => true,
+    })
+}
+
+// dotenv only reads the file once, a reload looks at it again. from_path, the suggested
+// replacement, can't be used since it only adds the file's values to the environment
+#[allow(deprecated)]
+fn env_file() -> Result<HashMap<String, String>, String> {
+    let iter = match dotenv::dotenv_iter() {
+        Ok(iter) => iter,
+        Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(HashMap::new());
+        }
+        Err(e) => return Err(format!("Can't read .env: {}", e)),
+    };
+    iter.collect::<Result<_, _>>().map_err(|e| format!("Can't parse .env: {}", e))
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RateLimits {
+    pub graphql_per_minute: u32,
+    pub batch_per_hour: u32,
+    pub takedown_per_hour: u32,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Reloaded {
+    pub log_level: String,
+    pub cors_origins: Option<Vec<String>>,
+    pub rate_limits: RateLimits,
+}
+
+// Everything is validated before anything is applied, a typo leaves the old configuration
+// in place. Settings (which hold the upload quotas and rate limits), permissions and feature
+// flags come from the database and are read again as well
+pub async fn reload(db: &database::Database) -> Result<Reloaded, String> {
+    let file = env_file()?;
+    let var = |name: &str| STARTED_WITH.get(name).or_else(|| file.get(name)).cloned();
+    let level = parse_level(var("LOG_LEVEL").as_deref())?;
+    let origins = parse_origins(var("CORS_ORIGINS").as_deref())?;
+
+    if let Some(handle) = LOG_LEVEL.get()
+        && let Err(e) = handle.reload(level)
+    {
+        return Err(format!("Can't change the log level: {}", e));
+    }
+    *CORS_ORIGINS.write().unwrap() = origins.clone();
+
+    settings::reload(db).await;
+    permissions::reload(db).await;
+    feature_flags::invalidate();
+
+    let settings = settings::current();
+    let reloaded = Reloaded {
+        log_level: level.to_string(),
+        cors_origins: origins.map(|origins| {
+            origins.iter().map(|o| o.to_str().unwrap_or_default().to_string()).collect()
+        }),
+        rate_limits: RateLimits {
+            graphql_per_minute: settings.graphql_rate_limit,
+            batch_per_hour: settings.batch_rate_limit,
+            takedown_per_hour: settings.takedown_rate_limit,
+        },
+    };
+    info!("Configuration reloaded: {:?}", reloaded);
+    Ok(reloaded)
+}
+
+#[cfg(unix)]
+pub async fn watch_signal(db: database::Database) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => return error!("Can't listen for SIGHUP: {}", e),
+    };
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reload(&db).await {
+            error!("Reload failed, keeping the old configuration: {}", e);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::events::QueueEvent;
+use crate::models::LevelId;
+use crate::namespace;
+use crate::{paths, storage};
+use image::imageops::FilterType;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+use webp::Encoder;
+
+const IMAGE_WIDTH: u32 = 1920;
+const IMAGE_HEIGHT: u32 = 1080;
+
+// levels the renderer couldn't do are not asked for again for a while
+const RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+struct Renderer {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+// RENDERER_URL is an external service that renders a level by ID, off unless set
+static RENDERER: LazyLock<Option<Renderer>> = LazyLock::new(|| {
+    Some(Renderer {
+        url: dotenv::var("RENDERER_URL").ok()?,
+        api_key: dotenv::var("RENDERER_API_KEY").ok(),
+        client: reqwest::ClientBuilder::new()
+            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("Failed to create HTTP client"),
+    })
+});
+
+static FAILED: LazyLock<Mutex<HashMap<LevelId, Instant>>> = LazyLock::new(Default::default);
+
+// RENDERER_CONCURRENCY caps renders running at once, any level id can be asked for so
+// requests past the cap get a 404 instead of queueing up behind the renderer
+static RENDERS: LazyLock<Semaphore> = LazyLock::new(|| {
+    let permits = dotenv::var("RENDERER_CONCURRENCY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|permits| *permits > 0)
+        .unwrap_or(4);
+    Semaphore::new(permits)
+});
+
+// one render per level, requests for a level already being rendered wait for that one
+static IN_FLIGHT: LazyLock<Mutex<HashMap<LevelId, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+// Shares the level's render lock, the last request holding it removes it from the map
+struct Flight {
+    level_id: LevelId,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Flight {
+    fn join(level_id: LevelId) -> Flight {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        let lock = in_flight.entry(level_id).or_default().clone();
+        Flight { level_id, lock }
+    }
+}
+
+impl Drop for Flight {
+    fn drop(&mut self) {
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        // one reference is the map's, the other this one
+        if Arc::strong_count(&self.lock) <= 2 {
+            in_flight.remove(&self.level_id);
+        }
+    }
+}
+
+fn recently_failed(level_id: LevelId) -> bool {
+    let Ok(mut failed) = FAILED.lock() else {
+        return false;
+    };
+    failed.retain(|_, at| at.elapsed() < RETRY_AFTER);
+    failed.contains_key(&level_id)
+}
+
+async fn render(renderer: &Renderer, level_id: LevelId) -> Result<Vec<u8>, String> {
+    let mut request = renderer.client.get(&renderer.url).query(&[("level_id", level_id)]);
+    if let Some(api_key) = &renderer.api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let response =
+        request.send().await.and_then(|r| r.error_for_status()).map_err(|e| e.to_string())?;
+    let data = response.bytes().await.map_err(|e| e.to_string())?;
+
+    // renders are normalized to the size of regular thumbnails
+    crate::encoder::run(move || -> Result<Vec<u8>, String> {
+        let image = image::load_from_memory(&data).map_err(|e| format!("Invalid render: {}", e))?;
+        let image = image.resize_exact(IMAGE_WIDTH, IMAGE_HEIGHT, FilterType::Lanczos3).to_rgb8();
+        Ok(Encoder::from_rgb(&image, IMAGE_WIDTH, IMAGE_HEIGHT).encode_lossless().to_vec())
+    })
+    .await?
+}
+
+// Path of the generated thumbnail for a level, rendering it on first use
+pub async fn get(level_id: LevelId) -> Option<PathBuf> {
+    let path = paths::auto_path(level_id);
+    let file = paths::local(&path);
+    if file.exists() {
+        return Some(path);
+    }
+
+    let renderer = RENDERER.as_ref()?;
+    if recently_failed(level_id) {
+        return None;
+    }
+
+    let flight = Flight::join(level_id);
+    let _rendering = flight.lock.lock().await;
+    // the request that held the lock before may have rendered it or failed to
+    if file.exists() {
+        return Some(path);
+    }
+    if recently_failed(level_id) {
+        return None;
+    }
+    let Ok(_permit) = RENDERS.try_acquire() else {
+        return None;
+    };
+
+    let write = async {
+        let data = render(renderer, level_id).await?;
+        storage::write_local(&path, &data).await.map_err(|e| e.to_string())
+    };
+
+    match write.await {
+        Ok(()) => {
+            info!("Rendered auto thumbnail for level {}", level_id);
+            Some(path)
+        }
+        Err(e) => {
+            warn!("Failed to render level {}: {}", level_id, e);
+            if let Ok(mut failed) = FAILED.lock() {
+                failed.insert(level_id, Instant::now());
+            }
+            None
+        }
+    }
+}
+
+// Called once a human upload goes live, the generated image is never served again
+pub async fn discard(level_id: LevelId) {
+    let _ = tokio::fs::remove_file(paths::local(paths::auto_path(level_id))).await;
+}
+
+// Rendered fallbacks are dropped once a real thumbnail goes live
+pub fn on_queue_event(event: QueueEvent) {
+    match event {
+        QueueEvent::Decided {
+            namespace,
+            level_id,
+            accepted: true,
+            ..
+        }
+        | QueueEvent::Published { namespace, level_id, .. }
+            if namespace == namespace::DEFAULT =>
+        {
+            tokio::spawn(discard(level_id));
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,159 @@
+use crate::settings::{self, Settings};
+use crate::{database, jobs, paths};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+const RUN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy)]
+enum Rule {
+    Rejected, // images of rejected uploads
+    Archived, // thumbnails that were replaced
+    Auto,     // thumbnails rendered for levels without an upload
+}
+
+impl Rule {
+    const ALL: [Rule; 3] = [Rule::Rejected, Rule::Archived, Rule::Auto];
+
+    fn name(self) -> &'static str {
+        match self {
+            Rule::Rejected => "rejected",
+            Rule::Archived => "archived",
+            Rule::Auto => "auto",
+        }
+    }
+
+    fn days(self, settings: &Settings) -> u32 {
+        match self {
+            Rule::Rejected => settings.retention_rejected_days,
+            Rule::Archived => settings.retention_archived_days,
+            Rule::Auto => settings.retention_auto_days,
+        }
+    }
+}
+
+static RECLAIMED_BYTES: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+static DELETED_FILES: [AtomicU64; 3] = [const { AtomicU64::new(0) }; 3];
+
+// Deletes a file and returns how big it was
+async fn remove(path: &Path) -> std::io::Result<u64> {
+    let path = paths::canonical(path).ok_or(std::io::ErrorKind::InvalidInput)?;
+    let path = paths::local(path);
+    let size = tokio::fs::metadata(&path).await?.len();
+    tokio::fs::remove_file(path).await?;
+    Ok(size)
+}
+
+fn record(rule: Rule, bytes: u64) {
+    RECLAIMED_BYTES[rule as usize].fetch_add(bytes, Ordering::Relaxed);
+    DELETED_FILES[rule as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+// Files tracked through uploads.archive_path, the column is cleared once the file is gone
+async fn expire_archives(db: &database::Database, rule: Rule, days: u32) -> u64 {
+    let mut reclaimed = 0;
+    loop {
+        let rejected = matches!(rule, Rule::Rejected);
+        let archives = match db.get_expired_archives(rejected, days, BATCH_SIZE).await {
+            Ok(archives) => archives,
+            Err(e) => {
+                error!("Failed to fetch expired {} images: {}", rule.name(), e);
+                return reclaimed;
+            }
+        };
+
+        let mut cleared = Vec::with_capacity(archives.len());
+        for (id, archive_path) in &archives {
+            match remove(Path::new(archive_path)).await {
+                Ok(size) => {
+                    record(rule, size);
+                    reclaimed += size;
+                    cleared.push(*id);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => cleared.push(*id),
+                Err(e) => error!("Failed to delete {}: {}", archive_path, e),
+            }
+        }
+
+        if let Err(e) = db.clear_archive_paths(&cleared).await {
+            error!("Failed to clear archive paths: {}", e);
+            return reclaimed;
+        }
+        // files that failed to delete stay in the next batch, so stop instead of spinning on them
+        if (archives.len() as i64) < BATCH_SIZE || cleared.len() < archives.len() {
+            return reclaimed;
+        }
+    }
+}
+
+// Auto thumbnails have no rows, their age is the file's
+async fn expire_auto(days: u32) -> u64 {
+    let cutoff = SystemTime::now() - Duration::from_secs(days as u64 * 24 * 60 * 60);
+    let mut entries = match tokio::fs::read_dir(paths::local(paths::AUTO_DIR)).await {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    let mut reclaimed = 0;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let modified = entry.metadata().await.and_then(|metadata| metadata.modified());
+        if !modified.is_ok_and(|modified| modified < cutoff) {
+            continue;
+        }
+        match remove(&Path::new(paths::AUTO_DIR).join(entry.file_name())).await {
+            Ok(size) => {
+                record(Rule::Auto, size);
+                reclaimed += size;
+            }
+            Err(e) => error!("Failed to delete {}: {}", entry.path().display(), e),
+        }
+    }
+    reclaimed
+}
+
+async fn enforce(db: &database::Database) {
+    let settings = settings::current();
+    for rule in Rule::ALL {
+        let days = rule.days(&settings);
+        if days == 0 {
+            continue;
+        }
+
+        let reclaimed = match rule {
+            Rule::Rejected | Rule::Archived => expire_archives(db, rule, days).await,
+            Rule::Auto => expire_auto(days).await,
+        };
+        if reclaimed > 0 {
+            info!("Retention rule {} reclaimed {} bytes", rule.name(), reclaimed);
+        }
+    }
+}
+
+pub async fn run(db: database::Database) {
+    loop {
+        tokio::time::sleep(RUN_INTERVAL).await;
+        jobs::run_exclusive(&db, "retention", RUN_INTERVAL, enforce(&db)).await;
+    }
+}
+
+pub fn write_metrics(body: &mut String) {
+    let _ = writeln!(body, "# HELP retention_reclaimed_bytes_total Bytes freed by retention rules");
+    let _ = writeln!(body, "# TYPE retention_reclaimed_bytes_total counter");
+    for rule in Rule::ALL {
+        let bytes = RECLAIMED_BYTES[rule as usize].load(Ordering::Relaxed);
+        let _ =
+            writeln!(body, "retention_reclaimed_bytes_total{{rule=\"{}\"}} {}", rule.name(), bytes);
+    }
+
+    let _ = writeln!(body, "# HELP retention_deleted_files_total Files deleted by retention rules");
+    let _ = writeln!(body, "# TYPE retention_deleted_files_total counter");
+    for rule in Rule::ALL {
+        let files = DELETED_FILES[rule as usize].load(Ordering::Relaxed);
+        let _ =
+            writeln!(body, "retention_deleted_files_total{{rule=\"{}\"}} {}", rule.name(), files);
+    }
+}
@@ -0,0 +1,1828 @@
+use crate::auth::{Admin, AuthedUser, RequireRole};
+use crate::feature_flags::{self, Flag};
+use crate::level_path::LevelPath;
+use crate::models::{LevelId, UserId};
+use crate::permissions::{
+    self, ManageUsers, Permission, PurgeCache, RequirePermission, ReviewHeld, ReviewUploads,
+};
+use crate::recent_auth::RequireRecentAuth;
+use crate::routes::{upload, user};
+use crate::upload_rules::{RuleAction, RuleCondition};
+use crate::webhooks::{self, WebhookEvent};
+use crate::{backup, cache_controller, database, email, experiments, paths, util, warmup};
+use crate::{encoder, impersonation, ip_bans, namespace, outbound, quarantine, settings};
+use crate::{reload, storage, takedown};
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::Deserialize;
+use serde_json::json;
+use std::net::IpAddr;
+use tracing::{error, info};
+
+pub async fn get_user_by_id(
+    RequirePermission(_, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+    Path(id): Path<UserId>,
+) -> Response {
+    user::get_user_info_with_usage(id, &db).await
+}
+
+pub async fn get_user_ips(
+    RequirePermission(_, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+    Path(id): Path<UserId>,
+) -> Response {
+    match db.get_user_ips(id).await {
+        Ok(ips) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "ips": ips,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching addresses: {}", e),
+        ),
+    }
+}
+
+pub async fn get_alt_accounts(
+    RequirePermission(_, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+    Path(id): Path<UserId>,
+) -> Response {
+    match db.get_alt_accounts(id).await {
+        Ok(accounts) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "accounts": accounts,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching alt accounts: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default, deserialize_with = "util::deserialize_utc_opt")]
+    since: Option<DateTime<Utc>>,
+}
+
+fn append_file(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    name: &str,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)
+}
+
+fn write_export(
+    writer: impl std::io::Write,
+    uploads: Vec<database::UploadExtended>,
+) -> std::io::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+
+    let manifest = serde_json::to_vec_pretty(&uploads).map_err(std::io::Error::other)?;
+    append_file(&mut builder, "manifest.json", &manifest)?;
+
+    for upload in &uploads {
+        let path = paths::thumbnail_path(namespace::DEFAULT, upload.level_id);
+        match std::fs::File::open(paths::local(&path)) {
+            Ok(mut file) => builder.append_file(&path, &mut file)?,
+            Err(e) => error!("Export skipped {}: {}", path, e),
+        }
+    }
+
+    builder.into_inner()?.flush()
+}
+
+pub async fn export(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let uploads = match db.get_active_uploads_since(query.since).await {
+        Ok(uploads) => uploads,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching thumbnails: {}", e),
+            );
+        }
+    };
+
+    // the writer only reads local files, so thumbnails that live in the bucket are pulled
+    // first, ones that can't be are left out of the manifest as well
+    let mut exported = Vec::with_capacity(uploads.len());
+    for upload in uploads {
+        let path = paths::thumbnail_path(namespace::DEFAULT, upload.level_id);
+        match storage::ensure_local(&path).await {
+            true => exported.push(upload),
+            false => error!("Export skipped {}: not found locally or in storage", path),
+        }
+    }
+    let uploads = exported;
+
+    info!("Export of {} thumbnail(s) requested by {}", uploads.len(), user.username);
+
+    let filename = match query.since {
+        Some(since) => format!("thumbnails-since-{}.tar", since.format("%Y%m%d%H%M%S")),
+        None => "thumbnails.tar".to_string(),
+    };
+    util::stream_archive("application/x-tar", &filename, "Export", move |writer| {
+        write_export(writer, uploads)
+    })
+}
+
+pub async fn get_backup_status(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    let Some(target) = backup::target_kind() else {
+        return util::str_response(StatusCode::NOT_FOUND, "Backups are not configured");
+    };
+
+    let lag = match db.get_backup_lag().await {
+        Ok(lag) => lag,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching backup lag: {}", e),
+            );
+        }
+    };
+    // how long the oldest upload that isn't backed up yet has been waiting
+    let lag_seconds =
+        lag.oldest_accepted_time.map(|at| (Utc::now() - at).num_seconds().max(0)).unwrap_or(0);
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "target": target,
+            "pending": lag.pending,
+            "lag_seconds": lag_seconds,
+            "progress": backup::progress(),
+        }),
+    )
+}
+
+pub async fn get_storage_status(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    let migration = match db.get_latest_storage_migration().await {
+        Ok(migration) => migration,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching storage migration: {}", e),
+            );
+        }
+    };
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "active": storage::active_backend(),
+            "s3_configured": storage::remote_configured(),
+            "migration": migration,
+            // live counts, only on the instance running the migration
+            "progress": storage::progress(),
+        }),
+    )
+}
+
+// Starts copying the live thumbnails to the storage bucket in the background, reads switch over
+// to it once every copy is verified
+pub async fn start_storage_migration(
+    _: RequireRecentAuth,
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    if !storage::remote_configured() {
+        return util::str_response(StatusCode::NOT_FOUND, "STORAGE_S3_BUCKET is not configured");
+    }
+    if storage::active_backend() == "s3" {
+        return util::str_response(StatusCode::CONFLICT, "Thumbnails are already served from s3");
+    }
+
+    let migration = match db.start_storage_migration("s3", user.id).await {
+        Ok(Some(migration)) => migration,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                "A storage migration is already running",
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error starting storage migration: {}", e),
+            );
+        }
+    };
+
+    info!("Storage migration {} to s3 started by {}", migration.id, user.username);
+    tokio::spawn(storage::migrate(db.clone(), migration.id));
+    util::response(
+        StatusCode::ACCEPTED,
+        json!({
+            "status": StatusCode::ACCEPTED.as_u16(),
+            "migration": migration,
+        }),
+    )
+}
+
+pub async fn get_webhooks(_: RequireRole<Admin>, State(db): State<database::Database>) -> Response {
+    match db.get_webhooks().await {
+        Ok(webhooks) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "webhooks": webhooks,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching webhooks: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WebhookPayload {
+    url: String,
+    events: Vec<WebhookEvent>,
+}
+
+pub async fn create_webhook(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<WebhookPayload>,
+) -> Response {
+    if !payload.url.starts_with("https://") && !payload.url.starts_with("http://") {
+        return util::str_response(StatusCode::BAD_REQUEST, "Webhook URL must be http(s)");
+    }
+
+    if payload.events.is_empty() {
+        return util::str_response(StatusCode::BAD_REQUEST, "At least one event is required");
+    }
+
+    let events: Vec<String> = payload.events.iter().map(|e| e.to_string()).collect();
+    let secret = webhooks::generate_secret();
+
+    match db.add_webhook(&payload.url, &secret, &events, user.id).await {
+        // the secret is only ever shown once, on creation
+        Ok(webhook) => util::response(
+            StatusCode::CREATED,
+            json!({
+                "status": StatusCode::CREATED.as_u16(),
+                "webhook": webhook,
+                "secret": secret,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating webhook: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_webhook(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_webhook(id).await {
+        Ok(true) => util::str_response(StatusCode::OK, &format!("Webhook {} deleted", id)),
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "Webhook not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting webhook: {}", e),
+        ),
+    }
+}
+
+pub async fn get_feature_flags(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_feature_flags().await {
+        Ok(flags) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "flags": flags,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching feature flags: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FeatureFlagPayload {
+    enabled: bool,
+}
+
+pub async fn set_feature_flag(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(flag): Path<Flag>,
+    Json(payload): Json<FeatureFlagPayload>,
+) -> Response {
+    match db.set_feature_flag(flag.as_str(), payload.enabled, user.id).await {
+        Ok(flag) => {
+            feature_flags::invalidate();
+            info!("Feature flag {} set to {} by {}", flag.name, flag.enabled, user.username);
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "flag": flag,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating feature flag: {}", e),
+        ),
+    }
+}
+
+pub async fn get_upload_rules(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_upload_rules().await {
+        Ok(rules) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "rules": rules,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching upload rules: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UploadRulePayload {
+    name: String,
+    role: Option<database::Role>,
+    condition: RuleCondition,
+    max_account_age_days: Option<i32>,
+    action: RuleAction,
+    #[serde(default)]
+    priority: i32,
+    enabled: Option<bool>,
+}
+
+impl UploadRulePayload {
+    fn validate(&self) -> Result<database::NewUploadRule<'_>, &'static str> {
+        if self.name.trim().is_empty() {
+            return Err("Rule name is required");
+        }
+
+        // roles that publish directly never reach the rules
+        if self.role.is_some_and(|role| permissions::has(role, Permission::PublishDirectly)) {
+            return Err("Rules can only target regular or verified users");
+        }
+
+        match self.max_account_age_days {
+            Some(_) if self.condition != RuleCondition::NewAccount => {
+                return Err("max_account_age_days only applies to new_account rules");
+            }
+            Some(days) if days <= 0 => {
+                return Err("max_account_age_days must be positive");
+            }
+            _ => {}
+        }
+
+        Ok(database::NewUploadRule {
+            name: self.name.trim(),
+            role: self.role,
+            condition: self.condition,
+            max_account_age_days: self.max_account_age_days,
+            action: self.action,
+            priority: self.priority,
+            enabled: self.enabled.unwrap_or(true),
+        })
+    }
+}
+
+pub async fn create_upload_rule(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<UploadRulePayload>,
+) -> Response {
+    let rule = match payload.validate() {
+        Ok(rule) => rule,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    match db.add_upload_rule(rule, user.id).await {
+        Ok(rule) => {
+            info!("Upload rule {} ({}) created by {}", rule.id, rule.name, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "rule": rule,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating upload rule: {}", e),
+        ),
+    }
+}
+
+pub async fn update_upload_rule(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UploadRulePayload>,
+) -> Response {
+    let rule = match payload.validate() {
+        Ok(rule) => rule,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    match db.update_upload_rule(id, rule).await {
+        Ok(Some(rule)) => {
+            info!("Upload rule {} ({}) updated by {}", rule.id, rule.name, user.username);
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "rule": rule,
+                }),
+            )
+        }
+        Ok(None) => util::str_response(StatusCode::NOT_FOUND, "Upload rule not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating upload rule: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_upload_rule(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_upload_rule(id).await {
+        Ok(true) => {
+            info!("Upload rule {} deleted by {}", id, user.username);
+            util::str_response(StatusCode::OK, &format!("Upload rule {} deleted", id))
+        }
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "Upload rule not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting upload rule: {}", e),
+        ),
+    }
+}
+
+pub async fn get_announcements(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_announcements().await {
+        Ok(announcements) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "announcements": announcements,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching announcements: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AnnouncementPayload {
+    title: String,
+    message: String,
+    severity: Option<database::AnnouncementSeverity>,
+    #[serde(default, deserialize_with = "util::deserialize_utc_opt")]
+    starts_at: Option<DateTime<Utc>>, // now when unset
+    #[serde(default, deserialize_with = "util::deserialize_utc_opt")]
+    ends_at: Option<DateTime<Utc>>,
+}
+
+impl AnnouncementPayload {
+    fn validate(&self) -> Result<database::NewAnnouncement<'_>, &'static str> {
+        if self.title.trim().is_empty() || self.message.trim().is_empty() {
+            return Err("Title and message are required");
+        }
+
+        if let Some(ends_at) = self.ends_at {
+            let starts_at = self.starts_at.unwrap_or_else(Utc::now);
+            if ends_at <= starts_at {
+                return Err("ends_at must be after starts_at");
+            }
+        }
+
+        Ok(database::NewAnnouncement {
+            title: self.title.trim(),
+            message: self.message.trim(),
+            severity: self.severity.unwrap_or(database::AnnouncementSeverity::Info),
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+        })
+    }
+}
+
+pub async fn create_announcement(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<AnnouncementPayload>,
+) -> Response {
+    let announcement = match payload.validate() {
+        Ok(announcement) => announcement,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    match db.add_announcement(announcement, user.id).await {
+        Ok(announcement) => {
+            info!("Announcement {} created by {}", announcement.id, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "announcement": announcement,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating announcement: {}", e),
+        ),
+    }
+}
+
+pub async fn update_announcement(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(payload): Json<AnnouncementPayload>,
+) -> Response {
+    let announcement = match payload.validate() {
+        Ok(announcement) => announcement,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    match db.update_announcement(id, announcement).await {
+        Ok(Some(announcement)) => {
+            info!("Announcement {} updated by {}", announcement.id, user.username);
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "announcement": announcement,
+                }),
+            )
+        }
+        Ok(None) => util::str_response(StatusCode::NOT_FOUND, "Announcement not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating announcement: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_announcement(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_announcement(id).await {
+        Ok(true) => {
+            info!("Announcement {} deleted by {}", id, user.username);
+            util::str_response(StatusCode::OK, &format!("Announcement {} deleted", id))
+        }
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "Announcement not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting announcement: {}", e),
+        ),
+    }
+}
+
+pub async fn get_upload_freezes(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_upload_freezes().await {
+        Ok(freezes) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "freezes": freezes,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching upload freezes: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UploadFreezePayload {
+    namespace: Option<String>,
+    name: String,
+    reason: String,
+    level_ids: Vec<LevelId>,
+    #[serde(default, deserialize_with = "util::deserialize_utc_opt")]
+    starts_at: Option<DateTime<Utc>>, // now when unset
+    #[serde(deserialize_with = "util::deserialize_utc")]
+    ends_at: DateTime<Utc>,
+}
+
+impl UploadFreezePayload {
+    fn validate(&self) -> Result<database::NewUploadFreeze<'_>, &'static str> {
+        if self.name.trim().is_empty() || self.reason.trim().is_empty() {
+            return Err("Name and reason are required");
+        }
+        if self.level_ids.is_empty() {
+            return Err("At least one level ID is required");
+        }
+
+        let starts_at = self.starts_at.unwrap_or_else(Utc::now);
+        if self.ends_at <= starts_at {
+            return Err("ends_at must be after starts_at");
+        }
+
+        Ok(database::NewUploadFreeze {
+            namespace: self.namespace.as_deref().unwrap_or(namespace::DEFAULT),
+            name: self.name.trim(),
+            reason: self.reason.trim(),
+            level_ids: &self.level_ids,
+            starts_at,
+            ends_at: self.ends_at,
+        })
+    }
+}
+
+pub async fn create_upload_freeze(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<UploadFreezePayload>,
+) -> Response {
+    let freeze = match payload.validate() {
+        Ok(freeze) => freeze,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+    if let Err(response) = namespace::resolve(&db, freeze.namespace).await {
+        return response;
+    }
+
+    match db.add_upload_freeze(freeze, user.id).await {
+        Ok(freeze) => {
+            info!("Upload freeze {} created by {}", freeze.id, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "freeze": freeze,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating upload freeze: {}", e),
+        ),
+    }
+}
+
+pub async fn update_upload_freeze(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(payload): Json<UploadFreezePayload>,
+) -> Response {
+    let freeze = match payload.validate() {
+        Ok(freeze) => freeze,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+    if let Err(response) = namespace::resolve(&db, freeze.namespace).await {
+        return response;
+    }
+
+    match db.update_upload_freeze(id, freeze).await {
+        Ok(Some(freeze)) => {
+            info!("Upload freeze {} updated by {}", freeze.id, user.username);
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "freeze": freeze,
+                }),
+            )
+        }
+        Ok(None) => util::str_response(StatusCode::NOT_FOUND, "Upload freeze not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating upload freeze: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_upload_freeze(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_upload_freeze(id).await {
+        Ok(true) => {
+            info!("Upload freeze {} deleted by {}", id, user.username);
+            util::str_response(StatusCode::OK, &format!("Upload freeze {} deleted", id))
+        }
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "Upload freeze not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting upload freeze: {}", e),
+        ),
+    }
+}
+
+pub async fn get_experiments(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_experiments().await {
+        Ok(experiments) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "experiments": experiments,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching experiments: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExperimentPayload {
+    level_id: LevelId,
+    upload_id: i64, // an earlier accepted upload of the level, served as slot B
+}
+
+pub async fn start_experiment(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<ExperimentPayload>,
+) -> Response {
+    if !experiments::is_eligible(&db, payload.level_id).await {
+        return util::str_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Experiments only run on official and rated levels",
+        );
+    }
+
+    match db.get_live_upload_id(namespace::DEFAULT, payload.level_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => {
+            return util::str_response(StatusCode::NOT_FOUND, "Level has no live thumbnail");
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching live thumbnail: {}", e),
+            );
+        }
+    }
+
+    let (user_id, archive_path) =
+        match db.get_archived_upload(namespace::DEFAULT, payload.level_id, payload.upload_id).await
+        {
+            Ok(Some(archived)) => archived,
+            Ok(None) => {
+                return util::str_response(
+                    StatusCode::NOT_FOUND,
+                    "No archived thumbnail with that upload ID for this level",
+                );
+            }
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error fetching upload: {}", e),
+                );
+            }
+        };
+
+    let image_path = paths::experiment_path(payload.level_id);
+    let added =
+        db.add_experiment(payload.level_id, payload.upload_id, user_id, &image_path, user.id).await;
+    let experiment = match added {
+        Ok(Some(experiment)) => experiment,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                "An experiment is already running for this level",
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error starting experiment: {}", e),
+            );
+        }
+    };
+
+    // slot B gets its own copy so retention can't delete it mid-experiment
+    let copied = async {
+        tokio::fs::create_dir_all(paths::local(paths::EXPERIMENT_DIR)).await?;
+        tokio::fs::copy(paths::local(&archive_path), paths::local(&image_path)).await
+    };
+    if let Err(e) = copied.await {
+        let _ = db.delete_experiment(payload.level_id).await;
+        return util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error copying archived thumbnail: {}", e),
+        );
+    }
+
+    experiments::reload(&db).await;
+    // shared caches and the CDN still hold slot A for everyone
+    cache_controller::invalidate(&db, namespace::DEFAULT, payload.level_id).await;
+    cache_controller::purge(namespace::DEFAULT, payload.level_id);
+    info!("Experiment on level {} started by {}", payload.level_id, user.username);
+    util::response(
+        StatusCode::CREATED,
+        json!({
+            "status": StatusCode::CREATED.as_u16(),
+            "experiment": experiment,
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct EndExperimentQuery {
+    keep: Option<experiments::Slot>, // b replaces the live thumbnail, anything else keeps it
+}
+
+pub async fn end_experiment(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    LevelPath(level_id): LevelPath<LevelId>,
+    Query(query): Query<EndExperimentQuery>,
+) -> Response {
+    // promoting slot B writes the live thumbnail, like any other upload of the level
+    let _lock = match db.try_lock_level(namespace::DEFAULT, level_id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                &format!("Another upload for level ID {} is in progress", level_id),
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error locking level: {}", e),
+            );
+        }
+    };
+
+    let kept = query.keep.unwrap_or(experiments::Slot::A);
+    let ended = match kept {
+        experiments::Slot::A => db.delete_experiment(level_id).await.map_err(|e| e.to_string()),
+        // the experiment keeps running when slot B can't be made live
+        experiments::Slot::B => match db.get_experiment(level_id).await {
+            Ok(Some(experiment)) => match experiments::promote(&db, &experiment).await {
+                Ok(promoted) => Ok(promoted.then_some(experiment)),
+                Err(e) => {
+                    error!("Failed to promote slot B of level {}: {}", level_id, e);
+                    return util::str_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("Error replacing thumbnail: {}", e),
+                    );
+                }
+            },
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        },
+    };
+    let experiment = match ended {
+        Ok(Some(experiment)) => experiment,
+        Ok(None) => return util::str_response(StatusCode::NOT_FOUND, "Experiment not found"),
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error ending experiment: {}", e),
+            );
+        }
+    };
+    experiments::reload(&db).await;
+    experiments::discard(level_id).await;
+
+    info!("Experiment on level {} ended by {}, kept slot {}", level_id, user.username, kept);
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "experiment": experiment,
+            "kept": kept,
+        }),
+    )
+}
+
+pub async fn get_tos_versions(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_tos_versions().await {
+        Ok(versions) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "versions": versions,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching terms of service: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TosPayload {
+    version: String,
+    url: String,
+}
+
+// Publishing a version makes everyone accept it again before their next upload
+pub async fn publish_tos(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<TosPayload>,
+) -> Response {
+    let version = payload.version.trim();
+    let url = payload.url.trim();
+    if version.is_empty() || !(url.starts_with("https://") || url.starts_with("http://")) {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "A version and a policy URL are required",
+        );
+    }
+
+    match db.publish_tos(version, url, user.id).await {
+        Ok(Some(tos)) => {
+            info!("Terms of service {} published by {}", tos.version, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "data": tos,
+                }),
+            )
+        }
+        Ok(None) => util::str_response(StatusCode::CONFLICT, "This version was already published"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error publishing terms of service: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApplicationQuery {
+    status: Option<database::ApplicationStatus>,
+}
+
+pub async fn get_applications(
+    _: RequirePermission<ReviewUploads>,
+    State(db): State<database::Database>,
+    Query(query): Query<ApplicationQuery>,
+) -> Response {
+    match db.get_verified_applications(query.status).await {
+        Ok(applications) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "applications": applications,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching applications: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ApplicationAction {
+    Approve, // make the applicant verified
+    Deny,
+}
+
+#[derive(Deserialize)]
+pub struct ResolveApplicationPayload {
+    action: ApplicationAction,
+    note: Option<String>, // sent to the applicant
+}
+
+pub async fn resolve_application(
+    RequirePermission(user, _): RequirePermission<ReviewUploads>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ResolveApplicationPayload>,
+) -> Response {
+    let (status, action) = match payload.action {
+        ApplicationAction::Approve => (database::ApplicationStatus::Approved, "approved"),
+        ApplicationAction::Deny => (database::ApplicationStatus::Denied, "denied"),
+    };
+    let note = payload.note.as_deref().map(str::trim).filter(|note| !note.is_empty());
+
+    let application = match db.resolve_verified_application(id, status, user.id, note).await {
+        Ok(Some(application)) => application,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::NOT_FOUND,
+                "No application waiting for review with that ID",
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error resolving application: {}", e),
+            );
+        }
+    };
+
+    info!(
+        "Verified application {} of {} {} by {}",
+        id, application.username, action, user.username
+    );
+    email::on_application_resolved(&db, &application);
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "application": application,
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct TakedownQuery {
+    status: Option<database::TakedownStatus>,
+}
+
+pub async fn get_takedowns(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Query(query): Query<TakedownQuery>,
+) -> Response {
+    match db.get_takedown_requests(query.status).await {
+        Ok(requests) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "requests": requests,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching takedown requests: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum TakedownAction {
+    Takedown, // remove the thumbnail
+    Dismiss,  // keep it
+}
+
+#[derive(Deserialize)]
+pub struct ResolveTakedownPayload {
+    action: TakedownAction,
+    note: Option<String>,
+}
+
+pub async fn resolve_takedown(
+    _: RequireRecentAuth,
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(payload): Json<ResolveTakedownPayload>,
+) -> Response {
+    let Some(request) = db.get_takedown_request(id).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "Takedown request not found");
+    };
+    if request.status != database::TakedownStatus::Open {
+        return util::str_response(StatusCode::CONFLICT, "Takedown request was already resolved");
+    }
+
+    let (status, action) = match payload.action {
+        TakedownAction::Takedown => (database::TakedownStatus::TakenDown, "takedown"),
+        TakedownAction::Dismiss => (database::TakedownStatus::Dismissed, "dismiss"),
+    };
+
+    if payload.action == TakedownAction::Takedown {
+        let reason = Some(format!("Takedown request {}", id));
+        if let Err(response) =
+            takedown::remove_thumbnail(&db, &request.namespace, request.level_id, &user, reason)
+                .await
+        {
+            return response;
+        }
+    }
+
+    let request = match db.resolve_takedown_request(id, status, user.id).await {
+        Ok(Some(request)) => request,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                "Takedown request was already resolved",
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error resolving takedown request: {}", e),
+            );
+        }
+    };
+
+    info!("Takedown request {} resolved with {} by {}", id, action, user.username);
+    if let Err(e) = db.add_takedown_audit(id, user.id, action, payload.note.as_deref()).await {
+        error!("Failed to record takedown audit entry: {}", e);
+    }
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "request": request,
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ImpersonatePayload {
+    reason: String, // shown to the user along with everything done in the session
+}
+
+pub async fn impersonate(
+    _: RequireRecentAuth,
+    RequireRole(admin, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(user_id): Path<UserId>,
+    Json(payload): Json<ImpersonatePayload>,
+) -> Response {
+    let reason = payload.reason.trim();
+    if reason.is_empty() {
+        return util::str_response(StatusCode::BAD_REQUEST, "A reason is required");
+    }
+
+    let Some(target) = db.get_user_by_id(user_id).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "User not found");
+    };
+    if target.id == admin.id || target.role == database::Role::Admin {
+        return util::str_response(StatusCode::FORBIDDEN, "This user cannot be impersonated");
+    }
+
+    match impersonation::start(&db, &admin, &target, reason).await {
+        Ok((token, expires_at)) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "message": format!("Impersonating {}", target.username),
+                "impersonating": true,
+                "user": target,
+                "token": token,
+                "expires_at": expires_at,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error starting impersonation: {}", e),
+        ),
+    }
+}
+
+pub async fn get_ip_bans(
+    RequirePermission(_, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_ip_bans().await {
+        Ok(bans) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "bans": bans,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching IP bans: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IpBanPayload {
+    range: String, // a single address or a CIDR range
+    reason: Option<String>,
+    #[serde(default, deserialize_with = "util::deserialize_utc_opt")]
+    expires_at: Option<DateTime<Utc>>,
+}
+
+pub async fn create_ip_ban(
+    RequirePermission(user, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+    Json(payload): Json<IpBanPayload>,
+) -> Response {
+    let range = payload.range.trim();
+    let range = match range.parse::<IpNet>() {
+        Ok(net) => net.trunc(),
+        Err(_) => match range.parse::<IpAddr>() {
+            Ok(ip) => IpNet::from(ip),
+            Err(_) => {
+                return util::str_response(StatusCode::BAD_REQUEST, "Invalid address or range");
+            }
+        },
+    };
+
+    let range = range.to_string();
+    match db.add_ip_ban(&range, payload.reason.as_deref(), payload.expires_at, user.id).await {
+        Ok(ban) => {
+            ip_bans::invalidate();
+            info!("IP range {} banned by {}", ban.ip_range, user.username);
+            webhooks::emit(
+                &db,
+                WebhookEvent::UserBanned,
+                json!({
+                    "ban_id": ban.id,
+                    "ip_range": ban.ip_range,
+                    "reason": ban.reason,
+                    "expires_at": ban.expires_at,
+                    "banned_by": user.id,
+                }),
+            );
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "ban": ban,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating IP ban: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_ip_ban(
+    RequirePermission(user, _): RequirePermission<ManageUsers>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_ip_ban(id).await {
+        Ok(true) => {
+            ip_bans::invalidate();
+            info!("IP ban {} lifted by {}", id, user.username);
+            util::str_response(StatusCode::OK, &format!("IP ban {} deleted", id))
+        }
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "IP ban not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting IP ban: {}", e),
+        ),
+    }
+}
+
+pub async fn get_settings(_: RequireRole<Admin>) -> Response {
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "settings": settings::current(),
+        }),
+    )
+}
+
+pub async fn update_settings(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(changes): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Response {
+    let updated = match settings::merge(&changes) {
+        Ok(updated) => updated,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    let rows: Vec<(String, String)> =
+        changes.iter().map(|(key, value)| (key.clone(), value.to_string())).collect();
+    if let Err(e) = db.set_settings(&rows, user.id).await {
+        return util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error saving settings: {}", e),
+        );
+    }
+
+    info!("Settings {:?} updated by {}", changes.keys().collect::<Vec<_>>(), user.username);
+    settings::set(updated.clone());
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "settings": updated,
+        }),
+    )
+}
+
+// Same as a SIGHUP, for deployments where signalling the process isn't an option
+pub async fn reload_config(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match reload::reload(&db).await {
+        Ok(reloaded) => {
+            info!("Configuration reloaded by {}", user.username);
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "reloaded": reloaded,
+                }),
+            )
+        }
+        Err(e) => util::str_response(StatusCode::BAD_REQUEST, &e),
+    }
+}
+
+pub async fn get_role_permissions(_: RequireRole<Admin>) -> Response {
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "permissions": Permission::ALL,
+            "roles": permissions::current(),
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct RolePermissionsPayload {
+    permissions: Vec<Permission>,
+}
+
+// Replaces everything the role is allowed to do. Changing what admins can do is
+// allowed, but admin endpoints themselves keep checking the admin role.
+pub async fn set_role_permissions(
+    _: RequireRecentAuth,
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Path(role): Path<database::Role>,
+    Json(payload): Json<RolePermissionsPayload>,
+) -> Response {
+    if let Err(e) = db.set_role_permissions(role, &payload.permissions).await {
+        return util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error saving permissions: {}", e),
+        );
+    }
+
+    info!("Permissions of {} set to {:?} by {}", role, payload.permissions, user.username);
+    permissions::reload(&db).await;
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "role": role,
+            "permissions": permissions::current().remove(&role).unwrap_or_default(),
+        }),
+    )
+}
+
+// Every access to quarantined content is recorded
+async fn audit_quarantine(
+    db: &database::Database,
+    admin: &database::User,
+    id: Option<i64>,
+    action: &str,
+) {
+    info!("Quarantine {} by {} (entry {:?})", action, admin.username, id);
+    if let Err(e) = db.add_quarantine_audit(id, admin.id, action).await {
+        error!("Failed to record quarantine audit entry: {}", e);
+    }
+}
+
+async fn quarantine_entry(
+    db: &database::Database,
+    id: i64,
+) -> Result<database::QuarantineEntry, Response> {
+    db.get_quarantine_entry(id)
+        .await
+        .ok_or_else(|| util::str_response(StatusCode::NOT_FOUND, "Quarantine entry not found"))
+}
+
+pub async fn get_quarantine(
+    RequirePermission(user, _): RequirePermission<ReviewHeld>,
+    State(db): State<database::Database>,
+) -> Response {
+    audit_quarantine(&db, &user, None, "list").await;
+    match db.get_quarantine().await {
+        Ok(entries) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "entries": entries,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching quarantine: {}", e),
+        ),
+    }
+}
+
+pub async fn get_quarantine_image(
+    RequirePermission(user, _): RequirePermission<ReviewHeld>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    let entry = match quarantine_entry(&db, id).await {
+        Ok(entry) => entry,
+        Err(response) => return response,
+    };
+
+    audit_quarantine(&db, &user, Some(id), "view").await;
+    match quarantine::read(&entry).await {
+        Ok(data) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.bin\"", id))
+            .header(header::CACHE_CONTROL, "private, no-store")
+            .body(data.into())
+            .unwrap(),
+        Err(e) => util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+// Sends a false positive from the scanner to the regular pending queue
+pub async fn release_quarantine(
+    RequirePermission(user, _): RequirePermission<ReviewHeld>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    let entry = match quarantine_entry(&db, id).await {
+        Ok(entry) => entry,
+        Err(response) => return response,
+    };
+
+    if entry.source == "hash_match" {
+        return util::str_response(
+            StatusCode::FORBIDDEN,
+            "Known-bad content matches cannot be released",
+        );
+    }
+
+    let uploader = match entry.user_id {
+        Some(user_id) => db.get_user_by_id(user_id).await,
+        None => None,
+    };
+    let Some(uploader) = uploader else {
+        return util::str_response(StatusCode::CONFLICT, "The uploader no longer exists");
+    };
+
+    let data = match quarantine::read(&entry).await {
+        Ok(data) => data,
+        Err(e) => return util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    };
+    let webp_data = match encoder::run(move || upload::process_image(&data)).await {
+        Ok(Ok(data)) => data,
+        Ok(Err(e)) => return util::str_response(StatusCode::UNPROCESSABLE_ENTITY, &e),
+        Err(e) => return util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    };
+
+    let response =
+        upload::release_to_pending(&db, &uploader, &entry.namespace, entry.level_id, &webp_data)
+            .await;
+    if response.status().is_success() {
+        audit_quarantine(&db, &user, Some(id), "release").await;
+        if let Err(e) = quarantine::remove(&db, &entry).await {
+            error!("Failed to remove released quarantine entry {}: {}", id, e);
+        }
+    }
+    response
+}
+
+pub async fn delete_quarantine(
+    RequirePermission(user, _): RequirePermission<ReviewHeld>,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    let entry = match quarantine_entry(&db, id).await {
+        Ok(entry) => entry,
+        Err(response) => return response,
+    };
+
+    audit_quarantine(&db, &user, Some(id), "delete").await;
+    match quarantine::remove(&db, &entry).await {
+        Ok(_) => util::str_response(StatusCode::OK, &format!("Quarantine entry {} deleted", id)),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting quarantine entry: {}", e),
+        ),
+    }
+}
+
+pub async fn get_namespaces(
+    _: RequireRole<Admin>,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_namespaces().await {
+        Ok(namespaces) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "namespaces": namespaces,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching namespaces: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NamespacePayload {
+    name: String,
+    display_name: String,
+}
+
+pub async fn create_namespace(
+    RequireRole(user, _): RequireRole<Admin>,
+    State(db): State<database::Database>,
+    Json(payload): Json<NamespacePayload>,
+) -> Response {
+    if !namespace::is_valid_name(&payload.name) {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "Namespace names are up to 32 lowercase letters, digits and dashes",
+        );
+    }
+
+    match db.add_namespace(&payload.name, &payload.display_name, user.id).await {
+        Ok(namespace) => {
+            info!("Namespace {} created by {}", namespace.name, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "namespace": namespace,
+                }),
+            )
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+            util::str_response(StatusCode::CONFLICT, "A namespace with this name already exists")
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating namespace: {}", e),
+        ),
+    }
+}
+
+// Global admins and admins of the namespace manage its roles, the default namespace
+// only has the global roles
+async fn check_namespace_admin(
+    db: &database::Database,
+    user: &database::User,
+    ns: &str,
+) -> Result<(), Response> {
+    if ns == namespace::DEFAULT {
+        return Err(util::str_response(
+            StatusCode::BAD_REQUEST,
+            "The default namespace uses global roles",
+        ));
+    }
+    namespace::resolve(db, ns).await?;
+
+    if namespace::role(db, user, ns).await != database::Role::Admin {
+        return Err(util::str_response(
+            StatusCode::FORBIDDEN,
+            "Only admins of this namespace can perform this action",
+        ));
+    }
+
+    Ok(())
+}
+
+pub async fn get_namespace_roles(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(ns): Path<String>,
+) -> Response {
+    if let Err(response) = check_namespace_admin(&db, &user, &ns).await {
+        return response;
+    }
+
+    match db.get_namespace_roles(&ns).await {
+        Ok(roles) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "roles": roles,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching namespace roles: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NamespaceRolePayload {
+    role: Option<database::Role>, // null removes the namespace role
+}
+
+pub async fn set_namespace_role(
+    _: RequireRecentAuth,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path((ns, user_id)): Path<(String, UserId)>,
+    Json(payload): Json<NamespaceRolePayload>,
+) -> Response {
+    if let Err(response) = check_namespace_admin(&db, &user, &ns).await {
+        return response;
+    }
+
+    if db.get_user_by_id(user_id).await.is_none() {
+        return util::str_response(StatusCode::NOT_FOUND, "User not found");
+    }
+
+    match db.set_namespace_role(user_id, &ns, payload.role).await {
+        Ok(()) => {
+            info!("Role of {} in {} set to {:?} by {}", user_id, ns, payload.role, user.username);
+            util::str_response(StatusCode::OK, &format!("Role of user {} updated", user_id))
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating namespace role: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CacheQuery {
+    namespace: Option<String>,
+}
+
+// Result of the CDN part of a purge, None when no CDN is configured
+async fn purge_cdn(
+    purge: impl Future<Output = Result<(), cache_controller::PurgeError>>,
+) -> Option<Result<(), String>> {
+    if !cache_controller::cdn_configured() {
+        return None;
+    }
+    Some(purge.await.map_err(|e| format!("{}: {}", e.status, e.body)))
+}
+
+fn purge_response(removed_files: usize, cdn: Option<Result<(), String>>) -> Response {
+    match cdn {
+        Some(Err(e)) => util::response(
+            StatusCode::BAD_GATEWAY,
+            json!({
+                "status": StatusCode::BAD_GATEWAY.as_u16(),
+                "message": format!("CDN purge failed: {}", e),
+                "removed_files": removed_files,
+            }),
+        ),
+        cdn => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "removed_files": removed_files,
+                "cdn_purged": cdn.is_some(),
+            }),
+        ),
+    }
+}
+
+pub async fn purge_cache(
+    RequirePermission(user, _): RequirePermission<PurgeCache>,
+    State(db): State<database::Database>,
+    LevelPath(id): LevelPath<LevelId>,
+    Query(query): Query<CacheQuery>,
+) -> Response {
+    let ns = query.namespace.as_deref().unwrap_or(namespace::DEFAULT);
+    if let Err(response) = namespace::resolve(&db, ns).await {
+        return response;
+    }
+
+    let removed = cache_controller::invalidate(&db, ns, id).await;
+    let cdn = purge_cdn(cache_controller::purge_now(ns, id)).await;
+    info!("Cache for level {} in {} purged by {}", id, ns, user.username);
+    purge_response(removed, cdn)
+}
+
+pub async fn purge_all_cache(
+    _: RequireRecentAuth,
+    RequirePermission(user, _): RequirePermission<PurgeCache>,
+    State(db): State<database::Database>,
+) -> Response {
+    let namespaces = match db.get_namespaces().await {
+        Ok(namespaces) => namespaces,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching namespaces: {}", e),
+            );
+        }
+    };
+
+    let mut removed = 0;
+    for ns in &namespaces {
+        removed += cache_controller::invalidate_namespace(&db, &ns.name).await;
+    }
+    let cdn = purge_cdn(cache_controller::purge_everything()).await;
+    info!("All caches purged by {}", user.username);
+    tokio::spawn(warmup::run(db.clone()));
+    purge_response(removed, cdn)
+}
+
+pub async fn get_cache_stats(
+    RequirePermission(_, _): RequirePermission<PurgeCache>,
+    State(db): State<database::Database>,
+) -> Response {
+    let namespaces = match db.get_namespaces().await {
+        Ok(namespaces) => namespaces,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching namespaces: {}", e),
+            );
+        }
+    };
+
+    let mut caches = serde_json::Map::new();
+    for ns in namespaces {
+        let stats = cache_controller::cache_stats(&ns.name).await;
+        caches.insert(ns.name, json!(stats));
+    }
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "caches": caches,
+            "cdn_configured": cache_controller::cdn_configured(),
+        }),
+    )
+}
+
+// Health of the third-party services we call, for the admin dashboard
+pub async fn get_outbound_stats(_: RequireRole<Admin>) -> Response {
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "alert_error_rate": settings::current().outbound_alert_error_rate,
+            "destinations": outbound::summary(),
+        }),
+    )
+}
@@ -0,0 +1,22 @@
+use crate::{database, util};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::json;
+
+// Polled by the mod and the dashboard, only returns what is currently active
+pub async fn get_announcements(State(db): State<database::Database>) -> Response {
+    match db.get_active_announcements().await {
+        Ok(announcements) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "announcements": announcements,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching announcements: {}", e),
+        ),
+    }
+}
@@ -0,0 +1,202 @@
+use crate::auth::AuthedUser;
+use crate::models::LevelId;
+use crate::{database, level_path, util};
+use axum::Json;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+const MAX_NAME_LENGTH: usize = 100;
+const MAX_LEVELS: usize = 500;
+const MAX_COLLECTIONS_PER_USER: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct CollectionPayload {
+    name: String,
+    level_ids: Vec<LevelId>,
+}
+
+impl CollectionPayload {
+    // Drops repeated levels but keeps the order the owner picked
+    fn validate(&mut self) -> Result<database::NewCollection<'_>, String> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(format!("A name of up to {} characters is required", MAX_NAME_LENGTH));
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        self.level_ids.retain(|id| seen.insert(*id));
+        if self.level_ids.len() > MAX_LEVELS {
+            return Err(format!("A collection can hold at most {} levels", MAX_LEVELS));
+        }
+        for &id in &self.level_ids {
+            level_path::check(id)?;
+        }
+
+        Ok(database::NewCollection {
+            name,
+            level_ids: &self.level_ids,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct CollectionThumbnail {
+    level_id: LevelId,
+    url: Option<String>, // None for levels without a thumbnail yet
+}
+
+pub async fn get_collection(State(db): State<database::Database>, Path(id): Path<i64>) -> Response {
+    let collection = match db.get_collection(id).await {
+        Ok(Some(collection)) => collection,
+        Ok(None) => return util::str_response(StatusCode::NOT_FOUND, "Collection not found"),
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching collection: {}", e),
+            );
+        }
+    };
+
+    let existing = match db.get_existing_levels(&collection.level_ids).await {
+        Ok(existing) => existing,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching thumbnails: {}", e),
+            );
+        }
+    };
+
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+    let thumbnails: Vec<CollectionThumbnail> = collection
+        .level_ids
+        .iter()
+        .map(|&level_id| CollectionThumbnail {
+            level_id,
+            url: existing
+                .contains(&level_id)
+                .then(|| format!("{}/thumbnail/{}", home_url, level_id)),
+        })
+        .collect();
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "collection": collection,
+            "thumbnails": thumbnails,
+        }),
+    )
+}
+
+pub async fn get_my_collections(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_user_collections(user.id).await {
+        Ok(collections) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "collections": collections,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching collections: {}", e),
+        ),
+    }
+}
+
+pub async fn create_collection(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(mut payload): Json<CollectionPayload>,
+) -> Response {
+    match db.count_user_collections(user.id).await {
+        Ok(count) if count >= MAX_COLLECTIONS_PER_USER => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                &format!("You can have at most {} collections", MAX_COLLECTIONS_PER_USER),
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error counting collections: {}", e),
+            );
+        }
+    }
+
+    let collection = match payload.validate() {
+        Ok(collection) => collection,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    match db.add_collection(collection, user.id).await {
+        Ok(collection) => {
+            info!("Collection {} created by {}", collection.id, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "collection": collection,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating collection: {}", e),
+        ),
+    }
+}
+
+pub async fn update_collection(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+    Json(mut payload): Json<CollectionPayload>,
+) -> Response {
+    let collection = match payload.validate() {
+        Ok(collection) => collection,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    match db.update_collection(id, user.id, collection).await {
+        Ok(Some(collection)) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "collection": collection,
+            }),
+        ),
+        Ok(None) => util::str_response(StatusCode::NOT_FOUND, "Collection not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating collection: {}", e),
+        ),
+    }
+}
+
+pub async fn delete_collection(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    match db.delete_collection(id, user.id).await {
+        Ok(true) => {
+            info!("Collection {} deleted by {}", id, user.username);
+            util::str_response(StatusCode::OK, &format!("Collection {} deleted", id))
+        }
+        Ok(false) => util::str_response(StatusCode::NOT_FOUND, "Collection not found"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error deleting collection: {}", e),
+        ),
+    }
+}
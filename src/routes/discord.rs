@@ -0,0 +1,159 @@
+use crate::models::LevelId;
+use crate::routes::upload::{self, PendingUploadAction};
+use crate::{database, namespace, two_factor, util};
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::Response;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde_json::{Value, json};
+
+// https://discord.com/developers/docs/interactions/receiving-and-responding
+const INTERACTION_PING: u64 = 1;
+const INTERACTION_APPLICATION_COMMAND: u64 = 2;
+const RESPONSE_PONG: u64 = 1;
+const RESPONSE_CHANNEL_MESSAGE: u64 = 4;
+const FLAG_EPHEMERAL: u64 = 1 << 6;
+
+fn verify_signature(headers: &HeaderMap, body: &[u8]) -> bool {
+    let Ok(public_key) = dotenv::var("DISCORD_PUBLIC_KEY") else {
+        return false;
+    };
+
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+    let (Some(signature), Some(timestamp)) =
+        (header("X-Signature-Ed25519"), header("X-Signature-Timestamp"))
+    else {
+        return false;
+    };
+
+    let key = hex::decode(public_key).ok().and_then(|k| <[u8; 32]>::try_from(k).ok());
+    let signature = hex::decode(signature).ok().and_then(|s| <[u8; 64]>::try_from(s).ok());
+    let (Some(key), Some(signature)) = (key, signature) else {
+        return false;
+    };
+
+    let Ok(key) = VerifyingKey::from_bytes(&key) else {
+        return false;
+    };
+
+    let message = [timestamp.as_bytes(), body].concat();
+    key.verify(&message, &Signature::from_bytes(&signature)).is_ok()
+}
+
+fn reply(content: &str) -> Response {
+    util::response(
+        StatusCode::OK,
+        json!({
+            "type": RESPONSE_CHANNEL_MESSAGE,
+            "data": {
+                "content": content,
+                "flags": FLAG_EPHEMERAL,
+            },
+        }),
+    )
+}
+
+fn option<'a>(options: &'a Value, name: &str) -> Option<&'a Value> {
+    options.as_array()?.iter().find(|o| o["name"] == name).map(|o| &o["value"])
+}
+
+// Discord sends snowflakes as strings, and commands may come from a guild or a DM
+fn invoking_user_id(interaction: &Value) -> Option<i64> {
+    interaction["member"]["user"]["id"]
+        .as_str()
+        .or_else(|| interaction["user"]["id"].as_str())?
+        .parse()
+        .ok()
+}
+
+async fn thumbnail_command(db: &database::Database, options: &Value) -> Response {
+    let Some(level_id) = option(options, "id").and_then(|v| v.as_i64()).map(LevelId) else {
+        return reply("Missing level ID");
+    };
+
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+    match db.get_upload_info(namespace::DEFAULT, level_id).await {
+        Some(info) => reply(&format!("{}/thumbnail/{} (by {})", home_url, level_id, info.username)),
+        None => reply(&format!("Level {} has no thumbnail", level_id)),
+    }
+}
+
+async fn pending_command(db: &database::Database, options: &Value) -> Response {
+    let subcommand = options.as_array().and_then(|o| o.first()).and_then(|o| o["name"].as_str());
+    match subcommand {
+        Some("count") => match db.count_pending_uploads().await {
+            Ok(count) => reply(&format!("There are {} pending upload(s)", count)),
+            Err(e) => reply(&format!("Error counting pending uploads: {}", e)),
+        },
+        _ => reply("Unknown subcommand"),
+    }
+}
+
+async fn approve_command(
+    db: &database::Database,
+    interaction: &Value,
+    options: &Value,
+) -> Response {
+    let Some(upload_id) = option(options, "id").and_then(|v| v.as_i64()) else {
+        return reply("Missing upload ID");
+    };
+
+    // Discord users act with the role of the account linked to their Discord ID
+    let moderator = match invoking_user_id(interaction) {
+        Some(discord_id) => db.get_user_by_discord_id(discord_id).await,
+        None => None,
+    };
+    let Some(mut moderator) = moderator else {
+        return reply("Your Discord account is not linked to a thumbnails account");
+    };
+    // a Discord command never went through a second factor, it gets what such a session gets
+    two_factor::limit_unverified(&mut moderator);
+    if moderator.limited {
+        return reply("Staff actions need two-factor authentication, use the dashboard instead");
+    }
+
+    // decide_upload checks the role the account has in the upload's namespace, same as over HTTP
+    let action = PendingUploadAction {
+        accepted: true,
+        reason: None,
+        edits: None,
+    };
+    let response = upload::decide_upload(db, &moderator, upload_id, action).await;
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap_or_default();
+    let message = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|v| v["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "Unknown result".to_string());
+    reply(&message)
+}
+
+pub async fn interactions(
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+    body: Bytes,
+) -> Response {
+    if !verify_signature(&headers, &body) {
+        return util::str_response(StatusCode::UNAUTHORIZED, "Invalid request signature");
+    }
+
+    let interaction: Value = match serde_json::from_slice(&body) {
+        Ok(interaction) => interaction,
+        Err(_) => return util::str_response(StatusCode::BAD_REQUEST, "Invalid interaction"),
+    };
+
+    match interaction["type"].as_u64() {
+        Some(INTERACTION_PING) => util::response(StatusCode::OK, json!({ "type": RESPONSE_PONG })),
+        Some(INTERACTION_APPLICATION_COMMAND) => {
+            let options = &interaction["data"]["options"];
+            match interaction["data"]["name"].as_str() {
+                Some("thumbnail") => thumbnail_command(&db, options).await,
+                Some("pending") => pending_command(&db, options).await,
+                Some("approve") => approve_command(&db, &interaction, options).await,
+                _ => reply("Unknown command"),
+            }
+        }
+        _ => util::str_response(StatusCode::BAD_REQUEST, "Unsupported interaction type"),
+    }
+}
@@ -0,0 +1,23 @@
+use crate::database;
+use crate::feature_flags::{self, Flag};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::{Map, Value, json};
+
+// Lets clients know which gated features to show
+pub async fn get_flags(State(db): State<database::Database>) -> Response {
+    let mut flags = Map::new();
+    for flag in Flag::ALL {
+        let enabled = feature_flags::is_enabled(&db, *flag).await;
+        flags.insert(flag.to_string(), Value::Bool(enabled));
+    }
+
+    crate::util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "flags": flags,
+        }),
+    )
+}
@@ -0,0 +1,32 @@
+use crate::client_ip::ClientIp;
+use crate::rate_limit::{self, Bucket};
+use crate::{database, graphql, util};
+use async_graphql::http::GraphiQLSource;
+use axum::Json;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::Response;
+
+pub async fn graphql_handler(
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+    ip: Option<ClientIp>,
+    Json(request): Json<async_graphql::Request>,
+) -> Response {
+    if let Some(response) = rate_limit::check(Bucket::Graphql, ip) {
+        return response;
+    }
+
+    // anonymous requests are allowed, resolvers enforce their own auth rules
+    let user = util::auth_middleware(&headers, &db).await.ok();
+    let response = graphql::schema().execute(request.data(db).data(user)).await;
+
+    util::response(StatusCode::OK, serde_json::to_value(&response).unwrap())
+}
+
+pub async fn graphiql() -> Response {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish().into())
+        .unwrap()
+}
@@ -1,20 +1,26 @@
-use crate::{auth, database, util};
+use crate::auth::AuthedUser;
+use crate::client_ip::{self, ClientIp};
+use crate::models::{AccountId, UserId};
+use crate::recent_auth::RequireRecentAuth;
+use crate::two_factor::{self, Verification};
+use crate::{auth, cookies, csrf, database, login_throttle, oauth, outbound, paths, util};
 use auth::UserSession;
 use axum::Json;
 use axum::extract::{Query, State};
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
 use axum::response::Response;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use std::env;
-use tracing::info;
+use tracing::{error, info};
 
 #[derive(Deserialize, Debug)]
 pub struct LoginPayload {
-    account_id: i64,
+    account_id: AccountId,
     user_id: i64,
     username: String,
     argon_token: String,
+    #[allow(dead_code)]
     discord_token: Option<String>,
 }
 
@@ -35,8 +41,37 @@ fn handle_verdict_error(verdict: auth::Verdict) -> Response {
     )
 }
 
+const CHALLENGE_COOKIE: &str = "mfa_challenge";
+
+fn two_factor_required(challenge: &str) -> Response {
+    util::response(
+        StatusCode::UNAUTHORIZED,
+        json!({
+            "status": StatusCode::UNAUTHORIZED.as_u16(),
+            "message": "Two-factor authentication required",
+            "two_factor_required": true,
+            "challenge": challenge,
+        }),
+    )
+}
+
+fn throttled(retry_after: std::time::Duration) -> Response {
+    let seconds = retry_after.as_secs_f64().ceil() as u64;
+    let mut response = util::response(
+        StatusCode::TOO_MANY_REQUESTS,
+        json!({
+            "status": StatusCode::TOO_MANY_REQUESTS.as_u16(),
+            "message": format!("Too many login attempts, try again in {} seconds", seconds),
+            "retry_after": seconds,
+        }),
+    );
+    response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
 pub async fn login(
     State(db): State<database::Database>,
+    ip: Option<ClientIp>,
     Json(payload): Json<LoginPayload>,
 ) -> Response {
     info!(
@@ -44,6 +79,12 @@ pub async fn login(
         payload.account_id, payload.user_id, payload.username
     );
 
+    // refused before Argon is asked, so guessing doesn't cost an upstream request each
+    let client_ip = ip.as_ref().map(|ip| ip.0);
+    if let Err(retry_after) = login_throttle::check(client_ip, payload.account_id) {
+        return throttled(retry_after);
+    }
+
     // Validate argon token
     let verdict = match auth::ArgonClient::get()
         .verify(payload.account_id, payload.user_id, &payload.username, &payload.argon_token)
@@ -65,16 +106,23 @@ pub async fn login(
     // Find or create entry in the database
     match verdict {
         auth::Verdict::Strong => {
+            login_throttle::succeed(client_ip, payload.account_id);
             match db.find_or_create_user(payload.account_id, &payload.username).await {
-                Ok(user) => util::response(
-                    StatusCode::OK,
-                    json!({
-                        "status": StatusCode::OK.as_u16(),
-                        "message": "User authenticated successfully",
-                        "user": user,
-                        "token": UserSession::new(user.id, payload.username).to_jwt(),
-                    }),
-                ),
+                Ok(user) => {
+                    client_ip::record(&db, user.id, ip, database::IpAction::Login).await;
+                    match two_factor::login(&db, &user, false).await {
+                        two_factor::Login::Session(token) => util::response(
+                            StatusCode::OK,
+                            json!({
+                                "status": StatusCode::OK.as_u16(),
+                                "message": "User authenticated successfully",
+                                "user": user,
+                                "token": token,
+                            }),
+                        ),
+                        two_factor::Login::Challenge(challenge) => two_factor_required(&challenge),
+                    }
+                }
                 Err(e) => util::response(
                     StatusCode::INTERNAL_SERVER_ERROR,
                     json!({
@@ -85,27 +133,86 @@ pub async fn login(
                 ),
             }
         }
-        verdict => handle_verdict_error(verdict),
+        verdict => {
+            login_throttle::fail(client_ip, payload.account_id);
+            handle_verdict_error(verdict)
+        }
     }
 }
 
+#[derive(Deserialize)]
+pub struct DiscordStartQuery {
+    redirect_uri: Option<String>, // one of DISCORD_REDIRECT_URIS, the first one when missing
+}
+
+// Starts the Discord login with a fresh state and PKCE challenge, the callback
+// below only accepts codes from flows started here
+pub async fn discord_oauth_start(Query(query): Query<DiscordStartQuery>) -> Response {
+    let Some(redirect_uri) = oauth::redirect_uri(query.redirect_uri.as_deref()) else {
+        return util::str_response(StatusCode::BAD_REQUEST, "Redirect URI is not allowed");
+    };
+
+    let client_id = env::var("DISCORD_CLIENT_ID").expect("DISCORD_CLIENT_ID must be set");
+    let pending = oauth::PendingLogin::new(redirect_uri);
+    let challenge = pending.challenge();
+    let url = reqwest::Url::parse_with_params(
+        "https://discord.com/oauth2/authorize",
+        [
+            ("client_id", client_id.as_str()),
+            ("response_type", "code"),
+            ("scope", "identify"),
+            ("redirect_uri", &pending.redirect_uri),
+            ("state", &pending.state),
+            ("code_challenge", &challenge),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .expect("Discord authorize URL is valid");
+
+    let cookie = cookies::cross_site_redirect(
+        oauth::STATE_COOKIE,
+        &pending.to_cookie(),
+        "/auth",
+        oauth::STATE_TTL.num_seconds(),
+    );
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header("Set-Cookie", cookie)
+        .header("Location", url.as_str())
+        .body("Redirecting to Discord...".into())
+        .unwrap()
+}
+
 #[derive(Deserialize, Debug)]
 pub struct DiscordOAuthPayload {
     code: String,
+    state: Option<String>,
 }
 
 pub async fn discord_oauth_handler(
     Query(query): Query<DiscordOAuthPayload>,
+    headers: HeaderMap,
     State(db): State<database::Database>,
+    ip: Option<ClientIp>,
 ) -> Response {
     if query.code.is_empty() {
         return util::str_response(StatusCode::BAD_REQUEST, "Missing code parameter");
     }
 
-    let client = reqwest::Client::new();
+    // the state has to match the one handed out to this browser, otherwise someone
+    // could log the victim into an account of their choosing
+    let pending = util::try_read_cookie(&headers, &format!("{}=", oauth::STATE_COOKIE))
+        .and_then(|cookie| oauth::PendingLogin::from_cookie(&cookie));
+    let Some(pending) = pending.filter(|pending| query.state.as_ref() == Some(&pending.state))
+    else {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid or expired login state, please try logging in again",
+        );
+    };
 
     // Use the code to fetch user info from Discord
-    let res = match client
+    let request = outbound::DISCORD
         .post("https://discord.com/api/oauth2/token")
         .header("Content-Type", "application/x-www-form-urlencoded")
         .form(&[
@@ -116,14 +223,10 @@ pub async fn discord_oauth_handler(
             ),
             ("code", query.code),
             ("grant_type", "authorization_code".to_string()),
-            (
-                "redirect_uri",
-                format!("{}/auth/discord", env::var("HOME_URL").expect("HOME_URL must be set")),
-            ),
-        ])
-        .send()
-        .await
-    {
+            ("redirect_uri", pending.redirect_uri),
+            ("code_verifier", pending.verifier),
+        ]);
+    let res = match outbound::DISCORD.send(request).await {
         Ok(response) => match response.json::<Value>().await {
             Ok(json) => json,
             Err(_) => {
@@ -141,17 +244,14 @@ pub async fn discord_oauth_handler(
         }
     };
 
-    if !res.get("access_token").is_some() {
+    if res.get("access_token").is_none() {
         return util::str_response(StatusCode::UNAUTHORIZED, "Invalid Discord code");
     }
 
     let access_token = res["access_token"].as_str().unwrap();
-    let user_info = match client
-        .get("https://discord.com/api/users/@me")
-        .bearer_auth(access_token)
-        .send()
-        .await
-    {
+    let request =
+        outbound::DISCORD.get("https://discord.com/api/users/@me").bearer_auth(access_token);
+    let user_info = match outbound::DISCORD.send(request).await {
         Ok(response) => match response.json::<Value>().await {
             Ok(json) => json,
             Err(_) => {
@@ -184,72 +284,195 @@ pub async fn discord_oauth_handler(
     let username = user_info["username"].as_str().unwrap_or("");
     match db.find_or_create_user_discord(discord_id, username).await {
         Ok(user) => {
-            let token = UserSession::new(user.id, user.username.clone()).to_jwt();
-            Response::builder()
-                .status(StatusCode::FOUND)
-                .header("Set-Cookie", format!("auth_token={}; HttpOnly; Path=/; SameSite=Lax; Expires=Fri, 31 Dec 9999 23:59:59 GMT", token))
-                .header("Set-Cookie", format!("auth_role={}; Path=/; SameSite=Lax; Expires=Fri, 31 Dec 9999 23:59:59 GMT", user.role.to_string()))
-                .header("Location", "/dashboard")
-                .body("Redirecting to dashboard...".into())
-                .unwrap()
+            if let Err(e) = db.set_discord_avatar(user.id, user_info["avatar"].as_str()).await {
+                error!("Failed to store Discord avatar of {}: {}", user.id, e);
+            }
+            client_ip::record(&db, user.id, ip, database::IpAction::Login).await;
+            match two_factor::login(&db, &user, true).await {
+                two_factor::Login::Session(token) => {
+                    let [auth_cookie, role_cookie, csrf_cookie] =
+                        cookies::session(&token, user.role);
+                    Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header("Set-Cookie", auth_cookie)
+                        .header("Set-Cookie", role_cookie)
+                        .header("Set-Cookie", csrf_cookie)
+                        .header("Set-Cookie", cookies::clear(oauth::STATE_COOKIE, "/auth", true))
+                        .header("Location", "/dashboard")
+                        .body("Redirecting to dashboard...".into())
+                        .unwrap()
+                }
+                // the dashboard asks for the code and finishes the login through /auth/2fa
+                two_factor::Login::Challenge(challenge) => Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(
+                        "Set-Cookie",
+                        cookies::temporary(CHALLENGE_COOKIE, &challenge, "/auth", 300),
+                    )
+                    .header("Set-Cookie", cookies::clear(oauth::STATE_COOKIE, "/auth", true))
+                    .header("Location", "/dashboard?two_factor=required")
+                    .body("Redirecting to dashboard...".into())
+                    .unwrap(),
+            }
         }
         Err(e) => util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
     }
 }
 
-pub async fn get_session(headers: HeaderMap, State(db): State<database::Database>) -> Response {
-    match util::auth_middleware(&headers, &db).await {
-        Ok(user) => util::response(
-            StatusCode::OK,
-            json!({
-                "status": StatusCode::OK.as_u16(),
-                "user": user,
-            }),
-        ),
-        Err(response) => response,
+#[derive(Deserialize)]
+pub struct TwoFactorLoginPayload {
+    challenge: Option<String>, // read from the cookie set by the Discord login when missing
+    code: String,              // TOTP or recovery code
+}
+
+// Second step of a login for users with 2FA enabled
+pub async fn two_factor_login(
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+    Json(payload): Json<TwoFactorLoginPayload>,
+) -> Response {
+    let challenge = payload
+        .challenge
+        .or_else(|| util::try_read_cookie(&headers, &format!("{}=", CHALLENGE_COOKIE)));
+    let Some((user_id, dashboard)) = challenge.as_deref().and_then(two_factor::decode_challenge)
+    else {
+        return util::str_response(StatusCode::UNAUTHORIZED, "Invalid or expired challenge");
+    };
+
+    complete_two_factor(&db, user_id, dashboard, &payload.code).await
+}
+
+// Re-confirms a logged in 2FA user, for endpoints that need a recent login
+pub async fn confirm_two_factor(
+    AuthedUser(user): AuthedUser,
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+    Json(payload): Json<TwoFactorCodePayload>,
+) -> Response {
+    let dashboard = util::is_dashboard_session(&headers);
+    complete_two_factor(&db, user.id, dashboard, &payload.code).await
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodePayload {
+    code: String,
+}
+
+// Checks the code and hands out a fresh session that counts as 2FA verified
+async fn complete_two_factor(
+    db: &database::Database,
+    user_id: UserId,
+    dashboard: bool,
+    code: &str,
+) -> Response {
+    let Some(user) = db.get_user_by_id(user_id).await else {
+        return util::str_response(StatusCode::FORBIDDEN, "User not found");
+    };
+
+    match two_factor::verify(db, user.id, code).await {
+        Ok(Verification::Valid) => {}
+        Ok(Verification::Invalid) => {
+            return util::str_response(StatusCode::UNAUTHORIZED, "Invalid code");
+        }
+        Ok(Verification::TooManyAttempts) => {
+            return util::str_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many invalid codes, try again later",
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error checking code: {}", e),
+            );
+        }
     }
+
+    info!("{} confirmed their second factor", user.username);
+    let token = UserSession::new(user.id, user.username.clone())
+        .with_mfa()
+        .for_dashboard(dashboard)
+        .to_jwt();
+    let session_cookies = cookies::session(&token, user.role);
+    let mut response = util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": "User authenticated successfully",
+            "user": user,
+            "token": token,
+        }),
+    );
+    let clear_challenge = cookies::clear(CHALLENGE_COOKIE, "/auth", true);
+    cookies::append(&mut response, &session_cookies);
+    cookies::append(&mut response, &[clear_challenge]);
+    response
+}
+
+// For dashboards that lost the csrf_token cookie, the token belongs to the session used here
+pub async fn get_csrf_token(_: AuthedUser, headers: HeaderMap) -> Response {
+    let token = util::session_token(&headers).map(|token| csrf::token(&token));
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "csrf_token": token,
+        }),
+    )
+}
+
+// Ends a dashboard session, tokens handed to other clients stay valid
+pub async fn logout() -> Response {
+    let mut response = util::str_response(StatusCode::OK, "Logged out");
+    cookies::append(&mut response, &cookies::clear_session());
+    response
+}
+
+pub async fn get_session(AuthedUser(user): AuthedUser) -> Response {
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "user": user,
+        }),
+    )
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 struct LinkToken {
-    id: i64,
+    id: UserId,
     exp: u64,
 }
 
-pub async fn get_link_token(headers: HeaderMap, State(db): State<database::Database>) -> Response {
-    match util::auth_middleware(&headers, &db).await {
-        Ok(user) => {
-            if user.account_id != -1 {
-                return util::str_response(
-                    StatusCode::BAD_REQUEST,
-                    "You already have a Geometry Dash account linked",
-                );
-            }
+pub async fn get_link_token(AuthedUser(user): AuthedUser) -> Response {
+    if user.account_id != AccountId(-1) {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "You already have a Geometry Dash account linked",
+        );
+    }
 
-            let link_token = LinkToken {
-                id: user.id,
-                exp: (chrono::Utc::now() + chrono::Duration::minutes(10)).timestamp() as u64,
-            };
+    let link_token = LinkToken {
+        id: user.id,
+        exp: (chrono::Utc::now() + chrono::Duration::minutes(10)).timestamp() as u64,
+    };
 
-            let jwt_secret = dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set");
-            let token = jsonwebtoken::encode(
-                &jsonwebtoken::Header::default(),
-                &link_token,
-                &jsonwebtoken::EncodingKey::from_secret(jwt_secret.as_bytes()),
-            )
-            .expect("Failed to encode JWT");
+    let jwt_secret = auth::secret();
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &link_token,
+        &jsonwebtoken::EncodingKey::from_secret(jwt_secret.as_bytes()),
+    )
+    .expect("Failed to encode JWT");
 
-            util::response(
-                StatusCode::OK,
-                json!({
-                    "status": StatusCode::OK.as_u16(),
-                    "message": "Link token generated successfully",
-                    "token": token,
-                }),
-            )
-        }
-        Err(response) => response,
-    }
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": "Link token generated successfully",
+            "token": token,
+        }),
+    )
 }
 
 #[derive(Deserialize, Debug)]
@@ -259,34 +482,46 @@ pub struct LinkPayload {
 
 async fn migrate_account(
     db: &database::Database,
-    user_id: i64,    // Geometry Dash user ID
-    discord_id: i64, // Discord user ID
+    user_id: UserId,    // Geometry Dash user ID
+    discord_id: UserId, // Discord user ID
+    session: &UserSession,
 ) -> Response {
     let pending = db.get_pending_uploads_for_user(user_id).await;
 
     match db.migrate_user_account(user_id, discord_id).await {
         Ok(user) => {
-            match pending {
-                Ok(uploads) => {
-                    for upload in uploads {
-                        tokio::fs::rename(
-                            format!("uploads/{}_{}.webp", user_id, upload.level_id),
-                            format!("uploads/{}_{}.webp", discord_id, upload.level_id),
-                        )
-                        .await
-                        .unwrap_or(());
-                    }
+            if let Ok(uploads) = pending {
+                for upload in uploads {
+                    tokio::fs::rename(
+                        paths::local(paths::pending_path(
+                            &upload.namespace,
+                            user_id,
+                            upload.level_id,
+                        )),
+                        paths::local(paths::pending_path(
+                            &upload.namespace,
+                            discord_id,
+                            upload.level_id,
+                        )),
+                    )
+                    .await
+                    .unwrap_or(());
                 }
-                Err(_) => {}
             }
 
+            // the new session keeps the second factor and origin of the one that linked it
+            let mut token =
+                UserSession::new(user.id, user.username.clone()).for_dashboard(session.dashboard);
+            if session.mfa {
+                token = token.with_mfa();
+            }
             util::response(
                 StatusCode::OK,
                 json!({
                     "status": StatusCode::OK.as_u16(),
                     "message": "Account linked successfully",
                     "user": user,
-                    "token": UserSession::new(user.id, user.username.clone()).to_jwt(),
+                    "token": token.to_jwt(),
                 }),
             )
         }
@@ -295,37 +530,41 @@ async fn migrate_account(
 }
 
 pub async fn link_account(
+    _: RequireRecentAuth,
+    AuthedUser(user): AuthedUser,
     headers: HeaderMap,
     State(db): State<database::Database>,
     Json(payload): Json<LinkPayload>,
 ) -> Response {
-    match util::auth_middleware(&headers, &db).await {
-        Ok(user) => {
-            if user.discord_id.is_some() {
-                return util::str_response(
-                    StatusCode::BAD_REQUEST,
-                    "You already have a Discord account linked",
-                );
-            }
+    if user.discord_id.is_some() {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "You already have a Discord account linked",
+        );
+    }
 
-            let jwt_secret = dotenv::var("JWT_SECRET").expect("JWT_SECRET must be set");
-            let validation = jsonwebtoken::Validation::default();
-            match jsonwebtoken::decode::<LinkToken>(
-                &payload.token,
-                &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_bytes()),
-                &validation,
-            ) {
-                Ok(decoded) => {
-                    migrate_account(
-                        &db,
-                        user.id,           // Geometry Dash user ID
-                        decoded.claims.id, // Discord user ID
-                    )
-                    .await
-                }
-                Err(_) => util::str_response(StatusCode::UNAUTHORIZED, "Invalid link token"),
-            }
+    let Some(session) =
+        util::session_token(&headers).and_then(|token| UserSession::from_jwt(&token).ok())
+    else {
+        return util::str_response(StatusCode::UNAUTHORIZED, "Invalid session");
+    };
+
+    let jwt_secret = auth::secret();
+    let validation = jsonwebtoken::Validation::default();
+    match jsonwebtoken::decode::<LinkToken>(
+        &payload.token,
+        &jsonwebtoken::DecodingKey::from_secret(jwt_secret.as_bytes()),
+        &validation,
+    ) {
+        Ok(decoded) => {
+            migrate_account(
+                &db,
+                user.id,           // Geometry Dash user ID
+                decoded.claims.id, // Discord user ID
+                &session,
+            )
+            .await
         }
-        Err(response) => response,
+        Err(_) => util::str_response(StatusCode::UNAUTHORIZED, "Invalid link token"),
     }
 }
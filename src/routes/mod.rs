@@ -1,4 +1,14 @@
+pub mod admin;
+pub mod announcements;
+pub mod collections;
+pub mod discord;
+pub mod flags;
+pub mod graphql;
 pub mod login;
+pub mod ops;
+pub mod stats;
+pub mod sync;
 pub mod thumbnail;
 pub mod upload;
 pub mod user;
+pub mod ws;
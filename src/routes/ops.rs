@@ -0,0 +1,52 @@
+use crate::{database, namespace, paths};
+use axum::extract::State;
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use std::fmt::Write;
+
+pub async fn healthz(State(db): State<database::Database>) -> Response {
+    let (status, body) = match sqlx::query("SELECT 1").execute(&*db.pool).await {
+        Ok(_) => (StatusCode::OK, "ok"),
+        Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "database unavailable"),
+    };
+
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(body.into())
+        .unwrap()
+}
+
+// Prometheus text exposition format
+pub async fn metrics(State(db): State<database::Database>) -> Response {
+    let mut body = String::new();
+    let mut gauge = |name: &str, help: &str, value: u64| {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} gauge", name);
+        let _ = writeln!(body, "{} {}", name, value);
+    };
+
+    let (storage_size, thumbnails) =
+        crate::get_dir_stats(&paths::local(paths::thumbnail_dir(namespace::DEFAULT)))
+            .await
+            .unwrap_or((0, 0));
+    gauge("thumbnails_total", "Number of accepted thumbnails on disk", thumbnails as u64);
+    gauge("thumbnails_storage_bytes", "Size of accepted thumbnails on disk", storage_size);
+
+    if let Ok(pending) = db.count_pending_uploads().await {
+        gauge("pending_uploads", "Number of uploads waiting for moderation", pending as u64);
+    }
+
+    gauge("db_pool_connections", "Open database connections", db.pool.size() as u64);
+    gauge("db_pool_idle_connections", "Idle database connections", db.pool.num_idle() as u64);
+    crate::outbound::write_metrics(&mut body);
+    crate::retention::write_metrics(&mut body);
+    crate::login_throttle::write_metrics(&mut body);
+    crate::attestation::write_metrics(&mut body);
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body.into())
+        .unwrap()
+}
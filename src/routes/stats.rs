@@ -0,0 +1,91 @@
+use crate::level_path::LevelPath;
+use crate::models::LevelId;
+use crate::{database, util};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde::Deserialize;
+use serde_json::json;
+
+const DEFAULT_DAYS: i64 = 30;
+const MAX_DAYS: i64 = 365;
+const DEFAULT_TOP_LIMIT: i64 = 50;
+const MAX_TOP_LIMIT: i64 = 100;
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    days: Option<i64>,
+    limit: Option<i64>,
+}
+
+impl StatsQuery {
+    fn days(&self) -> i64 {
+        self.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS)
+    }
+}
+
+pub async fn get_level_stats(
+    State(db): State<database::Database>,
+    LevelPath(id): LevelPath<LevelId>,
+    Query(query): Query<StatsQuery>,
+) -> Response {
+    match db.get_level_views(id, query.days()).await {
+        Ok(daily) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "level_id": id,
+                "days": query.days(),
+                "total": daily.iter().map(|d| d.views).sum::<i64>(),
+                "daily": daily,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching level stats: {}", e),
+        ),
+    }
+}
+
+pub async fn get_top_levels(
+    State(db): State<database::Database>,
+    Query(query): Query<StatsQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT).clamp(1, MAX_TOP_LIMIT);
+    match db.get_top_levels(query.days(), limit).await {
+        Ok(levels) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "days": query.days(),
+                "levels": levels,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching top levels: {}", e),
+        ),
+    }
+}
+
+// Uploads by where they were submitted from, to see how much comes from outside the game
+pub async fn get_upload_sources(
+    State(db): State<database::Database>,
+    Query(query): Query<StatsQuery>,
+) -> Response {
+    match db.get_upload_source_counts(query.days()).await {
+        Ok(counts) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "days": query.days(),
+                "total": counts.iter().map(|c| c.uploads).sum::<i64>(),
+                "sources": counts,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching upload sources: {}", e),
+        ),
+    }
+}
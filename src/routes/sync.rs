@@ -0,0 +1,84 @@
+use crate::database::SyncAction;
+use crate::{database, namespace, paths, sync, util};
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use serde::Deserialize;
+use serde_json::json;
+
+const DEFAULT_PAGE_SIZE: i64 = 500;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    since: Option<i64>,
+    limit: Option<i64>,
+}
+
+pub async fn get_changes(
+    State(db): State<database::Database>,
+    Query(query): Query<ChangesQuery>,
+) -> Response {
+    let since = query.since.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    let mut changes = match db.get_sync_changes(since, limit).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching changes: {}", e),
+            );
+        }
+    };
+
+    // entries seeded by the migration don't have a hash yet
+    for change in &mut changes {
+        if change.action != SyncAction::Accepted || change.content_hash.is_some() {
+            continue;
+        }
+
+        let path = paths::thumbnail_path(namespace::DEFAULT, change.level_id);
+        if let Ok(hash) = sync::hash_file(&path).await
+            && db.set_sync_change_hash(change.cursor, &hash).await.is_ok()
+        {
+            change.content_hash = Some(hash);
+        }
+    }
+
+    let next_cursor = changes.last().map(|c| c.cursor).unwrap_or(since);
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "changes": changes,
+            "next_cursor": next_cursor,
+        }),
+    )
+}
+
+pub async fn get_blob(Path(hash): Path<String>, State(db): State<database::Database>) -> Response {
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return util::str_response(StatusCode::BAD_REQUEST, "Invalid content hash");
+    }
+
+    let Some(level_id) = db.get_level_by_hash(&hash).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "Blob not found");
+    };
+
+    // the thumbnail may have been replaced since this hash was recorded
+    let image_data =
+        match tokio::fs::read(paths::local(paths::thumbnail_path(namespace::DEFAULT, level_id)))
+            .await
+        {
+            Ok(data) if sync::hash(&data) == hash => data,
+            _ => return util::str_response(StatusCode::NOT_FOUND, "Blob not found"),
+        };
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "image/webp")
+        .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .header(header::CONTENT_LENGTH, image_data.len())
+        .body(image_data.into())
+        .unwrap()
+}
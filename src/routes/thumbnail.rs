@@ -1,14 +1,38 @@
-use crate::{database, util};
-use axum::extract::{Path, State};
-use axum::http::{StatusCode, header};
+use crate::client_ip::ClientIp;
+use crate::experiments::{self, Slot};
+use crate::level_path::LevelPath;
+use crate::models::{AccountId, LevelId};
+use crate::permissions::{RequirePermission, ReviewUploads};
+use crate::rate_limit::{self, Bucket};
+use crate::routes::upload;
+use crate::{
+    card, database, encoder, level_info, namespace, paths, pipeline, renderer, settings, util,
+    view_stats,
+};
+use crate::{storage, takedown};
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
 use axum::response::Response;
 use image::ImageReader;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::IpAddr;
 use std::path::PathBuf;
-use tracing::info;
+use std::sync::Arc;
+use tracing::{error, info};
 use webp::Encoder;
+use zip::CompressionMethod;
+use zip::write::SimpleFileOptions;
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+const MAX_TAKEDOWN_CONTACT_LENGTH: usize = 256;
+const MAX_TAKEDOWN_REASON_LENGTH: usize = 5000;
+const MAX_BATCH_LEVELS: usize = 100;
+const MIN_SEARCH_LENGTH: usize = 2;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
 pub enum Res {
     #[serde(rename = "high")]
     High, // 1920x1080
@@ -38,11 +62,34 @@ impl std::fmt::Display for Res {
     }
 }
 
-fn image_response(image_data: Vec<u8>, id: u64, upload_info: &database::UploadInfo) -> Response {
-    Response::builder()
+fn cache_control() -> String {
+    format!("public, max-age={}, immutable", settings::current().thumbnail_max_age)
+}
+
+// Values that can't be sent as a header are left out, /info always has them
+fn with_attribution(
+    builder: axum::http::response::Builder,
+    upload_info: &database::UploadInfo,
+) -> axum::http::response::Builder {
+    if !settings::current().attribution_headers {
+        return builder;
+    }
+
+    [(upload::LICENSE_HEADER, &upload_info.license), (upload::CREDIT_HEADER, &upload_info.credit)]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, HeaderValue::from_str(value.as_deref()?).ok()?)))
+        .fold(builder, |builder, (name, value)| builder.header(name, value))
+}
+
+fn image_response(
+    image_data: Vec<u8>,
+    id: LevelId,
+    upload_info: &database::UploadInfo,
+) -> Response {
+    with_attribution(Response::builder(), upload_info)
         .header(header::CONTENT_TYPE, "image/webp")
         .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}.webp\"", id))
-        .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .header(header::CACHE_CONTROL, cache_control())
         .header(header::CONTENT_LENGTH, image_data.len())
         .header("X-Level-ID", id.to_string())
         .header("X-Thumbnail-Author", &upload_info.username)
@@ -51,18 +98,113 @@ fn image_response(image_data: Vec<u8>, id: u64, upload_info: &database::UploadIn
         .unwrap()
 }
 
+enum AccelMode {
+    Redirect(String), // nginx X-Accel-Redirect, with the internal location prefix
+    Sendfile,         // Apache/lighttpd X-Sendfile, with absolute paths
+}
+
+// accepted ACCEL_MODE values, checked by the doctor before the server starts
+pub const ACCEL_MODES: &[&str] = &["x-accel-redirect", "x-sendfile"];
+
+static ACCEL_MODE: std::sync::LazyLock<Option<AccelMode>> =
+    std::sync::LazyLock::new(|| match dotenv::var("ACCEL_MODE").ok()?.as_str() {
+        "x-accel-redirect" => Some(AccelMode::Redirect(
+            dotenv::var("ACCEL_PREFIX").unwrap_or_else(|_| "/internal".to_string()),
+        )),
+        "x-sendfile" => Some(AccelMode::Sendfile),
+        // refused by the doctor at startup
+        _ => None,
+    });
+
+// Let the reverse proxy serve the file instead of streaming it through the server
+fn accel_response(
+    mode: &AccelMode,
+    image_path: &std::path::Path,
+    id: LevelId,
+    upload_info: &database::UploadInfo,
+) -> Response {
+    let (header_name, target) = match mode {
+        AccelMode::Redirect(prefix) => (
+            "X-Accel-Redirect",
+            format!("{}/{}", prefix.trim_end_matches('/'), image_path.display()),
+        ),
+        AccelMode::Sendfile => match std::fs::canonicalize(paths::local(image_path)) {
+            Ok(path) => ("X-Sendfile", path.display().to_string()),
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Failed to resolve image path: {}", e),
+                );
+            }
+        },
+    };
+
+    with_attribution(Response::builder(), upload_info)
+        .header(header::CONTENT_TYPE, "image/webp")
+        .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}.webp\"", id))
+        .header(header::CACHE_CONTROL, cache_control())
+        .header(header_name, target)
+        .header("X-Level-ID", id.to_string())
+        .header("X-Thumbnail-Author", &upload_info.username)
+        .header("X-Thumbnail-User-ID", upload_info.account_id.to_string())
+        .body("".into())
+        .unwrap()
+}
+
+// Resized variants are written to disk so the proxy has a file to serve,
+// and regenerated whenever the original is newer than the cached copy
+async fn ensure_variant(
+    namespace: &str,
+    image_path: &PathBuf,
+    id: LevelId,
+    res: Res,
+) -> Result<PathBuf, Response> {
+    ensure_variant_in(&paths::variant_dir(namespace, &res.to_string()), image_path, id, res).await
+}
+
+async fn ensure_variant_in(
+    variant_dir: &str,
+    image_path: &PathBuf,
+    id: LevelId,
+    res: Res,
+) -> Result<PathBuf, Response> {
+    let variant_path = PathBuf::from(paths::level_file(variant_dir, id));
+
+    let modified =
+        |path: &PathBuf| std::fs::metadata(paths::local(path)).and_then(|m| m.modified()).ok();
+    if let (Some(variant), Some(original)) = (modified(&variant_path), modified(image_path))
+        && variant >= original
+    {
+        return Ok(variant_path);
+    }
+
+    let data = resize_image(image_path.clone(), res).await?;
+    storage::write_local(&variant_path, &data).await.map_err(|e| {
+        util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to write image variant: {}", e),
+        )
+    })?;
+
+    Ok(variant_path)
+}
+
 async fn get_upload_info(
     db: &database::Database,
-    id: u64,
+    namespace: &str,
+    id: LevelId,
 ) -> Result<database::UploadInfo, Response> {
-    match db.get_upload_info(id as i64).await {
+    match db.get_upload_info(namespace, id).await {
         Some(upload) => Ok(upload),
         None => Err(util::str_response(StatusCode::NOT_FOUND, "Image not found")),
     }
 }
 
-async fn read_original_image(image_path: &PathBuf) -> Result<Vec<u8>, Response> {
-    tokio::fs::read(image_path).await.map_err(|e| {
+pub async fn read_original_image(image_path: &PathBuf) -> Result<Vec<u8>, Response> {
+    let Some(image_path) = paths::canonical(image_path) else {
+        return Err(util::str_response(StatusCode::NOT_FOUND, "Image not found"));
+    };
+    tokio::fs::read(paths::local(image_path)).await.map_err(|e| {
         util::str_response(
             StatusCode::INTERNAL_SERVER_ERROR,
             &format!("Failed to read image file: {}", e),
@@ -70,24 +212,19 @@ async fn read_original_image(image_path: &PathBuf) -> Result<Vec<u8>, Response>
     })
 }
 
-async fn resize_image(image_path: PathBuf, target_res: Res) -> Result<Vec<u8>, Response> {
+pub async fn resize_image(image_path: PathBuf, target_res: Res) -> Result<Vec<u8>, Response> {
     let (width, height) = target_res.dimensions();
 
-    tokio::task::spawn_blocking(move || -> Result<Vec<u8>, String> {
-        let image = ImageReader::open(&image_path)
+    encoder::run(move || -> Result<Vec<u8>, String> {
+        let image = ImageReader::open(paths::local(&image_path))
             .map_err(|e| format!("Failed to open image: {}", e))?
             .decode()
             .map_err(|e| format!("Failed to decode image: {}", e))?;
 
-        let resized_image =
-            image.resize_exact(width, height, image::imageops::FilterType::Lanczos3).to_rgb8();
-
-        Ok(Encoder::from_rgb(&resized_image, width, height).encode_lossless().to_vec())
+        Ok(pipeline::resize(&image, width, height))
     })
     .await
-    .map_err(|e| {
-        util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("Task join error: {}", e))
-    })?
+    .map_err(|e| util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e))?
     .map_err(|e| {
         util::str_response(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -96,21 +233,38 @@ async fn resize_image(image_path: PathBuf, target_res: Res) -> Result<Vec<u8>, R
     })
 }
 
-async fn handle_image(id: u64, res: Res, db: database::Database) -> Response {
+async fn handle_image(namespace: &str, id: LevelId, res: Res, db: database::Database) -> Response {
     info!("Handling image request for ID: {}, Resolution: {:?}", id, res);
 
     // Check if image file exists
-    let image_path = PathBuf::from(format!("thumbnails/{}.webp", id));
-    if !image_path.exists() {
+    let image_path = paths::thumbnail_path(namespace, id);
+    if !storage::ensure_local(&image_path).await {
+        if namespace == namespace::DEFAULT
+            && let Some(auto_path) = renderer::get(id).await
+        {
+            return auto_image_response(auto_path, id, res).await;
+        }
         return util::str_response(StatusCode::NOT_FOUND, "Image not found");
     }
+    let image_path = PathBuf::from(image_path);
 
     // Verify image exists in database and get metadata
-    let upload_info = match get_upload_info(&db, id).await {
+    let upload_info = match get_upload_info(&db, namespace, id).await {
         Ok(info) => info,
         Err(response) => return response,
     };
 
+    if let Some(mode) = ACCEL_MODE.as_ref() {
+        let path = match res {
+            Res::High => image_path,
+            res => match ensure_variant(namespace, &image_path, id, res).await {
+                Ok(path) => path,
+                Err(response) => return response,
+            },
+        };
+        return accel_response(mode, &path, id, &upload_info);
+    }
+
     match res {
         Res::High => {
             // For high resolution, serve the original image
@@ -123,8 +277,12 @@ async fn handle_image(id: u64, res: Res, db: database::Database) -> Response {
         }
 
         Res::Medium | Res::Small => {
-            // For lower resolutions, resize the image
-            let resized_data = match resize_image(image_path, res).await {
+            // For lower resolutions, serve the cached resized copy
+            let variant_path = match ensure_variant(namespace, &image_path, id, res).await {
+                Ok(path) => path,
+                Err(response) => return response,
+            };
+            let resized_data = match read_original_image(&variant_path).await {
                 Ok(data) => data,
                 Err(response) => return response,
             };
@@ -134,25 +292,337 @@ async fn handle_image(id: u64, res: Res, db: database::Database) -> Response {
     }
 }
 
+// Builds the resized variants of a thumbnail ahead of the first request for them
+pub async fn warm_variants(namespace: &str, id: LevelId) -> Result<(), Response> {
+    let image_path = paths::thumbnail_path(namespace, id);
+    if !storage::ensure_local(&image_path).await {
+        return Ok(());
+    }
+    let image_path = PathBuf::from(image_path);
+
+    for res in [Res::Medium, Res::Small] {
+        ensure_variant(namespace, &image_path, id, res).await?;
+    }
+    Ok(())
+}
+
+// Generated thumbnails are labeled as such and cached briefly, a human upload replaces them
+async fn auto_image_response(image_path: PathBuf, id: LevelId, res: Res) -> Response {
+    let image_data = match res {
+        Res::High => read_original_image(&image_path).await,
+        res => resize_image(image_path, res).await,
+    };
+    let image_data = match image_data {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "image/webp")
+        .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}.webp\"", id))
+        .header(
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", settings::current().embed_max_age),
+        )
+        .header(header::CONTENT_LENGTH, image_data.len())
+        .header("X-Level-ID", id.to_string())
+        .header("X-Thumbnail-Auto", "true")
+        .body(image_data.into())
+        .unwrap()
+}
+
+// accepted HOTLINK_MODE values, checked by the doctor before the server starts
+pub const HOTLINK_MODES: &[&str] = &["forbid", "watermark"];
+
+enum HotlinkMode {
+    Forbid,    // 403 for other sites
+    Watermark, // other sites get a small watermarked copy
+}
+
+struct HotlinkPolicy {
+    allowed_hosts: Vec<String>,
+    mode: HotlinkMode,
+    bypass_token: Option<String>,
+}
+
+// Enforcement is off unless HOTLINK_ALLOWLIST is set
+static HOTLINK_POLICY: std::sync::LazyLock<Option<HotlinkPolicy>> =
+    std::sync::LazyLock::new(|| {
+        let mut allowed_hosts: Vec<String> = dotenv::var("HOTLINK_ALLOWLIST")
+            .ok()?
+            .split(',')
+            .map(|host| host.trim().to_lowercase())
+            .filter(|host| !host.is_empty())
+            .collect();
+
+        // our own frontend is always allowed
+        if let Some(host) = dotenv::var("HOME_URL")
+            .ok()
+            .and_then(|url| reqwest::Url::parse(&url).ok()?.host_str().map(str::to_string))
+        {
+            allowed_hosts.push(host);
+        }
+
+        let mode = match dotenv::var("HOTLINK_MODE").as_deref() {
+            Ok("watermark") => HotlinkMode::Watermark,
+            _ => HotlinkMode::Forbid,
+        };
+
+        Some(HotlinkPolicy {
+            allowed_hosts,
+            mode,
+            bypass_token: dotenv::var("HOTLINK_BYPASS_TOKEN").ok(),
+        })
+    });
+
+#[derive(Deserialize)]
+pub struct ImageQuery {
+    token: Option<String>,
+}
+
+impl HotlinkPolicy {
+    // Requests without Origin or Referer (the game, direct visits) are always allowed
+    fn allows(&self, headers: &HeaderMap, token: Option<&str>) -> bool {
+        let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+        if let Some(bypass) = &self.bypass_token
+            && header("X-Thumbnail-Token").or(token) == Some(bypass.as_str())
+        {
+            return true;
+        }
+
+        let Some(source) = header(header::ORIGIN.as_str()).or(header(header::REFERER.as_str()))
+        else {
+            return true;
+        };
+
+        let Some(host) =
+            reqwest::Url::parse(source).ok().and_then(|url| url.host_str().map(str::to_lowercase))
+        else {
+            return false;
+        };
+
+        self.allowed_hosts
+            .iter()
+            .any(|allowed| host == *allowed || host.ends_with(&format!(".{}", allowed)))
+    }
+}
+
+// Small copy with diagonal stripes across it, cached like the other variants
+async fn watermarked_variant(
+    namespace: &str,
+    image_path: &PathBuf,
+    id: LevelId,
+) -> Result<Vec<u8>, Response> {
+    let variant_dir = paths::variant_dir(namespace, "hotlink");
+    let variant_path = PathBuf::from(paths::level_file(&variant_dir, id));
+
+    let modified =
+        |path: &PathBuf| std::fs::metadata(paths::local(path)).and_then(|m| m.modified()).ok();
+    if let (Some(variant), Some(original)) = (modified(&variant_path), modified(image_path))
+        && variant >= original
+        && let Ok(data) = tokio::fs::read(paths::local(&variant_path)).await
+    {
+        return Ok(data);
+    }
+
+    let source = image_path.clone();
+    let (width, height) = Res::Small.dimensions();
+    let data = encoder::run(move || -> Result<Vec<u8>, String> {
+        let image = ImageReader::open(paths::local(&source))
+            .map_err(|e| format!("Failed to open image: {}", e))?
+            .decode()
+            .map_err(|e| format!("Failed to decode image: {}", e))?;
+
+        let mut image =
+            image.resize_exact(width, height, image::imageops::FilterType::Triangle).to_rgb8();
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if (x + y) / 24 % 2 == 0 {
+                pixel.0 = pixel.0.map(|channel| channel / 2);
+            }
+        }
+
+        Ok(Encoder::from_rgb(&image, width, height).encode(60.0).to_vec())
+    })
+    .await
+    .map_err(|e| util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e))?
+    .map_err(|e| {
+        util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Image processing error: {}", e),
+        )
+    })?;
+
+    if let Err(e) = storage::write_local(&variant_path, &data).await {
+        error!("Failed to cache watermarked variant for {}: {}", id, e);
+    }
+
+    Ok(data)
+}
+
+// Returns the response for a disallowed embed, or None when the request may go through
+async fn hotlink_response(
+    headers: &HeaderMap,
+    token: Option<&str>,
+    namespace: &str,
+    id: LevelId,
+) -> Option<Response> {
+    let policy = HOTLINK_POLICY.as_ref()?;
+    if policy.allows(headers, token) {
+        return None;
+    }
+
+    let image_path = paths::thumbnail_path(namespace, id);
+    if matches!(policy.mode, HotlinkMode::Forbid) || !storage::ensure_local(&image_path).await {
+        return Some(util::str_response(StatusCode::FORBIDDEN, "Hotlinking is not allowed"));
+    }
+    let image_path = PathBuf::from(image_path);
+
+    // never let a shared cache hand this copy to allowed sites
+    Some(match watermarked_variant(namespace, &image_path, id).await {
+        Ok(data) => Response::builder()
+            .header(header::CONTENT_TYPE, "image/webp")
+            .header(header::CACHE_CONTROL, "no-store")
+            .header(header::CONTENT_LENGTH, data.len())
+            .body(data.into())
+            .unwrap(),
+        Err(response) => response,
+    })
+}
+
+// Slot B of an experiment, served from its own copy with the original uploader's details
+async fn challenger_image(
+    db: &database::Database,
+    id: LevelId,
+    res: Res,
+    challenger: &experiments::Challenger,
+) -> Response {
+    let Some(upload_info) = db.get_upload_info_by_id(challenger.upload_id).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "Image not found");
+    };
+
+    let image_path = PathBuf::from(&challenger.image_path);
+    let path = match res {
+        Res::High => image_path,
+        res => {
+            let variant_dir = paths::experiment_variant_dir(&res.to_string());
+            match ensure_variant_in(&variant_dir, &image_path, id, res).await {
+                Ok(path) => path,
+                Err(response) => return response,
+            }
+        }
+    };
+
+    match read_original_image(&path).await {
+        Ok(image_data) => image_response(image_data, id, &upload_info),
+        Err(response) => response,
+    }
+}
+
+async fn serve_image(
+    db: database::Database,
+    namespace: &str,
+    id: LevelId,
+    res: Res,
+    query: ImageQuery,
+    headers: HeaderMap,
+    requester: Option<IpAddr>,
+) -> Response {
+    if let Some(mut response) =
+        hotlink_response(&headers, query.token.as_deref(), namespace, id).await
+    {
+        vary_on_embedder(&mut response);
+        return response;
+    }
+
+    // experiments only run in the main game, a requester always gets the same slot
+    let experiment = requester
+        .filter(|_| namespace == namespace::DEFAULT)
+        .and_then(|requester| experiments::pick(id, requester));
+    let mut response = match &experiment {
+        Some((Slot::B, challenger)) => challenger_image(&db, id, res, challenger).await,
+        _ => handle_image(namespace, id, res, db).await,
+    };
+
+    // view stats are only kept for the main game
+    if namespace == namespace::DEFAULT && response.status().is_success() {
+        view_stats::record(id);
+    }
+    if let Some((slot, _)) = experiment
+        && response.status().is_success()
+    {
+        experiments::record_view(id, slot);
+        // a shared cache would hand whichever slot it saw first to everyone
+        let cache_control = format!("private, max-age={}", settings::current().thumbnail_max_age);
+        let headers = response.headers_mut();
+        if let Ok(value) = HeaderValue::from_str(&cache_control) {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&slot.to_string()) {
+            headers.insert("X-Thumbnail-Slot", value);
+        }
+    }
+    vary_on_embedder(&mut response);
+    response
+}
+
+// With hotlink protection on, the same URL is answered differently depending on who embeds
+// it, so shared caches have to keep a copy per embedder
+fn vary_on_embedder(response: &mut Response) {
+    if HOTLINK_POLICY.is_some() {
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("Origin, Referer, X-Thumbnail-Token"));
+    }
+}
+
 pub async fn image_handler_with_res(
-    Path((id, res)): Path<(u64, Res)>,
+    LevelPath((id, res)): LevelPath<(LevelId, Res)>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+    ip: Option<ClientIp>,
     State(db): State<database::Database>,
 ) -> Response {
-    handle_image(id, res, db).await
+    let requester = ip.map(|ip| ip.0);
+    serve_image(db, namespace::DEFAULT, id, res, query, headers, requester).await
 }
 
 pub async fn image_handler_default(
-    Path(id): Path<u64>,
+    LevelPath(id): LevelPath<LevelId>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+    ip: Option<ClientIp>,
     State(db): State<database::Database>,
 ) -> Response {
-    handle_image(id, Res::High, db).await
+    let requester = ip.map(|ip| ip.0);
+    serve_image(db, namespace::DEFAULT, id, Res::High, query, headers, requester).await
 }
 
-pub async fn thumbnail_info_handler(
-    Path(id): Path<u64>,
+pub async fn namespaced_image_handler_with_res(
+    LevelPath((ns, id, res)): LevelPath<(String, LevelId, Res)>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
     State(db): State<database::Database>,
 ) -> Response {
-    match db.get_upload_extended(id as i64).await {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    serve_image(db, &ns, id, res, query, headers, None).await
+}
+
+pub async fn namespaced_image_handler_default(
+    LevelPath((ns, id)): LevelPath<(String, LevelId)>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    serve_image(db, &ns, id, Res::High, query, headers, None).await
+}
+
+async fn thumbnail_info(db: &database::Database, namespace: &str, id: LevelId) -> Response {
+    match db.get_upload_extended(namespace, id).await {
         Some(upload) => Response::builder()
             .status(StatusCode::OK)
             .header(header::CONTENT_TYPE, "application/json")
@@ -163,16 +633,308 @@ pub async fn thumbnail_info_handler(
     }
 }
 
-pub async fn handle_random(res: Res) -> Response {
+pub async fn thumbnail_info_handler(
+    LevelPath(id): LevelPath<LevelId>,
+    State(db): State<database::Database>,
+) -> Response {
+    thumbnail_info(&db, namespace::DEFAULT, id).await
+}
+
+pub async fn namespaced_thumbnail_info_handler(
+    LevelPath((ns, id)): LevelPath<(String, LevelId)>,
+    State(db): State<database::Database>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    thumbnail_info(&db, &ns, id).await
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    #[serde(flatten)]
+    level: database::LevelSearchResult,
+    url: String,
+}
+
+// Searches the cached level metadata, so clients don't have to ask the game servers
+pub async fn search_handler(
+    State(db): State<database::Database>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let q = query.q.trim();
+    if q.chars().count() < MIN_SEARCH_LENGTH {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            &format!("Search query must be at least {} characters", MIN_SEARCH_LENGTH),
+        );
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+    let Some(offset) = (page - 1).checked_mul(limit) else {
+        return util::str_response(StatusCode::BAD_REQUEST, "Page is out of range");
+    };
+    let levels = match db.search_levels(q, limit, offset).await {
+        Ok(levels) => levels,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error searching levels: {}", e),
+            );
+        }
+    };
+
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+    let results: Vec<SearchResult> = levels
+        .into_iter()
+        .map(|level| SearchResult {
+            url: format!("{}/thumbnail/{}", home_url, level.level_id),
+            level,
+        })
+        .collect();
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "page": page,
+            "limit": limit,
+            "data": results,
+        }),
+    )
+}
+
+fn default_batch_variants() -> Vec<Res> {
+    vec![Res::High]
+}
+
+#[derive(Deserialize)]
+pub struct BatchPayload {
+    ids: Vec<LevelId>,
+    #[serde(default = "default_batch_variants")]
+    variants: Vec<Res>,
+}
+
+#[derive(Serialize)]
+struct BatchManifest {
+    variants: Vec<Res>,
+    included: Vec<LevelId>,
+    missing: Vec<LevelId>, // no thumbnail, auto thumbnails aren't part of batches
+}
+
+fn write_batch(
+    writer: impl std::io::Write,
+    manifest: &BatchManifest,
+    files: Vec<(String, PathBuf)>,
+) -> zip::result::ZipResult<()> {
+    let mut zip = zip::ZipWriter::new_stream(writer);
+    // WebP is compressed already, deflating it again only costs CPU
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+
+    zip.start_file("manifest.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, manifest).map_err(std::io::Error::other)?;
+
+    for (name, path) in files {
+        match std::fs::File::open(paths::local(&path)) {
+            Ok(mut file) => {
+                zip.start_file(name, options)?;
+                std::io::copy(&mut file, &mut zip)?;
+            }
+            Err(e) => error!("Batch skipped {}: {}", path.display(), e),
+        }
+    }
+
+    zip.finish()?.into_inner().flush()?;
+    Ok(())
+}
+
+// One zip with the requested variants of many levels, laid out as {variant}/{id}.webp
+pub async fn batch_zip_handler(
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+    ip: Option<ClientIp>,
+    Json(payload): Json<BatchPayload>,
+) -> Response {
+    if let Some(response) = rate_limit::check(Bucket::Batch, ip) {
+        return response;
+    }
+    // a batch holds the full images, there is no watermarked copy to hand out instead
+    if HOTLINK_POLICY
+        .as_ref()
+        .is_some_and(|policy| !policy.allows(&headers, query.token.as_deref()))
+    {
+        return util::str_response(StatusCode::FORBIDDEN, "Hotlinking is not allowed");
+    }
+
+    let mut ids = payload.ids;
+    ids.sort_unstable();
+    ids.dedup();
+    let mut variants = Vec::new();
+    for res in payload.variants {
+        if !variants.contains(&res) {
+            variants.push(res);
+        }
+    }
+
+    if ids.is_empty() || variants.is_empty() {
+        return util::str_response(StatusCode::BAD_REQUEST, "No levels or variants requested");
+    }
+    if ids.len() > MAX_BATCH_LEVELS {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            &format!("At most {} levels can be fetched at once", MAX_BATCH_LEVELS),
+        );
+    }
+
+    // variants are resized before streaming starts, so a failure can still be reported
+    let (mut included, mut missing, mut files) = (Vec::new(), Vec::new(), Vec::new());
+    for id in ids {
+        let image_path = paths::thumbnail_path(namespace::DEFAULT, id);
+        if !storage::ensure_local(&image_path).await {
+            missing.push(id);
+            continue;
+        }
+        let image_path = PathBuf::from(image_path);
+        for &res in &variants {
+            let path = match res {
+                Res::High => image_path.clone(),
+                res => match ensure_variant(namespace::DEFAULT, &image_path, id, res).await {
+                    Ok(path) => path,
+                    Err(response) => return response,
+                },
+            };
+            files.push((format!("{}/{}.webp", res, id), path));
+        }
+        included.push(id);
+    }
+    for &id in &included {
+        view_stats::record(id);
+    }
+    let manifest = BatchManifest { variants, included, missing };
+
+    util::stream_archive("application/zip", "thumbnails.zip", "Batch download", move |writer| {
+        write_batch(writer, &manifest, files)
+    })
+}
+
+// Moderators can look at thumbnails that have since been replaced
+pub async fn archived_image_handler(
+    RequirePermission(_, _): RequirePermission<ReviewUploads>,
+    LevelPath((id, upload_id)): LevelPath<(LevelId, i64)>,
+    State(db): State<database::Database>,
+) -> Response {
+    let archive_path = match db.get_archive_path(namespace::DEFAULT, id, upload_id).await {
+        Ok(Some(archive_path)) => archive_path,
+        Ok(None) => return util::str_response(StatusCode::NOT_FOUND, "No archived image found"),
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error looking up archived image: {}", e),
+            );
+        }
+    };
+
+    match read_original_image(&PathBuf::from(archive_path)).await {
+        Ok(image_data) => Response::builder()
+            .header(header::CONTENT_TYPE, "image/webp")
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}_{}.webp\"", id, upload_id),
+            )
+            .header(header::CACHE_CONTROL, "private, no-store")
+            .body(image_data.into())
+            .unwrap(),
+        Err(response) => response,
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TakedownPayload {
+    namespace: Option<String>,
+    level_id: LevelId,
+    claimant_name: Option<String>,
+    claimant_contact: String, // email or postal address for the reply
+    reason: String,
+}
+
+// Public intake for copyright and other takedown claims, resolved by admins
+pub async fn submit_takedown(
+    State(db): State<database::Database>,
+    ip: Option<ClientIp>,
+    Json(payload): Json<TakedownPayload>,
+) -> Response {
+    if let Some(response) = rate_limit::check(Bucket::Takedown, ip) {
+        return response;
+    }
+
+    let namespace = payload.namespace.as_deref().unwrap_or(namespace::DEFAULT);
+    if let Err(response) = namespace::resolve(&db, namespace).await {
+        return response;
+    }
+
+    let contact = payload.claimant_contact.trim();
+    let reason = payload.reason.trim();
+    let name = payload.claimant_name.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if contact.is_empty() || contact.len() > MAX_TAKEDOWN_CONTACT_LENGTH {
+        return util::str_response(StatusCode::BAD_REQUEST, "A contact address is required");
+    }
+    if reason.is_empty() || reason.len() > MAX_TAKEDOWN_REASON_LENGTH {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            &format!("A reason of up to {} characters is required", MAX_TAKEDOWN_REASON_LENGTH),
+        );
+    }
+    if name.is_some_and(|name| name.len() > MAX_TAKEDOWN_CONTACT_LENGTH) {
+        return util::str_response(StatusCode::BAD_REQUEST, "Name is too long");
+    }
+
+    if db.get_upload_info(namespace, payload.level_id).await.is_none() {
+        return util::str_response(StatusCode::NOT_FOUND, "Image not found");
+    }
+
+    let ip = ip.map(|ip| ip.0.to_string());
+    let request = database::NewTakedownRequest {
+        namespace,
+        level_id: payload.level_id,
+        claimant_name: name,
+        claimant_contact: contact,
+        reason,
+        ip: ip.as_deref(),
+    };
+    match takedown::submit(&db, request).await {
+        Ok(request) => util::response(
+            StatusCode::CREATED,
+            json!({
+                "status": StatusCode::CREATED.as_u16(),
+                "message": "Your request was received and will be reviewed",
+                "id": request.id,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error recording takedown request: {}", e),
+        ),
+    }
+}
+
+pub async fn handle_random(db: &database::Database, res: Res) -> Response {
     // pick random id from directory
-    match tokio::fs::read_dir("thumbnails").await {
+    match tokio::fs::read_dir(paths::local(paths::thumbnail_dir(namespace::DEFAULT))).await {
         Ok(mut entries) => {
-            let mut ids: Vec<u64> = Vec::new();
+            let mut ids: Vec<LevelId> = Vec::new();
             while let Some(entry) = entries.next_entry().await.unwrap() {
-                if let Some(name) = entry.file_name().to_str() {
-                    if let Ok(id) = name.trim_end_matches(".webp").parse::<u64>() {
-                        ids.push(id);
-                    }
+                if let Some(name) = entry.file_name().to_str()
+                    && let Ok(id) = name.trim_end_matches(".webp").parse::<LevelId>()
+                {
+                    ids.push(id);
                 }
             }
 
@@ -180,8 +942,8 @@ pub async fn handle_random(res: Res) -> Response {
                 return util::str_response(StatusCode::NOT_FOUND, "No images found");
             }
 
-            let random_id = ids[rand::random::<u64>() as usize % ids.len()];
-            let url = format!("/thumbnail/{}/{}", random_id, res.to_string());
+            let random_id = ids[db.ids().pick(ids.len())];
+            let url = format!("/thumbnail/{}/{}", random_id, res);
             Response::builder()
                 .status(StatusCode::FOUND)
                 .header(header::LOCATION, url)
@@ -195,10 +957,207 @@ pub async fn handle_random(res: Res) -> Response {
     }
 }
 
-pub async fn random_handler() -> Response {
-    handle_random(Res::High).await
+pub async fn random_handler(State(db): State<database::Database>) -> Response {
+    handle_random(&db, Res::High).await
+}
+
+pub async fn random_res_handler(
+    State(db): State<database::Database>,
+    Path(res): Path<Res>,
+) -> Response {
+    handle_random(&db, res).await
+}
+
+// Share card for bots announcing levels, drawn on demand and kept for a while
+#[derive(Deserialize)]
+pub struct CardQuery {
+    author: Option<AccountId>, // account ID, the uploader's own style applies when it matches
+}
+
+async fn card_style(
+    db: &database::Database,
+    id: LevelId,
+    author: Option<AccountId>,
+) -> card::Style {
+    let Some(author) = author else {
+        return card::Style::default();
+    };
+    let Some(upload) = db.get_upload_info(namespace::DEFAULT, id).await else {
+        return card::Style::default();
+    };
+    if upload.account_id != author {
+        return card::Style::default();
+    }
+    let Some(theme) = db.get_card_theme_by_account(author).await else {
+        return card::Style::default();
+    };
+
+    card::Style {
+        accent: theme.card_accent.as_deref().and_then(card::parse_accent).unwrap_or(card::GOLD),
+        author: theme.card_show_author.then_some(upload.username),
+    }
+}
+
+pub async fn card_handler(
+    LevelPath(id): LevelPath<LevelId>,
+    State(db): State<database::Database>,
+    Query(query): Query<CardQuery>,
+) -> Response {
+    if !card::is_configured() || !level_info::is_configured() {
+        return util::str_response(StatusCode::NOT_IMPLEMENTED, "Share cards are not configured");
+    }
+
+    let live_path = paths::thumbnail_path(namespace::DEFAULT, id);
+    let mut image_path = PathBuf::from(&live_path);
+    let mut upload_id = None;
+    if storage::ensure_local(&live_path).await {
+        upload_id = db.get_live_upload_id(namespace::DEFAULT, id).await.ok().flatten();
+    } else {
+        match renderer::get(id).await {
+            Some(auto_path) => image_path = auto_path,
+            None => return util::str_response(StatusCode::NOT_FOUND, "Image not found"),
+        }
+    }
+
+    let style = card_style(&db, id, query.author).await;
+    let key = card::CacheKey::new(id, upload_id, &style);
+    let card_data = match card::cached(&key) {
+        Some(card_data) => card_data,
+        None => {
+            let Some(level) = level_info::get(&db, id).await else {
+                return util::str_response(StatusCode::NOT_FOUND, "Level metadata not found");
+            };
+            let thumbnail = match read_original_image(&image_path).await {
+                Ok(data) => data,
+                Err(response) => return response,
+            };
+
+            let drawn = encoder::run(move || card::render(&thumbnail, &level, &style)).await;
+            let card_data = match drawn {
+                Ok(Ok(data)) => Arc::new(data),
+                Ok(Err(e)) | Err(e) => {
+                    error!("Failed to draw card for level {}: {}", id, e);
+                    return util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+                }
+            };
+            card::remember(key, card_data.clone());
+            card_data
+        }
+    };
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "image/webp")
+        .header(header::CONTENT_DISPOSITION, format!("inline; filename=\"{}_card.webp\"", id))
+        .header(
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", settings::current().embed_max_age),
+        )
+        .header(header::CONTENT_LENGTH, card_data.len())
+        .header("X-Level-ID", id.to_string())
+        .body(card_data.to_vec().into())
+        .unwrap()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+pub async fn embed_handler(
+    LevelPath(id): LevelPath<LevelId>,
+    State(db): State<database::Database>,
+) -> Response {
+    let upload_info = match get_upload_info(&db, namespace::DEFAULT, id).await {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+    let page_url = format!("{}/thumbnail/{}/embed", home_url, id);
+    let image_url = format!("{}/thumbnail/{}", home_url, id);
+    let (width, height) = Res::High.dimensions();
+    let title = format!("Thumbnail for level {}", id);
+    let description = format!("Thumbnail by {}", escape_html(&upload_info.username));
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<meta property="og:type" content="website">
+<meta property="og:site_name" content="Level Thumbnails">
+<meta property="og:title" content="{title}">
+<meta property="og:description" content="{description}">
+<meta property="og:url" content="{page_url}">
+<meta property="og:image" content="{image_url}">
+<meta property="og:image:type" content="image/webp">
+<meta property="og:image:width" content="{width}">
+<meta property="og:image:height" content="{height}">
+<meta name="twitter:card" content="summary_large_image">
+<meta name="twitter:title" content="{title}">
+<meta name="twitter:description" content="{description}">
+<meta name="twitter:image" content="{image_url}">
+<link rel="alternate" type="application/json+oembed" href="{home_url}/oembed?url={page_url}">
+</head>
+<body>
+<img src="{image_url}" alt="{title}" width="100%">
+</body>
+</html>
+"#
+    );
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(
+            header::CACHE_CONTROL,
+            format!("public, max-age={}", settings::current().embed_max_age),
+        )
+        .body(html.into())
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct OEmbedQuery {
+    url: String,
 }
 
-pub async fn random_res_handler(Path(res): Path<Res>) -> Response {
-    handle_random(res).await
+// Extract the level ID from any /thumbnail/{id}[/...] URL served by this instance
+fn level_id_from_url(url: &str) -> Option<LevelId> {
+    let (_, rest) = url.split_once("/thumbnail/")?;
+    rest.split(['/', '?', '#']).next()?.parse().ok()
+}
+
+pub async fn oembed_handler(
+    Query(query): Query<OEmbedQuery>,
+    State(db): State<database::Database>,
+) -> Response {
+    let Some(id) = level_id_from_url(&query.url) else {
+        return util::str_response(StatusCode::NOT_FOUND, "Unsupported URL");
+    };
+
+    let upload_info = match get_upload_info(&db, namespace::DEFAULT, id).await {
+        Ok(info) => info,
+        Err(response) => return response,
+    };
+
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+    let (width, height) = Res::High.dimensions();
+    util::response(
+        StatusCode::OK,
+        serde_json::json!({
+            "version": "1.0",
+            "type": "photo",
+            "title": format!("Thumbnail for level {}", id),
+            "url": format!("{}/thumbnail/{}", home_url, id),
+            "width": width,
+            "height": height,
+            "author_name": upload_info.username,
+            "provider_name": "Level Thumbnails",
+            "provider_url": home_url,
+        }),
+    )
 }
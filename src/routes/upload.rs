@@ -1,76 +1,425 @@
-use crate::{cache_controller, database, util};
+use crate::attestation;
+use crate::auth::{self, AuthedUser};
+use crate::client_ip::{self, ClientIp};
+use crate::events::{self, QueueEvent};
+use crate::hash_match::{self, HashMatch};
+use crate::level_path::LevelPath;
+use crate::models::{LevelId, UserId};
+use crate::permissions::{self, Permission, RequirePermission, ReviewUploads};
+use crate::pipeline::{self, IMAGE_HEIGHT, IMAGE_WIDTH};
+use crate::scanner::{self, ScanResult, ScanVerdict};
+use crate::upload_rules::{self, RuleAction};
+use crate::upload_source::{self, UploadSource};
+use crate::{
+    archive, assignment, cache_controller, captcha, database, encoder, moderation, namespace,
+    object_storage, paths, quarantine, settings, storage, sync, tos, usage_stats, util,
+};
 use axum::Json;
 use axum::body::Bytes;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::{HeaderMap, StatusCode, header};
-use axum::response::Response;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, KeyInit, Mac};
+use image::ImageFormat;
+use image::imageops::FilterType;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
 use std::cmp::PartialEq;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::{error, info, warn};
 use webp::Encoder;
 
-const IMAGE_WIDTH: u32 = 1920;
-const IMAGE_HEIGHT: u32 = 1080;
+pub const LICENSE_HEADER: &str = "X-Thumbnail-License";
+pub const CREDIT_HEADER: &str = "X-Thumbnail-Credit";
+const MAX_LICENSE_LENGTH: usize = 64;
+const MAX_CREDIT_LENGTH: usize = 256;
 
-// Helper function to authenticate moderator/admin
-async fn authenticate_moderator(
-    headers: &HeaderMap,
+// bump when process_image changes how thumbnails come out, so old ones can be re-encoded
+pub const ENCODER_VERSION: i32 = 1;
+
+// THUMBNAIL_MAX_BYTES caps the stored file, 4 MiB by default
+static MAX_ENCODED_SIZE: LazyLock<usize> = LazyLock::new(|| {
+    dotenv::var("THUMBNAIL_MAX_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(4 * 1024 * 1024)
+});
+
+async fn check_moderator(
     db: &database::Database,
-) -> Result<database::User, Response> {
-    let user = util::auth_middleware(headers, db).await?;
+    user: &database::User,
+    namespace: &str,
+) -> Result<(), Response> {
+    permissions::require(db, user, namespace, Permission::ReviewUploads).await
+}
 
-    if !matches!(user.role, database::Role::Moderator | database::Role::Admin) {
-        return Err(util::str_response(
-            StatusCode::FORBIDDEN,
-            "Only moderators or admins can perform this action",
-        ));
-    }
+// UPLOAD_FORMATS is a comma-separated list of file extensions, png, jpeg and webp by default
+static ALLOWED_FORMATS: LazyLock<Vec<ImageFormat>> = LazyLock::new(|| {
+    let formats = dotenv::var("UPLOAD_FORMATS").unwrap_or_else(|_| "png,jpeg,webp".to_string());
+    formats
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let format = ImageFormat::from_extension(name);
+            if format.is_none() {
+                error!("Ignoring unknown UPLOAD_FORMATS entry {}", name);
+            }
+            format
+        })
+        .collect()
+});
+
+// Looks at the magic bytes only, nothing is decoded yet
+fn allowed_format(data: &[u8]) -> Option<ImageFormat> {
+    image::guess_format(data).ok().filter(|format| ALLOWED_FORMATS.contains(format))
+}
 
-    Ok(user)
+fn unsupported_format() -> Response {
+    let allowed: Vec<&str> = ALLOWED_FORMATS.iter().map(|format| format.to_mime_type()).collect();
+    util::response(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        json!({
+            "status": StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16(),
+            "message": format!("Unsupported image format, allowed types are {}", allowed.join(", ")),
+            "allowed_types": allowed,
+        }),
+    )
+}
+
+// 423 while an admin-scheduled freeze covers the level, with the reason and when it ends,
+// and 503 when the freezes can't be looked up rather than letting the upload slip through
+async fn frozen(db: &database::Database, namespace: &str, id: LevelId) -> Option<Response> {
+    let freeze = match db.get_active_freeze(namespace, id).await {
+        Ok(freeze) => freeze?,
+        Err(e) => {
+            error!("Failed to check upload freezes for level {}: {}", id, e);
+            return Some(util::str_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Failed to check upload freezes, try again later",
+            ));
+        }
+    };
+
+    Some(util::response(
+        StatusCode::LOCKED,
+        json!({
+            "status": StatusCode::LOCKED.as_u16(),
+            "message": format!("Uploads for this level are closed: {}", freeze.reason),
+            "freeze": freeze.name,
+            "ends_at": freeze.ends_at,
+        }),
+    ))
 }
 
 // Helper function to validate image dimensions and convert to WebP
-fn process_image(data: &[u8]) -> Result<Vec<u8>, String> {
-    let image = image::load_from_memory(data).map_err(|e| format!("Invalid image data: {}", e))?;
+pub fn process_image(data: &[u8]) -> Result<Vec<u8>, String> {
+    let image = pipeline::validate_upload(data, &ALLOWED_FORMATS)?;
+    pipeline::encode_thumbnail(image, *MAX_ENCODED_SIZE)
+}
 
-    if image.width() != IMAGE_WIDTH || image.height() != IMAGE_HEIGHT {
-        return Err(format!("Image must be exactly {}x{}", IMAGE_WIDTH, IMAGE_HEIGHT));
+// Walks the RIFF chunks, VP8L holds lossless data and "VP8 " lossy data
+fn webp_encoding(data: &[u8]) -> Option<&'static str> {
+    let mut chunks = data.get(12..)?;
+    while let Some(header) = chunks.get(..8) {
+        match &header[..4] {
+            b"VP8L" => return Some("lossless"),
+            b"VP8 " => return Some("lossy"),
+            _ => {}
+        }
+        let size = u32::from_le_bytes(header[4..].try_into().ok()?) as usize;
+        chunks = chunks.get(8 + size + size % 2..)?;
     }
+    None
+}
 
-    let rgb_data = image.into_rgb8();
-    let encoder = Encoder::from_rgb(&rgb_data, IMAGE_WIDTH, IMAGE_HEIGHT);
-    Ok(encoder.encode_lossless().to_owned())
+// Reads only the headers of an encoded thumbnail
+pub fn image_meta(data: &[u8]) -> database::ImageMeta {
+    let dimensions = image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok());
+    database::ImageMeta {
+        width: dimensions.map(|(width, _)| width as i32),
+        height: dimensions.map(|(_, height)| height as i32),
+        file_size: data.len() as i64,
+        encoding: webp_encoding(data),
+        encoder_version: Some(ENCODER_VERSION),
+    }
+}
+
+// License and original artist declared by the uploader, both optional
+#[derive(Debug, Default, Deserialize)]
+pub struct Attribution {
+    license: Option<String>,
+    credit: Option<String>,
+}
+
+impl Attribution {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|h| h.to_str().ok()).map(str::to_string);
+        Self {
+            license: header(LICENSE_HEADER),
+            credit: header(CREDIT_HEADER),
+        }
+    }
+
+    // Trims both fields and drops empty ones
+    fn validate(self) -> Result<Self, &'static str> {
+        let clean = |value: Option<String>, max: usize| match value.as_deref().map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(value) if value.chars().count() > max || value.chars().any(char::is_control) => {
+                Err(())
+            }
+            Some(value) => Ok(Some(value.to_string())),
+        };
+
+        Ok(Self {
+            license: clean(self.license, MAX_LICENSE_LENGTH).map_err(|_| "Invalid license")?,
+            credit: clean(self.credit, MAX_CREDIT_LENGTH).map_err(|_| "Invalid credit")?,
+        })
+    }
+
+    async fn store(&self, db: &database::Database, upload_id: i64) {
+        if self.license.is_none() && self.credit.is_none() {
+            return;
+        }
+
+        let (license, credit) = (self.license.as_deref(), self.credit.as_deref());
+        if let Err(e) = db.set_upload_attribution(upload_id, license, credit).await {
+            error!("Failed to store attribution for upload {}: {}", upload_id, e);
+        }
+    }
+}
+
+// What the client sent along with the image
+#[derive(Debug, Default)]
+pub struct Submission {
+    attribution: Attribution,
+    attested: bool,               // signed by the game mod
+    source: Option<UploadSource>, // None for uploads released from quarantine
+    ip: Option<String>,
+}
+
+impl Submission {
+    fn new(
+        headers: &HeaderMap,
+        ip: Option<ClientIp>,
+        attribution: Attribution,
+        attested: bool,
+    ) -> Result<Self, &'static str> {
+        Ok(Self {
+            attribution,
+            attested,
+            source: Some(upload_source::from_request(headers, attested)?),
+            ip: ip.map(|ClientIp(ip)| ip.to_string()),
+        })
+    }
+
+    async fn store(&self, db: &database::Database, upload_id: i64) {
+        self.attribution.store(db, upload_id).await;
+        if let Some(source) = self.source
+            && let Err(e) = db.set_upload_source(upload_id, source, self.attested).await
+        {
+            error!("Failed to store the source of upload {}: {}", upload_id, e);
+        }
+        if let Some(ip) = &self.ip
+            && let Err(e) = db.set_upload_ip(upload_id, ip).await
+        {
+            error!("Failed to store the address of upload {}: {}", upload_id, e);
+        }
+    }
+}
+
+// Status code contract of the upload endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum UploadOutcome {
+    Created,  // 201, the level's first thumbnail went live
+    Replaced, // 200, an existing thumbnail was replaced
+    Pending,  // 202, queued for a moderator
+}
+
+impl UploadOutcome {
+    fn status_code(self) -> StatusCode {
+        match self {
+            UploadOutcome::Created => StatusCode::CREATED,
+            UploadOutcome::Replaced => StatusCode::OK,
+            UploadOutcome::Pending => StatusCode::ACCEPTED,
+        }
+    }
+
+    fn message(self, level_id: LevelId) -> String {
+        match self {
+            UploadOutcome::Created => format!("Image for level ID {} uploaded", level_id),
+            UploadOutcome::Replaced => format!("Image for level ID {} replaced", level_id),
+            UploadOutcome::Pending => format!("Image for level ID {} is now pending", level_id),
+        }
+    }
 }
 
 // Handler for uploading images for admins/moderators (and verified for new thumbnails)
 async fn force_save(
-    id: u64,
+    namespace: &str,
+    id: LevelId,
     image_data: &[u8],
     user: &database::User,
     db: &database::Database,
-) -> Result<(), String> {
-    let image_path = format!("thumbnails/{}.webp", id);
-
-    tokio::fs::write(&image_path, image_data)
+    submission: &Submission,
+) -> Result<(i64, UploadOutcome), String> {
+    let image_path = paths::thumbnail_path(namespace, id);
+    let outcome = match is_image_uploaded(namespace, id).await {
+        true => UploadOutcome::Replaced,
+        false => UploadOutcome::Created,
+    };
+    // looked up before the new row becomes the live one
+    let previous = db
+        .get_live_upload_id(namespace, id)
         .await
-        .map_err(|e| format!("Failed to save image: {}", e))?;
+        .map_err(|e| format!("Failed to look up live upload: {}", e))?;
 
-    db.add_upload(id as i64, user.id, &image_path, true)
+    // the row goes in before the file so a failed insert never leaves an unrecorded image live
+    let upload_id = db
+        .add_upload(namespace, id, user.id, &image_path, true, &image_meta(image_data))
         .await
         .map_err(|e| format!("Failed to add upload entry: {}", e))?;
+    if let Some(previous) = previous {
+        archive::keep(db, namespace, id, previous).await;
+    }
+    if let Err(e) = storage::write_local(&image_path, image_data).await {
+        // the previous thumbnail is still live, so its row has to be as well
+        if let Err(remove) = db.remove_upload(upload_id).await {
+            error!("Failed to take down upload {}: {}", upload_id, remove);
+        }
+        return Err(format!("Failed to save image: {}", e));
+    }
+    submission.store(db, upload_id).await;
 
-    cache_controller::purge(id as i64);
-    Ok(())
+    // mirrors only replicate the main game
+    if namespace == namespace::DEFAULT {
+        sync::record_accepted(db, id, user.id, image_data).await;
+    }
+    cache_controller::replaced(db, namespace, id).await;
+    events::publish(QueueEvent::Published {
+        upload_id,
+        namespace: namespace.to_string(),
+        level_id: id,
+        user_id: user.id,
+    });
+    Ok((upload_id, outcome))
+}
+
+// Publishes the image right away and replies with the outcome
+async fn publish(
+    namespace: &str,
+    id: LevelId,
+    image_data: &[u8],
+    user: &database::User,
+    db: &database::Database,
+    submission: &Submission,
+) -> Response {
+    match force_save(namespace, id, image_data, user, db, submission).await {
+        Ok((upload_id, outcome)) => upload_response(db, user, outcome, id, upload_id).await,
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error saving image: {}", e),
+        ),
+    }
+}
+
+// Everything a client needs to act on an upload without parsing the message
+#[derive(Serialize)]
+struct UploadReceipt {
+    upload_id: i64,
+    namespace: String,
+    level_id: LevelId,
+    status: database::UploadStatus,
+    processing_status: database::ProcessingStatus,
+    url: Option<String>, // the live thumbnail, once there is one
+    status_url: String,
+    quota_remaining: Option<i64>, // None when the user has no upload quota
+}
+
+async fn upload_receipt(
+    db: &database::Database,
+    user: &database::User,
+    upload_id: i64,
+) -> Option<UploadReceipt> {
+    let upload = db.get_upload_processing(upload_id).await?;
+    let home_url = dotenv::var("HOME_URL").unwrap_or_default();
+
+    let quota = usage_stats::upload_quota(db, user).await;
+    let role = namespace::role(db, user, &upload.namespace).await;
+    let quota_remaining = if quota > 0 && !permissions::has(role, Permission::BypassQuota) {
+        db.count_recent_uploads(user.id).await.ok().map(|count| (quota - count).max(0))
+    } else {
+        None
+    };
+
+    Some(UploadReceipt {
+        upload_id,
+        url: (upload.status == database::UploadStatus::Accepted).then(|| {
+            format!(
+                "{}{}/thumbnail/{}",
+                home_url,
+                namespace::route_prefix(&upload.namespace),
+                upload.level_id
+            )
+        }),
+        status_url: format!("{}/upload/{}/status", home_url, upload_id),
+        namespace: upload.namespace,
+        level_id: upload.level_id,
+        status: upload.status,
+        processing_status: upload.processing_status,
+        quota_remaining,
+    })
+}
+
+#[derive(Serialize)]
+struct UploadResponse {
+    status: u16,
+    outcome: UploadOutcome,
+    message: String,
+    upload_id: i64, // kept at the top level for older clients
+    receipt: Option<UploadReceipt>,
+}
+
+async fn upload_response(
+    db: &database::Database,
+    user: &database::User,
+    outcome: UploadOutcome,
+    level_id: LevelId,
+    upload_id: i64,
+) -> Response {
+    let body = UploadResponse {
+        status: outcome.status_code().as_u16(),
+        outcome,
+        message: outcome.message(level_id),
+        upload_id,
+        receipt: upload_receipt(db, user, upload_id).await,
+    };
+    util::response(outcome.status_code(), json!(body))
 }
 
 async fn add_to_pending(
-    id: u64,
+    namespace: &str,
+    id: LevelId,
     image_data: &[u8],
     user: &database::User,
     db: &database::Database,
+    scan: Option<ScanResult>,
+    submission: &Submission,
 ) -> Response {
-    let image_path = format!("uploads/{}_{}.webp", user.id, id);
+    let image_path = paths::pending_path(namespace, user.id, id);
 
-    match tokio::fs::write(&image_path, image_data).await {
+    let write = async {
+        tokio::fs::create_dir_all(paths::local(paths::upload_dir(namespace))).await?;
+        tokio::fs::write(paths::local(&image_path), image_data).await
+    };
+    match write.await {
         Ok(_) => {}
         Err(e) => {
             return util::str_response(
@@ -80,10 +429,26 @@ async fn add_to_pending(
         }
     }
 
-    match db.add_upload(id as i64, user.id, &image_path, false).await {
-        Ok(_) => util::str_response(
-            StatusCode::ACCEPTED,
-            &format!("Image for level ID {} is now pending", id),
+    match db.add_upload(namespace, id, user.id, &image_path, false, &image_meta(image_data)).await {
+        Ok(upload_id) => {
+            if let Some(scan) = &scan
+                && let Err(e) = db.set_scan_result(upload_id, scan).await
+            {
+                error!("Failed to store scan result for upload {}: {}", upload_id, e);
+            }
+            submission.store(db, upload_id).await;
+
+            assignment::assign(db, namespace, upload_id).await;
+            events::publish(QueueEvent::Submitted {
+                upload_id,
+                level_id: id,
+                user_id: user.id,
+            });
+            upload_response(db, user, UploadOutcome::Pending, id, upload_id).await
+        }
+        Err(sqlx::Error::Database(e)) if e.is_unique_violation() => util::str_response(
+            StatusCode::CONFLICT,
+            &format!("You already have a pending thumbnail for level ID {}", id),
         ),
         Err(e) => util::str_response(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -92,119 +457,402 @@ async fn add_to_pending(
     }
 }
 
-async fn has_pending_upload(user_id: i64, level_id: u64) -> bool {
-    let image_path = format!("uploads/{}_{}.webp", user_id, level_id);
-    tokio::fs::metadata(&image_path).await.is_ok()
+// An upload released from quarantine goes through the same freeze, lock and pending
+// checks as a fresh one from its uploader would
+pub async fn release_to_pending(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+    id: LevelId,
+    webp_data: &[u8],
+) -> Response {
+    if let Some(response) = frozen(db, namespace, id).await {
+        return response;
+    }
+
+    let _lock = match db.try_lock_level(namespace, id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                &format!("Another upload for level ID {} is in progress", id),
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error locking level: {}", e),
+            );
+        }
+    };
+
+    let role = namespace::role(db, user, namespace).await;
+    if let Some(response) = pending_conflict(user, role, namespace, id).await {
+        return response;
+    }
+    add_to_pending(namespace, id, webp_data, user, db, None, &Submission::default()).await
+}
+
+async fn has_pending_upload(namespace: &str, user_id: UserId, level_id: LevelId) -> bool {
+    let image_path = paths::pending_path(namespace, user_id, level_id);
+    tokio::fs::metadata(paths::local(&image_path)).await.is_ok()
+}
+
+// Users without BypassQuota can only have one pending upload per level
+async fn pending_conflict(
+    user: &database::User,
+    role: database::Role,
+    namespace: &str,
+    id: LevelId,
+) -> Option<Response> {
+    if !permissions::has(role, Permission::BypassQuota)
+        && has_pending_upload(namespace, user.id, id).await
+    {
+        return Some(util::str_response(
+            StatusCode::CONFLICT,
+            &format!("You already have a pending thumbnail for level ID {}", id),
+        ));
+    }
+    None
+}
+
+// Fails closed, a level whose thumbnail can't be looked up in the bucket isn't treated as new
+async fn is_image_uploaded(namespace: &str, id: LevelId) -> bool {
+    let image_path = paths::thumbnail_path(namespace, id);
+    storage::exists(&image_path).await.unwrap_or(true)
 }
 
-async fn is_image_uploaded(id: u64) -> bool {
-    let image_path = format!("thumbnails/{}.webp", id);
-    tokio::fs::metadata(&image_path).await.is_ok()
+// Whether the user uploaded the thumbnail that is currently live for this level
+async fn is_active_author(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+    id: LevelId,
+) -> bool {
+    db.get_upload_extended(namespace, id).await.is_some_and(|active| active.user_id == user.id)
 }
 
 pub async fn upload(
     State(db): State<database::Database>,
+    AuthedUser(user): AuthedUser,
     headers: HeaderMap,
-    Path(id): Path<u64>,
+    LevelPath(id): LevelPath<LevelId>,
+    ip: Option<ClientIp>,
     data: Bytes,
 ) -> Response {
-    let user = match util::auth_middleware(&headers, &db).await {
-        Ok(user) => user,
-        Err(response) => return response,
+    client_ip::record(&db, user.id, ip, database::IpAction::Upload).await;
+    if let Err(response) = tos::check(&db, &user).await {
+        return response;
+    }
+    if let Err(response) = captcha::check(&db, &user, ip, &headers).await {
+        return response;
+    }
+    let attribution = Attribution::from_headers(&headers);
+    let attested = attestation::check(&headers, &data);
+    let submission = match Submission::new(&headers, ip, attribution, attested) {
+        Ok(submission) => submission,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
     };
+    save_upload(&db, &user, namespace::DEFAULT, id, data.into(), submission).await
+}
+
+pub async fn namespaced_upload(
+    State(db): State<database::Database>,
+    AuthedUser(user): AuthedUser,
+    headers: HeaderMap,
+    LevelPath((ns, id)): LevelPath<(String, LevelId)>,
+    ip: Option<ClientIp>,
+    data: Bytes,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
 
-    // Check for existing pending uploads for regular and verified users
-    if matches!(user.role, database::Role::User | database::Role::Verified) {
-        if has_pending_upload(user.id, id).await {
+    client_ip::record(&db, user.id, ip, database::IpAction::Upload).await;
+    if let Err(response) = tos::check(&db, &user).await {
+        return response;
+    }
+    if let Err(response) = captcha::check(&db, &user, ip, &headers).await {
+        return response;
+    }
+    let attribution = Attribution::from_headers(&headers);
+    let attested = attestation::check(&headers, &data);
+    let submission = match Submission::new(&headers, ip, attribution, attested) {
+        Ok(submission) => submission,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+    save_upload(&db, &user, &ns, id, data.into(), submission).await
+}
+
+// Uploaders can follow their own uploads, moderators can see all of them
+pub async fn get_upload_status(
+    State(db): State<database::Database>,
+    AuthedUser(user): AuthedUser,
+    Path(id): Path<i64>,
+) -> Response {
+    let upload = match db.get_upload_processing(id).await {
+        Some(upload) => upload,
+        None => return util::str_response(StatusCode::NOT_FOUND, "Upload not found"),
+    };
+
+    if upload.user_id != user.id && check_moderator(&db, &user, &upload.namespace).await.is_err() {
+        return util::str_response(StatusCode::NOT_FOUND, "Upload not found");
+    }
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "upload": upload,
+        }),
+    )
+}
+
+// Validate an uploaded image and either publish it or queue it, depending on the user's role
+async fn save_upload(
+    db: &database::Database,
+    user: &database::User,
+    namespace: &str,
+    id: LevelId,
+    data: Vec<u8>,
+    submission: Submission,
+) -> Response {
+    let submission = match submission.attribution.validate() {
+        Ok(attribution) => Submission { attribution, ..submission },
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+
+    if allowed_format(&data).is_none() {
+        return unsupported_format();
+    }
+    if let Some(response) = frozen(db, namespace, id).await {
+        return response;
+    }
+
+    let role = namespace::role(db, user, namespace).await;
+    if let Some(response) = pending_conflict(user, role, namespace, id).await {
+        return response;
+    }
+
+    usage_stats::record_upload(user.id, data.len());
+
+    // Users without BypassQuota are limited to a number of uploads per day
+    let quota = usage_stats::upload_quota(db, user).await;
+    if quota > 0 && !permissions::has(role, Permission::BypassQuota) {
+        match db.count_recent_uploads(user.id).await {
+            Ok(count) if count >= quota => {
+                return util::str_response(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    &format!("You can only upload {} thumbnails per day", quota),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error checking upload quota: {}", e),
+                );
+            }
+        }
+    }
+
+    // Sources can have a stricter quota of their own, like scripts using the API
+    if let Some(source) = submission.source
+        && !permissions::has(role, Permission::BypassQuota)
+    {
+        match upload_source::over_quota(db, user, source).await {
+            Ok(Some(quota)) => {
+                return util::str_response(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    &format!("You can only upload {} thumbnails per day from {}", quota, source),
+                );
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error checking upload quota: {}", e),
+                );
+            }
+        }
+    }
+
+    // Known illegal content is quarantined before it is written anywhere else
+    match hash_match::check(&data).await {
+        Ok(HashMatch::Clean) => {}
+        Ok(HashMatch::Match(reference)) => {
+            let detail = reference.as_deref();
+            if let Err(e) =
+                quarantine::store(db, user, namespace, id, &data, "hash_match", detail).await
+            {
+                error!("Failed to quarantine upload for level {}: {}", id, e);
+            }
             return util::str_response(
-                StatusCode::CONFLICT,
-                &format!("You already have a pending thumbnail for level ID {}", id),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "This image cannot be accepted",
+            );
+        }
+        Err(e) => {
+            error!("Hash match check failed: {}", e);
+            return util::str_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Uploads are temporarily unavailable, please try again later",
             );
         }
     }
 
     // Process and validate the image
-    let webp_data = match process_image(&data) {
-        Ok(data) => data,
-        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    let webp_data = match encoder::run(move || process_image(&data)).await {
+        Ok(Ok(data)) => data,
+        Ok(Err(e)) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+        Err(e) => return util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
     };
 
-    match user.role {
-        // Admins and moderators can upload and replace images directly
-        database::Role::Admin | database::Role::Moderator => {
-            match force_save(id, &webp_data, &user, &db).await {
-                Ok(_) => util::str_response(
-                    StatusCode::CREATED,
-                    &format!("Image for level ID {} uploaded", id),
-                ),
-                Err(e) => util::str_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    &format!("Error saving image: {}", e),
-                ),
+    // Staff publish directly, everyone else's uploads go through the content scanner
+    let publish_directly = permissions::has(role, Permission::PublishDirectly);
+    let (scan, scan_failed) = match publish_directly {
+        true => (None, false),
+        false => match scanner::scan(&webp_data).await {
+            Ok(scan) => (scan, false),
+            Err(e) => {
+                warn!("{}, sending the upload of {} to the queue", e, id);
+                (None, true)
             }
+        },
+    };
+    let scan_clean =
+        !scan_failed && scan.as_ref().is_none_or(|scan| scan.verdict == ScanVerdict::Clean);
+    let may_publish = scan_clean && !upload_source::needs_review(submission.source);
+
+    // held uploads never reach the pending queue, only admins can look at them
+    if let Some(scan) = &scan
+        && scan.verdict == ScanVerdict::Held
+    {
+        let label = scan.label.as_deref();
+        return match quarantine::store(db, user, namespace, id, &webp_data, "scanner", label).await
+        {
+            Ok(_) => util::str_response(
+                StatusCode::ACCEPTED,
+                &format!("Image for level ID {} is held for review", id),
+            ),
+            Err(e) => util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to hold image: {}", e),
+            ),
+        };
+    }
+
+    // Only one upload per level is written at a time, the pending check is repeated
+    // under the lock in case a concurrent request finished in the meantime
+    let _lock = match db.try_lock_level(namespace, id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                &format!("Another upload for level ID {} is in progress", id),
+            );
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error locking level: {}", e),
+            );
         }
+    };
 
-        // Verified users can upload new images directly, but replacements need approval
-        database::Role::Verified => {
-            if !is_image_uploaded(id).await {
-                match force_save(id, &webp_data, &user, &db).await {
-                    Ok(_) => util::str_response(
-                        StatusCode::CREATED,
-                        &format!("Image for level ID {} uploaded", id),
-                    ),
-                    Err(e) => util::str_response(
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        &format!("Error saving image: {}", e),
-                    ),
-                }
-            } else {
-                // Image exists, add to pending for approval
-                add_to_pending(id, &webp_data, &user, &db).await
+    if let Some(response) = pending_conflict(user, role, namespace, id).await {
+        return response;
+    }
+
+    // Admin-defined rules come before the defaults below, but nothing the scanner
+    // flagged or from a source under review is ever accepted automatically
+    if !publish_directly
+        && let Some(rule) =
+            upload_rules::evaluate(db, user, role, namespace, id, submission.attested).await
+    {
+        info!(
+            "Upload rule {} ({}) matched upload of {} by {}",
+            rule.id, rule.name, id, user.username
+        );
+        match rule.action {
+            RuleAction::Accept if may_publish => {
+                return publish(namespace, id, &webp_data, user, db, &submission).await;
+            }
+            RuleAction::Accept => {}
+            RuleAction::Hold => {
+                return add_to_pending(namespace, id, &webp_data, user, db, scan, &submission)
+                    .await;
             }
         }
+    }
+
+    // Admins and moderators can upload and replace images directly
+    if publish_directly {
+        return publish(namespace, id, &webp_data, user, db, &submission).await;
+    }
 
-        // Regular users must go through approval process
-        database::Role::User => add_to_pending(id, &webp_data, &user, &db).await,
+    // Verified users can upload new images and replace their own directly,
+    // replacing someone else's thumbnail needs approval. Anything the scanner
+    // didn't like or from a source under review goes to the queue instead.
+    if permissions::has(role, Permission::PublishOwn)
+        && may_publish
+        && (!is_image_uploaded(namespace, id).await
+            || is_active_author(db, user, namespace, id).await)
+    {
+        return publish(namespace, id, &webp_data, user, db, &submission).await;
     }
+
+    // Everyone else goes through the approval process
+    add_to_pending(namespace, id, &webp_data, user, db, scan, &submission).await
 }
 
 #[derive(PartialEq)]
 enum PendingFilter {
     All,
-    ByLevel(i64),
-    ByUser(i64),
+    ByLevel(LevelId),
+    ByUser(UserId),
+    Assigned,
 }
 
 async fn get_pending_uploads(
-    headers: HeaderMap,
+    user: database::User,
     db: &database::Database,
+    namespace: &str,
     filter: PendingFilter,
 ) -> Response {
-    let user = match authenticate_moderator(&headers, db).await {
-        Ok(user) => user,
-        Err(response) => return response,
-    };
+    if let Err(response) = check_moderator(db, &user, namespace).await {
+        return response;
+    }
 
     // Special case: users can view their own pending uploads
-    if let PendingFilter::ByUser(user_id) = filter {
-        if user.id != user_id {
-            return util::str_response(
-                StatusCode::FORBIDDEN,
-                "You can only view your own pending uploads",
-            );
-        }
+    if let PendingFilter::ByUser(user_id) = filter
+        && user.id != user_id
+    {
+        return util::str_response(
+            StatusCode::FORBIDDEN,
+            "You can only view your own pending uploads",
+        );
     }
 
     let uploads_result = match filter {
-        PendingFilter::All => db.get_pending_uploads().await,
-        PendingFilter::ByLevel(level_id) => db.get_pending_uploads_for_level(level_id).await,
+        PendingFilter::All => db.get_pending_uploads(namespace).await,
+        PendingFilter::ByLevel(level_id) => {
+            db.get_pending_uploads_for_level(namespace, level_id).await
+        }
         PendingFilter::ByUser(user_id) => db.get_pending_uploads_for_user(user_id).await,
+        PendingFilter::Assigned => db.get_assigned_uploads(user.id).await,
     };
 
     match uploads_result {
         Ok(mut uploads) => {
+            uploads.retain(|upload| upload.namespace == namespace);
+            // stable, so both halves stay oldest first
+            if settings::current().attested_first {
+                uploads.sort_by_key(|upload| !upload.attested);
+            }
             for upload in &mut uploads {
-                upload.replacement = is_image_uploaded(upload.level_id as u64).await;
+                upload.replacement = is_image_uploaded(namespace, upload.level_id).await;
+                upload.image_url = Some(pending_image_url(db, upload.id, user.id));
             }
 
             Response::builder()
@@ -221,44 +869,183 @@ async fn get_pending_uploads(
 }
 
 pub async fn get_pending_uploads_for_level(
-    headers: HeaderMap,
+    AuthedUser(user): AuthedUser,
     State(db): State<database::Database>,
-    Path(id): Path<i64>,
+    LevelPath(id): LevelPath<LevelId>,
 ) -> Response {
-    get_pending_uploads(headers, &db, PendingFilter::ByLevel(id)).await
+    get_pending_uploads(user, &db, namespace::DEFAULT, PendingFilter::ByLevel(id)).await
 }
 
 pub async fn get_all_pending_uploads(
-    headers: HeaderMap,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    get_pending_uploads(user, &db, namespace::DEFAULT, PendingFilter::All).await
+}
+
+pub async fn get_namespace_pending_uploads(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(ns): Path<String>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    get_pending_uploads(user, &db, &ns, PendingFilter::All).await
+}
+
+pub async fn get_namespace_pending_uploads_for_level(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    LevelPath((ns, id)): LevelPath<(String, LevelId)>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    get_pending_uploads(user, &db, &ns, PendingFilter::ByLevel(id)).await
+}
+
+pub async fn get_assigned_pending_uploads(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    get_pending_uploads(user, &db, namespace::DEFAULT, PendingFilter::Assigned).await
+}
+
+pub async fn get_namespace_assigned_pending_uploads(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(ns): Path<String>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    get_pending_uploads(user, &db, &ns, PendingFilter::Assigned).await
+}
+
+async fn presence(user: database::User, db: &database::Database, namespace: &str) -> Response {
+    if let Err(response) = check_moderator(db, &user, namespace).await {
+        return response;
+    }
+
+    assignment::ping(db, user.id).await;
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "queue_assignment": settings::current().queue_assignment,
+        }),
+    )
+}
+
+// Moderators ping this while they have the queue open to receive assignments
+pub async fn pending_presence(
+    AuthedUser(user): AuthedUser,
     State(db): State<database::Database>,
 ) -> Response {
-    get_pending_uploads(headers, &db, PendingFilter::All).await
+    presence(user, &db, namespace::DEFAULT).await
+}
+
+pub async fn namespaced_pending_presence(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(ns): Path<String>,
+) -> Response {
+    if let Err(response) = namespace::resolve(&db, &ns).await {
+        return response;
+    }
+    presence(user, &db, &ns).await
 }
 
 pub async fn get_pending_uploads_for_user(
-    headers: HeaderMap,
+    AuthedUser(user): AuthedUser,
     State(db): State<database::Database>,
-    Path(id): Path<i64>,
+    Path(id): Path<UserId>,
 ) -> Response {
-    get_pending_uploads(headers, &db, PendingFilter::ByUser(id)).await
+    get_pending_uploads(user, &db, namespace::DEFAULT, PendingFilter::ByUser(id)).await
+}
+
+const DEFAULT_DECIDED_PAGE_SIZE: i64 = 50;
+const MAX_DECIDED_PAGE_SIZE: i64 = 200;
+const RECENT_REJECTION_LIMIT: i64 = 5;
+
+#[derive(Deserialize)]
+pub struct DecidedQuery {
+    level_id: Option<LevelId>,
+    user_id: Option<UserId>,
+    before: Option<i64>, // upload ID to continue from, newest decisions come first
+    limit: Option<i64>,
+}
+
+pub async fn get_decided_uploads(
+    _: RequirePermission<ReviewUploads>,
+    State(db): State<database::Database>,
+    Query(query): Query<DecidedQuery>,
+) -> Response {
+    let limit = query.limit.unwrap_or(DEFAULT_DECIDED_PAGE_SIZE).clamp(1, MAX_DECIDED_PAGE_SIZE);
+    match db.get_decided_uploads(query.level_id, query.user_id, query.before, limit).await {
+        Ok(uploads) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "uploads": uploads,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching decided uploads: {}", e),
+        ),
+    }
+}
+
+#[derive(Serialize)]
+struct Uploader {
+    #[serde(flatten)]
+    summary: database::UploaderSummary,
+    standing: usage_stats::Standing,
+}
+
+#[derive(Serialize)]
+struct PendingInfo {
+    #[serde(flatten)]
+    upload: database::PendingUpload,
+    uploader: Option<Uploader>, // None if the account is gone
 }
 
 pub async fn get_pending_info(
-    headers: HeaderMap,
+    AuthedUser(user): AuthedUser,
     State(db): State<database::Database>,
     Path(id): Path<i64>,
 ) -> Response {
-    let _user = match authenticate_moderator(&headers, &db).await {
-        Ok(user) => user,
-        Err(response) => return response,
-    };
-
     match db.get_pending_upload(id).await {
-        Ok(upload) => Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, "application/json")
-            .body(serde_json::to_string(&upload).unwrap().into())
-            .unwrap(),
+        Ok(mut upload) => {
+            if let Err(response) = check_moderator(&db, &user, &upload.namespace).await {
+                return response;
+            }
+            upload.image_url = Some(pending_image_url(&db, upload.id, user.id));
+            events::publish(QueueEvent::Claimed {
+                upload_id: upload.id,
+                moderator_id: user.id,
+            });
+
+            let uploader = db
+                .get_uploader_summary(
+                    upload.user_id,
+                    usage_stats::TRUSTED_WINDOW_DAYS,
+                    RECENT_REJECTION_LIMIT,
+                )
+                .await
+                .map(|summary| Uploader {
+                    standing: usage_stats::standing(summary.created_at, summary.active_days),
+                    summary,
+                });
+            let info = PendingInfo { upload, uploader };
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(serde_json::to_string(&info).unwrap().into())
+                .unwrap()
+        }
         Err(e) => util::str_response(
             StatusCode::NOT_FOUND,
             &format!("No pending upload found with ID {}: {}", id, e),
@@ -270,19 +1057,123 @@ pub async fn get_pending_info(
 pub struct PendingUploadAction {
     pub accepted: bool,
     pub reason: Option<String>,
+    #[serde(default)]
+    pub edits: Option<ImageEdits>, // only used when accepting
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Small fixes a moderator can make instead of bouncing the upload back, applied in this order
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct ImageEdits {
+    pub rotate: Option<u32>,     // clockwise, 90, 180 or 270 degrees
+    pub crop: Option<CropRect>,  // in pixels of the rotated image
+    pub brightness: Option<i32>, // -255 to 255
+}
+
+// Applies the edits and scales the result back to the thumbnail size
+fn apply_edits(data: &[u8], edits: ImageEdits) -> Result<Vec<u8>, String> {
+    let mut image =
+        image::load_from_memory(data).map_err(|e| format!("Invalid image data: {}", e))?;
+
+    image = match edits.rotate.unwrap_or(0) {
+        0 => image,
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        other => return Err(format!("Can't rotate by {} degrees", other)),
+    };
+
+    if let Some(crop) = edits.crop {
+        if crop.width == 0
+            || crop.height == 0
+            || crop.x.saturating_add(crop.width) > image.width()
+            || crop.y.saturating_add(crop.height) > image.height()
+        {
+            return Err("Crop rectangle is outside the image".to_string());
+        }
+        image = image.crop_imm(crop.x, crop.y, crop.width, crop.height);
+    }
+
+    if let Some(brightness) = edits.brightness {
+        if !(-255..=255).contains(&brightness) {
+            return Err("Brightness must be between -255 and 255".to_string());
+        }
+        image = image.brighten(brightness);
+    }
+
+    if image.width() != IMAGE_WIDTH || image.height() != IMAGE_HEIGHT {
+        image = image.resize_to_fill(IMAGE_WIDTH, IMAGE_HEIGHT, FilterType::Lanczos3);
+    }
+
+    let rgb_data = image.into_rgb8();
+    Ok(Encoder::from_rgb(&rgb_data, IMAGE_WIDTH, IMAGE_HEIGHT).encode_lossless().to_owned())
+}
+
+// Writes the edited pending image to a scratch file next to where it goes live, the pending
+// image itself is left alone until the upload is accepted
+async fn edit_pending_image(
+    path: &str,
+    live_path: &str,
+    edits: ImageEdits,
+) -> Result<PathBuf, Response> {
+    let Some(path) = paths::canonical(path).map(paths::local) else {
+        return Err(util::str_response(StatusCode::NOT_FOUND, "Image not found"));
+    };
+    let data = tokio::fs::read(&path).await.map_err(|e| {
+        util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error reading image file: {}", e),
+        )
+    })?;
+
+    let edited = match encoder::run(move || apply_edits(&data, edits)).await {
+        Ok(Ok(data)) => data,
+        Ok(Err(e)) => return Err(util::str_response(StatusCode::BAD_REQUEST, &e)),
+        Err(e) => return Err(util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e)),
+    };
+
+    let staged = paths::partial_path(paths::local(live_path));
+    let write = async {
+        if let Some(parent) = staged.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&staged, edited).await
+    };
+    match write.await {
+        Ok(()) => Ok(staged),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged).await;
+            Err(util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error saving edited image: {}", e),
+            ))
+        }
+    }
 }
 
 pub async fn pending_action(
-    headers: HeaderMap,
+    AuthedUser(user): AuthedUser,
     State(db): State<database::Database>,
     Path(id): Path<i64>,
     Json(action): Json<PendingUploadAction>,
 ) -> Response {
-    let user = match authenticate_moderator(&headers, &db).await {
-        Ok(user) => user,
-        Err(response) => return response,
-    };
+    decide_upload(&db, &user, id, action).await
+}
 
+// Accept or reject a pending upload on behalf of an already authorized moderator
+pub async fn decide_upload(
+    db: &database::Database,
+    user: &database::User,
+    id: i64,
+    action: PendingUploadAction,
+) -> Response {
     let upload = match db.get_pending_upload(id).await {
         Ok(upload) => upload,
         Err(e) => {
@@ -293,58 +1184,457 @@ pub async fn pending_action(
         }
     };
 
-    if upload.accepted {
-        return util::str_response(StatusCode::CONFLICT, "This upload has already been accepted");
+    // moderators of one namespace can't decide uploads of another
+    if let Err(response) = check_moderator(db, user, &upload.namespace).await {
+        return response;
     }
 
-    let old_image_path = format!("uploads/{}_{}.webp", upload.user_id, upload.level_id);
+    let decision = match action.accepted {
+        true => moderation::Action::Accept,
+        false => moderation::Action::Reject,
+    };
+    if let Err(refusal) = moderation::next(upload.status, decision) {
+        return util::str_response(StatusCode::CONFLICT, &refusal.to_string());
+    }
+
+    let old_image_path = paths::pending_path(&upload.namespace, upload.user_id, upload.level_id);
 
     if action.accepted {
+        // the live file is replaced below, same as a direct upload or an undo would
+        let _lock = match db.try_lock_level(&upload.namespace, upload.level_id).await {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                return util::str_response(
+                    StatusCode::CONFLICT,
+                    &format!("Another upload for level ID {} is in progress", upload.level_id),
+                );
+            }
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error locking level: {}", e),
+                );
+            }
+        };
+
+        let new_image_path = paths::thumbnail_path(&upload.namespace, upload.level_id);
+        let edited = match action.edits {
+            Some(edits) => {
+                match edit_pending_image(&old_image_path, &new_image_path, edits).await {
+                    Ok(staged) => Some(staged),
+                    Err(response) => return response,
+                }
+            }
+            None => None,
+        };
+
+        // the row is claimed before any file moves so a second moderator deciding the same
+        // upload gets a 409, the live upload is looked up while it's still the newest one
+        let claimed = match db.get_live_upload_id(&upload.namespace, upload.level_id).await {
+            Ok(previous) => db
+                .accept_upload(upload.id, user.id, action.reason.clone(), true)
+                .await
+                .map(|accepted| accepted.then_some(previous)),
+            Err(e) => Err(e),
+        };
+        let previous = match claimed {
+            Ok(Some(previous)) => previous,
+            failed => {
+                if let Some(staged) = &edited {
+                    let _ = tokio::fs::remove_file(staged).await;
+                }
+                return match failed {
+                    Ok(_) => util::str_response(
+                        StatusCode::CONFLICT,
+                        &format!("Upload {} was already decided", id),
+                    ),
+                    Err(e) => util::str_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        &format!("Error accepting upload: {}", e),
+                    ),
+                };
+            }
+        };
+
         // Accept: move image from uploads to thumbnails
-        let new_image_path = format!("thumbnails/{}.webp", upload.level_id);
+        if let Some(previous) = previous {
+            archive::keep(db, &upload.namespace, upload.level_id, previous).await;
+        }
 
-        if let Err(e) = tokio::fs::rename(&old_image_path, &new_image_path).await {
+        // the image only goes live once the row says so, an edited one replaces the original
+        let source = edited.clone().unwrap_or_else(|| paths::local(&old_image_path));
+        let rename = async {
+            tokio::fs::create_dir_all(paths::local(paths::thumbnail_dir(&upload.namespace)))
+                .await?;
+            tokio::fs::rename(&source, paths::local(&new_image_path)).await
+        };
+        if let Err(e) = rename.await {
+            if let Some(staged) = &edited {
+                let _ = tokio::fs::remove_file(staged).await;
+            }
+            // the previous thumbnail is still live, the upload goes back to the queue
+            if let Err(reopen) = db.reopen_upload(upload.id, database::UploadStatus::Accepted).await
+            {
+                error!("Failed to reopen upload {}: {}", upload.id, reopen);
+            }
             return util::str_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 &format!("Error moving image: {}", e),
             );
         }
+        if edited.is_some() {
+            let _ = tokio::fs::remove_file(paths::local(&old_image_path)).await;
+        }
 
-        if let Err(e) = db.accept_upload(upload.id, user.id, action.reason, true).await {
-            return util::str_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                &format!("Error accepting upload: {}", e),
-            );
+        // recorded before the event goes out so mirrors never miss an accepted image
+        if upload.namespace == namespace::DEFAULT
+            && let Ok(image_data) = tokio::fs::read(paths::local(&new_image_path)).await
+        {
+            sync::record_accepted(db, upload.level_id, upload.user_id, &image_data).await;
         }
+        cache_controller::replaced(db, &upload.namespace, upload.level_id).await;
 
-        cache_controller::purge(upload.level_id);
+        events::publish(QueueEvent::Decided {
+            upload_id: upload.id,
+            namespace: upload.namespace,
+            level_id: upload.level_id,
+            user_id: upload.user_id,
+            accepted: true,
+            moderator_id: user.id,
+            reason: action.reason,
+        });
         util::str_response(StatusCode::OK, &format!("Upload {} accepted", id))
     } else {
-        // Reject: delete the pending image
-        if let Err(e) = tokio::fs::remove_file(&old_image_path).await {
+        match db.accept_upload(upload.id, user.id, action.reason.clone(), false).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return util::str_response(
+                    StatusCode::CONFLICT,
+                    &format!("Upload {} was already decided", id),
+                );
+            }
+            Err(e) => {
+                return util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error rejecting upload: {}", e),
+                );
+            }
+        }
+
+        // Reject: move the pending image aside, the retention job deletes it later
+        let rejected_path = paths::rejected_path(&upload.namespace, upload.id);
+        let rename = async {
+            tokio::fs::create_dir_all(paths::local(paths::rejected_dir(&upload.namespace))).await?;
+            tokio::fs::rename(paths::local(&old_image_path), paths::local(&rejected_path)).await
+        };
+        if let Err(e) = rename.await {
+            // back to the queue so the decision can be made again
+            if let Err(reopen) = db.reopen_upload(upload.id, database::UploadStatus::Rejected).await
+            {
+                error!("Failed to reopen upload {}: {}", upload.id, reopen);
+            }
             return util::str_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                &format!("Error deleting image: {}", e),
+                &format!("Error moving image: {}", e),
             );
         }
+        if let Err(e) = db.set_archive_path(upload.id, &rejected_path).await {
+            error!("Failed to record rejected image of upload {}: {}", upload.id, e);
+        }
+
+        events::publish(QueueEvent::Decided {
+            upload_id: upload.id,
+            namespace: upload.namespace,
+            level_id: upload.level_id,
+            user_id: upload.user_id,
+            accepted: false,
+            moderator_id: user.id,
+            reason: action.reason,
+        });
+
+        util::str_response(StatusCode::OK, &format!("Upload {} rejected", id))
+    }
+}
+
+// Moves the live thumbnail back to the queue and restores the one it replaced from the archive
+async fn revert_accept(
+    db: &database::Database,
+    upload: &database::UploadDecision,
+    pending_path: &str,
+) -> Result<(), Response> {
+    let conflict = |message: &str| util::str_response(StatusCode::CONFLICT, message);
+    let server_error = |e: String| util::str_response(StatusCode::INTERNAL_SERVER_ERROR, &e);
+
+    // an upload publishing over the thumbnail right now would race the file moves below
+    let _lock = match db.try_lock_level(&upload.namespace, upload.level_id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => return Err(conflict("An upload for this level is in progress")),
+        Err(e) => return Err(server_error(format!("Error locking level: {}", e))),
+    };
+    match db.is_replaced(upload).await {
+        Ok(false) => {}
+        Ok(true) => return Err(conflict("The thumbnail was replaced since, nothing to undo")),
+        Err(e) => return Err(server_error(format!("Error checking thumbnail: {}", e))),
+    }
+
+    let live_path = paths::thumbnail_path(&upload.namespace, upload.level_id);
+    let rename = async {
+        tokio::fs::create_dir_all(paths::local(paths::upload_dir(&upload.namespace))).await?;
+        tokio::fs::rename(paths::local(&live_path), paths::local(pending_path)).await
+    };
+    if let Err(e) = rename.await {
+        return Err(server_error(format!("Error moving image: {}", e)));
+    }
+
+    match db.reopen_upload(upload.id, database::UploadStatus::Accepted).await {
+        Ok(true) => {}
+        result => {
+            let _ = tokio::fs::rename(paths::local(pending_path), paths::local(&live_path)).await;
+            return Err(match result {
+                Err(e) => server_error(format!("Error reopening upload: {}", e)),
+                _ => conflict("The decision changed in the meantime"),
+            });
+        }
+    }
 
-        if let Err(e) = db.accept_upload(upload.id, user.id, action.reason, false).await {
+    // whatever was live before is live again, levels that had nothing go back to nothing
+    let previous = match db.get_live_upload_id(&upload.namespace, upload.level_id).await {
+        Ok(Some(previous)) => db
+            .get_archived_upload(&upload.namespace, upload.level_id, previous)
+            .await
+            .map(|archived| Some((previous, archived))),
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+    match previous {
+        Ok(Some((previous, Some((user_id, archive_path))))) => {
+            match tokio::fs::rename(paths::local(&archive_path), paths::local(&live_path)).await {
+                Ok(()) => {
+                    if let Err(e) = db.clear_archive_paths(&[previous]).await {
+                        error!("Failed to clear archive path of upload {}: {}", previous, e);
+                    }
+                    if upload.namespace == namespace::DEFAULT
+                        && let Ok(data) = tokio::fs::read(paths::local(&live_path)).await
+                    {
+                        sync::record_accepted(db, upload.level_id, user_id, &data).await;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to restore upload {} from the archive: {}", previous, e);
+                    remove_previous(db, upload, previous).await;
+                }
+            }
+        }
+        // retention deleted the archived file already, nothing is live anymore
+        Ok(Some((previous, None))) => remove_previous(db, upload, previous).await,
+        Ok(None) => {
+            if upload.namespace == namespace::DEFAULT {
+                sync::record_removed(db, upload.level_id).await;
+            }
+        }
+        Err(e) => {
+            error!("Failed to look up the previous thumbnail of level {}: {}", upload.level_id, e)
+        }
+    }
+
+    cache_controller::replaced(db, &upload.namespace, upload.level_id).await;
+    Ok(())
+}
+
+// The upload that was live before can't be brought back, so its row stops claiming it is
+async fn remove_previous(
+    db: &database::Database,
+    upload: &database::UploadDecision,
+    previous: i64,
+) {
+    if let Err(e) = db.remove_upload(previous).await {
+        error!("Failed to mark upload {} as removed: {}", previous, e);
+    }
+    if upload.namespace == namespace::DEFAULT {
+        sync::record_removed(db, upload.level_id).await;
+    }
+}
+
+// Moves a rejected image out of the rejected folder, unless retention already deleted it
+async fn revert_reject(
+    db: &database::Database,
+    upload: &database::UploadDecision,
+    pending_path: &str,
+) -> Result<(), Response> {
+    let Some(rejected_path) = &upload.archive_path else {
+        return Err(util::str_response(StatusCode::GONE, "The rejected image is already deleted"));
+    };
+
+    let rename = async {
+        tokio::fs::create_dir_all(paths::local(paths::upload_dir(&upload.namespace))).await?;
+        tokio::fs::rename(paths::local(rejected_path), paths::local(pending_path)).await
+    };
+    if let Err(e) = rename.await {
+        return Err(util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error moving image: {}", e),
+        ));
+    }
+
+    match db.reopen_upload(upload.id, database::UploadStatus::Rejected).await {
+        Ok(true) => Ok(()),
+        result => {
+            let _ =
+                tokio::fs::rename(paths::local(pending_path), paths::local(rejected_path)).await;
+            Err(match result {
+                Err(e) => util::str_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("Error reopening upload: {}", e),
+                ),
+                _ => {
+                    util::str_response(StatusCode::CONFLICT, "The decision changed in the meantime")
+                }
+            })
+        }
+    }
+}
+
+// Puts a decided upload back in the queue, for the moderator who decided it or staff allowed
+// to undo other decisions in the namespace, within the undo window. Edits made while accepting stay applied to the image
+pub async fn undo_decision(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Path(id): Path<i64>,
+) -> Response {
+    let upload = match db.get_upload_decision(id).await {
+        Ok(Some(upload)) => upload,
+        Ok(None) => {
+            return util::str_response(
+                StatusCode::NOT_FOUND,
+                &format!("No upload found with ID {}", id),
+            );
+        }
+        Err(e) => {
             return util::str_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                &format!("Error rejecting upload: {}", e),
+                &format!("Error fetching upload: {}", e),
             );
         }
+    };
 
-        util::str_response(StatusCode::OK, &format!("Upload {} rejected", id))
+    if let Err(response) = check_moderator(&db, &user, &upload.namespace).await {
+        return response;
+    }
+    let role = namespace::role(&db, &user, &upload.namespace).await;
+    if upload.accepted_by != Some(user.id) && !permissions::has(role, Permission::UndoDecisions) {
+        return util::str_response(
+            StatusCode::FORBIDDEN,
+            "Only the moderator who decided this upload can undo it",
+        );
+    }
+
+    let window = chrono::Duration::minutes(settings::current().undo_window as i64);
+    let decided_at = upload.accepted_time.unwrap_or_default();
+    if let Err(refusal) = moderation::undo(upload.status, decided_at, db.now(), window) {
+        return util::str_response(StatusCode::CONFLICT, &refusal.to_string());
     }
+    let accepted = upload.status == database::UploadStatus::Accepted;
+
+    // the uploader may have sent another image for the level since, which would be overwritten
+    let pending_path = paths::pending_path(&upload.namespace, upload.user_id, upload.level_id);
+    if tokio::fs::try_exists(paths::local(&pending_path)).await.unwrap_or(false) {
+        return util::str_response(
+            StatusCode::CONFLICT,
+            "The uploader has another pending upload for this level",
+        );
+    }
+
+    let reverted = match accepted {
+        true => revert_accept(&db, &upload, &pending_path).await,
+        false => revert_reject(&db, &upload, &pending_path).await,
+    };
+    if let Err(response) = reverted {
+        return response;
+    }
+
+    info!("Decision on upload {} undone by {}", id, user.username);
+    events::publish(QueueEvent::Undone {
+        upload_id: upload.id,
+        namespace: upload.namespace,
+        level_id: upload.level_id,
+        user_id: upload.user_id,
+        was_accepted: accepted,
+        moderator_id: user.id,
+    });
+    util::str_response(StatusCode::OK, &format!("Upload {} is pending again", id))
+}
+
+const DEFAULT_PENDING_IMAGE_URL_TTL: i64 = 5 * 60;
+
+fn pending_image_signature(upload_id: i64, moderator_id: UserId, expires: i64) -> Hmac<Sha256> {
+    let secret = auth::secret();
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("pending-image:{}:{}:{}", upload_id, moderator_id, expires).as_bytes());
+    mac
+}
+
+// Short-lived link to a pending image for one moderator, so it can be used in an <img> tag
+pub fn pending_image_url(db: &database::Database, upload_id: i64, moderator_id: UserId) -> String {
+    let ttl = dotenv::var("PENDING_IMAGE_URL_TTL")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PENDING_IMAGE_URL_TTL);
+    let expires = db.now().timestamp() + ttl;
+    let signature = pending_image_signature(upload_id, moderator_id, expires).finalize();
+    format!(
+        "/pending/{}/image?moderator={}&expires={}&signature={}",
+        upload_id,
+        moderator_id,
+        expires,
+        hex::encode(signature.into_bytes())
+    )
+}
+
+#[derive(Deserialize)]
+pub struct PendingImageQuery {
+    moderator: Option<UserId>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+// Returns the user the URL was signed for, if the signature is valid and not expired
+async fn verify_pending_image_url(
+    db: &database::Database,
+    upload_id: i64,
+    query: &PendingImageQuery,
+) -> Result<database::User, Response> {
+    let invalid = || util::str_response(StatusCode::FORBIDDEN, "Invalid or expired image URL");
+    let (Some(moderator_id), Some(expires), Some(signature)) =
+        (query.moderator, query.expires, &query.signature)
+    else {
+        return Err(invalid());
+    };
+
+    if expires < db.now().timestamp() {
+        return Err(invalid());
+    }
+
+    let signature = hex::decode(signature).map_err(|_| invalid())?;
+    pending_image_signature(upload_id, moderator_id, expires)
+        .verify_slice(&signature)
+        .map_err(|_| invalid())?;
+
+    db.get_user_by_id(moderator_id).await.ok_or_else(invalid)
 }
 
 pub async fn get_pending_image(
     headers: HeaderMap,
     State(db): State<database::Database>,
     Path(id): Path<i64>,
+    Query(query): Query<PendingImageQuery>,
 ) -> Response {
-    let _user = match authenticate_moderator(&headers, &db).await {
+    // signed URLs work without credentials, API clients can still send their token
+    let user = match query.signature {
+        Some(_) => verify_pending_image_url(&db, id, &query).await,
+        None => util::auth_middleware(&headers, &db).await,
+    };
+    let user = match user {
         Ok(user) => user,
         Err(response) => return response,
     };
@@ -359,8 +1649,23 @@ pub async fn get_pending_image(
         }
     };
 
-    let image_path = format!("uploads/{}_{}.webp", upload.user_id, upload.level_id);
-    let image_data = match tokio::fs::read(&image_path).await {
+    if let Err(response) = check_moderator(&db, &user, &upload.namespace).await {
+        return response;
+    }
+
+    // held images are only shown to admins
+    let role = namespace::role(&db, &user, &upload.namespace).await;
+    if upload.scan_verdict == Some(ScanVerdict::Held)
+        && !permissions::has(role, Permission::ReviewHeld)
+    {
+        return util::str_response(
+            StatusCode::FORBIDDEN,
+            "This upload is held by the content scanner until an admin reviews it",
+        );
+    }
+
+    let image_path = paths::pending_path(&upload.namespace, upload.user_id, upload.level_id);
+    let image_data = match tokio::fs::read(paths::local(&image_path)).await {
         Ok(data) => data,
         Err(e) => {
             return util::str_response(
@@ -376,8 +1681,104 @@ pub async fn get_pending_image(
             header::CONTENT_DISPOSITION,
             format!("inline; filename=\"pending_{}_{}.webp\"", upload.user_id, id),
         )
-        .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .header(header::CACHE_CONTROL, "private, no-store")
         .header(header::CONTENT_LENGTH, image_data.len())
         .body(image_data.into())
         .unwrap()
 }
+
+pub async fn pending_stream(_: RequirePermission<ReviewUploads>) -> Response {
+    // lagging subscribers just skip the events they missed
+    let stream = BroadcastStream::new(events::subscribe()).filter_map(|event| {
+        let event = event.ok()?;
+        Some(Ok::<_, Infallible>(
+            Event::default().event(event.name()).data(serde_json::to_string(&event).unwrap()),
+        ))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct CompleteUploadPayload {
+    key: String,
+    sha256: Option<String>,
+    #[serde(flatten)]
+    attribution: Attribution,
+}
+
+pub async fn presign_upload(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    LevelPath(id): LevelPath<LevelId>,
+) -> Response {
+    let Some(storage) = object_storage::ObjectStorage::get() else {
+        return util::str_response(StatusCode::NOT_IMPLEMENTED, "Object storage is not configured");
+    };
+    // no point in sending the image to storage when the upload would be refused anyway
+    if let Some(response) = frozen(&db, namespace::DEFAULT, id).await {
+        return response;
+    }
+
+    let key = format!("{}{}", paths::incoming_prefix(user.id, id), db.ids().hex_id());
+    let (url, expires_in) = storage.presign_put(&key);
+
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "key": key,
+            "url": url,
+            "method": "PUT",
+            "expires_in": expires_in,
+            "max_size": storage.max_upload_size(),
+        }),
+    )
+}
+
+pub async fn complete_upload(
+    State(db): State<database::Database>,
+    AuthedUser(user): AuthedUser,
+    headers: HeaderMap,
+    LevelPath(id): LevelPath<LevelId>,
+    ip: Option<ClientIp>,
+    Json(payload): Json<CompleteUploadPayload>,
+) -> Response {
+    client_ip::record(&db, user.id, ip, database::IpAction::Upload).await;
+    if let Err(response) = tos::check(&db, &user).await {
+        return response;
+    }
+    if let Err(response) = captcha::check(&db, &user, ip, &headers).await {
+        return response;
+    }
+
+    let Some(storage) = object_storage::ObjectStorage::get() else {
+        return util::str_response(StatusCode::NOT_IMPLEMENTED, "Object storage is not configured");
+    };
+
+    // only objects presigned for this user and level can be completed
+    if !payload.key.starts_with(&paths::incoming_prefix(user.id, id)) {
+        return util::str_response(StatusCode::FORBIDDEN, "Invalid upload key");
+    }
+
+    let data = match storage.fetch(&payload.key).await {
+        Ok(data) => data,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    // the object is consumed either way, the client has to start over on failure
+    storage.delete(&payload.key).await;
+
+    if let Some(expected) = &payload.sha256
+        && !expected.eq_ignore_ascii_case(&sync::hash(&data))
+    {
+        return util::str_response(StatusCode::BAD_REQUEST, "Uploaded object hash mismatch");
+    }
+
+    // the mod sends its uploads directly, presigned ones are never attested
+    let submission = match Submission::new(&headers, ip, payload.attribution, false) {
+        Ok(submission) => submission,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, e),
+    };
+    save_upload(&db, &user, namespace::DEFAULT, id, data, submission).await
+}
@@ -1,9 +1,26 @@
-use crate::{database, util};
-use axum::extract::{Path, State};
-use axum::http::{HeaderMap, StatusCode};
+use crate::auth::{AuthedUser, UserSession};
+use crate::impersonation::NotImpersonating;
+use crate::models::{AccountId, UserId};
+use crate::permissions::{RequirePermission, ReviewUploads};
+use crate::two_factor::{self, Verification};
+use crate::{avatar, card, database, email, util};
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::Response;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
 
-pub async fn get_user_info(id: i64, db: &database::Database) -> Response {
+const MIN_SEARCH_LENGTH: usize = 2;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+const USAGE_DAYS: i64 = 30;
+const MAX_EMAIL_LENGTH: usize = 254;
+const MAX_APPLICATION_LINKS: usize = 5;
+const MAX_APPLICATION_MESSAGE: usize = 2000;
+
+pub async fn get_user_info(id: UserId, db: &database::Database) -> Response {
     match db.get_user_stats(id).await {
         Some(user) => util::response(
             StatusCode::OK,
@@ -16,13 +33,643 @@ pub async fn get_user_info(id: i64, db: &database::Database) -> Response {
     }
 }
 
-pub async fn get_me(headers: HeaderMap, State(db): State<database::Database>) -> Response {
-    match util::auth_middleware(&headers, &db).await {
-        Ok(user) => get_user_info(user.id, &db).await,
-        Err(response) => response,
+// API usage is only shown to the user themselves and to admins
+pub async fn get_user_info_with_usage(id: UserId, db: &database::Database) -> Response {
+    let Some(user) = db.get_user_stats(id).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "User not found");
+    };
+
+    match db.get_api_usage(id, USAGE_DAYS).await {
+        Ok(daily) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "data": user,
+                "usage": {
+                    "days": USAGE_DAYS,
+                    "requests": daily.iter().map(|d| d.requests).sum::<i64>(),
+                    "upload_bytes": daily.iter().map(|d| d.upload_bytes).sum::<i64>(),
+                    "daily": daily,
+                },
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching API usage: {}", e),
+        ),
     }
 }
 
-pub async fn get_user_by_id(Path(id): Path<i64>, State(db): State<database::Database>) -> Response {
+pub async fn get_me(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    get_user_info_with_usage(user.id, &db).await
+}
+
+pub async fn get_user_by_id(
+    Path(id): Path<UserId>,
+    State(db): State<database::Database>,
+) -> Response {
     get_user_info(id, &db).await
 }
+
+// Image headers expose GD account IDs, the response carries both IDs
+pub async fn get_user_by_account(
+    Path(account_id): Path<AccountId>,
+    State(db): State<database::Database>,
+) -> Response {
+    // Discord-only users share the placeholder account ID -1
+    if account_id.0 <= 0 {
+        return util::str_response(StatusCode::NOT_FOUND, "User not found");
+    }
+
+    match db.get_user_id_by_account_id(account_id).await {
+        Some(id) => get_user_info(id, &db).await,
+        None => util::str_response(StatusCode::NOT_FOUND, "User not found"),
+    }
+}
+
+// Uploader faces for dashboards, proxied so clients never hit the icon services directly
+pub async fn get_user_avatar(
+    Path(id): Path<UserId>,
+    State(db): State<database::Database>,
+) -> Response {
+    let Some(user) = db.get_user_by_id(id).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "User not found");
+    };
+
+    let Some(path) = avatar::get(&db, &user).await else {
+        return util::str_response(StatusCode::NOT_FOUND, "No avatar available");
+    };
+
+    match tokio::fs::read(&path).await {
+        Ok(data) => Response::builder()
+            .header(header::CONTENT_TYPE, "image/webp")
+            .header(header::CACHE_CONTROL, format!("public, max-age={}", avatar::MAX_AGE.as_secs()))
+            .body(data.into())
+            .unwrap(),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error reading avatar: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    q: String,
+    role: Option<database::Role>,
+    page: Option<i64>,
+    limit: Option<i64>,
+}
+
+// Username lookup for moderators, matching both GD and Discord names
+pub async fn search_users(
+    _: RequirePermission<ReviewUploads>,
+    State(db): State<database::Database>,
+    Query(query): Query<SearchQuery>,
+) -> Response {
+    let q = query.q.trim();
+    if q.chars().count() < MIN_SEARCH_LENGTH {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            &format!("Search query must be at least {} characters", MIN_SEARCH_LENGTH),
+        );
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT).clamp(1, MAX_SEARCH_LIMIT);
+    let Some(offset) = (page - 1).checked_mul(limit) else {
+        return util::str_response(StatusCode::BAD_REQUEST, "Page is out of range");
+    };
+    match db.search_users(q, query.role, limit, offset).await {
+        Ok(users) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "page": page,
+                "limit": limit,
+                "data": users,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error searching users: {}", e),
+        ),
+    }
+}
+
+pub async fn get_my_email(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_email_settings(user.id).await {
+        Some(settings) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "data": settings,
+                "available": email::is_configured(),
+            }),
+        ),
+        None => util::str_response(StatusCode::NOT_FOUND, "User not found"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct EmailPayload {
+    email: Option<String>,
+    notifications: bool,
+}
+
+// Just a sanity check, the SMTP relay has the final say on deliverability
+fn is_valid_email(email: &str) -> bool {
+    email.len() <= MAX_EMAIL_LENGTH
+        && !email.chars().any(char::is_whitespace)
+        && email
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+}
+
+pub async fn set_my_email(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(payload): Json<EmailPayload>,
+) -> Response {
+    let email = payload.email.as_deref().map(str::trim).filter(|e| !e.is_empty());
+    if let Some(email) = email
+        && !is_valid_email(email)
+    {
+        return util::str_response(StatusCode::BAD_REQUEST, "Invalid email address");
+    }
+    if payload.notifications && email.is_none() {
+        return util::str_response(
+            StatusCode::BAD_REQUEST,
+            "An email address is required to turn on notifications",
+        );
+    }
+
+    let verified = match db.set_email_settings(user.id, email, payload.notifications).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error updating email settings: {}", e),
+            );
+        }
+    };
+
+    match email {
+        Some(address) if !verified && email::is_configured() => {
+            match email::send_verification(&db, &user, address).await {
+                Ok(()) => util::str_response(
+                    StatusCode::OK,
+                    "Email settings updated, open the link we sent to confirm the address",
+                ),
+                Err(e) => util::str_response(
+                    StatusCode::BAD_GATEWAY,
+                    &format!("Email settings updated, but the confirmation email failed: {}", e),
+                ),
+            }
+        }
+        _ => util::str_response(StatusCode::OK, "Email settings updated"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct VerifyEmailQuery {
+    token: String,
+}
+
+// The link from the confirmation email, the token is all it needs
+pub async fn verify_email(
+    State(db): State<database::Database>,
+    Query(query): Query<VerifyEmailQuery>,
+) -> Response {
+    match db.verify_email(&crate::sync::hash(query.token.trim().as_bytes())).await {
+        Ok(true) => util::str_response(StatusCode::OK, "Email address confirmed"),
+        Ok(false) => {
+            util::str_response(StatusCode::BAD_REQUEST, "This link is invalid or has expired")
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error confirming email address: {}", e),
+        ),
+    }
+}
+
+pub async fn get_my_card_theme(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    match db.get_card_theme(user.id).await {
+        Some(theme) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "data": theme,
+            }),
+        ),
+        None => util::str_response(StatusCode::NOT_FOUND, "User not found"),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CardThemePayload {
+    accent: Option<String>,
+    show_author: bool,
+}
+
+pub async fn set_my_card_theme(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(payload): Json<CardThemePayload>,
+) -> Response {
+    let accent = payload.accent.as_deref().map(str::trim).filter(|a| !a.is_empty());
+    if accent.is_some_and(|accent| card::parse_accent(accent).is_none()) {
+        return util::str_response(StatusCode::BAD_REQUEST, "Accent must be a #rrggbb color");
+    }
+
+    match db.set_card_theme(user.id, accent, payload.show_author).await {
+        Ok(()) => util::str_response(StatusCode::OK, "Card theme updated"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error updating card theme: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ApplicationPayload {
+    links: Vec<String>, // levels, art or earlier thumbnails to judge the applicant by
+    message: String,
+}
+
+impl ApplicationPayload {
+    fn validate(&mut self) -> Result<database::NewVerifiedApplication<'_>, String> {
+        self.links = self.links.iter().map(|link| link.trim().to_string()).collect();
+        self.links.retain(|link| !link.is_empty());
+        if self.links.len() > MAX_APPLICATION_LINKS {
+            return Err(format!("At most {} links are allowed", MAX_APPLICATION_LINKS));
+        }
+        let is_web_url = |link: &String| {
+            reqwest::Url::parse(link).is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+        };
+        if !self.links.iter().all(is_web_url) {
+            return Err("Links must be http or https URLs".to_string());
+        }
+
+        let message = self.message.trim();
+        if message.is_empty() || message.chars().count() > MAX_APPLICATION_MESSAGE {
+            return Err(format!(
+                "A message of up to {} characters is required",
+                MAX_APPLICATION_MESSAGE
+            ));
+        }
+
+        Ok(database::NewVerifiedApplication { links: &self.links, message })
+    }
+}
+
+pub async fn apply_verified(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(mut payload): Json<ApplicationPayload>,
+) -> Response {
+    if user.role != database::Role::User {
+        return util::str_response(StatusCode::CONFLICT, "You are already verified");
+    }
+
+    let application = match payload.validate() {
+        Ok(application) => application,
+        Err(e) => return util::str_response(StatusCode::BAD_REQUEST, &e),
+    };
+
+    match db.add_verified_application(user.id, application).await {
+        Ok(Some(application)) => {
+            info!("Verified application {} submitted by {}", application.id, user.username);
+            util::response(
+                StatusCode::CREATED,
+                json!({
+                    "status": StatusCode::CREATED.as_u16(),
+                    "application": application,
+                }),
+            )
+        }
+        Ok(None) => util::str_response(
+            StatusCode::CONFLICT,
+            "You already have an application waiting for review",
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error submitting application: {}", e),
+        ),
+    }
+}
+
+// The policy the mod links to before the first upload
+pub async fn get_tos(State(db): State<database::Database>) -> Response {
+    match db.get_current_tos().await {
+        Ok(Some(tos)) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "data": tos,
+            }),
+        ),
+        Ok(None) => util::str_response(StatusCode::NOT_FOUND, "No terms of service published"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching terms of service: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AcceptTosPayload {
+    version: Option<String>, // guards against accepting a version the user never saw
+}
+
+pub async fn accept_tos(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    payload: Option<Json<AcceptTosPayload>>,
+) -> Response {
+    let current = match db.get_current_tos().await {
+        Ok(Some(current)) => current,
+        Ok(None) => {
+            return util::str_response(StatusCode::NOT_FOUND, "No terms of service published");
+        }
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching terms of service: {}", e),
+            );
+        }
+    };
+
+    if let Some(Json(AcceptTosPayload { version: Some(version) })) = &payload
+        && *version != current.version
+    {
+        return util::response(
+            StatusCode::CONFLICT,
+            json!({
+                "status": StatusCode::CONFLICT.as_u16(),
+                "message": "A newer version of the terms of service was published",
+                "version": current.version,
+                "url": current.url,
+            }),
+        );
+    }
+
+    match db.accept_tos(user.id, &current.version).await {
+        Ok(acceptance) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "data": acceptance,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error recording acceptance: {}", e),
+        ),
+    }
+}
+
+pub async fn get_two_factor(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    let enabled = db.get_two_factor(user.id).await.is_some_and(|tf| tf.totp_enabled);
+    match db.count_recovery_codes(user.id).await {
+        Ok(remaining) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "enabled": enabled,
+                "recovery_codes_remaining": remaining,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching recovery codes: {}", e),
+        ),
+    }
+}
+
+// Starts enrollment with a fresh secret, it only takes effect once a code was verified
+pub async fn enroll_two_factor(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    if db.get_two_factor(user.id).await.is_some_and(|tf| tf.totp_enabled) {
+        return util::str_response(StatusCode::CONFLICT, "Two-factor authentication is already on");
+    }
+
+    let secret = two_factor::generate_secret();
+    match db.set_two_factor(user.id, Some(&secret), false).await {
+        Ok(()) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "secret": secret,
+                "otpauth_url": two_factor::otpauth_url(&secret, &user.username),
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error starting enrollment: {}", e),
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorCodePayload {
+    code: String,
+}
+
+async fn issue_recovery_codes(
+    db: &database::Database,
+    user_id: UserId,
+) -> Result<Vec<String>, sqlx::Error> {
+    let codes = two_factor::generate_recovery_codes();
+    let hashes: Vec<String> = codes.iter().map(|c| two_factor::hash_recovery_code(c)).collect();
+    db.replace_recovery_codes(user_id, &hashes).await?;
+    Ok(codes)
+}
+
+// Finishes enrollment, the recovery codes are only ever shown in this response
+pub async fn verify_two_factor(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    headers: HeaderMap,
+    State(db): State<database::Database>,
+    Json(payload): Json<TwoFactorCodePayload>,
+) -> Response {
+    let secret = match db.get_two_factor(user.id).await {
+        Some(database::TwoFactor { totp_enabled: true, .. }) => {
+            return util::str_response(
+                StatusCode::CONFLICT,
+                "Two-factor authentication is already on",
+            );
+        }
+        Some(database::TwoFactor { totp_secret: Some(secret), .. }) => secret,
+        _ => return util::str_response(StatusCode::BAD_REQUEST, "Start enrollment first"),
+    };
+
+    let Some(step) = two_factor::code_step(&secret, &payload.code) else {
+        return util::str_response(StatusCode::UNAUTHORIZED, "Invalid code");
+    };
+    match db.use_totp_step(user.id, step).await {
+        Ok(true) => {}
+        Ok(false) => return util::str_response(StatusCode::UNAUTHORIZED, "Invalid code"),
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error checking code: {}", e),
+            );
+        }
+    }
+
+    let codes = match issue_recovery_codes(&db, user.id).await {
+        Ok(codes) => codes,
+        Err(e) => {
+            return util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error creating recovery codes: {}", e),
+            );
+        }
+    };
+    if let Err(e) = db.set_two_factor(user.id, Some(&secret), true).await {
+        return util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error enabling two-factor authentication: {}", e),
+        );
+    }
+
+    // the code was just checked, so the new session counts as a 2FA session
+    let token = UserSession::new(user.id, user.username.clone())
+        .with_mfa()
+        .for_dashboard(util::is_dashboard_session(&headers))
+        .to_jwt();
+    util::response(
+        StatusCode::OK,
+        json!({
+            "status": StatusCode::OK.as_u16(),
+            "message": "Two-factor authentication enabled",
+            "recovery_codes": codes,
+            "token": token,
+        }),
+    )
+}
+
+async fn check_two_factor_code(
+    db: &database::Database,
+    user: &database::User,
+    code: &str,
+) -> Result<(), Response> {
+    match two_factor::verify(db, user.id, code).await {
+        Ok(Verification::Valid) => Ok(()),
+        Ok(Verification::Invalid) => {
+            Err(util::str_response(StatusCode::UNAUTHORIZED, "Invalid code"))
+        }
+        Ok(Verification::TooManyAttempts) => Err(util::str_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many invalid codes, try again later",
+        )),
+        Err(e) => Err(util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error checking code: {}", e),
+        )),
+    }
+}
+
+pub async fn regenerate_recovery_codes(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(payload): Json<TwoFactorCodePayload>,
+) -> Response {
+    if let Err(response) = check_two_factor_code(&db, &user, &payload.code).await {
+        return response;
+    }
+
+    match issue_recovery_codes(&db, user.id).await {
+        Ok(codes) => util::response(
+            StatusCode::OK,
+            json!({
+                "status": StatusCode::OK.as_u16(),
+                "recovery_codes": codes,
+            }),
+        ),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error creating recovery codes: {}", e),
+        ),
+    }
+}
+
+pub async fn disable_two_factor(
+    _: NotImpersonating,
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+    Json(payload): Json<TwoFactorCodePayload>,
+) -> Response {
+    if let Err(response) = check_two_factor_code(&db, &user, &payload.code).await {
+        return response;
+    }
+
+    let result = async {
+        db.set_two_factor(user.id, None, false).await?;
+        db.replace_recovery_codes(user.id, &[]).await
+    };
+    match result.await {
+        Ok(()) => util::str_response(StatusCode::OK, "Two-factor authentication disabled"),
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error disabling two-factor authentication: {}", e),
+        ),
+    }
+}
+
+// Everything admins did while impersonating the user, so nothing happens behind their back
+pub async fn get_my_impersonations(
+    AuthedUser(user): AuthedUser,
+    State(db): State<database::Database>,
+) -> Response {
+    let result = async {
+        let sessions = db.get_impersonation_sessions(user.id).await?;
+        let ids: Vec<i64> = sessions.iter().map(|s| s.id).collect();
+        let actions = db.get_impersonation_actions(&ids).await?;
+        Ok::<_, sqlx::Error>((sessions, actions))
+    };
+
+    match result.await {
+        Ok((sessions, actions)) => {
+            let sessions: Vec<_> = sessions
+                .into_iter()
+                .map(|session| {
+                    let actions: Vec<_> =
+                        actions.iter().filter(|a| a.session_id == session.id).collect();
+                    json!({ "session": session, "actions": actions })
+                })
+                .collect();
+            util::response(
+                StatusCode::OK,
+                json!({
+                    "status": StatusCode::OK.as_u16(),
+                    "data": sessions,
+                }),
+            )
+        }
+        Err(e) => util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error fetching impersonation history: {}", e),
+        ),
+    }
+}
@@ -0,0 +1,150 @@
+use crate::events::{self, QueueEvent};
+use crate::permissions::{self, Permission};
+use crate::{database, util};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::LazyLock;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+
+// A queue event with the queue depth after it, counted once here rather than by every socket
+#[derive(Debug, Clone)]
+struct SocketEvent {
+    event: QueueEvent,
+    pending: Option<i64>,
+}
+
+static SOCKET_EVENTS: LazyLock<broadcast::Sender<SocketEvent>> =
+    LazyLock::new(|| broadcast::channel(256).0);
+
+// Passes queue events on to the sockets, spawned once per process
+pub fn relay(db: database::Database) -> impl Future<Output = ()> {
+    let mut receiver = events::subscribe();
+    async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Websocket relay missed {} queue events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let changes_queue = matches!(
+                event,
+                QueueEvent::Submitted { .. }
+                    | QueueEvent::Decided { .. }
+                    | QueueEvent::Undone { .. }
+            );
+            // nobody to tell while no socket is open
+            let pending = match changes_queue && SOCKET_EVENTS.receiver_count() > 0 {
+                true => db.count_pending_uploads().await.ok(),
+                false => None,
+            };
+            // sending only fails while no socket is open
+            let _ = SOCKET_EVENTS.send(SocketEvent { event, pending });
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WsQuery {
+    // browsers can't set headers on websocket requests, so allow the token here too
+    token: Option<String>,
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Query(query): Query<WsQuery>,
+    State(db): State<database::Database>,
+) -> Response {
+    let user = match &query.token {
+        Some(token) => util::session_response(token, &db).await.ok(),
+        None => util::auth_middleware(&headers, &db).await.ok(),
+    };
+
+    ws.on_upgrade(move |socket| handle_socket(socket, user)).into_response()
+}
+
+fn messages_for(socket_event: &SocketEvent, user: Option<&database::User>) -> Vec<Value> {
+    let SocketEvent { event, pending } = socket_event;
+    let mut messages = Vec::new();
+
+    // public channel: newly live thumbnails
+    match event {
+        QueueEvent::Decided { level_id, accepted: true, .. }
+        | QueueEvent::Published { level_id, .. } => {
+            messages.push(json!({
+                "channel": "public",
+                "type": "thumbnail_accepted",
+                "level_id": level_id,
+            }));
+        }
+        _ => {}
+    }
+
+    let Some(user) = user else {
+        return messages;
+    };
+
+    // user channel: decisions on their own uploads
+    if let QueueEvent::Decided {
+        upload_id,
+        level_id,
+        user_id,
+        accepted,
+        ..
+    } = event
+        && *user_id == user.id
+    {
+        messages.push(json!({
+            "channel": "user",
+            "type": "upload_decided",
+            "upload_id": upload_id,
+            "level_id": level_id,
+            "accepted": accepted,
+        }));
+    }
+
+    // queue channel: queue depth for moderators
+    if permissions::has(user.role, Permission::ReviewUploads)
+        && let Some(pending) = pending
+    {
+        messages.push(json!({
+            "channel": "queue",
+            "type": "queue_depth",
+            "pending": pending,
+        }));
+    }
+
+    messages
+}
+
+async fn handle_socket(mut socket: WebSocket, user: Option<database::User>) {
+    let mut receiver = SOCKET_EVENTS.subscribe();
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Ok(event) => {
+                    for message in messages_for(&event, user.as_ref()) {
+                        if socket.send(Message::Text(message.to_string().into())).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                _ => {}
+            },
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::info;
+
+const DEFAULT_FLAG_THRESHOLD: f32 = 0.5;
+const DEFAULT_HOLD_THRESHOLD: f32 = 0.9;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type, async_graphql::Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ScanVerdict {
+    Clean,   // nothing found
+    Flagged, // shown to moderators with a warning
+    Held,    // kept out of the queue until an admin looks at it
+}
+
+impl std::fmt::Display for ScanVerdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScanVerdict::Clean => write!(f, "clean"),
+            ScanVerdict::Flagged => write!(f, "flagged"),
+            ScanVerdict::Held => write!(f, "held"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanResult {
+    pub verdict: ScanVerdict,
+    pub score: f32,
+    pub label: Option<String>,
+}
+
+pub type ScanFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<(f32, Option<String>), String>> + Send + 'a>>;
+
+// A content scanner returns how likely an image is to break the rules (0 to 1),
+// optionally with the category it matched
+pub trait Scanner: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn score<'a>(&'a self, image: &'a [u8]) -> ScanFuture<'a>;
+}
+
+// Sends the image to an external moderation API, which must answer with
+// `{"score": 0.0-1.0, "label": "optional category"}`
+pub struct HttpScanner {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct HttpScanResponse {
+    score: f32,
+    label: Option<String>,
+}
+
+impl HttpScanner {
+    fn from_env() -> Self {
+        Self {
+            url: dotenv::var("SCANNER_URL").expect("SCANNER_URL must be set for the http scanner"),
+            api_key: dotenv::var("SCANNER_API_KEY").ok(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(30))
+                .build()
+                .expect("Failed to create HTTP client"),
+        }
+    }
+}
+
+impl Scanner for HttpScanner {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn score<'a>(&'a self, image: &'a [u8]) -> ScanFuture<'a> {
+        Box::pin(async move {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "image/webp")
+                .body(image.to_vec());
+            if let Some(api_key) = &self.api_key {
+                request = request.bearer_auth(api_key);
+            }
+
+            let response = request
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| e.to_string())?;
+            let result: HttpScanResponse = response.json().await.map_err(|e| e.to_string())?;
+            Ok((result.score, result.label))
+        })
+    }
+}
+
+// Runs a local image classification model, e.g. the common 5-class NSFW models
+// taking a 224x224 NHWC RGB input scaled to 0-1
+#[cfg(feature = "nsfw-onnx")]
+pub struct OnnxScanner {
+    model: std::sync::Arc<OnnxModel>,
+}
+
+#[cfg(feature = "nsfw-onnx")]
+struct OnnxModel {
+    session: std::sync::Mutex<ort::session::Session>,
+    input_size: u32,
+    labels: Vec<String>,
+    flagged_classes: Vec<usize>,
+}
+
+#[cfg(feature = "nsfw-onnx")]
+impl OnnxModel {
+    fn from_env() -> Self {
+        let model =
+            dotenv::var("SCANNER_MODEL").expect("SCANNER_MODEL must be set for the onnx scanner");
+        let session = ort::session::Session::builder()
+            .and_then(|builder| builder.commit_from_file(&model))
+            .expect("Failed to load SCANNER_MODEL");
+
+        let list = |name: &str, default: &str| -> Vec<String> {
+            dotenv::var(name)
+                .unwrap_or_else(|_| default.to_string())
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        };
+
+        OnnxModel {
+            session: std::sync::Mutex::new(session),
+            input_size: dotenv::var("SCANNER_INPUT_SIZE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(224),
+            labels: list("SCANNER_LABELS", "drawings,hentai,neutral,porn,sexy"),
+            flagged_classes: list("SCANNER_FLAGGED_CLASSES", "1,3,4")
+                .iter()
+                .filter_map(|v| v.parse().ok())
+                .collect(),
+        }
+    }
+
+    fn classify(&self, image: &[u8]) -> Result<(f32, Option<String>), String> {
+        let size = self.input_size;
+        let image = image::load_from_memory(image).map_err(|e| e.to_string())?;
+        let pixels = image
+            .resize_exact(size, size, image::imageops::FilterType::Triangle)
+            .to_rgb8()
+            .into_raw()
+            .into_iter()
+            .map(|v| v as f32 / 255.0)
+            .collect::<Vec<f32>>();
+
+        let input =
+            ort::value::Tensor::from_array(([1usize, size as usize, size as usize, 3], pixels))
+                .map_err(|e| e.to_string())?;
+        let mut session = self.session.lock().map_err(|e| e.to_string())?;
+        let outputs = session.run(ort::inputs![input]).map_err(|e| e.to_string())?;
+        let (_, scores) = outputs[0].try_extract_tensor::<f32>().map_err(|e| e.to_string())?;
+
+        let score = self.flagged_classes.iter().filter_map(|&i| scores.get(i)).sum::<f32>();
+        let label = self
+            .flagged_classes
+            .iter()
+            .filter(|&&i| i < scores.len())
+            .max_by(|&&a, &&b| scores[a].total_cmp(&scores[b]))
+            .and_then(|&i| self.labels.get(i).cloned());
+        Ok((score.min(1.0), label))
+    }
+}
+
+#[cfg(feature = "nsfw-onnx")]
+impl Scanner for OnnxScanner {
+    fn name(&self) -> &'static str {
+        "onnx"
+    }
+
+    // inference is CPU bound, it runs on the encode pool like the image jobs
+    fn score<'a>(&'a self, image: &'a [u8]) -> ScanFuture<'a> {
+        let model = self.model.clone();
+        let image = image.to_vec();
+        Box::pin(async move { crate::encoder::run(move || model.classify(&image)).await? })
+    }
+}
+
+struct Thresholds {
+    flag: f32,
+    hold: f32,
+}
+
+// the values SCANNER may have in this build
+pub const SCANNERS: &[&str] = if cfg!(feature = "nsfw-onnx") {
+    &["http", "onnx"]
+} else {
+    &["http"]
+};
+
+static SCANNER: LazyLock<Option<Box<dyn Scanner>>> = LazyLock::new(|| {
+    let scanner: Box<dyn Scanner> = match dotenv::var("SCANNER").ok()?.as_str() {
+        "http" => Box::new(HttpScanner::from_env()),
+        #[cfg(feature = "nsfw-onnx")]
+        "onnx" => Box::new(OnnxScanner {
+            model: std::sync::Arc::new(OnnxModel::from_env()),
+        }),
+        // refused by the doctor at startup
+        _ => return None,
+    };
+    info!("Scanning uploads with the {} scanner", scanner.name());
+    Some(scanner)
+});
+
+static THRESHOLDS: LazyLock<Thresholds> = LazyLock::new(|| {
+    let threshold = |name: &str, default: f32| {
+        dotenv::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+    };
+    Thresholds {
+        flag: threshold("SCANNER_FLAG_THRESHOLD", DEFAULT_FLAG_THRESHOLD),
+        hold: threshold("SCANNER_HOLD_THRESHOLD", DEFAULT_HOLD_THRESHOLD),
+    }
+});
+
+// Ok(None) when no scanner is configured. A failed scan is an error, the caller sends the
+// upload to the queue instead of treating it as clean
+pub async fn scan(image: &[u8]) -> Result<Option<ScanResult>, String> {
+    let Some(scanner) = SCANNER.as_ref() else {
+        return Ok(None);
+    };
+    let (score, label) = scanner
+        .score(image)
+        .await
+        .map_err(|e| format!("Content scan with {} failed: {}", scanner.name(), e))?;
+
+    let verdict = if score >= THRESHOLDS.hold {
+        ScanVerdict::Held
+    } else if score >= THRESHOLDS.flag {
+        ScanVerdict::Flagged
+    } else {
+        ScanVerdict::Clean
+    };
+    Ok(Some(ScanResult { verdict, score, label }))
+}
@@ -0,0 +1,229 @@
+use crate::database::{self, Role, UploadStatus};
+use crate::models::{AccountId, LevelId, UserId};
+use crate::routes::upload;
+use crate::{encoder, namespace, paths};
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use webp::Encoder;
+
+// far above real Geometry Dash IDs, so seeded rows are easy to tell apart
+const ACCOUNT_BASE: i64 = 1_000_000_000;
+const LEVEL_BASE: i64 = 1_000_000_000;
+const IMAGE_WIDTH: u32 = 1920;
+const IMAGE_HEIGHT: u32 = 1080;
+const HISTORY_DAYS: i64 = 90;
+const PENDING_DAYS: i64 = 7; // pending uploads stay recent so they don't look expired
+// encoding a full size image is slow, so levels share one of a few colors
+const PALETTE_SIZE: i64 = 12;
+
+const REJECTION_REASONS: [&str; 5] = [
+    "Not a screenshot of the level",
+    "Image is too blurry",
+    "UI elements are visible",
+    "Wrong level",
+    "Existing thumbnail is better",
+];
+
+pub struct SeedOptions {
+    pub uploads: usize,
+    pub uploaders: usize,
+    pub seed: u64, // the same seed always produces the same data
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        Self {
+            uploads: 300,
+            uploaders: 25,
+            seed: 1,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SeedSummary {
+    pub users: usize,
+    pub accepted: usize,
+    pub pending: usize,
+    pub rejected: usize,
+    pub withdrawn: usize,
+    pub expired: usize,
+}
+
+impl SeedSummary {
+    fn count(&mut self, status: UploadStatus) {
+        match status {
+            UploadStatus::Accepted => self.accepted += 1,
+            UploadStatus::Pending => self.pending += 1,
+            UploadStatus::Rejected => self.rejected += 1,
+            UploadStatus::Withdrawn => self.withdrawn += 1,
+            UploadStatus::Expired | UploadStatus::Removed => self.expired += 1,
+        }
+    }
+}
+
+// One account per role, with usernames matching it, so logging in as each is straightforward
+const STAFF: [(&str, Role); 4] = [
+    ("seed_admin", Role::Admin),
+    ("seed_moderator", Role::Moderator),
+    ("seed_verified", Role::Verified),
+    ("seed_user", Role::User),
+];
+
+async fn add_user(
+    db: &database::Database,
+    account_id: AccountId,
+    username: &str,
+    role: Role,
+) -> Result<database::User, String> {
+    let user = db.find_or_create_user(account_id, username).await.map_err(|e| e.to_string())?;
+    if role == Role::User {
+        return Ok(user);
+    }
+    let users = db.set_user_role(account_id, role).await.map_err(|e| e.to_string())?;
+    Ok(users.into_iter().next().unwrap_or(user))
+}
+
+// A diagonal gradient in one of the palette's colors, neighbouring levels get different ones
+fn placeholder_image(color: i64) -> Vec<u8> {
+    let hue = (color * 360 / PALETTE_SIZE) as f32;
+    let image = image::RgbImage::from_fn(IMAGE_WIDTH, IMAGE_HEIGHT, |x, y| {
+        let shade = 0.35 + 0.65 * (x + y) as f32 / (IMAGE_WIDTH + IMAGE_HEIGHT) as f32;
+        image::Rgb(hue_to_rgb(hue).map(|channel| (channel * shade * 255.0) as u8))
+    });
+    Encoder::from_rgb(&image, IMAGE_WIDTH, IMAGE_HEIGHT).encode(75.0).to_vec()
+}
+
+fn hue_to_rgb(hue: f32) -> [f32; 3] {
+    let x = 1.0 - ((hue / 60.0) % 2.0 - 1.0).abs();
+    match hue as u32 / 60 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+async fn write_image(path: &str, data: &[u8]) -> Result<(), String> {
+    let path = paths::local(path);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+    }
+    tokio::fs::write(path, data).await.map_err(|e| e.to_string())
+}
+
+fn pick_status(rng: &mut StdRng) -> UploadStatus {
+    match rng.random_range(0..100) {
+        0..45 => UploadStatus::Accepted,
+        45..70 => UploadStatus::Pending,
+        70..90 => UploadStatus::Rejected,
+        90..95 => UploadStatus::Withdrawn,
+        _ => UploadStatus::Expired,
+    }
+}
+
+// Fills an empty database with sample users and uploads for local development. Runs once,
+// a second run finds the seeded admin and stops
+pub async fn run(db: &database::Database, options: &SeedOptions) -> Result<SeedSummary, String> {
+    if db.get_user_id_by_account_id(AccountId(ACCOUNT_BASE)).await.is_some() {
+        return Err("The database is already seeded".to_string());
+    }
+
+    let mut summary = SeedSummary::default();
+    let mut staff = Vec::with_capacity(STAFF.len());
+    for (i, (username, role)) in STAFF.into_iter().enumerate() {
+        staff.push(add_user(db, AccountId(ACCOUNT_BASE + i as i64), username, role).await?);
+    }
+    let moderators: Vec<UserId> = staff[..2].iter().map(|user| user.id).collect();
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut uploaders: Vec<UserId> = staff[2..].iter().map(|user| user.id).collect();
+    for i in 0..options.uploaders {
+        // every fifth uploader is verified, like the regulars on the real site
+        let role = if i % 5 == 0 { Role::Verified } else { Role::User };
+        let account_id = AccountId(ACCOUNT_BASE + (STAFF.len() + i) as i64);
+        let user = add_user(db, account_id, &format!("seed_uploader_{}", i + 1), role).await?;
+        uploaders.push(user.id);
+    }
+    summary.users = STAFF.len() + options.uploaders;
+
+    // about one level in five gets a second upload, so some thumbnails have been replaced
+    let level_count = (options.uploads * 4 / 5).max(1) as i64;
+    let now = Utc::now();
+    let mut plans: Vec<(DateTime<Utc>, LevelId, UploadStatus)> = (0..options.uploads)
+        .map(|i| {
+            let level_id = LevelId(LEVEL_BASE + i as i64 % level_count);
+            let status = pick_status(&mut rng);
+            let days = match status {
+                UploadStatus::Pending => PENDING_DAYS,
+                _ => HISTORY_DAYS,
+            };
+            let age = TimeDelta::seconds(rng.random_range(0..days * 24 * 60 * 60));
+            (now - age, level_id, status)
+        })
+        .collect();
+    // oldest first, so the newest accepted upload of a level is the one left on disk
+    plans.sort_by_key(|(upload_time, _, _)| *upload_time);
+
+    let mut palette = Vec::with_capacity(PALETTE_SIZE as usize);
+    for color in 0..PALETTE_SIZE {
+        palette.push(encoder::run(move || placeholder_image(color)).await?);
+    }
+
+    let mut live_levels = Vec::new();
+    for (upload_time, level_id, status) in plans {
+        let user_id = uploaders[rng.random_range(0..uploaders.len())];
+        let moderator_id = moderators[rng.random_range(0..moderators.len())];
+        let decided_at = (upload_time + TimeDelta::hours(rng.random_range(1..48))).min(now);
+        let reason = (status == UploadStatus::Rejected)
+            .then(|| REJECTION_REASONS[rng.random_range(0..REJECTION_REASONS.len())]);
+
+        let color = level_id.0 % PALETTE_SIZE;
+        let data = &palette[color as usize];
+        let image_path = match status {
+            UploadStatus::Accepted => {
+                live_levels.push(level_id);
+                paths::thumbnail_path(namespace::DEFAULT, level_id)
+            }
+            _ => paths::pending_path(namespace::DEFAULT, user_id, level_id),
+        };
+        // rejected images are moved aside below, withdrawn and expired ones are gone
+        if matches!(status, UploadStatus::Accepted | UploadStatus::Pending) {
+            write_image(&image_path, data).await?;
+        }
+
+        let decided = matches!(status, UploadStatus::Accepted | UploadStatus::Rejected);
+        let seed_upload = database::SeedUpload {
+            namespace: namespace::DEFAULT,
+            level_id,
+            user_id,
+            image_path: &image_path,
+            status,
+            upload_time,
+            decided_by: decided.then_some(moderator_id),
+            decided_at: decided.then_some(decided_at),
+            reason,
+            meta: &upload::image_meta(data),
+        };
+        let id = db.add_seed_upload(&seed_upload).await.map_err(|e| e.to_string())?;
+
+        if status == UploadStatus::Rejected {
+            let rejected_path = paths::rejected_path(namespace::DEFAULT, id);
+            write_image(&rejected_path, data).await?;
+            db.set_archive_path(id, &rejected_path).await.map_err(|e| e.to_string())?;
+        }
+        summary.count(status);
+    }
+
+    // today's views, so the stats endpoints have something to rank
+    live_levels.sort_unstable();
+    live_levels.dedup();
+    let views: Vec<(LevelId, i64)> =
+        live_levels.iter().map(|&level_id| (level_id, rng.random_range(1..5000))).collect();
+    db.add_thumbnail_views(&views).await.map_err(|e| e.to_string())?;
+
+    Ok(summary)
+}
@@ -0,0 +1,124 @@
+use crate::database;
+use crate::upload_source::UploadSource;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+use tracing::error;
+
+// other instances pick up changes made through the admin endpoint within this interval
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub upload_quota: u32,              // uploads per user per day, 0 is unlimited
+    pub new_account_upload_quota: u32,  // for accounts younger than a week, 0 uses upload_quota
+    pub trusted_upload_quota: u32,      // active on 14 of the last 90 days, 0 uses upload_quota
+    pub thumbnail_max_age: u64,         // Cache-Control max-age for thumbnail images
+    pub embed_max_age: u64,             // Cache-Control max-age for embed pages
+    pub queue_assignment: bool,         // distribute pending uploads among active moderators
+    pub assignment_timeout: u64,        // minutes before an assigned upload goes to someone else
+    pub attribution_headers: bool,      // send license and credit along with thumbnail images
+    pub require_staff_2fa: bool,        // moderators and admins only get their role with 2FA
+    pub recent_auth_window: u64,        // minutes a login counts as recent for destructive actions
+    pub outbound_alert_error_rate: u32, // percent of failed third-party calls that raises an alert
+    pub retention_rejected_days: u32,   // days rejected images are kept, 0 keeps them forever
+    pub retention_archived_days: u32, // days replaced thumbnails stay in the archive, 0 is forever
+    pub retention_auto_days: u32,     // days rendered auto thumbnails are kept, 0 is forever
+    pub undo_window: u64, // minutes a moderator can take back a decision, 0 turns undo off
+    pub attested_first: bool, // uploads signed by the game mod go to the front of the queue
+    pub source_upload_quotas: BTreeMap<UploadSource, u32>, // daily uploads per source, 0 is unlimited
+    pub review_sources: Vec<UploadSource>, // sources whose uploads always go to the queue
+    pub graphql_rate_limit: u32, // GraphQL requests per minute per address, 0 is unlimited
+    pub batch_rate_limit: u32,   // batch downloads per hour per address, 0 is unlimited
+    pub takedown_rate_limit: u32, // takedown requests per hour per address, 0 is unlimited
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            upload_quota: 0,
+            new_account_upload_quota: 0,
+            trusted_upload_quota: 0,
+            thumbnail_max_age: 31536000,
+            embed_max_age: 3600,
+            queue_assignment: false,
+            assignment_timeout: 30,
+            attribution_headers: false,
+            require_staff_2fa: false,
+            recent_auth_window: 10,
+            outbound_alert_error_rate: 20,
+            retention_rejected_days: 30,
+            retention_archived_days: 365,
+            retention_auto_days: 0,
+            undo_window: 10,
+            attested_first: false,
+            source_upload_quotas: BTreeMap::new(),
+            review_sources: Vec::new(),
+            graphql_rate_limit: 60,
+            batch_rate_limit: 10,
+            takedown_rate_limit: 5,
+        }
+    }
+}
+
+static SETTINGS: LazyLock<RwLock<Settings>> = LazyLock::new(|| RwLock::new(Settings::default()));
+
+pub fn current() -> Settings {
+    SETTINGS.read().unwrap().clone()
+}
+
+fn to_map(settings: &Settings) -> Map<String, Value> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+// Applies a partial update on top of the current settings, rejecting unknown keys and bad values
+pub fn merge(changes: &Map<String, Value>) -> Result<Settings, String> {
+    let mut map = to_map(&current());
+    for (key, value) in changes {
+        if !map.contains_key(key) {
+            return Err(format!("Unknown setting: {}", key));
+        }
+        map.insert(key.clone(), value.clone());
+    }
+
+    serde_json::from_value(Value::Object(map)).map_err(|e| format!("Invalid setting: {}", e))
+}
+
+pub fn set(settings: Settings) {
+    *SETTINGS.write().unwrap() = settings;
+}
+
+pub async fn reload(db: &database::Database) {
+    let rows = match db.get_settings().await {
+        Ok(rows) => rows,
+        Err(e) => return error!("Failed to load settings: {}", e),
+    };
+
+    // settings that were stored but no longer exist are ignored
+    let mut map = to_map(&Settings::default());
+    for row in rows {
+        if map.contains_key(&row.key)
+            && let Ok(value) = serde_json::from_str(&row.value)
+        {
+            map.insert(row.key, value);
+        }
+    }
+
+    match serde_json::from_value(Value::Object(map)) {
+        Ok(settings) => set(settings),
+        Err(e) => error!("Failed to parse settings: {}", e),
+    }
+}
+
+pub async fn watch(db: database::Database) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        reload(&db).await;
+    }
+}
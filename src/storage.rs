@@ -0,0 +1,466 @@
+use crate::{database, paths, sync};
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const PRESIGN_DURATION: Duration = Duration::from_secs(10 * 60);
+// other instances switch to the new backend within this interval once a migration completes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+// progress is written back to the migration row every this many thumbnails
+const PROGRESS_BATCH: usize = 100;
+const COPY_ATTEMPTS: usize = 3;
+// paths the bucket said it doesn't have are not asked for again for this long
+const MISSING_TTL: Duration = Duration::from_secs(60);
+const MIRROR_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const MIRROR_BATCH: i64 = 500;
+
+// Second home for the live thumbnails. Local disk keeps every write and serves reads first,
+// the bucket fills the gaps once a migration made it the source of truth
+struct Remote {
+    bucket: Bucket,
+    credentials: Credentials,
+    client: reqwest::Client,
+}
+
+// Where the bucket is, the STORAGE_S3_* variables unless the app config names one
+#[derive(Debug, Clone)]
+pub struct BucketConfig {
+    pub endpoint: String,
+    pub name: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl BucketConfig {
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            name: dotenv::var("STORAGE_S3_BUCKET").ok()?,
+            endpoint: dotenv::var("STORAGE_S3_ENDPOINT")
+                .expect("STORAGE_S3_ENDPOINT must be set with STORAGE_S3_BUCKET"),
+            region: dotenv::var("STORAGE_S3_REGION").unwrap_or_else(|_| "auto".to_string()),
+            access_key: dotenv::var("STORAGE_S3_ACCESS_KEY")
+                .expect("STORAGE_S3_ACCESS_KEY must be set"),
+            secret_key: dotenv::var("STORAGE_S3_SECRET_KEY")
+                .expect("STORAGE_S3_SECRET_KEY must be set"),
+        })
+    }
+}
+
+static REMOTE: OnceLock<Option<Remote>> = OnceLock::new();
+
+fn remote() -> Option<&'static Remote> {
+    REMOTE.get_or_init(|| BucketConfig::from_env().map(|config| Remote::new(&config))).as_ref()
+}
+
+// Sets the bucket instead of the STORAGE_S3_* variables, the first configuration wins so it
+// has to happen before anything touches storage
+pub fn configure(config: &BucketConfig) {
+    let _ = REMOTE.set(Some(Remote::new(config)));
+}
+
+// set once a migration to the bucket completed
+static REMOTE_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// Negative cache for bucket lookups, every request for a level without a thumbnail would
+// otherwise go out to the bucket
+static MISSING: LazyLock<Mutex<HashMap<String, Instant>>> = LazyLock::new(Default::default);
+
+fn known_missing(path: &str) -> bool {
+    MISSING.lock().unwrap().get(path).is_some_and(|at| at.elapsed() < MISSING_TTL)
+}
+
+fn set_missing(path: &str) {
+    MISSING.lock().unwrap().insert(path.to_string(), Instant::now());
+}
+
+// Called whenever a live thumbnail changes, here or on another instance
+pub fn forget(path: &str) {
+    MISSING.lock().unwrap().remove(path);
+}
+
+pub fn forget_all() {
+    MISSING.lock().unwrap().clear();
+}
+
+impl Remote {
+    fn new(config: &BucketConfig) -> Self {
+        let endpoint = config.endpoint.parse().expect("STORAGE_S3_ENDPOINT must be a valid URL");
+        let bucket =
+            Bucket::new(endpoint, UrlStyle::Path, config.name.clone(), config.region.clone())
+                .expect("Failed to configure storage bucket");
+        let client = reqwest::ClientBuilder::new()
+            .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            bucket,
+            credentials: Credentials::new(config.access_key.clone(), config.secret_key.clone()),
+            client,
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), String> {
+        let url = self.bucket.put_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        self.client
+            .put(url)
+            .body(data)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    // None when the key isn't in the bucket
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let url = self.bucket.get_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        let response = self.client.get(url).send().await.map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status().map_err(|e| e.to_string())?;
+        let data = response.bytes().await.map_err(|e| e.to_string())?;
+        Ok(Some(data.to_vec()))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, String> {
+        let url = self.bucket.head_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        let response = self.client.head(url).send().await.map_err(|e| e.to_string())?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status().map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), String> {
+        let url = self.bucket.delete_object(Some(&self.credentials), key).sign(PRESIGN_DURATION);
+        self.client
+            .delete(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+pub fn remote_configured() -> bool {
+    remote().is_some()
+}
+
+pub fn active_backend() -> &'static str {
+    match REMOTE_ACTIVE.load(Ordering::Relaxed) {
+        true => "s3",
+        false => "local",
+    }
+}
+
+// The bucket is only read from after a migration, until then it may be missing anything
+fn active_remote() -> Option<&'static Remote> {
+    remote().filter(|_| REMOTE_ACTIVE.load(Ordering::Relaxed))
+}
+
+// Whether a live thumbnail exists on disk or in the active bucket. Callers decide what a
+// failed lookup means, it's not the same as a thumbnail that isn't there
+pub async fn exists(path: &str) -> Result<bool, String> {
+    if tokio::fs::try_exists(paths::local(path)).await.unwrap_or(false) {
+        return Ok(true);
+    }
+    let Some(remote) = active_remote() else {
+        return Ok(false);
+    };
+    if known_missing(path) {
+        return Ok(false);
+    }
+    let exists = remote.exists(path).await.inspect_err(|e| {
+        error!("Failed to look up {} in storage: {}", path, e);
+    })?;
+    if !exists {
+        set_missing(path);
+    }
+    Ok(exists)
+}
+
+// Writes a file so a concurrent reader only ever sees the old or the new one, never half
+pub async fn write_local(path: impl AsRef<Path>, data: &[u8]) -> std::io::Result<()> {
+    let path = paths::local(path);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let partial = paths::partial_path(&path);
+    let written = async {
+        tokio::fs::write(&partial, data).await?;
+        tokio::fs::rename(&partial, &path).await
+    };
+    let result = written.await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial).await;
+    }
+    result
+}
+
+// Makes sure a live thumbnail is on local disk, pulling it from the active bucket when it
+// isn't, since variants and accelerated responses all work from the local file
+pub async fn ensure_local(path: &str) -> bool {
+    if paths::canonical(path).is_none() {
+        return false;
+    }
+    if tokio::fs::try_exists(paths::local(path)).await.unwrap_or(false) {
+        return true;
+    }
+    let Some(remote) = active_remote() else {
+        return false;
+    };
+    if known_missing(path) {
+        return false;
+    }
+
+    let data = match remote.get(path).await {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            set_missing(path);
+            return false;
+        }
+        Err(e) => {
+            error!("Failed to fetch {} from storage: {}", path, e);
+            return false;
+        }
+    };
+
+    match write_local(path, &data).await {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to store {} fetched from storage: {}", path, e);
+            false
+        }
+    }
+}
+
+// Copies the current state of a live thumbnail to the bucket, removing it there when it's gone
+// locally. Writes are mirrored whenever a bucket is configured so a running migration doesn't
+// miss thumbnails that change behind it. The change is queued in the database first, a copy
+// that fails is retried by run_mirror_queue until it goes through
+pub async fn mirror(db: &database::Database, path: &str) {
+    if let Some(queued) = queue(db, path).await
+        && let Some(remote) = remote()
+    {
+        copy_queued(db, remote, &queued).await;
+    }
+}
+
+// Like mirror, but the copy itself happens in the background once the change is queued
+pub async fn mirror_later(db: &database::Database, path: &str) {
+    if let Some(queued) = queue(db, path).await
+        && let Some(remote) = remote()
+    {
+        let db = db.clone();
+        tokio::spawn(async move { copy_queued(&db, remote, &queued).await });
+    }
+}
+
+async fn queue(db: &database::Database, path: &str) -> Option<database::QueuedMirror> {
+    forget(path);
+    remote()?;
+
+    let hash = match sync::hash_file(path).await {
+        Ok(hash) => Some(hash),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            error!("Failed to read {} to mirror it: {}", path, e);
+            return None;
+        }
+    };
+    if let Err(e) = db.queue_mirror(path, hash.as_deref()).await {
+        error!("Failed to queue {} for storage: {}", path, e);
+    }
+    Some(database::QueuedMirror { path: path.to_string(), hash })
+}
+
+// Only the state that was queued is copied. With a disk per instance, an instance that pulled
+// an older copy of the file leaves the row to the one that wrote the new one
+async fn copy_queued(db: &database::Database, remote: &Remote, queued: &database::QueuedMirror) {
+    let path = queued.path.as_str();
+    let result = match &queued.hash {
+        Some(hash) => match tokio::fs::read(paths::local(path)).await {
+            Ok(data) if sync::hash(&data) == *hash => remote.put(path, data).await,
+            Ok(_) => return,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => Err(e.to_string()),
+        },
+        None if tokio::fs::try_exists(paths::local(path)).await.unwrap_or(false) => return,
+        None => remote.delete(path).await,
+    };
+
+    let recorded = match &result {
+        Ok(()) => db.finish_mirror(queued).await,
+        Err(e) => {
+            error!("Failed to mirror {} to storage: {}", path, e);
+            db.fail_mirror(queued, e).await
+        }
+    };
+    if let Err(e) = recorded {
+        error!("Failed to update the storage queue for {}: {}", path, e);
+    }
+}
+
+// Retries the copies that didn't make it to the bucket
+pub async fn run_mirror_queue(db: database::Database) {
+    let Some(remote) = remote() else {
+        return;
+    };
+    loop {
+        tokio::time::sleep(MIRROR_RETRY_INTERVAL).await;
+        match db.get_queued_mirrors(MIRROR_BATCH).await {
+            Ok(queued) => {
+                for queued in &queued {
+                    copy_queued(&db, remote, queued).await;
+                }
+            }
+            Err(e) => error!("Failed to load the storage queue: {}", e),
+        }
+    }
+}
+
+pub async fn reload(db: &database::Database) {
+    match db.get_latest_storage_migration().await {
+        Ok(migration) => {
+            let completed = migration.is_some_and(|migration| {
+                migration.status == database::StorageMigrationStatus::Completed
+            });
+            REMOTE_ACTIVE.store(completed && remote().is_some(), Ordering::Relaxed);
+        }
+        Err(e) => error!("Failed to load the storage backend: {}", e),
+    }
+}
+
+pub async fn watch(db: database::Database) {
+    loop {
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+        reload(&db).await;
+        MISSING.lock().unwrap().retain(|_, at| at.elapsed() < MISSING_TTL);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct MigrationProgress {
+    pub total: i64,
+    pub copied: i64,   // uploaded to the bucket
+    pub verified: i64, // read back with a matching hash
+    pub skipped: i64,  // accepted levels without a file on disk, like taken down ones
+    pub failed: i64,
+}
+
+// only the instance running a migration has this, others read the row
+static PROGRESS: LazyLock<Mutex<Option<MigrationProgress>>> = LazyLock::new(Default::default);
+
+pub fn progress() -> Option<MigrationProgress> {
+    PROGRESS.lock().ok().and_then(|progress| *progress)
+}
+
+fn set_progress(progress: MigrationProgress) {
+    if let Ok(mut current) = PROGRESS.lock() {
+        *current = Some(progress);
+    }
+}
+
+// Uploads one thumbnail and reads it back, Ok(false) when there is nothing on disk to copy
+async fn copy_verified(remote: &Remote, path: &str) -> Result<bool, String> {
+    // a thumbnail replaced mid-copy was mirrored already, but possibly before this older copy
+    // landed, so it's copied again until the file on disk stays put
+    for _ in 0..COPY_ATTEMPTS {
+        let data = match tokio::fs::read(paths::local(path)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.to_string()),
+        };
+        let expected = sync::hash(&data);
+        remote.put(path, data).await?;
+
+        match remote.get(path).await? {
+            Some(copy) if sync::hash(&copy) == expected => {}
+            Some(_) => return Err("hash mismatch after upload".to_string()),
+            None => return Err("missing after upload".to_string()),
+        }
+        if sync::hash_file(path).await.is_ok_and(|current| current == expected) {
+            return Ok(true);
+        }
+    }
+    Err("kept changing while being copied".to_string())
+}
+
+// Copies every live thumbnail to the bucket while reads keep coming from disk, then makes the
+// bucket the active backend. Nothing flips when any copy fails, the migration can be started again
+pub async fn migrate(db: database::Database, migration_id: i64) {
+    let Some(remote) = remote() else {
+        return;
+    };
+
+    let levels = match db.get_live_thumbnail_levels().await {
+        Ok(levels) => levels,
+        Err(e) => {
+            let message = format!("Failed to list thumbnails: {}", e);
+            error!("Storage migration {} failed: {}", migration_id, message);
+            let status = database::StorageMigrationStatus::Failed;
+            let _ = db.finish_storage_migration(migration_id, status, Some(&message)).await;
+            return;
+        }
+    };
+
+    let mut progress = MigrationProgress {
+        total: levels.len() as i64,
+        ..Default::default()
+    };
+    set_progress(progress);
+    info!("Storage migration {} started, {} thumbnails to copy", migration_id, progress.total);
+
+    for (i, (namespace, level_id)) in levels.iter().enumerate() {
+        let path = paths::thumbnail_path(namespace, *level_id);
+        match copy_verified(remote, &path).await {
+            Ok(true) => {
+                progress.copied += 1;
+                progress.verified += 1;
+            }
+            Ok(false) => progress.skipped += 1,
+            Err(e) => {
+                warn!("Failed to migrate {}: {}", path, e);
+                progress.failed += 1;
+            }
+        }
+
+        set_progress(progress);
+        if (i + 1) % PROGRESS_BATCH == 0
+            && let Err(e) = db.update_storage_migration(migration_id, &progress).await
+        {
+            error!("Failed to record storage migration progress: {}", e);
+        }
+    }
+
+    if let Err(e) = db.update_storage_migration(migration_id, &progress).await {
+        error!("Failed to record storage migration progress: {}", e);
+    }
+    let (status, message) = match progress.failed {
+        0 => (database::StorageMigrationStatus::Completed, None),
+        failed => (
+            database::StorageMigrationStatus::Failed,
+            Some(format!("{} thumbnail(s) failed to copy or verify", failed)),
+        ),
+    };
+    if let Err(e) = db.finish_storage_migration(migration_id, status, message.as_deref()).await {
+        return error!("Failed to finish storage migration {}: {}", migration_id, e);
+    }
+
+    reload(&db).await;
+    match message {
+        None => info!("Storage migration {} completed, serving from s3", migration_id),
+        Some(message) => error!("Storage migration {} failed: {}", migration_id, message),
+    }
+}
@@ -0,0 +1,174 @@
+use crate::database::{self, SyncAction, SyncChange};
+use crate::models::{AccountId, LevelId, UserId};
+use crate::routes::upload;
+use crate::{archive, namespace, paths};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+const CURSOR_FILE: &str = "sync_cursor";
+
+pub fn hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+pub async fn hash_file(path: &str) -> Result<String, std::io::Error> {
+    let data = tokio::fs::read(paths::local(path)).await?;
+    Ok(hash(&data))
+}
+
+// Record a thumbnail going live so mirrors pick it up
+pub async fn record_accepted(
+    db: &database::Database,
+    level_id: LevelId,
+    user_id: UserId,
+    data: &[u8],
+) {
+    let hash = hash(data);
+    if let Err(e) =
+        db.add_sync_change(level_id, SyncAction::Accepted, Some(&hash), Some(user_id)).await
+    {
+        error!("Failed to record sync change for level {}: {}", level_id, e);
+    }
+}
+
+pub async fn record_removed(db: &database::Database, level_id: LevelId) {
+    if let Err(e) = db.add_sync_change(level_id, SyncAction::Removed, None, None).await {
+        error!("Failed to record sync change for level {}: {}", level_id, e);
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangesResponse {
+    changes: Vec<SyncChange>,
+}
+
+async fn read_cursor() -> i64 {
+    tokio::fs::read_to_string(paths::local(CURSOR_FILE))
+        .await
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+async fn apply_change(
+    db: &database::Database,
+    client: &reqwest::Client,
+    upstream: &str,
+    change: &SyncChange,
+) -> Result<(), String> {
+    let image_path = paths::thumbnail_path(namespace::DEFAULT, change.level_id);
+
+    match change.action {
+        SyncAction::Removed => match tokio::fs::remove_file(paths::local(&image_path)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+            _ => Ok(()),
+        },
+        SyncAction::Accepted => {
+            let Some(content_hash) = &change.content_hash else {
+                return Err("missing content hash".to_string());
+            };
+
+            let response = client
+                .get(format!("{}/sync/blob/{}", upstream, content_hash))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            // the blob was superseded upstream, a later change carries its replacement
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(());
+            }
+
+            let data = response
+                .error_for_status()
+                .map_err(|e| e.to_string())?
+                .bytes()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            if hash(&data) != *content_hash {
+                return Err("content hash mismatch".to_string());
+            }
+
+            let user = db
+                .find_or_create_user(
+                    change.account_id.unwrap_or(AccountId(-1)),
+                    change.username.as_deref().unwrap_or("unknown"),
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+
+            archive::supersede(db, namespace::DEFAULT, change.level_id).await;
+            tokio::fs::write(paths::local(&image_path), &data).await.map_err(|e| e.to_string())?;
+            // encoded by the source instance, which may run a different version
+            let meta = database::ImageMeta {
+                encoder_version: None,
+                ..upload::image_meta(&data)
+            };
+            db.add_upload(namespace::DEFAULT, change.level_id, user.id, &image_path, true, &meta)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+async fn sync_once(
+    db: &database::Database,
+    client: &reqwest::Client,
+    upstream: &str,
+    cursor: &mut i64,
+) -> Result<usize, String> {
+    let response: ChangesResponse = client
+        .get(format!("{}/sync/changes", upstream))
+        .query(&[("since", *cursor)])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    for change in &response.changes {
+        if let Err(e) = apply_change(db, client, upstream, change).await {
+            // stop here so the change is retried on the next poll
+            return Err(format!("change {} for level {}: {}", change.cursor, change.level_id, e));
+        }
+
+        *cursor = change.cursor;
+        tokio::fs::write(paths::local(CURSOR_FILE), cursor.to_string())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(response.changes.len())
+}
+
+// Keep a read-only mirror up to date with its upstream instance
+pub async fn run_mirror(db: database::Database, upstream: String) {
+    let interval =
+        dotenv::var("SYNC_INTERVAL").ok().and_then(|v| v.parse::<u64>().ok()).unwrap_or(60);
+
+    let client = reqwest::ClientBuilder::new()
+        .user_agent(format!("level-thumbnails-server/{}", env!("CARGO_PKG_VERSION")))
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .expect("Failed to create HTTP client");
+
+    let upstream = upstream.trim_end_matches('/').to_string();
+    let mut cursor = read_cursor().await;
+    info!("Mirroring {} from cursor {}", upstream, cursor);
+
+    loop {
+        match sync_once(&db, &client, &upstream, &mut cursor).await {
+            // keep fetching until the upstream has nothing new
+            Ok(count) if count > 0 => continue,
+            Ok(_) => {}
+            Err(e) => error!("Mirror sync failed: {}", e),
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+    }
+}
@@ -0,0 +1,104 @@
+use crate::events::{self, QueueEvent};
+use crate::models::LevelId;
+use crate::webhooks::{self, WebhookEvent};
+use crate::{cache_controller, database, namespace, paths, sync, util};
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::json;
+use tracing::{info, warn};
+
+// Records a claim and lets the admins know through the takedown.requested webhook
+pub async fn submit(
+    db: &database::Database,
+    request: database::NewTakedownRequest<'_>,
+) -> Result<database::TakedownRequest, sqlx::Error> {
+    let request = db.add_takedown_request(request).await?;
+
+    warn!(
+        "Takedown request {} filed for level {} in {}",
+        request.id, request.level_id, request.namespace
+    );
+    webhooks::emit(
+        db,
+        WebhookEvent::TakedownRequested,
+        json!({
+            "takedown_id": request.id,
+            "namespace": request.namespace,
+            "level_id": request.level_id,
+            "reason": request.reason,
+        }),
+    );
+    Ok(request)
+}
+
+// Pulls the live thumbnail of a level along with everything derived from it. The uploads are
+// marked as removed under the level lock first, so an upload can't slip in between and a
+// failed update leaves the thumbnail live instead of gone with an accepted row
+pub async fn remove_thumbnail(
+    db: &database::Database,
+    namespace: &str,
+    level_id: LevelId,
+    admin: &database::User,
+    reason: Option<String>,
+) -> Result<(), Response> {
+    let _lock = match db.try_lock_level(namespace, level_id).await {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            return Err(util::str_response(
+                StatusCode::CONFLICT,
+                &format!("Another upload for level ID {} is in progress", level_id),
+            ));
+        }
+        Err(e) => {
+            return Err(util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error locking level: {}", e),
+            ));
+        }
+    };
+
+    if let Err(e) = db.remove_thumbnail(namespace, level_id).await {
+        return Err(util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Failed to mark thumbnail as removed: {}", e),
+        ));
+    }
+
+    let image_path = paths::thumbnail_path(namespace, level_id);
+    match tokio::fs::remove_file(paths::local(&image_path)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to remove thumbnail: {}", e),
+            ));
+        }
+    }
+    cache_controller::replaced(db, namespace, level_id).await;
+
+    // mirrors only replicate the main game
+    if namespace == namespace::DEFAULT {
+        sync::record_removed(db, level_id).await;
+    }
+
+    info!("Thumbnail for level {} in {} removed by {}", level_id, namespace, admin.username);
+    // the webhook goes out directly, a lagging bus subscriber could drop it
+    webhooks::emit(
+        db,
+        WebhookEvent::ThumbnailRemoved,
+        json!({
+            "namespace": namespace,
+            "level_id": level_id,
+            "removed_by": admin.id,
+            "reason": reason,
+        }),
+    );
+    events::publish(QueueEvent::Removed {
+        namespace: namespace.to_string(),
+        level_id,
+        admin_id: admin.id,
+        reason,
+    });
+    Ok(())
+}
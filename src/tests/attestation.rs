@@ -0,0 +1,78 @@
+use crate::attestation::{self, MAX_SKEW, Verdict, Verifier};
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::{Digest, Sha256};
+
+const KEY: &str = "mod-key";
+const BODY: &[u8] = b"not really a png";
+const NOW: i64 = 1_757_500_000;
+
+// what the mod does before it sends an upload
+fn sign(key: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+    mac.update(format!("{}:{}", timestamp, hex::encode(Sha256::digest(body))).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+// what check() does with the headers of an upload
+fn verify(
+    verifier: &mut Verifier,
+    key: &str,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    body: &[u8],
+    now: i64,
+) -> Verdict {
+    match attestation::signed(key, timestamp, signature, body, now) {
+        Ok((signature, timestamp)) => verifier.remember(signature, timestamp, now),
+        Err(verdict) => verdict,
+    }
+}
+
+#[test]
+fn signed_uploads_are_attested_once() {
+    let mut verifier = Verifier::default();
+    let signature = sign(KEY, NOW, BODY);
+    let timestamp = NOW.to_string();
+
+    let verdict = verify(&mut verifier, KEY, Some(&timestamp), Some(&signature), BODY, NOW + 10);
+    assert_eq!(verdict, Verdict::Attested);
+    let verdict = verify(&mut verifier, KEY, Some(&timestamp), Some(&signature), BODY, NOW + 20);
+    assert_eq!(verdict, Verdict::Replayed);
+
+    // the replay window is pruned, but by then the timestamp is too old anyway
+    let later = NOW + 3 * MAX_SKEW;
+    let verdict = verify(&mut verifier, KEY, Some(&timestamp), Some(&signature), BODY, later);
+    assert_eq!(verdict, Verdict::Stale);
+}
+
+#[test]
+fn bad_signatures_are_not_attested() {
+    let mut verifier = Verifier::default();
+    let timestamp = NOW.to_string();
+    let signature = sign(KEY, NOW, BODY);
+
+    assert_eq!(verify(&mut verifier, KEY, None, None, BODY, NOW), Verdict::Unsigned);
+    assert_eq!(verify(&mut verifier, KEY, Some(&timestamp), None, BODY, NOW), Verdict::Invalid);
+    assert_eq!(
+        verify(&mut verifier, KEY, Some("soon"), Some(&signature), BODY, NOW),
+        Verdict::Invalid
+    );
+    assert_eq!(
+        verify(&mut verifier, KEY, Some(&timestamp), Some("zz"), BODY, NOW),
+        Verdict::Invalid
+    );
+
+    // another body, timestamp or key doesn't match the signature
+    let verdict = verify(&mut verifier, KEY, Some(&timestamp), Some(&signature), b"other", NOW);
+    assert_eq!(verdict, Verdict::Invalid);
+    let shifted = (NOW + 1).to_string();
+    let verdict = verify(&mut verifier, KEY, Some(&shifted), Some(&signature), BODY, NOW);
+    assert_eq!(verdict, Verdict::Invalid);
+    let verdict = verify(&mut verifier, "other-key", Some(&timestamp), Some(&signature), BODY, NOW);
+    assert_eq!(verdict, Verdict::Invalid);
+
+    let early = sign(KEY, NOW + MAX_SKEW + 1, BODY);
+    let early_timestamp = (NOW + MAX_SKEW + 1).to_string();
+    let verdict = verify(&mut verifier, KEY, Some(&early_timestamp), Some(&early), BODY, NOW);
+    assert_eq!(verdict, Verdict::Stale);
+}
@@ -0,0 +1,35 @@
+use super::harness::{TestApp, level_id};
+use crate::database::{ImageMeta, IpAction, Role};
+use crate::namespace;
+
+#[tokio::test]
+async fn rejections_count_against_the_address_they_came_from() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, _) = app.user(Role::User).await;
+    let (moderator, _) = app.user(Role::Moderator).await;
+
+    // the uploader has sent uploads from both addresses
+    app.db.record_user_ip(user.id, "192.0.2.10", IpAction::Upload).await.unwrap();
+    app.db.record_user_ip(user.id, "198.51.100.7", IpAction::Upload).await.unwrap();
+
+    let meta = ImageMeta {
+        width: Some(1920),
+        height: Some(1080),
+        file_size: 100,
+        encoding: Some("lossless"),
+        encoder_version: None,
+    };
+    let upload_id = app
+        .db
+        .add_upload(namespace::DEFAULT, level_id(), user.id, "pending/test.webp", false, &meta)
+        .await
+        .unwrap();
+    app.db.set_upload_ip(upload_id, "192.0.2.10").await.unwrap();
+    app.db.accept_upload(upload_id, moderator.id, None, false).await.unwrap();
+
+    assert_eq!(app.db.count_recent_rejections_from_ip("192.0.2.10", 7).await.unwrap(), 1);
+    assert_eq!(app.db.count_recent_rejections_from_ip("198.51.100.7", 7).await.unwrap(), 0);
+    app.cleanup().await;
+}
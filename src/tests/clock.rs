@@ -0,0 +1,39 @@
+use super::harness::{TestApp, level_id};
+use crate::database::Role;
+use axum::http::StatusCode;
+use chrono::TimeDelta;
+
+#[tokio::test]
+async fn undo_window_closes() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let upload_id = app.accepted_upload(level_id(), &uploader, &moderator, [90, 90, 20]).await;
+
+    // the default window is 10 minutes
+    app.clock.advance(TimeDelta::minutes(11));
+    let undo = app.post(&format!("/pending/{}/undo", upload_id), Some(&moderator), vec![]).await;
+    assert_eq!(undo.status, StatusCode::CONFLICT);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn stats_window_follows_the_clock() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let level = level_id();
+    app.db.add_thumbnail_views(&[(level, 42)]).await.unwrap();
+
+    let top = app.get("/stats/top-levels?days=7", None).await;
+    assert_eq!(top.json()["levels"][0]["level_id"].as_i64(), Some(level.0));
+
+    app.clock.advance(TimeDelta::days(7));
+    let top = app.get("/stats/top-levels?days=7", None).await;
+    assert_eq!(top.json()["levels"], serde_json::json!([]));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,30 @@
+use crate::doctor::{self, Status};
+
+#[test]
+fn weak_jwt_secrets_are_reported() {
+    assert_eq!(doctor::jwt_secret(None).status, Status::Fail);
+    assert_eq!(doctor::jwt_secret(Some("MySuperSecretJWTSecret1234567890")).status, Status::Fail);
+    assert_eq!(doctor::jwt_secret(Some("hunter2")).status, Status::Warn);
+    assert_eq!(doctor::jwt_secret(Some(&"ab".repeat(32))).status, Status::Warn);
+
+    let secret = "9f2c41d7e08b5a36c1f4e97d20ab58c3e6d1074f8b29a5c6e3d0f71b84a2c95e";
+    assert_eq!(doctor::jwt_secret(Some(secret)).status, Status::Ok);
+}
+
+#[test]
+fn quarantine_key_is_required_when_uploads_can_be_held() {
+    let key = "9f2c41d7e08b5a36c1f4e97d20ab58c3e6d1074f8b29a5c6e3d0f71b84a2c95e";
+    assert_eq!(doctor::quarantine_key(None, false).status, Status::Ok);
+    assert_eq!(doctor::quarantine_key(None, true).status, Status::Fail);
+    assert_eq!(doctor::quarantine_key(Some("not hex"), false).status, Status::Fail);
+    assert_eq!(doctor::quarantine_key(Some(&key[..32]), true).status, Status::Fail);
+    assert_eq!(doctor::quarantine_key(Some(key), true).status, Status::Ok);
+}
+
+#[test]
+fn unknown_modes_are_reported() {
+    let allowed = &["forbid", "watermark"];
+    assert_eq!(doctor::one_of("mode", "MODE", None, allowed).status, Status::Ok);
+    assert_eq!(doctor::one_of("mode", "MODE", Some("watermark"), allowed).status, Status::Ok);
+    assert_eq!(doctor::one_of("mode", "MODE", Some("blur"), allowed).status, Status::Fail);
+}
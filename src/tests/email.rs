@@ -0,0 +1,39 @@
+use super::harness::TestApp;
+use crate::database::Role;
+use crate::email;
+
+#[test]
+fn placeholders_in_values_are_not_expanded() {
+    let vars = [("username", "{reason}"), ("reason", "spam")];
+    assert_eq!(email::render("Hi {username}, {reason}", &vars), "Hi {reason}, spam");
+    assert_eq!(email::render("{unknown} {reason", &vars), "{unknown} {reason");
+}
+
+#[tokio::test]
+async fn notifications_wait_for_a_confirmed_address() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, _) = app.user(Role::User).await;
+    let verified = app.db.set_email_settings(user.id, Some("a@example.com"), true).await.unwrap();
+    assert!(!verified);
+    assert!(app.db.get_email_recipient(user.id).await.is_none());
+
+    let hash = crate::sync::hash(b"token");
+    app.db
+        .set_email_token(user.id, &hash, app.db.now() + chrono::Duration::hours(1))
+        .await
+        .unwrap();
+    assert!(app.db.verify_email(&hash).await.unwrap());
+    assert!(!app.db.verify_email(&hash).await.unwrap());
+    assert!(app.db.get_email_recipient(user.id).await.is_some());
+
+    // the same address stays confirmed, a new one has to be confirmed again
+    let verified = app.db.set_email_settings(user.id, Some("a@example.com"), true).await.unwrap();
+    assert!(verified);
+    let verified = app.db.set_email_settings(user.id, Some("b@example.com"), true).await.unwrap();
+    assert!(!verified);
+    assert!(app.db.get_email_recipient(user.id).await.is_none());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,12 @@
+use crate::experiments;
+use crate::models::LevelId;
+
+#[test]
+fn official_levels_are_known_by_id() {
+    for id in [1, 22, 1001, 2010, 3001, 4003, 5004] {
+        assert!(experiments::is_official(LevelId(id)), "{}", id);
+    }
+    for id in [23, 128, 1004, 3002, 5005, 128_000_000] {
+        assert!(!experiments::is_official(LevelId(id)), "{}", id);
+    }
+}
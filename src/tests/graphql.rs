@@ -0,0 +1,55 @@
+use crate::graphql;
+use async_graphql::Request;
+
+// the query GraphiQL sends to load the docs, TypeRef is what makes it deep
+const INTROSPECTION: &str = "
+query IntrospectionQuery {
+  __schema {
+    queryType { name }
+    mutationType { name }
+    subscriptionType { name }
+    types { ...FullType }
+    directives { name description locations args { ...InputValue } }
+  }
+}
+fragment FullType on __Type {
+  kind name description
+  fields(includeDeprecated: true) {
+    name description args { ...InputValue } type { ...TypeRef } isDeprecated deprecationReason
+  }
+  inputFields { ...InputValue }
+  interfaces { ...TypeRef }
+  enumValues(includeDeprecated: true) { name description isDeprecated deprecationReason }
+  possibleTypes { ...TypeRef }
+}
+fragment InputValue on __InputValue { name description type { ...TypeRef } defaultValue }
+fragment TypeRef on __Type {
+  kind name
+  ofType { kind name ofType { kind name ofType { kind name ofType { kind name
+    ofType { kind name ofType { kind name ofType { kind name } } } } } } }
+}";
+
+// validation runs before any resolver, so none of these touch the database
+async fn errors(query: &str) -> Vec<String> {
+    let response = graphql::schema().execute(Request::new(query)).await;
+    response.errors.into_iter().map(|e| e.message).collect()
+}
+
+#[tokio::test]
+async fn introspection_fits_the_limits() {
+    assert_eq!(errors(INTROSPECTION).await, Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn nested_queries_are_refused() {
+    let query = "{ thumbnail(levelId: 1) { history { history { history { id } } } } }";
+    let refused = errors(query).await;
+    assert!(refused.iter().any(|e| e.contains("complex")), "{:?}", refused);
+
+    let mut query = "name".to_string();
+    for _ in 0..12 {
+        query = format!("ofType {{ {} }}", query);
+    }
+    let refused = errors(&format!("{{ __schema {{ types {{ {} }} }} }}", query)).await;
+    assert!(refused.iter().any(|e| e.contains("nested too deep")), "{:?}", refused);
+}
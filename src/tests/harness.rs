@@ -0,0 +1,283 @@
+use crate::auth::UserSession;
+use crate::clock::{Clock, IdGenerator};
+use crate::database::{self, Role};
+use crate::models::{AccountId, LevelId};
+use crate::{app, paths, permissions, settings, storage};
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode, Uri, header};
+use chrono::{DateTime, TimeDelta, Utc};
+use http_body_util::BodyExt;
+use sqlx::postgres::{PgConnectOptions, PgPool};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use tower::ServiceExt;
+
+// The storage root is set once per process, so every test shares one temporary directory and
+// keeps apart by using its own level IDs
+static WORK_DIR: LazyLock<tempfile::TempDir> =
+    LazyLock::new(|| tempfile::tempdir().expect("Failed to create a temporary directory"));
+
+// Stands in for the S3 bucket. It only serves what a test put there with bucket_only, writes
+// mirrored by other tests are accepted and dropped so they never show up in their reads
+struct FakeBucket {
+    address: SocketAddr,
+    objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+const BUCKET_NAME: &str = "thumbnails";
+
+static BUCKET: LazyLock<FakeBucket> = LazyLock::new(|| {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("Failed to bind the bucket");
+    listener.set_nonblocking(true).unwrap();
+    let address = listener.local_addr().unwrap();
+    let objects = Arc::new(Mutex::new(HashMap::new()));
+
+    // each test has its own runtime, the bucket outlives them on a thread of its own
+    let served = objects.clone();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let bucket = Router::new().fallback(move |method: Method, uri: Uri| {
+                let key = uri.path().trim_start_matches(&format!("/{}/", BUCKET_NAME)).to_string();
+                let object = served.lock().unwrap().get(&key).cloned();
+                async move {
+                    match (method, object) {
+                        (Method::GET | Method::HEAD, Some(data)) => (StatusCode::OK, data),
+                        (Method::GET | Method::HEAD, None) => (StatusCode::NOT_FOUND, Vec::new()),
+                        _ => (StatusCode::OK, Vec::new()),
+                    }
+                }
+            });
+            axum::serve(listener, bucket).await.unwrap();
+        });
+    });
+    FakeBucket { address, objects }
+});
+
+// Starts at the real time and only moves when a test says so
+#[derive(Debug)]
+pub struct FixedClock(Mutex<DateTime<Utc>>);
+
+impl FixedClock {
+    pub fn advance(&self, by: TimeDelta) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+// Counts up from zero, so random picks and generated keys are known in advance
+#[derive(Debug, Default)]
+pub struct SequentialIds(AtomicU64);
+
+impl IdGenerator for SequentialIds {
+    fn next_u64(&self) -> u64 {
+        self.0.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+pub struct TestApp {
+    pub router: Router,
+    pub db: database::Database,
+    pub clock: Arc<FixedClock>,
+    schema: String,
+    admin: PgPool,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+impl TestApp {
+    // None when TEST_DATABASE_URL isn't set, the calling test should return early
+    pub async fn new() -> Option<Self> {
+        let url = std::env::var("TEST_DATABASE_URL").ok()?;
+
+        let admin = PgPool::connect(&url).await.expect("Failed to connect to TEST_DATABASE_URL");
+        // installed once in public, an extension created inside a test schema would vanish with it
+        let _ = sqlx::query("CREATE EXTENSION IF NOT EXISTS pg_trgm SCHEMA public")
+            .execute(&admin)
+            .await;
+        let schema = format!("test_{}", hex::encode(rand::random::<[u8; 6]>()));
+        sqlx::query(&format!("CREATE SCHEMA {}", schema))
+            .execute(&admin)
+            .await
+            .expect("Failed to create the test schema");
+
+        let options = url
+            .parse::<PgConnectOptions>()
+            .expect("TEST_DATABASE_URL must be a valid Postgres URL")
+            .options([("search_path", format!("{},public", schema))]);
+        let clock = Arc::new(FixedClock(Mutex::new(Utc::now())));
+        let db = database::Database::connect(options)
+            .await
+            .expect("Failed to migrate the test schema")
+            .with_clock(clock.clone())
+            .with_ids(Arc::new(SequentialIds::default()));
+        settings::reload(&db).await;
+        permissions::reload(&db).await;
+
+        let config = app::Config {
+            mirror: false,
+            separate_internal: false,
+            dist_dir: "dist".to_string(),
+            jwt_secret: "test-secret".to_string(),
+            storage_root: WORK_DIR.path().to_path_buf(),
+            storage_bucket: Some(storage::BucketConfig {
+                endpoint: format!("http://{}", BUCKET.address),
+                name: BUCKET_NAME.to_string(),
+                region: "auto".to_string(),
+                access_key: "test".to_string(),
+                secret_key: "test".to_string(),
+            }),
+        };
+        let router = app::app(&config, db.clone());
+        Some(Self {
+            router,
+            db,
+            clock,
+            schema,
+            admin,
+        })
+    }
+
+    // Drops the schema, a test that panics before this leaves it behind
+    pub async fn cleanup(self) {
+        let _ =
+            sqlx::query(&format!("DROP SCHEMA {} CASCADE", self.schema)).execute(&self.admin).await;
+    }
+
+    // Leaves a live thumbnail only in the bucket, like a replica sees it before pulling it.
+    // The bucket becomes the backend of the whole process, which other tests don't notice
+    // since it has nothing of theirs
+    pub async fn bucket_only(&self, path: &str) {
+        let local = paths::local(path);
+        let data = tokio::fs::read(&local).await.expect("the thumbnail is on disk");
+        BUCKET.objects.lock().unwrap().insert(path.to_string(), data);
+        tokio::fs::remove_file(&local).await.unwrap();
+        storage::forget(path);
+
+        let (admin, _) = self.user(Role::Admin).await;
+        let migration = self.db.start_storage_migration("s3", admin.id).await.unwrap().unwrap();
+        self.db
+            .finish_storage_migration(
+                migration.id,
+                database::StorageMigrationStatus::Completed,
+                None,
+            )
+            .await
+            .unwrap();
+        storage::reload(&self.db).await;
+    }
+
+    // A user with the role and a session token for it, staff sessions count as 2FA checked
+    pub async fn user(&self, role: Role) -> (database::User, String) {
+        let account_id = AccountId(rand::random_range(1..1_000_000_000));
+        let username = format!("{}_{}", role, account_id);
+        let user = self.db.find_or_create_user(account_id, &username).await.unwrap();
+        let user = match role {
+            Role::User => user,
+            role => self.db.set_user_role(account_id, role).await.unwrap().remove(0),
+        };
+
+        let mut session = UserSession::new(user.id, user.username.clone());
+        session.mfa = true;
+        session.auth_time = chrono::Utc::now().timestamp() as u64;
+        (user, session.to_jwt())
+    }
+
+    fn builder(method: &str, path: &str, token: Option<&str>) -> axum::http::request::Builder {
+        let request = Request::builder().method(method).uri(path);
+        match token {
+            Some(token) => request.header(header::AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+
+    pub async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.expect("infallible");
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = response.into_body().collect().await.unwrap().to_bytes().to_vec();
+        TestResponse { status, content_type, body }
+    }
+
+    pub async fn get(&self, path: &str, token: Option<&str>) -> TestResponse {
+        self.send(Self::builder("GET", path, token).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post(&self, path: &str, token: Option<&str>, body: Vec<u8>) -> TestResponse {
+        self.send(Self::builder("POST", path, token).body(Body::from(body)).unwrap()).await
+    }
+
+    pub async fn post_json(
+        &self,
+        path: &str,
+        token: Option<&str>,
+        body: serde_json::Value,
+    ) -> TestResponse {
+        let request = Self::builder("POST", path, token)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    // Uploads an image for the level and has the moderator accept it, returns the upload ID
+    pub async fn accepted_upload(
+        &self,
+        level: LevelId,
+        uploader: &str,
+        moderator: &str,
+        color: [u8; 3],
+    ) -> i64 {
+        let response =
+            self.post(&format!("/upload/{}", level), Some(uploader), test_image(color)).await;
+        assert!(response.status.is_success(), "upload failed: {:?}", response.json());
+        let pending = self.get(&format!("/pending/level/{}", level), Some(moderator)).await;
+        let upload_id = pending.json()[0]["id"].as_i64().expect("upload is pending");
+
+        let decision = self
+            .post_json(
+                &format!("/pending/{}", upload_id),
+                Some(moderator),
+                serde_json::json!({ "accepted": true }),
+            )
+            .await;
+        assert_eq!(decision.status, StatusCode::OK, "{:?}", decision.json());
+        upload_id
+    }
+}
+
+// A plain PNG of the size uploads must have
+pub fn test_image(color: [u8; 3]) -> Vec<u8> {
+    let image = image::RgbImage::from_pixel(1920, 1080, image::Rgb(color));
+    let mut data = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut data, image::ImageFormat::Png).unwrap();
+    data.into_inner()
+}
+
+// Level IDs no other test in the run uses
+pub fn level_id() -> LevelId {
+    LevelId(rand::random_range(1_000_000..1_000_000_000))
+}
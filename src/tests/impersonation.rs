@@ -0,0 +1,31 @@
+use super::harness::TestApp;
+use crate::database::Role;
+use crate::impersonation;
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn impersonation_cant_touch_account_security() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (admin, _) = app.user(Role::Admin).await;
+    let (target, own_token) = app.user(Role::User).await;
+    let (token, _) =
+        impersonation::start(&app.db, &admin, &target, "support ticket").await.unwrap();
+
+    for path in ["/user/me/2fa/enroll", "/user/me/accept-tos"] {
+        let response = app.post_json(path, Some(&token), serde_json::json!({})).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{}", path);
+    }
+    let response = app
+        .post_json("/user/me/2fa/verify", Some(&token), serde_json::json!({ "code": "000000" }))
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    // the user themselves still can
+    let response =
+        app.post_json("/user/me/2fa/enroll", Some(&own_token), serde_json::json!({})).await;
+    assert_eq!(response.status, StatusCode::OK, "{:?}", response.json());
+
+    app.cleanup().await;
+}
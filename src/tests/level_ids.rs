@@ -0,0 +1,43 @@
+use super::harness::{TestApp, test_image};
+use crate::database::Role;
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn garbage_level_ids_are_bad_requests() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+
+    for id in ["0", "-5", "abc", "99999999999", "18446744073709551616"] {
+        let response = app.get(&format!("/thumbnail/{}", id), None).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "thumbnail {}", id);
+        let message = response.json()["message"].as_str().unwrap_or_default().to_string();
+        assert!(message.contains("Level IDs"), "unhelpful message for {}: {}", id, message);
+
+        let response = app.get(&format!("/thumbnail/{}/info", id), None).await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST, "info {}", id);
+    }
+
+    // other bad parameters keep their own errors
+    let response = app.get("/thumbnail/128/huge", None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert!(!response.json()["message"].as_str().unwrap_or_default().contains("Level IDs"));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn uploads_for_garbage_level_ids_are_refused() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, uploader) = app.user(Role::User).await;
+
+    let response = app.post("/upload/-1", Some(&uploader), test_image([40, 40, 200])).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    let pending = app.db.get_pending_uploads_for_user(user.id).await.unwrap();
+    assert!(pending.is_empty());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,99 @@
+use crate::login_throttle::{Key, Throttle};
+use crate::models::AccountId;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+fn ip(address: &str) -> Key {
+    Key::ip(address.parse::<IpAddr>().unwrap())
+}
+
+#[test]
+fn repeated_failures_lock_out_for_longer_each_time() {
+    let mut throttle = Throttle::default();
+    let address: IpAddr = "203.0.113.5".parse().unwrap();
+    let keys = [Key::ip(address), Key::account(AccountId(7), Some(address))];
+    let mut now = Instant::now();
+
+    for _ in 0..5 {
+        assert!(throttle.check(&keys, now).is_ok());
+        assert!(throttle.fail(&keys, now).is_empty());
+        now += Duration::from_secs(7);
+    }
+
+    // the sixth failure locks the account, then each one after doubles the wait
+    let mut lockouts = Vec::new();
+    for _ in 0..3 {
+        assert!(throttle.check(&keys, now).is_ok());
+        assert_eq!(throttle.fail(&keys, now), [keys[1]]);
+        let wait = throttle.check(&keys, now).unwrap_err();
+        lockouts.push(wait);
+        now += wait;
+    }
+    assert_eq!(lockouts, [30, 60, 120].map(Duration::from_secs));
+
+    // other accounts from the same address aren't affected until the address runs out too
+    let other = Key::account(AccountId(8), Some(address));
+    assert!(throttle.check(&[keys[0], other], now).is_ok());
+
+    throttle.succeed(keys[1]);
+    assert!(throttle.check(&keys, now).is_ok());
+}
+
+#[test]
+fn failures_from_one_address_dont_lock_out_another() {
+    let mut throttle = Throttle::default();
+    let attacker: IpAddr = "203.0.113.5".parse().unwrap();
+    let owner: IpAddr = "198.51.100.7".parse().unwrap();
+    let now = Instant::now();
+
+    let keys = [Key::ip(attacker), Key::account(AccountId(7), Some(attacker))];
+    for _ in 0..6 {
+        assert!(throttle.check(&keys, now).is_ok());
+        throttle.fail(&keys, now);
+    }
+    assert!(throttle.check(&keys, now).is_err());
+
+    let keys = [Key::ip(owner), Key::account(AccountId(7), Some(owner))];
+    assert!(throttle.check(&keys, now).is_ok());
+}
+
+#[test]
+fn forgotten_keys_are_pruned() {
+    let mut throttle = Throttle::default();
+    let keys = [ip("203.0.113.5")];
+    let now = Instant::now();
+
+    for _ in 0..11 {
+        assert!(throttle.check(&keys, now).is_ok());
+        throttle.fail(&keys, now);
+    }
+    assert!(throttle.check(&keys, now).is_err());
+
+    // pruning keeps the lockout, only keys quiet for long enough are dropped
+    throttle.prune(now);
+    assert!(throttle.check(&keys, now).is_err());
+    let later = now + Duration::from_secs(7 * 60 * 60);
+    throttle.prune(later);
+    assert!(throttle.check(&keys, later).is_ok());
+    assert!(throttle.fail(&keys, later).is_empty());
+}
+
+#[test]
+fn attempts_are_limited_per_address_and_account() {
+    let mut throttle = Throttle::default();
+    let now = Instant::now();
+
+    let account = [Key::account(AccountId(1), None)];
+    for _ in 0..10 {
+        assert!(throttle.check(&account, now).is_ok());
+    }
+    assert_eq!(throttle.check(&account, now), Err(Duration::from_secs(60)));
+    assert!(throttle.check(&account, now + Duration::from_secs(60)).is_ok());
+
+    // a whole IPv6 /64 counts as one address
+    for n in 0..30 {
+        assert!(throttle.check(&[ip(&format!("2001:db8::{:x}", n + 1))], now).is_ok());
+    }
+    assert!(throttle.check(&[ip("2001:db8::ffff:1")], now).is_err());
+    assert!(throttle.check(&[ip("2001:db8:0:1::1")], now).is_ok());
+}
@@ -0,0 +1,24 @@
+// End-to-end tests against the real router and a throwaway Postgres schema, they are skipped
+// unless TEST_DATABASE_URL points at a database they may create schemas in
+mod attestation;
+mod captcha;
+mod clock;
+mod doctor;
+mod email;
+mod experiments;
+mod graphql;
+mod harness;
+mod impersonation;
+mod level_ids;
+mod login_throttle;
+mod moderation;
+mod paths;
+mod rate_limit;
+mod reload;
+mod restore;
+mod search;
+mod storage;
+mod two_factor;
+mod upload_flow;
+mod upload_source;
+mod upload_validation;
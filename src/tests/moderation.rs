@@ -0,0 +1,77 @@
+use crate::models::UploadStatus;
+use crate::moderation::{self, Action, Refusal};
+use chrono::{DateTime, TimeDelta};
+use proptest::prelude::*;
+use proptest::sample::select;
+
+const STATUSES: [UploadStatus; 6] = [
+    UploadStatus::Pending,
+    UploadStatus::Accepted,
+    UploadStatus::Rejected,
+    UploadStatus::Withdrawn,
+    UploadStatus::Expired,
+    UploadStatus::Removed,
+];
+
+const ACTIONS: [Action; 4] = [Action::Accept, Action::Reject, Action::Remove, Action::Undo];
+
+fn is_final(status: UploadStatus) -> bool {
+    matches!(status, UploadStatus::Withdrawn | UploadStatus::Expired | UploadStatus::Removed)
+}
+
+proptest! {
+    #[test]
+    fn only_pending_uploads_get_decided(status in select(&STATUSES[..]), action in select(&ACTIONS[..])) {
+        let decision = matches!(action, Action::Accept | Action::Reject);
+        if decision {
+            prop_assert_eq!(moderation::next(status, action).is_ok(), status == UploadStatus::Pending);
+        }
+        if is_final(status) {
+            prop_assert!(moderation::next(status, action).is_err());
+        }
+    }
+
+    // Replays a history from a fresh upload, refused actions leave the status as it was
+    #[test]
+    fn histories_only_take_legal_steps(actions in prop::collection::vec(select(&ACTIONS[..]), 0..40)) {
+        let mut status = UploadStatus::Pending;
+        for action in actions {
+            let Ok(next) = moderation::next(status, action) else {
+                continue;
+            };
+            match next {
+                // back in the queue only by undoing a decision
+                UploadStatus::Pending => prop_assert!(matches!(
+                    (status, action),
+                    (UploadStatus::Accepted | UploadStatus::Rejected, Action::Undo)
+                )),
+                UploadStatus::Removed => prop_assert_eq!(status, UploadStatus::Accepted),
+                _ => prop_assert_eq!(status, UploadStatus::Pending),
+            }
+            prop_assert!(!is_final(status));
+            status = next;
+        }
+    }
+
+    #[test]
+    fn undo_needs_a_decision_inside_the_window(
+        status in select(&STATUSES[..]),
+        window in 0i64..120,
+        elapsed in 0i64..240,
+    ) {
+        let decided_at = DateTime::from_timestamp(1_750_000_000, 0).unwrap();
+        let now = decided_at + TimeDelta::minutes(elapsed);
+        let result = moderation::undo(status, decided_at, now, TimeDelta::minutes(window));
+
+        let decided = matches!(status, UploadStatus::Accepted | UploadStatus::Rejected);
+        match result {
+            Ok(next) => {
+                prop_assert_eq!(next, UploadStatus::Pending);
+                prop_assert!(decided && window > 0 && elapsed <= window);
+            }
+            Err(Refusal::NothingToUndo(_)) => prop_assert!(!decided),
+            Err(Refusal::UndoWindowOver) => prop_assert!(decided && (window == 0 || elapsed > window)),
+            Err(refusal) => prop_assert!(false, "unexpected refusal {:?}", refusal),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use super::harness::{TestApp, level_id};
+use crate::models::{LevelId, UserId};
+use crate::{namespace, paths};
+use axum::http::StatusCode;
+use std::path::PathBuf;
+
+#[test]
+fn stored_paths_stay_inside_the_data_directories() {
+    assert_eq!(paths::canonical("thumbnails/5.webp"), Some(PathBuf::from("thumbnails/5.webp")));
+    assert_eq!(paths::canonical("./archive/5/12.webp"), Some(PathBuf::from("archive/5/12.webp")));
+
+    for path in [
+        "/etc/passwd",
+        "../thumbnails/5.webp",
+        "thumbnails/../../etc/passwd",
+        "thumbnails/../.env",
+        "logs/app.log",
+        ".env",
+        "",
+    ] {
+        assert_eq!(paths::canonical(path), None, "{} was accepted", path);
+    }
+
+    let built = [
+        paths::thumbnail_path("some-gdps", LevelId(5)),
+        paths::pending_path(namespace::DEFAULT, UserId(3), LevelId(5)),
+        paths::archive_path("some-gdps", LevelId(5), 9),
+        paths::experiment_path(LevelId(5)),
+    ];
+    for path in built {
+        assert!(paths::canonical(&path).is_some(), "{} was refused", path);
+    }
+}
+
+#[test]
+fn partial_files_stay_next_to_their_destination() {
+    let path = paths::thumbnail_path(namespace::DEFAULT, LevelId(5));
+    let first = paths::partial_path(&path);
+    let second = paths::partial_path(&path);
+    assert_ne!(first, second);
+    assert_eq!(first.parent(), Some(std::path::Path::new("thumbnails")));
+    assert!(paths::canonical(&first).is_some());
+}
+
+#[test]
+#[should_panic]
+fn unchecked_namespaces_never_become_directories() {
+    paths::thumbnail_path("../../etc", LevelId(5));
+}
+
+#[tokio::test]
+async fn namespace_traversal_is_not_found() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+
+    let url = format!("/gdps/..%2F..%2Fetc/thumbnail/{}", level_id());
+    let response = app.get(&url, None).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+    app.cleanup().await;
+}
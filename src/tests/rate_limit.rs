@@ -0,0 +1,34 @@
+use crate::rate_limit::{Bucket, Limiter};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[test]
+fn windows_start_over() {
+    let mut limiter = Limiter::default();
+    let ip: IpAddr = "203.0.113.9".parse().unwrap();
+    let other: IpAddr = "203.0.113.10".parse().unwrap();
+    let minute = Duration::from_secs(60);
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        assert!(limiter.hit(Bucket::Graphql, ip, 3, minute, now).is_ok());
+    }
+    let later = now + Duration::from_secs(20);
+    assert_eq!(limiter.hit(Bucket::Graphql, ip, 3, minute, later), Err(Duration::from_secs(40)));
+    assert!(limiter.hit(Bucket::Graphql, other, 3, minute, later).is_ok());
+
+    limiter.prune(now + minute);
+    assert!(limiter.hit(Bucket::Graphql, ip, 3, minute, now + minute).is_ok());
+}
+
+#[test]
+fn buckets_count_separately() {
+    let mut limiter = Limiter::default();
+    let ip: IpAddr = "203.0.113.9".parse().unwrap();
+    let hour = Duration::from_secs(60 * 60);
+    let now = Instant::now();
+
+    assert!(limiter.hit(Bucket::Batch, ip, 1, hour, now).is_ok());
+    assert!(limiter.hit(Bucket::Batch, ip, 1, hour, now).is_err());
+    assert!(limiter.hit(Bucket::Graphql, ip, 1, hour, now).is_ok());
+}
@@ -0,0 +1,36 @@
+use super::harness::TestApp;
+use crate::database::Role;
+use crate::reload;
+use axum::http::StatusCode;
+
+#[test]
+fn cors_origins_are_parsed() {
+    assert_eq!(reload::parse_origins(None), Ok(None));
+    assert_eq!(reload::parse_origins(Some("*")), Ok(None));
+
+    let origins = reload::parse_origins(Some("https://a.example, https://b.example/")).unwrap();
+    let origins: Vec<_> =
+        origins.unwrap().into_iter().map(|o| o.to_str().unwrap().to_string()).collect();
+    assert_eq!(origins, ["https://a.example", "https://b.example"]);
+
+    assert!(reload::parse_origins(Some("https://a.example,bad\norigin")).is_err());
+}
+
+#[tokio::test]
+async fn admins_can_reload_the_configuration() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, user) = app.user(Role::User).await;
+    let (_, admin) = app.user(Role::Admin).await;
+
+    let response = app.post("/admin/reload", Some(&user), Vec::new()).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = app.post("/admin/reload", Some(&admin), Vec::new()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(response.json()["reloaded"]["log_level"].is_string());
+    assert!(response.json()["reloaded"]["rate_limits"]["graphql_per_minute"].is_u64());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,58 @@
+use super::harness::{TestApp, level_id};
+use crate::database::{BackupEntry, ImageMeta, Role};
+use crate::{namespace, paths};
+
+#[tokio::test]
+async fn restored_ids_never_move_to_another_level() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, _) = app.user(Role::User).await;
+
+    let (level, other) = (level_id(), level_id());
+    let meta = ImageMeta {
+        width: Some(1920),
+        height: Some(1080),
+        file_size: 100,
+        encoding: Some("lossless"),
+        encoder_version: None,
+    };
+    let live_path = paths::thumbnail_path(namespace::DEFAULT, level);
+    let upload_id = app
+        .db
+        .add_upload(namespace::DEFAULT, level, user.id, &live_path, true, &meta)
+        .await
+        .unwrap();
+
+    let entry = BackupEntry {
+        id: upload_id,
+        namespace: namespace::DEFAULT.to_string(),
+        level_id: other,
+        account_id: user.account_id,
+        username: user.username.clone(),
+        upload_time: app.db.now(),
+        accepted_time: Some(app.db.now()),
+        accepted_by: None,
+        license: None,
+        credit: None,
+        width: None,
+        height: None,
+        file_size: None,
+        encoding: None,
+        encoder_version: None,
+    };
+    let other_path = paths::thumbnail_path(namespace::DEFAULT, other);
+    let restored = app.db.restore_upload(&entry, user.id, &other_path, Some(app.db.now())).await;
+    assert_eq!(restored.unwrap(), None);
+    assert_eq!(
+        app.db.get_live_upload_id(namespace::DEFAULT, level).await.unwrap(),
+        Some(upload_id)
+    );
+    assert_eq!(app.db.get_live_upload_id(namespace::DEFAULT, other).await.unwrap(), None);
+
+    let entry = BackupEntry { level_id: level, ..entry };
+    let restored = app.db.restore_upload(&entry, user.id, &live_path, None).await;
+    assert_eq!(restored.unwrap(), Some(0));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,23 @@
+use super::harness::TestApp;
+use crate::database::Role;
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn pages_past_the_end_are_refused() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, moderator) = app.user(Role::Moderator).await;
+
+    let response = app.get("/search?q=stereo&page=2", None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.json()["page"], 2);
+
+    let far = format!("page={}&limit=50", i64::MAX);
+    let response = app.get(&format!("/search?q=stereo&{}", far), None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = app.get(&format!("/user/search?q=mod&{}", far), Some(&moderator)).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    app.cleanup().await;
+}
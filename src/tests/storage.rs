@@ -0,0 +1,50 @@
+use super::harness::{TestApp, level_id, test_image};
+use crate::database::{QueuedMirror, Role};
+use crate::{namespace, paths};
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn newer_changes_outlive_older_copies() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+
+    let path = "thumbnails/5.webp";
+    app.db.queue_mirror(path, Some("old")).await.unwrap();
+    let old = app.db.get_queued_mirrors(10).await.unwrap().remove(0);
+    app.db.queue_mirror(path, None).await.unwrap();
+
+    // the copy of the old file finishing late leaves the removal queued
+    app.db.finish_mirror(&old).await.unwrap();
+    let queued = app.db.get_queued_mirrors(10).await.unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].hash, None);
+
+    let removal = QueuedMirror {
+        path: path.to_string(),
+        hash: None,
+    };
+    app.db.finish_mirror(&removal).await.unwrap();
+    assert!(app.db.get_queued_mirrors(10).await.unwrap().is_empty());
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn thumbnails_only_in_the_bucket_still_belong_to_their_uploader() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, author) = app.user(Role::User).await;
+    let (_, verified) = app.user(Role::Verified).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+    app.accepted_upload(level, &author, &moderator, [30, 60, 90]).await;
+    app.bucket_only(&paths::thumbnail_path(namespace::DEFAULT, level)).await;
+
+    let upload =
+        app.post(&format!("/upload/{}", level), Some(&verified), test_image([90, 60, 30])).await;
+    assert_eq!(upload.status, StatusCode::ACCEPTED, "{:?}", upload.json());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,24 @@
+use super::harness::TestApp;
+use crate::database::Role;
+use crate::two_factor::{self, Verification};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+#[tokio::test]
+async fn totp_codes_cant_be_replayed() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, _) = app.user(Role::User).await;
+    let secret = two_factor::generate_secret();
+    app.db.set_two_factor(user.id, Some(&secret), true).await.unwrap();
+
+    let bytes = Secret::Encoded(secret).to_bytes().unwrap();
+    let code = TOTP::new(Algorithm::SHA1, 6, 0, 30, bytes).unwrap().generate_current().unwrap();
+
+    let first = two_factor::verify(&app.db, user.id, &code).await.unwrap();
+    assert!(matches!(first, Verification::Valid));
+    let replayed = two_factor::verify(&app.db, user.id, &code).await.unwrap();
+    assert!(matches!(replayed, Verification::Invalid));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,301 @@
+use super::harness::{TestApp, level_id, test_image};
+use crate::database::{NewUploadFreeze, Role};
+use crate::{namespace, paths};
+use axum::http::StatusCode;
+
+#[tokio::test]
+async fn accepted_upload_is_served() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let missing = app.get(&format!("/thumbnail/{}", level), None).await;
+    assert_eq!(missing.status, StatusCode::NOT_FOUND);
+
+    app.accepted_upload(level, &uploader, &moderator, [200, 40, 40]).await;
+
+    let served = app.get(&format!("/thumbnail/{}", level), None).await;
+    assert_eq!(served.status, StatusCode::OK);
+    assert_eq!(served.content_type.as_deref(), Some("image/webp"));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn rejected_upload_is_not_served() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let response =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([40, 40, 200])).await;
+    assert!(response.status.is_success(), "upload failed: {:?}", response.json());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&moderator)).await;
+    assert_eq!(pending.status, StatusCode::OK);
+    let upload_id = pending.json()[0]["id"].as_i64().expect("upload is pending");
+    let upload_time = pending.json()[0]["upload_time"].as_str().unwrap_or_default().to_string();
+    assert!(
+        chrono::DateTime::parse_from_rfc3339(&upload_time).is_ok(),
+        "upload_time isn't RFC 3339: {}",
+        upload_time
+    );
+
+    let decision = app
+        .post_json(
+            &format!("/pending/{}", upload_id),
+            Some(&moderator),
+            serde_json::json!({ "accepted": false, "reason": "Wrong level" }),
+        )
+        .await;
+    assert_eq!(decision.status, StatusCode::OK, "{:?}", decision.json());
+
+    let served = app.get(&format!("/thumbnail/{}", level), None).await;
+    assert_eq!(served.status, StatusCode::NOT_FOUND);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn only_moderators_review_uploads() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let level = level_id();
+
+    let anonymous = app.post(&format!("/upload/{}", level), None, test_image([0, 0, 0])).await;
+    assert_eq!(anonymous.status, StatusCode::UNAUTHORIZED);
+
+    let response =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([0, 120, 0])).await;
+    assert!(response.status.is_success(), "upload failed: {:?}", response.json());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&uploader)).await;
+    assert_eq!(pending.status, StatusCode::FORBIDDEN);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn trial_mods_review_but_their_uploads_are_queued() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, trial_mod) = app.user(Role::TrialMod).await;
+    let level = level_id();
+
+    app.accepted_upload(level, &uploader, &trial_mod, [120, 0, 120]).await;
+
+    // replacing someone else's thumbnail still needs another reviewer
+    let response =
+        app.post(&format!("/upload/{}", level), Some(&trial_mod), test_image([0, 0, 120])).await;
+    assert!(response.status.is_success(), "upload failed: {:?}", response.json());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&trial_mod)).await;
+    assert_eq!(pending.json().as_array().map(Vec::len), Some(1));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn undoing_a_replacement_without_an_archive_takes_the_old_one_down() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let first = app.accepted_upload(level, &uploader, &moderator, [10, 60, 10]).await;
+    app.clock.advance(chrono::TimeDelta::seconds(1));
+    let second = app.accepted_upload(level, &uploader, &moderator, [60, 10, 10]).await;
+
+    // what retention does once the archive is old enough
+    app.db.clear_archive_paths(&[first]).await.unwrap();
+
+    let undo = app.post(&format!("/pending/{}/undo", second), Some(&moderator), vec![]).await;
+    assert_eq!(undo.status, StatusCode::OK, "{:?}", undo.json());
+    assert_eq!(app.db.get_live_upload_id(crate::namespace::DEFAULT, level).await.unwrap(), None);
+    let served = app.get(&format!("/thumbnail/{}", level), None).await;
+    assert_eq!(served.status, StatusCode::NOT_FOUND);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn presence_is_shared_through_the_database() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (moderator, token) = app.user(Role::Moderator).await;
+
+    let pinged = app.post("/pending/presence", Some(&token), Vec::new()).await;
+    assert_eq!(pinged.status, StatusCode::OK);
+    let timeout = std::time::Duration::from_secs(5 * 60);
+    let present = app.db.get_present_moderators(timeout).await.unwrap();
+    assert!(present.contains(&moderator.id));
+
+    app.clock.advance(chrono::TimeDelta::minutes(6));
+    let present = app.db.get_present_moderators(timeout).await.unwrap();
+    assert!(!present.contains(&moderator.id));
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn frozen_levels_refuse_uploads() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (admin, _) = app.user(Role::Admin).await;
+    let (_, uploader) = app.user(Role::Verified).await;
+    let level = level_id();
+
+    let freeze = NewUploadFreeze {
+        namespace: namespace::DEFAULT,
+        name: "judging",
+        reason: "contest judging",
+        level_ids: &[level],
+        starts_at: app.db.now(),
+        ends_at: app.db.now() + chrono::TimeDelta::days(1),
+    };
+    app.db.add_upload_freeze(freeze, admin.id).await.unwrap();
+
+    let upload =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([10, 10, 10])).await;
+    assert_eq!(upload.status, StatusCode::LOCKED);
+    assert_eq!(upload.json()["freeze"], "judging");
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn accepting_waits_for_the_level_lock() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let upload =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([9, 9, 9])).await;
+    assert!(upload.status.is_success());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&moderator)).await;
+    let upload_id = pending.json()[0]["id"].as_i64().unwrap();
+
+    let lock = app.db.try_lock_level(namespace::DEFAULT, level).await.unwrap();
+    assert!(lock.is_some());
+    let accept = serde_json::json!({ "accepted": true });
+    let decision =
+        app.post_json(&format!("/pending/{}", upload_id), Some(&moderator), accept).await;
+    assert_eq!(decision.status, StatusCode::CONFLICT);
+
+    drop(lock);
+    let accept = serde_json::json!({ "accepted": true });
+    let decision =
+        app.post_json(&format!("/pending/{}", upload_id), Some(&moderator), accept).await;
+    assert_eq!(decision.status, StatusCode::OK);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn an_upload_is_decided_only_once() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (_, uploader) = app.user(Role::User).await;
+    let (_, first) = app.user(Role::Moderator).await;
+    let (_, second) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let upload =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([7, 7, 7])).await;
+    assert!(upload.status.is_success());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&first)).await;
+    let path = format!("/pending/{}", pending.json()[0]["id"].as_i64().unwrap());
+
+    let (accept, reject) = tokio::join!(
+        app.post_json(&path, Some(&first), serde_json::json!({ "accepted": true })),
+        app.post_json(&path, Some(&second), serde_json::json!({ "accepted": false })),
+    );
+    let decided = [accept.status, reject.status].iter().filter(|s| **s == StatusCode::OK).count();
+    assert_eq!(decided, 1, "{:?} {:?}", accept.json(), reject.json());
+
+    let served = app.get(&format!("/thumbnail/{}", level), None).await;
+    let live = if accept.status == StatusCode::OK {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    assert_eq!(served.status, live);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn a_failed_move_puts_the_upload_back_in_the_queue() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, uploader) = app.user(Role::User).await;
+    let (_, moderator) = app.user(Role::Moderator).await;
+    let level = level_id();
+
+    let upload =
+        app.post(&format!("/upload/{}", level), Some(&uploader), test_image([5, 5, 5])).await;
+    assert!(upload.status.is_success());
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&moderator)).await;
+    let upload_id = pending.json()[0]["id"].as_i64().unwrap();
+
+    let pending_path = paths::pending_path(namespace::DEFAULT, user.id, level);
+    tokio::fs::remove_file(paths::local(&pending_path)).await.unwrap();
+    let accept = serde_json::json!({ "accepted": true });
+    let decision =
+        app.post_json(&format!("/pending/{}", upload_id), Some(&moderator), accept).await;
+    assert_eq!(decision.status, StatusCode::INTERNAL_SERVER_ERROR);
+
+    let pending = app.get(&format!("/pending/level/{}", level), Some(&moderator)).await;
+    assert_eq!(pending.json()[0]["id"].as_i64(), Some(upload_id));
+    let served = app.get(&format!("/thumbnail/{}", level), None).await;
+    assert_eq!(served.status, StatusCode::NOT_FOUND);
+
+    app.cleanup().await;
+}
+
+#[tokio::test]
+async fn namespace_admins_undo_decisions_in_their_namespace() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (owner, _) = app.user(Role::Admin).await;
+    let (_, uploader) = app.user(Role::User).await;
+    let (moderator, moderator_token) = app.user(Role::User).await;
+    let (admin, admin_token) = app.user(Role::User).await;
+    let ns = format!("gdps-{}", level_id());
+    app.db.add_namespace(&ns, "Test GDPS", owner.id).await.unwrap();
+    app.db.set_namespace_role(moderator.id, &ns, Some(Role::Moderator)).await.unwrap();
+    app.db.set_namespace_role(admin.id, &ns, Some(Role::Admin)).await.unwrap();
+
+    let level = level_id();
+    let upload = app
+        .post(&format!("/gdps/{}/upload/{}", ns, level), Some(&uploader), test_image([3, 2, 1]))
+        .await;
+    assert_eq!(upload.status, StatusCode::ACCEPTED, "{:?}", upload.json());
+    let upload_id = upload.json()["upload_id"].as_i64().unwrap();
+    let accept = serde_json::json!({ "accepted": true });
+    let decision =
+        app.post_json(&format!("/pending/{}", upload_id), Some(&moderator_token), accept).await;
+    assert_eq!(decision.status, StatusCode::OK, "{:?}", decision.json());
+
+    let undo = app.post(&format!("/pending/{}/undo", upload_id), Some(&admin_token), vec![]).await;
+    assert_eq!(undo.status, StatusCode::OK, "{:?}", undo.json());
+
+    app.cleanup().await;
+}
@@ -0,0 +1,83 @@
+use super::harness::TestApp;
+use crate::auth::UserSession;
+use crate::database::Role;
+use crate::settings;
+use crate::upload_source::{self, UploadSource, resolve};
+use axum::http::{HeaderMap, HeaderValue, header};
+use serde_json::json;
+
+#[test]
+fn dashboard_sessions_upload_as_web() {
+    assert_eq!(resolve(None, true, false, true), Ok(UploadSource::Web));
+    assert_eq!(resolve(Some("web"), true, false, true), Ok(UploadSource::Web));
+    // a signed upload with a dashboard session is still the dashboard's
+    assert_eq!(resolve(None, true, true, true), Ok(UploadSource::Web));
+    assert!(resolve(Some("mod"), true, false, false).is_err());
+    assert!(resolve(Some("api"), true, false, false).is_err());
+}
+
+#[test]
+fn token_uploads_are_api_unless_the_mod_shows_it() {
+    assert_eq!(resolve(None, false, false, true), Ok(UploadSource::Api));
+    assert_eq!(resolve(Some("api"), false, false, true), Ok(UploadSource::Api));
+    assert_eq!(resolve(None, false, true, true), Ok(UploadSource::Mod));
+    assert_eq!(resolve(Some("mod"), false, true, true), Ok(UploadSource::Mod));
+
+    // the claim alone only counts while there is no attestation to check it with
+    assert_eq!(resolve(Some("mod"), false, false, true), Ok(UploadSource::Api));
+    assert_eq!(resolve(Some("mod"), false, false, false), Ok(UploadSource::Mod));
+
+    assert!(resolve(Some("web"), false, false, false).is_err());
+}
+
+#[test]
+fn unknown_claims_are_refused() {
+    for claim in ["import", "MOD", "game", "mod, web"] {
+        assert_eq!(
+            resolve(Some(claim), false, true, true),
+            Err("Unknown upload source"),
+            "{}",
+            claim
+        );
+    }
+    assert_eq!(resolve(Some("  "), false, false, true), Ok(UploadSource::Api));
+}
+
+#[test]
+fn source_policies_are_settings() {
+    let changes = json!({"source_upload_quotas": {"api": 5}, "review_sources": ["api", "web"]});
+    let merged = settings::merge(changes.as_object().unwrap()).unwrap();
+    assert_eq!(merged.source_upload_quotas.get(&UploadSource::Api), Some(&5));
+    assert_eq!(merged.review_sources, [UploadSource::Api, UploadSource::Web]);
+
+    let changes = json!({"source_upload_quotas": {"game": 5}});
+    assert!(settings::merge(changes.as_object().unwrap()).is_err());
+}
+
+#[tokio::test]
+async fn web_comes_from_where_the_session_was_issued() {
+    let Some(app) = TestApp::new().await else {
+        return;
+    };
+    let (user, _) = app.user(Role::User).await;
+
+    let headers = |session: UserSession, cookie: bool| {
+        let mut headers = HeaderMap::new();
+        let token = session.to_jwt();
+        let (name, value) = match cookie {
+            true => (header::COOKIE, format!("auth_token={}", token)),
+            false => (header::AUTHORIZATION, format!("Bearer {}", token)),
+        };
+        headers.insert(name, HeaderValue::from_str(&value).unwrap());
+        headers
+    };
+    let session = || UserSession::new(user.id, user.username.clone());
+
+    // a game login sent as a cookie is still not the dashboard
+    let game = headers(session(), true);
+    assert_eq!(upload_source::from_request(&game, false), Ok(UploadSource::Api));
+    let dashboard = headers(session().for_dashboard(true), false);
+    assert_eq!(upload_source::from_request(&dashboard, false), Ok(UploadSource::Web));
+
+    app.cleanup().await;
+}
@@ -0,0 +1,35 @@
+use super::harness::test_image;
+use crate::pipeline::{self, IMAGE_HEIGHT, IMAGE_WIDTH};
+use image::ImageFormat;
+use std::io::Cursor;
+
+const FORMATS: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut data = Cursor::new(Vec::new());
+    image::RgbImage::new(width, height).write_to(&mut data, ImageFormat::Png).unwrap();
+    data.into_inner()
+}
+
+#[test]
+fn only_thumbnail_sized_images_in_allowed_formats_pass() {
+    let image = pipeline::validate_upload(&test_image([10, 20, 30]), &FORMATS).unwrap();
+    assert_eq!((image.width(), image.height()), (IMAGE_WIDTH, IMAGE_HEIGHT));
+
+    assert!(pipeline::validate_upload(&test_image([10, 20, 30]), &[ImageFormat::WebP]).is_err());
+    assert!(pipeline::validate_upload(&png(1919, 1080), &FORMATS).is_err());
+    assert!(pipeline::validate_upload(b"", &FORMATS).is_err());
+    assert!(pipeline::validate_upload(b"\x89PNG\r\n\x1a\n garbage", &FORMATS).is_err());
+
+    // a valid header with the data cut off fails to decode instead of panicking
+    let truncated = test_image([10, 20, 30]);
+    assert!(pipeline::validate_upload(&truncated[..truncated.len() / 2], &FORMATS).is_err());
+}
+
+#[test]
+fn oversized_headers_are_refused_before_decoding() {
+    // 60000x60000 would take over 10 GB once decoded, the header alone says so
+    let mut data = png(60000, 1);
+    data[20..24].copy_from_slice(&60000u32.to_be_bytes());
+    assert!(pipeline::validate_upload(&data, &FORMATS).is_err());
+}
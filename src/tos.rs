@@ -0,0 +1,35 @@
+use crate::{database, util};
+use axum::http::StatusCode;
+use axum::response::Response;
+use serde_json::json;
+
+// Uploads are refused until the user accepted the newest published terms
+pub async fn check(db: &database::Database, user: &database::User) -> Result<(), Response> {
+    let current = match db.get_current_tos().await {
+        Ok(Some(current)) => current,
+        Ok(None) => return Ok(()), // nothing published yet
+        Err(e) => {
+            return Err(util::str_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Error fetching terms of service: {}", e),
+            ));
+        }
+    };
+
+    match db.has_accepted_tos(user.id, &current.version).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(util::response(
+            StatusCode::CONFLICT,
+            json!({
+                "status": StatusCode::CONFLICT.as_u16(),
+                "message": "You need to accept the current terms of service before uploading",
+                "version": current.version,
+                "url": current.url,
+            }),
+        )),
+        Err(e) => Err(util::str_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("Error checking terms of service: {}", e),
+        )),
+    }
+}
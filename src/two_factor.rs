@@ -0,0 +1,204 @@
+use crate::auth::{self, UserSession};
+use crate::models::UserId;
+use crate::{database, settings};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "Level Thumbnails";
+const RECOVERY_CODE_COUNT: usize = 10;
+// how long a login can wait for its second factor
+const CHALLENGE_TTL: chrono::Duration = chrono::Duration::minutes(5);
+// wrong codes allowed per user within ATTEMPT_WINDOW
+const MAX_ATTEMPTS: u32 = 5;
+const ATTEMPT_WINDOW: Duration = Duration::from_secs(300);
+
+static ATTEMPTS: LazyLock<Mutex<HashMap<UserId, (u32, Instant)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn generate_secret() -> String {
+    match Secret::Raw(rand::random::<[u8; 20]>().to_vec()).to_encoded() {
+        Secret::Encoded(secret) => secret,
+        Secret::Raw(_) => unreachable!("to_encoded always returns an encoded secret"),
+    }
+}
+
+fn totp(secret: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string()).to_bytes().ok()?;
+    TOTP::new(Algorithm::SHA1, 6, 0, 30, secret).ok()
+}
+
+// The time step a code belongs to, allowing one step of clock drift either way. Callers
+// claim it with db.use_totp_step so the same code can't be used twice
+pub fn code_step(secret: &str, code: &str) -> Option<i64> {
+    let code = code.trim();
+    let totp = totp(secret)?;
+    let current = chrono::Utc::now().timestamp() as u64 / totp.step;
+    (current.saturating_sub(1)..=current + 1)
+        .find(|step| totp.check(code, step * totp.step))
+        .map(|step| step as i64)
+}
+
+// What authenticator apps scan, usually rendered as a QR code by the dashboard
+pub fn otpauth_url(secret: &str, username: &str) -> String {
+    let label = format!("{}:{}", ISSUER, username.replace(':', ""));
+    reqwest::Url::parse_with_params(
+        &format!("otpauth://totp/{}", urlencode(&label)),
+        &[("secret", secret), ("issuer", ISSUER), ("algorithm", "SHA1"), ("digits", "6")],
+    )
+    .map(|url| url.to_string())
+    .unwrap_or_default()
+}
+
+fn urlencode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 5]>());
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
+
+// Counts a failed attempt, false once the user ran out of attempts
+fn allow_attempt(user_id: UserId) -> bool {
+    let mut attempts = ATTEMPTS.lock().unwrap();
+    attempts.retain(|_, (_, since)| since.elapsed() < ATTEMPT_WINDOW);
+    attempts.get(&user_id).is_none_or(|(count, _)| *count < MAX_ATTEMPTS)
+}
+
+fn record_failure(user_id: UserId) {
+    let mut attempts = ATTEMPTS.lock().unwrap();
+    attempts.entry(user_id).or_insert((0, Instant::now())).0 += 1;
+}
+
+pub enum Verification {
+    Valid,
+    Invalid,
+    TooManyAttempts,
+}
+
+// Accepts either a current TOTP code or one of the unused recovery codes
+pub async fn verify(
+    db: &database::Database,
+    user_id: UserId,
+    code: &str,
+) -> Result<Verification, sqlx::Error> {
+    if !allow_attempt(user_id) {
+        return Ok(Verification::TooManyAttempts);
+    }
+
+    let valid = match db.get_two_factor(user_id).await {
+        Some(database::TwoFactor {
+            totp_secret: Some(secret),
+            totp_enabled: true,
+        }) => match code_step(&secret, code) {
+            Some(step) => db.use_totp_step(user_id, step).await?,
+            None => db.use_recovery_code(user_id, &hash_recovery_code(code)).await?,
+        },
+        _ => false,
+    };
+
+    if !valid {
+        record_failure(user_id);
+        return Ok(Verification::Invalid);
+    }
+    Ok(Verification::Valid)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    id: UserId,
+    #[serde(default)]
+    dashboard: bool, // the session it turns into is for the dashboard
+    exp: u64,
+}
+
+// Challenges are signed with their own key so they can never pass as another kind of token
+fn challenge_key() -> Vec<u8> {
+    let jwt_secret = auth::secret();
+    format!("{}:2fa", jwt_secret).into_bytes()
+}
+
+pub fn challenge_token(user_id: UserId, dashboard: bool) -> String {
+    let challenge = Challenge {
+        id: user_id,
+        dashboard,
+        exp: (chrono::Utc::now() + CHALLENGE_TTL).timestamp() as u64,
+    };
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &challenge,
+        &jsonwebtoken::EncodingKey::from_secret(&challenge_key()),
+    )
+    .expect("Failed to encode JWT")
+}
+
+// The user and whether the login came from the dashboard
+pub fn decode_challenge(token: &str) -> Option<(UserId, bool)> {
+    jsonwebtoken::decode::<Challenge>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret(&challenge_key()),
+        &jsonwebtoken::Validation::default(),
+    )
+    .ok()
+    .map(|data| (data.claims.id, data.claims.dashboard))
+}
+
+pub enum Login {
+    Session(String),   // a token the client can use right away
+    Challenge(String), // the user has to send a code along with this first
+}
+
+// Users with 2FA enabled only get a session after POST /auth/2fa
+pub async fn login(db: &database::Database, user: &database::User, dashboard: bool) -> Login {
+    match db.get_two_factor(user.id).await {
+        Some(database::TwoFactor { totp_enabled: true, .. }) => {
+            Login::Challenge(challenge_token(user.id, dashboard))
+        }
+        _ => Login::Session(
+            UserSession::new(user.id, user.username.clone()).for_dashboard(dashboard).to_jwt(),
+        ),
+    }
+}
+
+// Sessions without a second factor never carry moderator or admin rights
+pub fn limit_session(user: &mut database::User, session: &UserSession) {
+    if !session.mfa {
+        limit_unverified(user);
+    }
+}
+
+// For requests that can't carry a second factor at all, like Discord commands
+pub fn limit_unverified(user: &mut database::User) {
+    if !settings::current().require_staff_2fa {
+        return;
+    }
+
+    user.limited = true;
+    user.role = cap_role(user, user.role);
+}
+
+pub fn cap_role(user: &database::User, role: database::Role) -> database::Role {
+    match user.limited {
+        true => role.min(database::Role::Verified),
+        false => role,
+    }
+}
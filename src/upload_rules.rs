@@ -0,0 +1,94 @@
+use crate::database;
+use crate::feature_flags::{self, Flag};
+use crate::models::LevelId;
+use crate::{level_info, namespace, paths, storage};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+// account age used by new_account rules that don't set their own
+const DEFAULT_ACCOUNT_AGE_DAYS: i32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum RuleCondition {
+    NewLevel,     // the level has no thumbnail yet
+    LevelCreator, // the uploader made the level, only known for the default namespace
+    NewAccount,   // the account is younger than max_account_age_days
+    Attested,     // signed by the game mod, see attestation.rs
+    Unattested,   // anything without a valid mod signature
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum RuleAction {
+    Accept, // published right away
+    Hold,   // always goes to the pending queue
+}
+
+async fn matches(
+    db: &database::Database,
+    rule: &database::UploadRule,
+    user: &database::User,
+    namespace: &str,
+    level_id: LevelId,
+    attested: bool,
+) -> bool {
+    match rule.condition {
+        RuleCondition::NewLevel => {
+            let path = paths::thumbnail_path(namespace, level_id);
+            // fails closed, a level that can't be looked up isn't treated as new
+            !storage::exists(&path).await.unwrap_or(true)
+        }
+        RuleCondition::LevelCreator => {
+            namespace == namespace::DEFAULT
+                && level_info::get(db, level_id)
+                    .await
+                    .and_then(|level| level.author_account_id)
+                    .is_some_and(|account_id| account_id == user.account_id)
+        }
+        RuleCondition::NewAccount => {
+            let days = rule.max_account_age_days.unwrap_or(DEFAULT_ACCOUNT_AGE_DAYS);
+            let now = db.now();
+            db.get_user_created_at(user.id)
+                .await
+                .is_some_and(|created_at| now - created_at < chrono::Duration::days(days as i64))
+        }
+        RuleCondition::Attested => attested,
+        RuleCondition::Unattested => !attested,
+    }
+}
+
+// First enabled rule that matches an upload by a regular or verified user,
+// rules are only consulted while the auto_accept flag is on
+pub async fn evaluate(
+    db: &database::Database,
+    user: &database::User,
+    role: database::Role,
+    namespace: &str,
+    level_id: LevelId,
+    attested: bool,
+) -> Option<database::UploadRule> {
+    if !feature_flags::is_enabled(db, Flag::AutoAccept).await {
+        return None;
+    }
+
+    let rules = match db.get_enabled_upload_rules().await {
+        Ok(rules) => rules,
+        Err(e) => {
+            error!("Failed to load upload rules: {}", e);
+            return None;
+        }
+    };
+
+    for rule in rules {
+        if rule.role.is_some_and(|r| r != role) {
+            continue;
+        }
+        if matches(db, &rule, user, namespace, level_id, attested).await {
+            return Some(rule);
+        }
+    }
+    None
+}
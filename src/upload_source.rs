@@ -0,0 +1,102 @@
+use crate::{attestation, database, settings, util};
+use axum::http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+// Clients say where an upload comes from in X-Upload-Source. The claim is checked against
+// what the request shows: dashboard uploads come with a session the Discord login issued to
+// the dashboard, and while attestation is on only signed uploads count as the mod's. Anything
+// else is a script using the API
+
+pub const SOURCE_HEADER: &str = "X-Upload-Source";
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    sqlx::Type,
+    async_graphql::Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum UploadSource {
+    Mod,    // the Geode mod in game
+    Web,    // the dashboard
+    Api,    // anything else holding a token
+    Import, // the import command, never sent over HTTP
+}
+
+impl std::fmt::Display for UploadSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UploadSource::Mod => write!(f, "mod"),
+            UploadSource::Web => write!(f, "web"),
+            UploadSource::Api => write!(f, "api"),
+            UploadSource::Import => write!(f, "import"),
+        }
+    }
+}
+
+// A claimed mod upload without a valid signature is recorded as an API upload instead of
+// being refused, older mod versions don't sign theirs
+pub fn resolve(
+    claim: Option<&str>,
+    dashboard_session: bool,
+    attested: bool,
+    mod_needs_attestation: bool,
+) -> Result<UploadSource, &'static str> {
+    let claim = match claim.map(str::trim) {
+        None | Some("") => None,
+        Some("mod") => Some(UploadSource::Mod),
+        Some("web") => Some(UploadSource::Web),
+        Some("api") => Some(UploadSource::Api),
+        Some(_) => return Err("Unknown upload source"),
+    };
+
+    match (claim, dashboard_session) {
+        (None | Some(UploadSource::Web), true) => Ok(UploadSource::Web),
+        (Some(_), true) => Err("Dashboard sessions can only upload as web"),
+        (Some(UploadSource::Web), false) => Err("Only dashboard sessions can upload as web"),
+        _ if attested => Ok(UploadSource::Mod),
+        (Some(UploadSource::Mod), false) if !mod_needs_attestation => Ok(UploadSource::Mod),
+        _ => Ok(UploadSource::Api),
+    }
+}
+
+pub fn from_request(headers: &HeaderMap, attested: bool) -> Result<UploadSource, &'static str> {
+    let claim = match headers.get(SOURCE_HEADER).map(|h| h.to_str()) {
+        Some(Ok(claim)) => Some(claim),
+        Some(Err(_)) => return Err("Unknown upload source"),
+        None => None,
+    };
+    resolve(claim, util::is_dashboard_session(headers), attested, attestation::is_enabled())
+}
+
+// Daily uploads a user may send from this source, None when only the user's quota applies
+fn quota(source: UploadSource) -> Option<i64> {
+    let quotas = settings::current().source_upload_quotas;
+    quotas.get(&source).filter(|quota| **quota > 0).map(|quota| *quota as i64)
+}
+
+// Uploads from these sources always wait for a moderator, unless the uploader publishes directly
+pub fn needs_review(source: Option<UploadSource>) -> bool {
+    source.is_some_and(|source| settings::current().review_sources.contains(&source))
+}
+
+// Over the source quota, checked on top of the user's daily quota
+pub async fn over_quota(
+    db: &database::Database,
+    user: &database::User,
+    source: UploadSource,
+) -> Result<Option<i64>, sqlx::Error> {
+    let Some(quota) = quota(source) else {
+        return Ok(None);
+    };
+    let count = db.count_recent_uploads_from(user.id, source).await?;
+    Ok((count >= quota).then_some(quota))
+}
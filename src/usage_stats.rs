@@ -0,0 +1,120 @@
+use crate::models::UserId;
+use crate::{access_log, database, settings};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::error;
+
+// counts are kept in memory and rolled up into the daily table on this interval
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+// accounts younger than this get the new account quota
+const NEW_ACCOUNT_AGE: chrono::Duration = chrono::Duration::days(7);
+// accounts that used the API on this many of the last TRUSTED_WINDOW_DAYS get the trusted quota
+const TRUSTED_ACTIVE_DAYS: i64 = 14;
+pub const TRUSTED_WINDOW_DAYS: i64 = 90;
+
+// Where an account stands by the same rules that pick its upload quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Standing {
+    New,     // younger than NEW_ACCOUNT_AGE
+    Regular, // neither new nor trusted
+    Trusted, // active on TRUSTED_ACTIVE_DAYS of the last TRUSTED_WINDOW_DAYS
+}
+
+fn is_new_account(created_at: DateTime<Utc>) -> bool {
+    Utc::now() - created_at < NEW_ACCOUNT_AGE
+}
+
+pub fn standing(created_at: DateTime<Utc>, active_days: i64) -> Standing {
+    if is_new_account(created_at) {
+        Standing::New
+    } else if active_days >= TRUSTED_ACTIVE_DAYS {
+        Standing::Trusted
+    } else {
+        Standing::Regular
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    requests: i64,
+    upload_bytes: i64,
+}
+
+static PENDING_USAGE: LazyLock<Mutex<HashMap<UserId, Usage>>> = LazyLock::new(Default::default);
+
+pub fn record_upload(user_id: UserId, bytes: usize) {
+    if let Ok(mut usage) = PENDING_USAGE.lock() {
+        usage.entry(user_id).or_default().upload_bytes += bytes as i64;
+    }
+}
+
+// Counts requests carrying a session, the token is only decoded here
+pub async fn track(request: Request, next: Next) -> Response {
+    if let Some(user_id) = access_log::session_user_id(&request)
+        && let Ok(mut usage) = PENDING_USAGE.lock()
+    {
+        usage.entry(user_id).or_default().requests += 1;
+    }
+    next.run(request).await
+}
+
+async fn flush(db: &database::Database) {
+    let usage = match PENDING_USAGE.lock() {
+        Ok(mut usage) => std::mem::take(&mut *usage),
+        Err(_) => return,
+    };
+
+    if usage.is_empty() {
+        return;
+    }
+
+    let rows: Vec<(UserId, i64, i64)> = usage
+        .into_iter()
+        .map(|(user_id, usage)| (user_id, usage.requests, usage.upload_bytes))
+        .collect();
+    if let Err(e) = db.add_api_usage(&rows).await {
+        error!("Failed to store API usage of {} users: {}", rows.len(), e);
+    }
+}
+
+pub async fn run_flusher(db: database::Database) {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        flush(&db).await;
+    }
+}
+
+// Daily upload limit of a user, 0 is unlimited. New accounts and accounts with a long
+// usage history can get their own limits on top of the regular one
+pub async fn upload_quota(db: &database::Database, user: &database::User) -> i64 {
+    let settings = settings::current();
+    let quota = settings.upload_quota as i64;
+    if quota == 0 {
+        return 0;
+    }
+
+    if settings.new_account_upload_quota > 0
+        && db.get_user_created_at(user.id).await.is_some_and(is_new_account)
+    {
+        return settings.new_account_upload_quota as i64;
+    }
+
+    if settings.trusted_upload_quota > 0
+        && db
+            .count_active_days(user.id, TRUSTED_WINDOW_DAYS)
+            .await
+            .is_ok_and(|days| days >= TRUSTED_ACTIVE_DAYS)
+    {
+        return settings.trusted_upload_quota as i64;
+    }
+
+    quota
+}
@@ -1,8 +1,14 @@
 use crate::auth::UserSession;
-use crate::database;
+use crate::{database, two_factor};
+use axum::body::Body;
 use axum::http::{HeaderMap, StatusCode, header};
 use axum::response::Response;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Deserializer, de::Error};
 use serde_json::json;
+use tokio::io::DuplexStream;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use tracing::error;
 
 pub fn response(status: StatusCode, body: serde_json::Value) -> Response {
     Response::builder()
@@ -23,7 +29,61 @@ pub fn str_response(status: StatusCode, message: &str) -> Response {
     )
 }
 
-fn try_read_cookie(headers: &HeaderMap, cookie_name: &str) -> Option<String> {
+// Timestamps without an offset are read as UTC, which is what they always meant here
+// The archive is written on a blocking thread and streamed back through a pipe, so a large
+// one neither holds up the runtime nor sits in memory
+pub fn stream_archive<F, E>(
+    content_type: &str,
+    filename: &str,
+    name: &'static str,
+    write: F,
+) -> Response
+where
+    F: FnOnce(SyncIoBridge<DuplexStream>) -> Result<(), E> + Send + 'static,
+    E: std::fmt::Display,
+{
+    let (writer, reader) = tokio::io::duplex(64 * 1024);
+    let writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = write(writer) {
+            error!("{} failed: {}", name, e);
+        }
+    });
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from_stream(ReaderStream::new(reader)))
+        .unwrap()
+}
+
+pub fn parse_utc(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|at| at.with_timezone(&Utc))
+        .ok()
+        .or_else(|| value.parse::<NaiveDateTime>().ok().map(|at| at.and_utc()))
+}
+
+pub fn deserialize_utc<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse_utc(&value).ok_or_else(|| D::Error::custom(format!("invalid timestamp: {}", value)))
+}
+
+pub fn deserialize_utc_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => parse_utc(&value)
+            .map(Some)
+            .ok_or_else(|| D::Error::custom(format!("invalid timestamp: {}", value))),
+        None => Ok(None),
+    }
+}
+
+pub fn try_read_cookie(headers: &HeaderMap, cookie_name: &str) -> Option<String> {
     headers.get("Cookie").and_then(|cookie| {
         cookie.to_str().ok().and_then(|cookie_str| {
             cookie_str.split(';').find_map(|part| {
@@ -38,31 +98,83 @@ fn try_read_cookie(headers: &HeaderMap, cookie_name: &str) -> Option<String> {
     })
 }
 
-async fn session_response(
+pub async fn session_response(
     token: &str,
     db: &database::Database,
 ) -> Result<database::User, Response> {
     match UserSession::from_jwt(token) {
         Ok(session) => match db.get_user_by_id(session.id).await {
-            Some(user) => Ok(user),
+            Some(mut user) => {
+                two_factor::limit_session(&mut user, &session);
+                Ok(user)
+            }
             None => Err(str_response(StatusCode::FORBIDDEN, "User not found")),
         },
         Err(e) => Err(str_response(StatusCode::UNAUTHORIZED, &e.to_string())),
     }
 }
 
+// "Bearer <token>" with the scheme in any case, or just the token as older clients send it
+fn bearer_token(value: &str) -> Option<&str> {
+    let value = value.trim();
+    let token = match value.split_once(char::is_whitespace) {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        Some(_) => return None,
+        None => value,
+    };
+    (!token.is_empty()).then_some(token)
+}
+
+fn header_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(bearer_token)
+        .map(str::to_string)
+}
+
+fn cookie_token(headers: &HeaderMap) -> Option<String> {
+    try_read_cookie(headers, "auth_token=").filter(|token| !token.is_empty())
+}
+
+// The Authorization header wins over the dashboard cookie, an empty or unusable header
+// doesn't hide the cookie either
+pub fn session_token(headers: &HeaderMap) -> Option<String> {
+    header_token(headers).or_else(|| cookie_token(headers))
+}
+
+// Browsers attach cookies on their own, so these sessions need CSRF protection
+pub fn is_cookie_session(headers: &HeaderMap) -> bool {
+    header_token(headers).is_none() && cookie_token(headers).is_some()
+}
+
+// Whether the session was issued to the dashboard, however the token is sent along
+pub fn is_dashboard_session(headers: &HeaderMap) -> bool {
+    session_token(headers)
+        .and_then(|token| UserSession::from_jwt(&token).ok())
+        .is_some_and(|session| session.dashboard)
+}
+
 pub async fn auth_middleware(
     headers: &HeaderMap,
     db: &database::Database,
 ) -> Result<database::User, Response> {
-    match headers.get("Authorization").and_then(|h| h.to_str().ok()) {
-        Some(token) => session_response(token, db).await,
-        None => {
-            if let Some(token) = try_read_cookie(headers, "auth_token=") {
-                session_response(&token, db).await
-            } else {
-                Err(str_response(StatusCode::UNAUTHORIZED, "Missing Authorization header"))
-            }
+    let result = match session_token(headers) {
+        Some(token) => session_response(&token, db).await,
+        None if headers.contains_key(header::AUTHORIZATION) => Err(str_response(
+            StatusCode::UNAUTHORIZED,
+            "Authorization header must be a Bearer token",
+        )),
+        None => Err(str_response(StatusCode::UNAUTHORIZED, "Missing Authorization header")),
+    };
+
+    // tells clients which scheme to retry with
+    result.map_err(|mut response| {
+        if response.status() == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
         }
-    }
+        response
+    })
 }
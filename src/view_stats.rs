@@ -0,0 +1,41 @@
+use crate::database;
+use crate::models::LevelId;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::error;
+
+// counts are kept in memory and rolled up into the daily table on this interval
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+static PENDING_VIEWS: LazyLock<Mutex<HashMap<LevelId, i64>>> = LazyLock::new(Default::default);
+
+// Only requests that reach this server are counted, CDN cache hits never get here
+pub fn record(level_id: LevelId) {
+    if let Ok(mut views) = PENDING_VIEWS.lock() {
+        *views.entry(level_id).or_default() += 1;
+    }
+}
+
+async fn flush(db: &database::Database) {
+    let views = match PENDING_VIEWS.lock() {
+        Ok(mut views) => std::mem::take(&mut *views),
+        Err(_) => return,
+    };
+
+    if views.is_empty() {
+        return;
+    }
+
+    let views: Vec<(LevelId, i64)> = views.into_iter().collect();
+    if let Err(e) = db.add_thumbnail_views(&views).await {
+        error!("Failed to store {} thumbnail view counts: {}", views.len(), e);
+    }
+}
+
+pub async fn run_flusher(db: database::Database) {
+    loop {
+        tokio::time::sleep(FLUSH_INTERVAL).await;
+        flush(&db).await;
+    }
+}
@@ -0,0 +1,38 @@
+use crate::database;
+use crate::namespace;
+use crate::routes::thumbnail;
+use tracing::{info, warn};
+
+const DEFAULT_WARMUP_COUNT: i64 = 100;
+const WARMUP_DAYS: i64 = 7;
+
+// Pre-builds the variants of the most viewed thumbnails, so the first requests after a
+// deploy or a purge don't all pay for the resize
+pub async fn run(db: database::Database) {
+    let count = dotenv::var("WARMUP_COUNT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_WARMUP_COUNT);
+    if count <= 0 {
+        return;
+    }
+
+    let levels = match db.get_top_levels(WARMUP_DAYS, count).await {
+        Ok(levels) => levels,
+        Err(e) => {
+            warn!("Cache warm-up skipped, failed to fetch top levels: {}", e);
+            return;
+        }
+    };
+
+    let mut warmed = 0;
+    for level in &levels {
+        match thumbnail::warm_variants(namespace::DEFAULT, level.level_id).await {
+            Ok(()) => warmed += 1,
+            Err(response) => {
+                warn!("Failed to warm level {}: {}", level.level_id, response.status())
+            }
+        }
+    }
+    info!("Cache warm-up finished, {} of {} levels ready", warmed, levels.len());
+}
@@ -0,0 +1,214 @@
+use crate::events::QueueEvent;
+use crate::{database, outbound};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "thumbnail.accepted")]
+    ThumbnailAccepted,
+    #[serde(rename = "thumbnail.rejected")]
+    ThumbnailRejected,
+    #[serde(rename = "thumbnail.removed")]
+    ThumbnailRemoved,
+    #[serde(rename = "thumbnail.undone")]
+    ThumbnailUndone,
+    #[serde(rename = "user.banned")]
+    UserBanned,
+    #[serde(rename = "upload.quarantined")]
+    UploadQuarantined,
+    #[serde(rename = "takedown.requested")]
+    TakedownRequested,
+    #[serde(rename = "outbound.alert")]
+    OutboundAlert,
+}
+
+impl std::fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookEvent::ThumbnailAccepted => write!(f, "thumbnail.accepted"),
+            WebhookEvent::ThumbnailRejected => write!(f, "thumbnail.rejected"),
+            WebhookEvent::ThumbnailRemoved => write!(f, "thumbnail.removed"),
+            WebhookEvent::ThumbnailUndone => write!(f, "thumbnail.undone"),
+            WebhookEvent::UserBanned => write!(f, "user.banned"),
+            WebhookEvent::UploadQuarantined => write!(f, "upload.quarantined"),
+            WebhookEvent::TakedownRequested => write!(f, "takedown.requested"),
+            WebhookEvent::OutboundAlert => write!(f, "outbound.alert"),
+        }
+    }
+}
+
+pub fn generate_secret() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+// Covers "{timestamp}.{body}" so receivers can refuse old deliveries that are sent again
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn deliver(webhook: database::Webhook, event: WebhookEvent, body: String) {
+    let max_retries = 5;
+
+    for attempt in 1..=max_retries {
+        // signed again for every attempt, a retry shouldn't look like a stale replay
+        let timestamp = chrono::Utc::now().timestamp();
+        let signature = sign(&webhook.secret, timestamp, body.as_bytes());
+        let request = outbound::WEBHOOK
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Id", webhook.id.to_string())
+            .header("X-Webhook-Event", event.to_string())
+            .header("X-Webhook-Timestamp", timestamp.to_string())
+            .header("X-Webhook-Signature", &signature)
+            .body(body.clone());
+        let response = outbound::WEBHOOK.send(request).await;
+
+        let retryable = match response {
+            Ok(resp) if resp.status().is_success() => {
+                info!("Webhook {} delivered {} (attempt {})", webhook.id, event, attempt);
+                return;
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                error!("Webhook {} returned {} for {}", webhook.id, status, event);
+                status.as_u16() == 429 || status.is_server_error()
+            }
+            Err(e) => {
+                error!("Webhook {} delivery failed for {}: {}", webhook.id, event, e);
+                true
+            }
+        };
+
+        if !retryable {
+            return;
+        }
+
+        if attempt < max_retries {
+            tokio::time::sleep(std::time::Duration::from_secs(30 * attempt)).await;
+        }
+    }
+
+    error!("Webhook {} gave up on {} after {} attempts", webhook.id, event, max_retries);
+}
+
+// Deliver an event to every subscribed webhook, waiting for all deliveries to finish
+pub async fn dispatch(db: &database::Database, event: WebhookEvent, data: serde_json::Value) {
+    let webhooks = match db.get_webhooks_for_event(&event.to_string()).await {
+        Ok(webhooks) => webhooks,
+        Err(e) => {
+            error!("Failed to load webhooks for {}: {}", event, e);
+            return;
+        }
+    };
+
+    let body = serde_json::json!({
+        "event": event,
+        "timestamp": chrono::Utc::now().timestamp(),
+        "data": data,
+    })
+    .to_string();
+
+    let deliveries: Vec<_> = webhooks
+        .into_iter()
+        .map(|webhook| tokio::spawn(deliver(webhook, event, body.clone())))
+        .collect();
+
+    for delivery in deliveries {
+        let _ = delivery.await;
+    }
+}
+
+// Deliver an event in the background
+pub fn emit(db: &database::Database, event: WebhookEvent, data: serde_json::Value) {
+    let db = db.clone();
+    tokio::spawn(async move { dispatch(&db, event, data).await });
+}
+
+// Moderation decisions and direct publishes, as seen on the queue event bus
+pub fn on_queue_event(db: &database::Database, event: QueueEvent) {
+    match event {
+        QueueEvent::Decided {
+            upload_id,
+            namespace,
+            level_id,
+            user_id,
+            accepted: true,
+            moderator_id,
+            ..
+        } => emit(
+            db,
+            WebhookEvent::ThumbnailAccepted,
+            serde_json::json!({
+                "namespace": namespace,
+                "level_id": level_id,
+                "upload_id": upload_id,
+                "user_id": user_id,
+                "accepted_by": moderator_id,
+            }),
+        ),
+        QueueEvent::Decided {
+            upload_id,
+            namespace,
+            level_id,
+            user_id,
+            accepted: false,
+            moderator_id,
+            reason,
+        } => emit(
+            db,
+            WebhookEvent::ThumbnailRejected,
+            serde_json::json!({
+                "namespace": namespace,
+                "level_id": level_id,
+                "upload_id": upload_id,
+                "user_id": user_id,
+                "rejected_by": moderator_id,
+                "reason": reason,
+            }),
+        ),
+        QueueEvent::Published {
+            upload_id,
+            namespace,
+            level_id,
+            user_id,
+        } => emit(
+            db,
+            WebhookEvent::ThumbnailAccepted,
+            serde_json::json!({
+                "namespace": namespace,
+                "level_id": level_id,
+                "upload_id": upload_id,
+                "user_id": user_id,
+                "accepted_by": user_id,
+            }),
+        ),
+        QueueEvent::Undone {
+            upload_id,
+            namespace,
+            level_id,
+            user_id,
+            was_accepted,
+            moderator_id,
+        } => emit(
+            db,
+            WebhookEvent::ThumbnailUndone,
+            serde_json::json!({
+                "namespace": namespace,
+                "level_id": level_id,
+                "upload_id": upload_id,
+                "user_id": user_id,
+                "was_accepted": was_accepted,
+                "undone_by": moderator_id,
+            }),
+        ),
+        // takedowns emit thumbnail.removed themselves
+        QueueEvent::Submitted { .. } | QueueEvent::Claimed { .. } | QueueEvent::Removed { .. } => {}
+    }
+}